@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    abi::RawLog,
+    prelude::{abigen, EthEvent},
+    providers::Middleware,
+    types::{Log, H160, H256},
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amm::{
+        factory::{AutomatedMarketMakerFactory, Factory, TASK_LIMIT},
+        uniswap_v3::UniswapV3Pool,
+        AutomatedMarketMaker, AMM,
+    },
+    errors::AMMError,
+};
+
+use super::{AlgebraPool, ALGEBRA_TICK_SPACING};
+
+abigen!(
+    IAlgebraFactory,
+    r#"[
+        function poolByPair(address tokenA, address tokenB) external view returns (address pool)
+        event Pool(address indexed token0, address indexed token1, address pool)
+    ]"#;
+);
+
+pub const POOL_EVENT_SIGNATURE: H256 = H256(POOL_EVENT_SIGNATURE_BYTES);
+pub const POOL_EVENT_SIGNATURE_BYTES: [u8; 32] = [
+    145, 204, 170, 122, 39, 129, 48, 182, 81, 104, 195, 160, 200, 211, 188, 174, 132, 207, 94, 67,
+    112, 67, 66, 189, 62, 192, 181, 158, 89, 192, 54, 219,
+];
+
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AlgebraFactory {
+    pub address: H160,
+    pub creation_block: u64,
+}
+
+#[async_trait]
+impl AutomatedMarketMakerFactory for AlgebraFactory {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn creation_block(&self) -> u64 {
+        self.creation_block
+    }
+
+    fn amm_created_event_signature(&self) -> H256 {
+        POOL_EVENT_SIGNATURE
+    }
+
+    async fn new_amm_from_log<M: 'static + Middleware>(
+        &self,
+        log: Log,
+        middleware: Arc<M>,
+    ) -> Result<AMM, AMMError<M>> {
+        if let Some(block_number) = log.block_number {
+            let pool_filter = PoolFilter::decode_log(&RawLog::from(log))?;
+            Ok(AMM::AlgebraPool(
+                AlgebraPool::new_from_address(pool_filter.pool, block_number.as_u64(), middleware)
+                    .await?,
+            ))
+        } else {
+            Err(AMMError::BlockNumberNotFound)
+        }
+    }
+
+    async fn get_all_amms<M: 'static + Middleware>(
+        &self,
+        to_block: Option<u64>,
+        middleware: Arc<M>,
+        step: u64,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        if let Some(block) = to_block {
+            Factory::AlgebraFactory(*self)
+                .get_all_pools_from_logs(self.creation_block, block, step, None, middleware)
+                .await
+        } else {
+            Err(AMMError::BlockNumberNotFound)
+        }
+    }
+
+    //There is no batch contract for the globalState layout, so each pool is populated through its own calls with up to
+    //TASK_LIMIT pools in flight
+    async fn populate_amm_data<M: Middleware>(
+        &self,
+        amms: &mut [AMM],
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let results: Vec<Result<(), AMMError<M>>> = stream::iter(amms.iter_mut())
+            .map(|amm| amm.populate_data(block_number, middleware.clone()))
+            .buffer_unordered(TASK_LIMIT)
+            .collect()
+            .await;
+
+        for result in results {
+            result?;
+        }
+
+        Ok(())
+    }
+
+    fn new_empty_amm_from_log(log: Log) -> Result<AMM, ethers::abi::Error> {
+        let factory = log.address;
+        let pool_event = PoolFilter::decode_log(&RawLog::from(log))?;
+
+        Ok(AMM::AlgebraPool(AlgebraPool {
+            pool: UniswapV3Pool {
+                address: pool_event.pool,
+                token_a: pool_event.token_0,
+                token_b: pool_event.token_1,
+                tick_spacing: ALGEBRA_TICK_SPACING,
+                factory,
+                ..Default::default()
+            },
+        }))
+    }
+}
+
+impl AlgebraFactory {
+    pub fn new(address: H160, creation_block: u64) -> AlgebraFactory {
+        AlgebraFactory {
+            address,
+            creation_block,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        types::{Log, H160, H256},
+    };
+
+    use crate::amm::{factory::AutomatedMarketMakerFactory, AutomatedMarketMaker, AMM};
+
+    use super::{AlgebraFactory, POOL_EVENT_SIGNATURE};
+
+    #[test]
+    fn test_new_empty_amm_from_pool_log() -> eyre::Result<()> {
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pool_address = H160::from_low_u64_be(3);
+
+        let log = Log {
+            topics: vec![
+                POOL_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: encode(&[Token::Address(pool_address)]).into(),
+            ..Default::default()
+        };
+
+        let amm = AlgebraFactory::new_empty_amm_from_log(log)?;
+
+        assert!(matches!(amm, AMM::AlgebraPool(_)));
+        assert_eq!(amm.address(), pool_address);
+        assert_eq!(amm.tokens(), vec![token_0, token_1]);
+
+        Ok(())
+    }
+}
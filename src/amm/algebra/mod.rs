@@ -0,0 +1,390 @@
+pub mod factory;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    abi::RawLog,
+    prelude::{abigen, EthEvent},
+    providers::Middleware,
+    types::{Log, H160, H256, U256},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amm::AutomatedMarketMaker,
+    errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+};
+
+use super::{
+    uniswap_v2::IErc20,
+    uniswap_v3::{UniswapV3Pool, BURN_EVENT_SIGNATURE, MINT_EVENT_SIGNATURE, SWAP_EVENT_SIGNATURE},
+};
+
+abigen!(
+    IAlgebraPool,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function liquidity() external view returns (uint128)
+        function globalState() external view returns (uint160, int24, uint16, uint16, uint8, uint8, bool)
+        function tickTable(int16 wordPos) external view returns (uint256)
+        function ticks(int24 tick) external view returns (uint128, int128, uint256, uint256, int56, uint160, uint32, bool)
+        event Fee(uint16 fee)
+    ]"#;
+);
+
+//Algebra pools have a fixed tick spacing instead of one derived from the fee tier
+pub const ALGEBRA_TICK_SPACING: i32 = 60;
+
+pub const FEE_EVENT_SIGNATURE: H256 = H256([
+    89, 139, 159, 4, 60, 129, 58, 166, 190, 52, 38, 202, 96, 209, 198, 93, 23, 37, 99, 18, 137, 11,
+    229, 17, 141, 171, 85, 176, 119, 94, 190, 42,
+]);
+
+//A pool of an Algebra based dex, eg. QuickSwap V3 or Camelot. Algebra keeps the concentrated liquidity model of uniswap v3,
+//so the tick state lives in the inner v3 pool, but replaces slot0 with globalState and adjusts the fee dynamically.
+//The current dynamic fee is stored in the fee of the inner pool, in hundredths of a bip
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlgebraPool {
+    #[serde(flatten)]
+    pub pool: UniswapV3Pool,
+}
+
+#[async_trait]
+impl AutomatedMarketMaker for AlgebraPool {
+    fn address(&self) -> H160 {
+        self.pool.address
+    }
+
+    async fn sync<M: Middleware>(&mut self, middleware: Arc<M>) -> Result<(), AMMError<M>> {
+        self.populate_global_state(None, middleware).await
+    }
+
+    //Swap, Mint and Burn share their signatures with uniswap v3, the Fee event is emitted whenever the dynamic fee changes
+    fn sync_on_event_signatures(&self) -> Vec<H256> {
+        vec![
+            SWAP_EVENT_SIGNATURE,
+            MINT_EVENT_SIGNATURE,
+            BURN_EVENT_SIGNATURE,
+            FEE_EVENT_SIGNATURE,
+        ]
+    }
+
+    fn sync_from_log(&mut self, log: Log) -> Result<(), EventLogError> {
+        if log.topics[0] == FEE_EVENT_SIGNATURE {
+            let fee_event = FeeFilter::decode_log(&RawLog::from(log))?;
+            self.pool.fee = fee_event.fee as u32;
+
+            Ok(())
+        } else {
+            self.pool.sync_from_log(log)
+        }
+    }
+
+    fn tokens(&self) -> Vec<H160> {
+        self.pool.tokens()
+    }
+
+    fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        self.pool.calculate_price(base_token)
+    }
+
+    //Like the v3 pool this does not populate the tick maps, use populate_tick_window or populate_tick_data on the inner
+    //pool to load them
+    async fn populate_data<M: Middleware>(
+        &mut self,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let algebra_pool = IAlgebraPool::new(self.pool.address, middleware.clone());
+
+        let mut token_0 = algebra_pool.token_0();
+        let mut token_1 = algebra_pool.token_1();
+        if let Some(block_number) = block_number {
+            token_0 = token_0.block(block_number);
+            token_1 = token_1.block(block_number);
+        }
+
+        //Independent reads are made concurrently to cut the round trips of a pool
+        (self.pool.token_a, self.pool.token_b) = tokio::try_join!(token_0.call(), token_1.call())?;
+
+        let token_a_decimals = IErc20::new(self.pool.token_a, middleware.clone()).decimals();
+        let token_b_decimals = IErc20::new(self.pool.token_b, middleware.clone()).decimals();
+        (self.pool.token_a_decimals, self.pool.token_b_decimals) =
+            tokio::try_join!(token_a_decimals.call(), token_b_decimals.call())?;
+        self.pool.tick_spacing = ALGEBRA_TICK_SPACING;
+
+        self.populate_global_state(block_number, middleware).await
+    }
+
+    fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, SwapSimulationError> {
+        self.pool.simulate_swap(token_in, amount_in)
+    }
+
+    fn simulate_swap_mut(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        self.pool.simulate_swap_mut(token_in, amount_in)
+    }
+
+    fn get_token_out(&self, token_in: H160) -> H160 {
+        self.pool.get_token_out(token_in)
+    }
+
+    fn max_output(&self, token_out: H160) -> U256 {
+        self.pool.max_output(token_out)
+    }
+}
+
+impl AlgebraPool {
+    //Creates a new instance of the pool from the pool address, populating its data and tick maps from the creation block
+    pub async fn new_from_address<M: 'static + Middleware>(
+        pool_address: H160,
+        creation_block: u64,
+        middleware: Arc<M>,
+    ) -> Result<Self, AMMError<M>> {
+        let mut pool = AlgebraPool {
+            pool: UniswapV3Pool {
+                address: pool_address,
+                tick_spacing: ALGEBRA_TICK_SPACING,
+                ..Default::default()
+            },
+        };
+
+        //Mint and Burn logs share their layout with uniswap v3, so the v3 tick logic can be reused as is
+        let synced_block = pool
+            .pool
+            .populate_tick_data(creation_block, middleware.clone())
+            .await?;
+
+        pool.populate_data(Some(synced_block), middleware).await?;
+
+        if !pool.data_is_populated() {
+            return Err(AMMError::PoolDataError);
+        }
+
+        Ok(pool)
+    }
+
+    pub fn data_is_populated(&self) -> bool {
+        self.pool.data_is_populated()
+    }
+
+    //Returns the current dynamic fee in hundredths of a bip
+    pub fn fee(&self) -> u32 {
+        self.pool.fee
+    }
+
+    //Updates the price, tick, dynamic fee and liquidity from globalState, which replaces slot0 and the static fee of v3
+    pub async fn populate_global_state<M: Middleware>(
+        &mut self,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let algebra_pool = IAlgebraPool::new(self.pool.address, middleware);
+
+        let mut global_state = algebra_pool.global_state();
+        let mut liquidity = algebra_pool.liquidity();
+        if let Some(block_number) = block_number {
+            global_state = global_state.block(block_number);
+            liquidity = liquidity.block(block_number);
+        }
+
+        let ((sqrt_price, tick, fee, _, _, _, _), liquidity) =
+            tokio::try_join!(global_state.call(), liquidity.call())?;
+        self.pool.sqrt_price = sqrt_price;
+        self.pool.tick = tick;
+        self.pool.fee = fee as u32;
+        self.pool.liquidity = liquidity;
+
+        Ok(())
+    }
+
+    //Loads the initialized ticks of the tick table words within `tick_window` words on each side of the current tick.
+    //Algebra names the tick bitmap tickTable, so the v3 tick data batch request cannot read it and the words and their
+    //initialized ticks are read from the pool directly. The ticks share their layout up to the liquidity delta with v3
+    pub async fn populate_tick_window<M: Middleware>(
+        &mut self,
+        tick_window: u16,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let tick_spacing = self.pool.tick_spacing;
+        if tick_spacing == 0 {
+            return Err(AMMError::PoolDataError);
+        }
+
+        let algebra_pool = IAlgebraPool::new(self.pool.address, middleware);
+        let (current_word, _) =
+            uniswap_v3_math::tick_bitmap::position(self.pool.tick.div_euclid(tick_spacing));
+        let first_word = (current_word as i32 - tick_window as i32).max(i16::MIN as i32) as i16;
+        let last_word = (current_word as i32 + tick_window as i32).min(i16::MAX as i32) as i16;
+
+        for word_pos in first_word..=last_word {
+            let mut tick_table = algebra_pool.tick_table(word_pos);
+            if let Some(block_number) = block_number {
+                tick_table = tick_table.block(block_number);
+            }
+            let word = tick_table.call().await?;
+
+            for bit_pos in (0..256).filter(|bit_pos| word.bit(*bit_pos)) {
+                let tick = (word_pos as i32 * 256 + bit_pos as i32) * tick_spacing;
+                let mut tick_info = algebra_pool.ticks(tick);
+                if let Some(block_number) = block_number {
+                    tick_info = tick_info.block(block_number);
+                }
+
+                let (_, liquidity_delta, _, _, _, _, _, _) = tick_info.call().await?;
+                self.pool.insert_initialized_tick(tick, liquidity_delta);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers::{
+        abi::{encode, Token},
+        providers::{JsonRpcError, MockProvider, MockResponse, Provider},
+        types::{Bytes, Log, H160, I256, U256},
+    };
+
+    use crate::amm::{uniswap_v3::UniswapV3Pool, AutomatedMarketMaker};
+
+    use super::{AlgebraPool, ALGEBRA_TICK_SPACING, FEE_EVENT_SIGNATURE};
+
+    #[tokio::test]
+    async fn test_populate_data_from_global_state() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let sqrt_price = U256::from_dec_str("79228162514264337593543950336")?;
+
+        //globalState packs the dynamic fee where slot0 holds the observation index
+        let global_state = encode(&[
+            Token::Uint(sqrt_price),
+            Token::Int(U256::from(120)),
+            Token::Uint(U256::from(1500)),
+            Token::Uint(U256::from(7)),
+            Token::Uint(U256::from(10)),
+            Token::Uint(U256::from(10)),
+            Token::Bool(true),
+        ]);
+
+        //Responses are popped in reverse order
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(1_000_000))]).into())?;
+        mock.push::<Bytes, _>(global_state.into())?;
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(6))]).into())?;
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::from(18))]).into())?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_b)]).into())?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(token_a)]).into())?;
+
+        let mut pool = AlgebraPool {
+            pool: UniswapV3Pool {
+                address: H160::from_low_u64_be(3),
+                ..Default::default()
+            },
+        };
+        pool.populate_data(None, middleware.clone()).await?;
+
+        assert_eq!(pool.pool.token_a, token_a);
+        assert_eq!(pool.pool.token_b, token_b);
+        assert_eq!(pool.pool.token_a_decimals, 18);
+        assert_eq!(pool.pool.token_b_decimals, 6);
+        assert_eq!(pool.pool.sqrt_price, sqrt_price);
+        assert_eq!(pool.pool.tick, 120);
+        assert_eq!(pool.fee(), 1500);
+        assert_eq!(pool.pool.liquidity, 1_000_000);
+        assert_eq!(pool.pool.tick_spacing, ALGEBRA_TICK_SPACING);
+        assert!(pool.data_is_populated());
+
+        //The v3 decoder calls slot0 and fee, which do not exist on an Algebra pool and revert
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+
+        let mut v3_pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(3),
+            ..Default::default()
+        };
+        assert!(v3_pool.populate_data(None, middleware).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_populate_tick_window() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let tick_info = |liquidity_delta: i128| -> Bytes {
+            encode(&[
+                Token::Uint(U256::from(liquidity_delta.unsigned_abs())),
+                Token::Int(I256::from(liquidity_delta).into_raw()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ])
+            .into()
+        };
+
+        //Responses are popped in reverse order: the words -1, 0 and 1 around tick 0, each followed by its initialized ticks
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::zero())]).into())?;
+        mock.push::<Bytes, _>(tick_info(-1000))?;
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::one() << 1)]).into())?;
+        mock.push::<Bytes, _>(tick_info(1000))?;
+        mock.push::<Bytes, _>(encode(&[Token::Uint(U256::one() << 255)]).into())?;
+
+        let mut pool = AlgebraPool {
+            pool: UniswapV3Pool {
+                address: H160::from_low_u64_be(3),
+                tick_spacing: ALGEBRA_TICK_SPACING,
+                ..Default::default()
+            },
+        };
+        pool.populate_tick_window(1, Some(100), Arc::new(provider))
+            .await?;
+
+        assert_eq!(pool.pool.ticks.len(), 2);
+        assert_eq!(pool.pool.ticks[&-60].liquidity_net, 1000);
+        assert_eq!(pool.pool.ticks[&60].liquidity_net, -1000);
+        assert_eq!(pool.pool.tick_bitmap[&-1], U256::one() << 255);
+        assert_eq!(pool.pool.tick_bitmap[&0], U256::one() << 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_from_fee_log() -> eyre::Result<()> {
+        let mut pool = AlgebraPool {
+            pool: UniswapV3Pool {
+                fee: 500,
+                ..Default::default()
+            },
+        };
+
+        let log = Log {
+            topics: vec![FEE_EVENT_SIGNATURE],
+            data: encode(&[Token::Uint(U256::from(2500))]).into(),
+            ..Default::default()
+        };
+        pool.sync_from_log(log)?;
+
+        assert_eq!(pool.fee(), 2500);
+
+        Ok(())
+    }
+}
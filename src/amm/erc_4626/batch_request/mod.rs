@@ -5,7 +5,10 @@ use ethers::{
 };
 use std::sync::Arc;
 
-use crate::{amm::AutomatedMarketMaker, errors::AMMError};
+use crate::{
+    amm::{log_batch_payload, with_request_timeout, AutomatedMarketMaker},
+    errors::AMMError,
+};
 
 use ethers::prelude::abigen;
 
@@ -72,7 +75,8 @@ pub async fn get_4626_vault_data_batch_request<M: Middleware>(
 
     let deployer = IGetERC4626VaultDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = with_request_timeout(deployer.call_raw()).await?;
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // vault token
@@ -14,15 +14,17 @@ use serde::{Deserialize, Serialize};
 use crate::{
     amm::AutomatedMarketMaker,
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+    serde_helpers::u256_string,
 };
 
 use ethers::prelude::abigen;
 
-use super::uniswap_v2::{div_uu, q64_to_f64, U128_0X10000000000000000};
+use super::uniswap_v2::{div_uu, q64_to_f64, scale_by_decimal_shift, U128_0X10000000000000000};
 
 abigen!(
     IERC4626Vault,
     r#"[
+        function asset() external view returns (address)
         function totalAssets() external view returns (uint256)
         function totalSupply() external view returns (uint256)
         function decimals() external view returns (uint8)
@@ -48,10 +50,18 @@ pub struct ERC4626Vault {
     pub vault_token_decimals: u8,
     pub asset_token: H160, // token received from withdrawing, i.e. underlying token
     pub asset_token_decimals: u8,
+    #[serde(with = "u256_string")]
     pub vault_reserve: U256, // total supply of vault tokens
+    #[serde(with = "u256_string")]
     pub asset_reserve: U256, // total balance of asset tokens held by vault
-    pub deposit_fee: u32,    // deposit fee in basis points
-    pub withdraw_fee: u32,   // withdrawal fee in basis points
+    pub deposit_fee: u32,  // deposit fee in basis points
+    pub withdraw_fee: u32, // withdrawal fee in basis points
+    //User annotations, preserved across resyncs and untouched by population
+    #[serde(default)]
+    pub tags: Vec<String>,
+    //Block of the last event applied to the amm by the state space, zero if no event has been applied
+    #[serde(default)]
+    pub last_synced_block: u64,
 }
 
 #[async_trait]
@@ -107,6 +117,10 @@ impl AutomatedMarketMaker for ERC4626Vault {
     }
 
     fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, SwapSimulationError> {
+        if token_in != self.vault_token && token_in != self.asset_token {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if self.vault_token == token_in {
             Ok(self.get_amount_out(amount_in, self.vault_reserve, self.asset_reserve))
         } else {
@@ -119,6 +133,10 @@ impl AutomatedMarketMaker for ERC4626Vault {
         token_in: H160,
         amount_in: U256,
     ) -> Result<U256, SwapSimulationError> {
+        if token_in != self.vault_token && token_in != self.asset_token {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if self.vault_token == token_in {
             let amount_out = self.get_amount_out(amount_in, self.vault_reserve, self.asset_reserve);
 
@@ -143,6 +161,17 @@ impl AutomatedMarketMaker for ERC4626Vault {
             self.vault_token
         }
     }
+
+    fn max_output(&self, token_out: H160) -> U256 {
+        if self.asset_token == token_out {
+            self.asset_reserve
+        } else if self.vault_token == token_out {
+            //Shares are minted on deposit, so the vault token output is unbounded
+            U256::MAX
+        } else {
+            U256::zero()
+        }
+    }
 }
 
 impl ERC4626Vault {
@@ -166,6 +195,8 @@ impl ERC4626Vault {
             asset_reserve,
             deposit_fee,
             withdraw_fee,
+            tags: vec![],
+            last_synced_block: 0,
         }
     }
 
@@ -182,6 +213,8 @@ impl ERC4626Vault {
             asset_reserve: U256::zero(),
             deposit_fee: 0,
             withdraw_fee: 0,
+            tags: vec![],
+            last_synced_block: 0,
         };
 
         vault.populate_data(None, middleware.clone()).await?;
@@ -196,6 +229,7 @@ impl ERC4626Vault {
     pub fn data_is_populated(&self) -> bool {
         !(self.vault_token.is_zero()
             || self.asset_token.is_zero()
+            || self.vault_token == self.asset_token
             || self.vault_reserve.is_zero()
             || self.asset_reserve.is_zero())
     }
@@ -221,17 +255,18 @@ impl ERC4626Vault {
     }
 
     pub fn calculate_price_64_x_64(&self, base_token: H160) -> Result<u128, ArithmeticError> {
-        let decimal_shift = self.vault_token_decimals as i8 - self.asset_token_decimals as i8;
+        //Widened so that tokens with more than 127 decimals do not wrap
+        let decimal_shift = self.vault_token_decimals as i16 - self.asset_token_decimals as i16;
 
         // Normalize reserves by decimal shift
         let (r_v, r_a) = match decimal_shift.cmp(&0) {
             Ordering::Less => (
-                self.vault_reserve * U256::from(10u128.pow(decimal_shift.unsigned_abs() as u32)),
+                scale_by_decimal_shift(self.vault_reserve, decimal_shift.unsigned_abs() as u32)?,
                 self.asset_reserve,
             ),
             _ => (
                 self.vault_reserve,
-                self.asset_reserve * U256::from(10u128.pow(decimal_shift as u32)),
+                scale_by_decimal_shift(self.asset_reserve, decimal_shift as u32)?,
             ),
         };
 
@@ -280,10 +315,46 @@ mod tests {
         types::{H160, U256},
     };
 
-    use crate::amm::AutomatedMarketMaker;
+    use crate::{amm::AutomatedMarketMaker, errors::SwapSimulationError};
 
     use super::ERC4626Vault;
 
+    #[test]
+    fn test_max_output() -> eyre::Result<()> {
+        let vault = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            asset_token: H160::from_low_u64_be(2),
+            vault_reserve: U256::from(1000000),
+            asset_reserve: U256::from(2000000),
+            ..Default::default()
+        };
+
+        assert_eq!(vault.max_output(vault.asset_token), U256::from(2000000));
+        assert_eq!(vault.max_output(vault.vault_token), U256::MAX);
+        assert_eq!(vault.max_output(H160::from_low_u64_be(3)), U256::zero());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_token_not_in_pool() -> eyre::Result<()> {
+        let vault = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            asset_token: H160::from_low_u64_be(2),
+            vault_reserve: U256::from(1000000),
+            asset_reserve: U256::from(1000000),
+            ..Default::default()
+        };
+
+        let foreign_token = H160::from_low_u64_be(3);
+        assert!(matches!(
+            vault.simulate_swap(foreign_token, U256::from(1000)),
+            Err(SwapSimulationError::TokenNotInPool(token)) if token == foreign_token
+        ));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_vault_data() -> eyre::Result<()> {
         let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
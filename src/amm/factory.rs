@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use ethers::{
@@ -6,18 +9,23 @@ use ethers::{
     types::{BlockNumber, Filter, Log, ValueOrArray, H160, H256, U64},
 };
 use serde::{Deserialize, Serialize};
-use tokio::task::JoinHandle;
+use tokio::{sync::mpsc::Sender, task::JoinHandle};
 
-use crate::errors::{AMMError, EventLogError};
+use crate::{
+    errors::{AMMError, EventLogError},
+    sync,
+};
 
 use super::{
+    algebra::factory::{AlgebraFactory, POOL_EVENT_SIGNATURE, POOL_EVENT_SIGNATURE_BYTES},
+    log_source::{LogSource, MiddlewareLogSource},
     uniswap_v2::factory::{
         UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE, PAIR_CREATED_EVENT_SIGNATURE_BYTES,
     },
     uniswap_v3::factory::{
         UniswapV3Factory, POOL_CREATED_EVENT_SIGNATURE, POOL_CREATED_EVENT_SIGNATURE_BYTES,
     },
-    AMM,
+    AutomatedMarketMaker, AMM,
 };
 
 pub const TASK_LIMIT: usize = 25;
@@ -54,10 +62,15 @@ pub trait AutomatedMarketMakerFactory {
     fn new_empty_amm_from_log(log: Log) -> Result<AMM, ethers::abi::Error>;
 }
 
+//Variant names are pinned for checkpoint compatibility, see `AMM`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Factory {
+    #[serde(rename = "UniswapV2Factory")]
     UniswapV2Factory(UniswapV2Factory),
+    #[serde(rename = "UniswapV3Factory")]
     UniswapV3Factory(UniswapV3Factory),
+    #[serde(rename = "AlgebraFactory")]
+    AlgebraFactory(AlgebraFactory),
 }
 
 #[async_trait]
@@ -66,6 +79,7 @@ impl AutomatedMarketMakerFactory for Factory {
         match self {
             Factory::UniswapV2Factory(factory) => factory.address(),
             Factory::UniswapV3Factory(factory) => factory.address(),
+            Factory::AlgebraFactory(factory) => factory.address(),
         }
     }
 
@@ -73,6 +87,7 @@ impl AutomatedMarketMakerFactory for Factory {
         match self {
             Factory::UniswapV2Factory(factory) => factory.amm_created_event_signature(),
             Factory::UniswapV3Factory(factory) => factory.amm_created_event_signature(),
+            Factory::AlgebraFactory(factory) => factory.amm_created_event_signature(),
         }
     }
 
@@ -81,16 +96,21 @@ impl AutomatedMarketMakerFactory for Factory {
         log: Log,
         middleware: Arc<M>,
     ) -> Result<AMM, AMMError<M>> {
-        match self {
-            Factory::UniswapV2Factory(factory) => factory.new_amm_from_log(log, middleware).await,
-            Factory::UniswapV3Factory(factory) => factory.new_amm_from_log(log, middleware).await,
-        }
+        let mut amm = match self {
+            Factory::UniswapV2Factory(factory) => factory.new_amm_from_log(log, middleware).await?,
+            Factory::UniswapV3Factory(factory) => factory.new_amm_from_log(log, middleware).await?,
+            Factory::AlgebraFactory(factory) => factory.new_amm_from_log(log, middleware).await?,
+        };
+
+        amm.set_factory(self.address());
+        Ok(amm)
     }
 
     fn new_empty_amm_from_log(log: Log) -> Result<AMM, ethers::abi::Error> {
         match log.topics[0].0 {
             PAIR_CREATED_EVENT_SIGNATURE_BYTES => UniswapV2Factory::new_empty_amm_from_log(log),
             POOL_CREATED_EVENT_SIGNATURE_BYTES => UniswapV3Factory::new_empty_amm_from_log(log),
+            POOL_EVENT_SIGNATURE_BYTES => AlgebraFactory::new_empty_amm_from_log(log),
             _ => Err(ethers::abi::Error::InvalidData),
         }
     }
@@ -101,14 +121,24 @@ impl AutomatedMarketMakerFactory for Factory {
         middleware: Arc<M>,
         step: u64,
     ) -> Result<Vec<AMM>, AMMError<M>> {
-        match self {
+        let mut amms = match self {
             Factory::UniswapV2Factory(factory) => {
-                factory.get_all_amms(to_block, middleware, step).await
+                factory.get_all_amms(to_block, middleware, step).await?
             }
             Factory::UniswapV3Factory(factory) => {
-                factory.get_all_amms(to_block, middleware, step).await
+                factory.get_all_amms(to_block, middleware, step).await?
+            }
+            Factory::AlgebraFactory(factory) => {
+                factory.get_all_amms(to_block, middleware, step).await?
             }
+        };
+
+        //Attribute the amms to the factory, amms discovered without a log such as from allPairs do not carry it
+        for amm in amms.iter_mut() {
+            amm.set_factory(self.address());
         }
+
+        Ok(amms)
     }
 
     async fn populate_amm_data<M: Middleware>(
@@ -126,6 +156,11 @@ impl AutomatedMarketMakerFactory for Factory {
                     .populate_amm_data(amms, block_number, middleware)
                     .await
             }
+            Factory::AlgebraFactory(factory) => {
+                factory
+                    .populate_amm_data(amms, block_number, middleware)
+                    .await
+            }
         }
     }
 
@@ -133,66 +168,404 @@ impl AutomatedMarketMakerFactory for Factory {
         match self {
             Factory::UniswapV2Factory(uniswap_v2_factory) => uniswap_v2_factory.creation_block,
             Factory::UniswapV3Factory(uniswap_v3_factory) => uniswap_v3_factory.creation_block,
+            Factory::AlgebraFactory(algebra_factory) => algebra_factory.creation_block,
+        }
+    }
+}
+
+//Block range sizes of an expanding log scan, see `Factory::get_all_pools_from_log_source_expanding`
+#[derive(Debug, Clone, Copy)]
+pub struct RangeExpansion {
+    //Range size the scan starts with
+    pub initial_step: u64,
+    //Smallest range size, the scan fails once the provider rejects a range of this size
+    pub min_step: u64,
+    //Largest range size the scan grows to over empty ranges
+    pub max_step: u64,
+}
+
+impl Default for RangeExpansion {
+    fn default() -> Self {
+        RangeExpansion {
+            initial_step: 100_000,
+            min_step: 1_000,
+            max_step: 1_000_000,
         }
     }
 }
 
+//Distribution of the block range sizes used by an expanding log scan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeStats {
+    //Number of successful get_logs calls per range size
+    pub ranges: BTreeMap<u64, usize>,
+    //Number of ranges rejected by the provider and retried at half the size
+    pub rejected: usize,
+}
+
+impl RangeStats {
+    //Total number of get_logs calls of the scan, including the rejected ones
+    pub fn calls(&self) -> usize {
+        self.ranges.values().sum::<usize>() + self.rejected
+    }
+}
+
 impl Factory {
+    //Gets all amms created by the factory within the block range and populates their data, independent of any checkpoint.
+    //If max_pools is set, only the earliest max_pools amms by block order are returned
+    pub async fn sync_range<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        max_pools: Option<usize>,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        let amms = self
+            .get_all_pools_from_logs(from_block, to_block, step, max_pools, middleware.clone())
+            .await?;
+
+        self.populate_new_amms(amms, to_block, middleware).await
+    }
+
+    //Populates the amms discovered from the pool created logs of the factory at the block, dropping empty pools
+    pub(crate) async fn populate_new_amms<M: 'static + Middleware>(
+        &self,
+        mut amms: Vec<AMM>,
+        block_number: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        self.populate_amm_data(&mut amms, Some(block_number), middleware)
+            .await?;
+
+        //Clean empty pools
+        amms = sync::remove_empty_amms(amms);
+
+        // If the factory is UniswapV2, set the fee for each pool according to the factory fee
+        if let Factory::UniswapV2Factory(factory) = self {
+            for amm in amms.iter_mut() {
+                if let AMM::UniswapV2Pool(ref mut pool) = amm {
+                    pool.fee = factory.fee;
+                }
+            }
+        }
+
+        Ok(amms)
+    }
+
+    //Gets all pools created by the factory within the block range. If max_pools is set, scanning stops once max_pools
+    //pools have been discovered and the earliest max_pools pools by block order are returned
     pub async fn get_all_pools_from_logs<M: 'static + Middleware>(
         &self,
-        mut from_block: u64,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        max_pools: Option<usize>,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        self.get_all_pools_from_log_source(
+            from_block,
+            to_block,
+            step,
+            max_pools,
+            Arc::new(MiddlewareLogSource::new(middleware)),
+        )
+        .await
+    }
+
+    //Gets all pools created by the factory within the block range, fetching the pool created logs from the log source
+    pub async fn get_all_pools_from_log_source<M, L>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        max_pools: Option<usize>,
+        log_source: Arc<L>,
+    ) -> Result<Vec<AMM>, AMMError<M>>
+    where
+        M: 'static + Middleware,
+        L: 'static + LogSource<M> + Send + Sync,
+    {
+        self.get_pools_from_log_source(from_block, to_block, step, max_pools, None, log_source)
+            .await
+    }
+
+    //Gets the pools created by the factory within the block range that contain any of the tokens. The pool created events
+    //of all supported factories index token0 and token1, so the node filters the logs by token instead of returning
+    //every pool of the factory
+    pub async fn get_pools_with_tokens_from_logs<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
         to_block: u64,
         step: u64,
+        tokens: Vec<H160>,
         middleware: Arc<M>,
     ) -> Result<Vec<AMM>, AMMError<M>> {
-        let factory_address = self.address();
-        let amm_created_event_signature = self.amm_created_event_signature();
-        let mut log_group = vec![];
+        self.get_pools_with_tokens_from_log_source(
+            from_block,
+            to_block,
+            step,
+            tokens,
+            Arc::new(MiddlewareLogSource::new(middleware)),
+        )
+        .await
+    }
+
+    pub async fn get_pools_with_tokens_from_log_source<M, L>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        tokens: Vec<H160>,
+        log_source: Arc<L>,
+    ) -> Result<Vec<AMM>, AMMError<M>>
+    where
+        M: 'static + Middleware,
+        L: 'static + LogSource<M> + Send + Sync,
+    {
+        self.get_pools_from_log_source(
+            from_block,
+            to_block,
+            step,
+            None,
+            Some(Arc::new(tokens)),
+            log_source,
+        )
+        .await
+    }
+
+    //Gets and populates the amms created by the factory within the block range like `sync_range`, scanning the logs with
+    //an expanding range instead of a fixed step
+    pub async fn sync_range_expanding<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        expansion: RangeExpansion,
+        middleware: Arc<M>,
+    ) -> Result<(Vec<AMM>, RangeStats), AMMError<M>> {
+        let (amms, stats) = self
+            .get_all_pools_from_log_source_expanding(
+                from_block,
+                to_block,
+                expansion,
+                Arc::new(MiddlewareLogSource::new(middleware.clone())),
+            )
+            .await?;
+
+        Ok((
+            self.populate_new_amms(amms, to_block, middleware).await?,
+            stats,
+        ))
+    }
+
+    //Gets all pools created by the factory within the block range with as few get_logs calls as possible, for backfilling
+    //large gaps where most ranges hold no pools. The scan starts with a large range, halves it whenever the log source
+    //rejects a range and doubles it after every empty range. Growth is capped below the smallest rejected range so that
+    //the scan does not keep hitting the provider limit. Ranges are scanned sequentially since each range size depends on
+    //the outcome of the previous range
+    pub async fn get_all_pools_from_log_source_expanding<M, L>(
+        &self,
+        mut from_block: u64,
+        to_block: u64,
+        expansion: RangeExpansion,
+        log_source: Arc<L>,
+    ) -> Result<(Vec<AMM>, RangeStats), AMMError<M>>
+    where
+        M: 'static + Middleware,
+        L: 'static + LogSource<M> + Send + Sync,
+    {
+        let min_step = expansion.min_step.max(1);
+        let mut max_step = expansion.max_step.max(min_step);
+        let mut step = expansion.initial_step.clamp(min_step, max_step);
+
+        let mut amms = vec![];
+        let mut stats = RangeStats::default();
+
+        while from_block < to_block {
+            let target_block = (from_block + step - 1).min(to_block);
+
+            match log_source
+                .get_logs(&self.amm_created_filter(from_block, target_block))
+                .await
+            {
+                Ok(logs) => {
+                    *stats
+                        .ranges
+                        .entry(target_block - from_block + 1)
+                        .or_default() += 1;
+
+                    if logs.is_empty() {
+                        step = (step * 2).min(max_step);
+                    }
+
+                    for log in logs {
+                        push_discovered_amm(&mut amms, Factory::new_empty_amm_from_log(log)?);
+                    }
+
+                    from_block = target_block + 1;
+                }
+                Err(err) if step > min_step => {
+                    tracing::debug!(%err, step, "Log range rejected, retrying at half the range");
+                    stats.rejected += 1;
+                    step = (step / 2).max(min_step);
+                    max_step = step;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((amms, stats))
+    }
+
+    //Filter for the pool created events of the factory within the block range, filtered by the node on the factory
+    //address and event signature
+    pub fn amm_created_filter(&self, from_block: u64, to_block: u64) -> Filter {
+        Filter::new()
+            .topic0(ValueOrArray::Value(self.amm_created_event_signature()))
+            .address(self.address())
+            .from_block(BlockNumber::Number(U64([from_block])))
+            .to_block(BlockNumber::Number(U64([to_block])))
+    }
+
+    //Filters for the pool created events containing any of the tokens. Topics are matched together across positions,
+    //so one filter matches the tokens as token0 and another as token1
+    pub fn amm_created_filters_with_tokens(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        tokens: &[H160],
+    ) -> [Filter; 2] {
+        let token_topics: Vec<H256> = tokens.iter().map(|token| H256::from(*token)).collect();
+
+        [
+            self.amm_created_filter(from_block, to_block)
+                .topic1(token_topics.clone()),
+            self.amm_created_filter(from_block, to_block)
+                .topic2(token_topics),
+        ]
+    }
+
+    async fn get_pools_from_log_source<M, L>(
+        &self,
+        mut from_block: u64,
+        to_block: u64,
+        step: u64,
+        max_pools: Option<usize>,
+        tokens: Option<Arc<Vec<H160>>>,
+        log_source: Arc<L>,
+    ) -> Result<Vec<AMM>, AMMError<M>>
+    where
+        M: 'static + Middleware,
+        L: 'static + LogSource<M> + Send + Sync,
+    {
         let mut handles = vec![];
-        let mut tasks = 0;
+        let mut seen = HashSet::new();
         let mut aggregated_amms: Vec<AMM> = vec![];
 
         while from_block < to_block {
-            let middleware = middleware.clone();
+            let log_source = log_source.clone();
             let mut target_block = from_block + step - 1;
             if target_block > to_block {
                 target_block = to_block;
             }
 
+            let filters = match &tokens {
+                Some(tokens) => self
+                    .amm_created_filters_with_tokens(from_block, target_block, tokens)
+                    .to_vec(),
+                None => vec![self.amm_created_filter(from_block, target_block)],
+            };
+
             handles.push(tokio::spawn(async move {
-                let logs = middleware
-                    .get_logs(
-                        &Filter::new()
-                            .topic0(ValueOrArray::Value(amm_created_event_signature))
-                            .address(factory_address)
-                            .from_block(BlockNumber::Number(U64([from_block])))
-                            .to_block(BlockNumber::Number(U64([target_block]))),
-                    )
-                    .await
-                    .map_err(AMMError::MiddlewareError)?;
+                let mut logs = vec![];
+                for filter in filters.iter() {
+                    logs.extend(log_source.get_logs(filter).await?);
+                }
 
+                //Logs of the different token filters are merged back into block order
+                logs.sort_by_key(|log| (log.block_number, log.log_index));
                 Ok::<Vec<Log>, AMMError<M>>(logs)
             }));
 
             from_block += step;
-            tasks += 1;
-            if tasks == TASK_LIMIT_LOGS {
-                self.process_logs_from_handles(handles, &mut log_group)
-                    .await?;
+            if handles.len() == TASK_LIMIT_LOGS || from_block >= to_block {
+                //The handles are processed in block order so the earliest pools are kept, and the remaining ranges are
+                //abandoned once enough pools have been discovered. Pools dropped below do not count towards the limit
+                let mut handles_iter = std::mem::take(&mut handles).into_iter();
+                for handle in handles_iter.by_ref() {
+                    for log in handle.await?? {
+                        //A pool containing two of the tokens is matched by both token filters, the log data holds the
+                        //pool address
+                        if tokens.is_some() && !seen.insert(log.data.clone()) {
+                            continue;
+                        }
+
+                        push_discovered_amm(
+                            &mut aggregated_amms,
+                            Factory::new_empty_amm_from_log(log)?,
+                        );
+                    }
+
+                    if matches!(max_pools, Some(max_pools) if aggregated_amms.len() >= max_pools) {
+                        break;
+                    }
+                }
 
-                handles = vec![];
-                tasks = 0;
+                if let Some(max_pools) = max_pools {
+                    if aggregated_amms.len() >= max_pools {
+                        handles_iter.for_each(|handle| handle.abort());
+                        aggregated_amms.truncate(max_pools);
+                        break;
+                    }
+                }
             }
         }
 
-        self.process_logs_from_handles(handles, &mut log_group)
-            .await?;
+        Ok(aggregated_amms)
+    }
+
+    //Discovers the pools created by the factory within the block range like `get_all_pools_from_logs`, but sends the pools
+    //of every group of scanned ranges to the sender, tagged with the factory address, as soon as the group is scanned
+    //instead of aggregating them. This lets the pools be populated while the rest of the range is still being scanned.
+    //Scanning stops early once the receiver is dropped
+    pub async fn send_pools_from_logs<M: 'static + Middleware>(
+        &self,
+        mut from_block: u64,
+        to_block: u64,
+        step: u64,
+        middleware: Arc<M>,
+        sender: Sender<(H160, Vec<AMM>)>,
+    ) -> Result<(), AMMError<M>> {
+        let log_source = Arc::new(MiddlewareLogSource::new(middleware));
+        let mut handles = vec![];
+
+        while from_block < to_block {
+            let log_source = log_source.clone();
+            let target_block = (from_block + step - 1).min(to_block);
+            let filter = self.amm_created_filter(from_block, target_block);
+
+            handles.push(tokio::spawn(
+                async move { log_source.get_logs(&filter).await },
+            ));
+
+            from_block += step;
+            if handles.len() == TASK_LIMIT_LOGS || from_block >= to_block {
+                let mut log_group = vec![];
+                self.process_logs_from_handles(std::mem::take(&mut handles), &mut log_group)
+                    .await?;
+
+                let mut amms = vec![];
+                for log in log_group {
+                    push_discovered_amm(&mut amms, Factory::new_empty_amm_from_log(log)?);
+                }
 
-        for log in log_group {
-            aggregated_amms.push(Factory::new_empty_amm_from_log(log)?);
+                if !amms.is_empty() && sender.send((self.address(), amms)).await.is_err() {
+                    break;
+                }
+            }
         }
 
-        Ok(aggregated_amms)
+        Ok(())
     }
 
     async fn process_logs_from_handles<M: Middleware>(
@@ -218,8 +591,722 @@ impl TryFrom<H256> for Factory {
             Ok(Factory::UniswapV2Factory(UniswapV2Factory::default()))
         } else if value == POOL_CREATED_EVENT_SIGNATURE {
             Ok(Factory::UniswapV3Factory(UniswapV3Factory::default()))
+        } else if value == POOL_EVENT_SIGNATURE {
+            Ok(Factory::AlgebraFactory(AlgebraFactory::default()))
         } else {
             return Err(EventLogError::InvalidEventSignature);
         }
     }
 }
+
+//Builds a single log filter over the pool created events of several factories, so that each block range is scanned with
+//one get_logs call for all factories instead of one call per factory, and routes the returned logs back to the factory
+//that emitted them
+#[derive(Debug, Clone, Default)]
+pub struct MultiFactoryFilter {
+    factories: HashMap<H160, Factory>,
+}
+
+impl MultiFactoryFilter {
+    pub fn new(factories: Vec<Factory>) -> MultiFactoryFilter {
+        MultiFactoryFilter {
+            factories: factories
+                .into_iter()
+                .map(|factory| (factory.address(), factory))
+                .collect(),
+        }
+    }
+
+    //Filter for the pool created events of all factories within the block range. The node matches any of the addresses
+    //with any of the signatures, so the logs are checked against their factory again in `new_empty_amm_from_log`
+    pub fn filter(&self, from_block: u64, to_block: u64) -> Filter {
+        let mut addresses: Vec<H160> = self.factories.keys().copied().collect();
+        addresses.sort();
+
+        let mut event_signatures: Vec<H256> = self
+            .factories
+            .values()
+            .map(|factory| factory.amm_created_event_signature())
+            .collect();
+        event_signatures.sort();
+        event_signatures.dedup();
+
+        Filter::new()
+            .topic0(event_signatures)
+            .address(addresses)
+            .from_block(BlockNumber::Number(U64([from_block])))
+            .to_block(BlockNumber::Number(U64([to_block])))
+    }
+
+    //Decodes the log with the factory that emitted it, returning None for logs of unknown addresses or of another event
+    //than the pool created event of the emitting factory
+    pub fn new_empty_amm_from_log(&self, log: Log) -> Option<Result<AMM, ethers::abi::Error>> {
+        let factory = self.factories.get(&log.address)?;
+        if log.topics.first() != Some(&factory.amm_created_event_signature()) {
+            return None;
+        }
+
+        Some(Factory::new_empty_amm_from_log(log))
+    }
+
+    //Gets the pools created by the factories within the block range, grouped by the address of their factory
+    pub async fn get_all_pools_from_logs<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<HashMap<H160, Vec<AMM>>, AMMError<M>> {
+        self.get_all_pools_from_log_source(
+            from_block,
+            to_block,
+            step,
+            Arc::new(MiddlewareLogSource::new(middleware)),
+        )
+        .await
+    }
+
+    //Gets the pools created by the factories within the block range like `get_all_pools_from_logs`, fetching the logs
+    //from the log source
+    pub async fn get_all_pools_from_log_source<M, L>(
+        &self,
+        mut from_block: u64,
+        to_block: u64,
+        step: u64,
+        log_source: Arc<L>,
+    ) -> Result<HashMap<H160, Vec<AMM>>, AMMError<M>>
+    where
+        M: 'static + Middleware,
+        L: 'static + LogSource<M> + Send + Sync,
+    {
+        let mut pools = HashMap::new();
+
+        //A filter without addresses would match the pool created events of every contract
+        if self.factories.is_empty() {
+            return Ok(pools);
+        }
+
+        let mut handles = vec![];
+        while from_block < to_block {
+            let log_source = log_source.clone();
+            let target_block = (from_block + step - 1).min(to_block);
+            let filter = self.filter(from_block, target_block);
+
+            handles.push(tokio::spawn(
+                async move { log_source.get_logs(&filter).await },
+            ));
+
+            from_block += step;
+            if handles.len() == TASK_LIMIT_LOGS {
+                self.route_logs_from_handles(std::mem::take(&mut handles), &mut pools)
+                    .await?;
+            }
+        }
+
+        self.route_logs_from_handles(handles, &mut pools).await?;
+
+        Ok(pools)
+    }
+
+    //Gets and populates the pools created by the factories within the block range like `Factory::sync_range`, scanning
+    //the logs of all factories together
+    pub async fn sync_range<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        let pools = self
+            .get_all_pools_from_logs(from_block, to_block, step, middleware.clone())
+            .await?;
+
+        let mut amms = vec![];
+        for (factory_address, factory_amms) in pools {
+            amms.extend(
+                self.factories[&factory_address]
+                    .populate_new_amms(factory_amms, to_block, middleware.clone())
+                    .await?,
+            );
+        }
+
+        Ok(amms)
+    }
+
+    async fn route_logs_from_handles<M: Middleware>(
+        &self,
+        handles: Vec<JoinHandle<Result<Vec<Log>, AMMError<M>>>>,
+        pools: &mut HashMap<H160, Vec<AMM>>,
+    ) -> Result<(), AMMError<M>> {
+        for handle in handles {
+            for log in handle.await?? {
+                let factory_address = log.address;
+                if let Some(amm) = self.new_empty_amm_from_log(log) {
+                    push_discovered_amm(pools.entry(factory_address).or_default(), amm?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//Adds an amm decoded from a pool created log, dropping pools created with the same token on both sides
+fn push_discovered_amm(amms: &mut Vec<AMM>, amm: AMM) {
+    if amm.has_identical_tokens() {
+        tracing::warn!(pool = ?amm.address(), token = ?amm.tokens()[0], "Dropping pool with identical tokens");
+    } else {
+        amms.push(amm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ethers::{
+        abi::Token,
+        providers::{MockProvider, Provider},
+        types::{Bytes, Filter, Log, Topic, ValueOrArray, H160, H256, U256, U64},
+    };
+
+    use crate::{
+        amm::{
+            log_source::LogSource,
+            uniswap_v2::{
+                factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+                UniswapV2Pool,
+            },
+            uniswap_v3::factory::{UniswapV3Factory, POOL_CREATED_EVENT_SIGNATURE},
+            AutomatedMarketMaker, AMM,
+        },
+        errors::AMMError,
+        sync,
+    };
+
+    use super::{AutomatedMarketMakerFactory, Factory, MultiFactoryFilter, RangeExpansion};
+
+    struct StaticLogSource {
+        logs: Vec<Log>,
+    }
+
+    #[async_trait]
+    impl LogSource<Provider<MockProvider>> for StaticLogSource {
+        async fn get_logs(
+            &self,
+            _filter: &Filter,
+        ) -> Result<Vec<Log>, AMMError<Provider<MockProvider>>> {
+            Ok(self.logs.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_all_pools_from_log_source() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(1);
+        let pair = H160::from_low_u64_be(4);
+
+        let pair_created_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(2)),
+                H256::from(H160::from_low_u64_be(3)),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+
+        let log_source = Arc::new(StaticLogSource {
+            logs: vec![pair_created_log],
+        });
+
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+        let amms = factory
+            .get_all_pools_from_log_source::<Provider<MockProvider>, _>(
+                100, 200, 1000, None, log_source,
+            )
+            .await?;
+
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), pair);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_all_pools_drops_self_pairs() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(1);
+        let token = H160::from_low_u64_be(2);
+
+        let pair_created_log = |token_a: H160, token_b: H160, pair: H160| Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_a),
+                H256::from(token_b),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+
+        let log_source = Arc::new(StaticLogSource {
+            logs: vec![
+                pair_created_log(token, token, H160::from_low_u64_be(4)),
+                pair_created_log(token, H160::from_low_u64_be(3), H160::from_low_u64_be(5)),
+            ],
+        });
+
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+        let amms = factory
+            .get_all_pools_from_log_source::<Provider<MockProvider>, _>(
+                100,
+                200,
+                1000,
+                None,
+                log_source.clone(),
+            )
+            .await?;
+
+        //The pair created with the same token on both sides is dropped
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), H160::from_low_u64_be(5));
+
+        //And does not count towards max pools, so the later valid pair is still returned
+        let amms = factory
+            .get_all_pools_from_log_source::<Provider<MockProvider>, _>(
+                100,
+                200,
+                1000,
+                Some(1),
+                log_source,
+            )
+            .await?;
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), H160::from_low_u64_be(5));
+
+        let self_pair = UniswapV2Pool {
+            token_a: token,
+            token_b: token,
+            reserve_0: 1,
+            reserve_1: 1,
+            ..Default::default()
+        };
+        assert!(!self_pair.data_is_populated());
+        assert!(sync::remove_empty_amms(vec![AMM::UniswapV2Pool(self_pair)]).is_empty());
+
+        Ok(())
+    }
+
+    struct RecordingLogSource {
+        logs: Vec<Log>,
+        filters: Mutex<Vec<Filter>>,
+    }
+
+    #[async_trait]
+    impl LogSource<Provider<MockProvider>> for RecordingLogSource {
+        async fn get_logs(
+            &self,
+            filter: &Filter,
+        ) -> Result<Vec<Log>, AMMError<Provider<MockProvider>>> {
+            self.filters
+                .lock()
+                .expect("Filters lock should not be poisoned")
+                .push(filter.clone());
+            Ok(self.logs.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_pools_with_tokens_from_log_source() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(1);
+        let token_0 = H160::from_low_u64_be(2);
+        let token_1 = H160::from_low_u64_be(3);
+        let pair = H160::from_low_u64_be(4);
+
+        let pair_created_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+
+        let log_source = Arc::new(RecordingLogSource {
+            logs: vec![pair_created_log],
+            filters: Mutex::new(vec![]),
+        });
+
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+        let amms = factory
+            .get_pools_with_tokens_from_log_source::<Provider<MockProvider>, _>(
+                100,
+                200,
+                1000,
+                vec![token_0, token_1],
+                log_source.clone(),
+            )
+            .await?;
+
+        //The pair matches both the token0 and the token1 filter but is only returned once
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), pair);
+
+        let filters = log_source
+            .filters
+            .lock()
+            .expect("Filters lock should not be poisoned");
+        assert_eq!(filters.len(), 2);
+
+        let token_topics = Some(Topic::from(vec![H256::from(token_0), H256::from(token_1)]));
+        for (filter, token_position) in filters.iter().zip([1, 2]) {
+            assert_eq!(filter.address, Some(ValueOrArray::Value(factory_address)));
+            assert_eq!(
+                filter.topics[0],
+                Some(Topic::from(PAIR_CREATED_EVENT_SIGNATURE))
+            );
+            assert_eq!(filter.topics[token_position], token_topics);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_factory_filter_routes_logs() -> eyre::Result<()> {
+        let v2_factory_address = H160::from_low_u64_be(1);
+        let v3_factory_address = H160::from_low_u64_be(2);
+        let token_0 = H160::from_low_u64_be(3);
+        let token_1 = H160::from_low_u64_be(4);
+        let pair = H160::from_low_u64_be(5);
+        let pool = H160::from_low_u64_be(6);
+
+        let pair_created_log = |address: H160| Log {
+            address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+        let pool_created_log = |address: H160| Log {
+            address,
+            topics: vec![
+                POOL_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+                H256::from_low_u64_be(500),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Int(U256::from(10)),
+                Token::Address(pool),
+            ])),
+            block_number: Some(U64::from(160)),
+            ..Default::default()
+        };
+
+        //The combined filter also matches a factory emitting the creation event of another factory type
+        let log_source = Arc::new(RecordingLogSource {
+            logs: vec![
+                pair_created_log(v2_factory_address),
+                pool_created_log(v3_factory_address),
+                pool_created_log(v2_factory_address),
+                pair_created_log(H160::from_low_u64_be(7)),
+            ],
+            filters: Mutex::new(vec![]),
+        });
+
+        let multi_factory_filter = MultiFactoryFilter::new(vec![
+            Factory::UniswapV2Factory(UniswapV2Factory::new(v2_factory_address, 0, 300)),
+            Factory::UniswapV3Factory(UniswapV3Factory::new(v3_factory_address, 0)),
+        ]);
+        let pools = multi_factory_filter
+            .get_all_pools_from_log_source::<Provider<MockProvider>, _>(
+                100,
+                200,
+                1000,
+                log_source.clone(),
+            )
+            .await?;
+
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[&v2_factory_address].len(), 1);
+        assert!(matches!(
+            pools[&v2_factory_address][0],
+            AMM::UniswapV2Pool(_)
+        ));
+        assert_eq!(pools[&v2_factory_address][0].address(), pair);
+        assert_eq!(pools[&v3_factory_address].len(), 1);
+        assert!(matches!(
+            pools[&v3_factory_address][0],
+            AMM::UniswapV3Pool(_)
+        ));
+        assert_eq!(pools[&v3_factory_address][0].address(), pool);
+
+        //Both factories are scanned with a single get_logs call
+        let filters = log_source
+            .filters
+            .lock()
+            .expect("Filters lock should not be poisoned");
+        assert_eq!(filters.len(), 1);
+        assert_eq!(
+            filters[0].address,
+            Some(ValueOrArray::Array(vec![
+                v2_factory_address,
+                v3_factory_address
+            ]))
+        );
+
+        Ok(())
+    }
+
+    //Returns the logs within the filter range, rejecting ranges larger than max_range like a provider with a range limit
+    struct RangeLimitedLogSource {
+        logs: Vec<Log>,
+        max_range: u64,
+    }
+
+    #[async_trait]
+    impl LogSource<Provider<MockProvider>> for RangeLimitedLogSource {
+        async fn get_logs(
+            &self,
+            filter: &Filter,
+        ) -> Result<Vec<Log>, AMMError<Provider<MockProvider>>> {
+            let from_block = filter.get_from_block().unwrap_or_default().as_u64();
+            let to_block = filter.get_to_block().unwrap_or_default().as_u64();
+
+            if to_block - from_block + 1 > self.max_range {
+                return Err(AMMError::LogSourceError(
+                    "Block range is too large".to_string(),
+                ));
+            }
+
+            Ok(self
+                .logs
+                .iter()
+                .filter(|log| {
+                    let block_number = log.block_number.unwrap_or_default().as_u64();
+                    from_block <= block_number && block_number <= to_block
+                })
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_all_pools_expanding() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(1);
+        let pair_created_log = |pair: u64, block_number: u64| Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(2)),
+                H256::from(H160::from_low_u64_be(3)),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(pair)),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            ..Default::default()
+        };
+
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+        let expansion = RangeExpansion {
+            initial_step: 1000,
+            min_step: 100,
+            max_step: 1_000_000,
+        };
+
+        //A sparse gap is covered by growing ranges in far fewer calls than the 1000 of a fixed step
+        let sparse_source = Arc::new(RangeLimitedLogSource {
+            logs: vec![pair_created_log(10, 500_000)],
+            max_range: 1_000_000,
+        });
+        let (amms, stats) = factory
+            .get_all_pools_from_log_source_expanding::<Provider<MockProvider>, _>(
+                0,
+                1_000_000,
+                expansion,
+                sparse_source,
+            )
+            .await?;
+        assert_eq!(amms.len(), 1);
+        assert!(stats.calls() < 20);
+        assert_eq!(stats.rejected, 0);
+        assert!(stats.ranges.keys().any(|range| *range >= 100_000));
+
+        //Ranges above the provider limit are rejected and shrunk back below it
+        let limited_source = Arc::new(RangeLimitedLogSource {
+            logs: vec![pair_created_log(10, 500_000)],
+            max_range: 10_000,
+        });
+        let (amms, stats) = factory
+            .get_all_pools_from_log_source_expanding::<Provider<MockProvider>, _>(
+                0,
+                1_000_000,
+                expansion,
+                limited_source,
+            )
+            .await?;
+        assert_eq!(amms.len(), 1);
+        assert!(stats.rejected > 0);
+        assert!(stats.ranges.keys().all(|range| *range <= 10_000));
+
+        //Dense ranges keep the initial step since only empty ranges grow it
+        let dense_source = Arc::new(RangeLimitedLogSource {
+            logs: (0..10)
+                .map(|i| pair_created_log(10 + i, i * 1000 + 500))
+                .collect(),
+            max_range: 1_000_000,
+        });
+        let (amms, stats) = factory
+            .get_all_pools_from_log_source_expanding::<Provider<MockProvider>, _>(
+                0,
+                10_000,
+                expansion,
+                dense_source,
+            )
+            .await?;
+        assert_eq!(amms.len(), 10);
+        assert_eq!(stats.ranges.get(&1000), Some(&10));
+        assert_eq!(stats.calls(), 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_range() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(1);
+        let token_0 = H160::from_low_u64_be(2);
+        let token_1 = H160::from_low_u64_be(3);
+        let pair = H160::from_low_u64_be(4);
+
+        let pair_created_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+
+        let pool_data = ethers::abi::encode(&[Token::Array(vec![Token::Tuple(vec![
+            Token::Address(token_0),
+            Token::Uint(U256::from(18)),
+            Token::Address(token_1),
+            Token::Uint(U256::from(6)),
+            Token::Uint(U256::from(1000)),
+            Token::Uint(U256::from(2000)),
+        ])])]);
+
+        //Mocked responses are popped in reverse order, so the batch call response is pushed before the logs
+        mock.push::<Bytes, _>(Bytes::from(pool_data))?;
+        mock.push::<Vec<Log>, _>(vec![pair_created_log])?;
+
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+        let amms = factory
+            .sync_range(100, 200, 1000, None, Arc::new(provider))
+            .await?;
+
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!(pool.address, pair);
+            assert_eq!(pool.token_a, token_0);
+            assert_eq!(pool.token_a_decimals, 18);
+            assert_eq!(pool.token_b, token_1);
+            assert_eq!(pool.token_b_decimals, 6);
+            assert_eq!(pool.reserve_0, 1000);
+            assert_eq!(pool.reserve_1, 2000);
+            assert_eq!(pool.fee, 300);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_factory_attributes_amms() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let factory_address = H160::from_low_u64_be(10);
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pair = H160::from_low_u64_be(3);
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+
+        //Pairs listed through allPairs carry no factory, responses are popped in reverse order
+        mock.push::<Bytes, _>(
+            ethers::abi::encode(&[Token::Array(vec![Token::Address(pair)])]).into(),
+        )?;
+        mock.push::<Bytes, _>(ethers::abi::encode(&[Token::Uint(U256::one())]).into())?;
+
+        let amms = factory
+            .get_all_amms(Some(100), middleware.clone(), 1000)
+            .await?;
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), pair);
+        assert_eq!(amms[0].factory(), factory_address);
+
+        //totalSupply, then the pool data batch request
+        mock.push::<Bytes, _>(ethers::abi::encode(&[Token::Uint(U256::from(1000))]).into())?;
+        mock.push::<Bytes, _>(
+            ethers::abi::encode(&[Token::Array(vec![Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ])])])
+            .into(),
+        )?;
+
+        let log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: ethers::abi::encode(&[Token::Address(pair), Token::Uint(U256::one())]).into(),
+            block_number: Some(U64::from(50)),
+            ..Default::default()
+        };
+        let amm = factory.new_amm_from_log(log, middleware).await?;
+        assert_eq!(amm.address(), pair);
+        assert_eq!(amm.factory(), factory_address);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::{
+    providers::Middleware,
+    types::{Filter, Log},
+};
+
+use crate::errors::AMMError;
+
+//A source of event logs, allowing pool creation events to be fetched from an indexer instead of the node.
+//Implementations receive a filter with an inclusive from_block/to_block range (at most `step` blocks), the factory address
+//and the pool created event signature as topic0, and must return every log matching the filter ordered by block
+#[async_trait]
+pub trait LogSource<M: Middleware> {
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, AMMError<M>>;
+}
+
+//Default log source that fetches logs with eth_getLogs through the middleware
+#[derive(Debug, Clone)]
+pub struct MiddlewareLogSource<M> {
+    pub middleware: Arc<M>,
+}
+
+impl<M> MiddlewareLogSource<M> {
+    pub fn new(middleware: Arc<M>) -> Self {
+        MiddlewareLogSource { middleware }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware> LogSource<M> for MiddlewareLogSource<M> {
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, AMMError<M>> {
+        self.middleware
+            .get_logs(filter)
+            .await
+            .map_err(AMMError::MiddlewareError)
+    }
+}
@@ -1,20 +1,78 @@
+pub mod algebra;
 pub mod erc_4626;
 pub mod factory;
+pub mod log_source;
+pub mod pool_info;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 
-use std::sync::Arc;
+use std::{collections::HashSet, future::Future, str::FromStr, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use ethers::{
+    prelude::ContractError,
     providers::Middleware,
-    types::{Log, H160, H256, U256},
+    types::{Bytes, Log, H160, H256, U256},
 };
 use serde::{Deserialize, Serialize};
 
-use crate::errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError};
+use crate::{
+    constants::{max_response_bytes, request_timeout},
+    errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+};
+
+use self::{
+    algebra::AlgebraPool, erc_4626::ERC4626Vault, uniswap_v2::UniswapV2Pool,
+    uniswap_v3::UniswapV3Pool,
+};
+
+//Logs the raw return data of a batch request before it is decoded, so that the payload behind a failed decode or a
+//`BatchRequestError` can be inspected when adding a fork with a nonstandard layout. Only enabled with the `debug-payloads`
+//feature since the payloads of large batches would flood normal logs
+#[cfg(feature = "debug-payloads")]
+pub(crate) fn log_batch_payload(return_data: &Bytes) {
+    tracing::debug!(payload = %return_data, "Batch request payload");
+}
+
+#[cfg(not(feature = "debug-payloads"))]
+pub(crate) fn log_batch_payload(_return_data: &Bytes) {}
+
+//Bounds a batch request by the timeout set through `set_request_timeout`, so that a hung call cannot stall the sync task
+//awaiting it. Wrap each attempt inside the retry closure so that a timed out attempt is retried
+pub(crate) async fn with_request_timeout<M: Middleware, T>(
+    request: impl Future<Output = Result<T, ContractError<M>>>,
+) -> Result<T, AMMError<M>> {
+    with_timeout(request_timeout(), request).await
+}
+
+//Caps the max batch size of a batch request so that its estimated response, ie. the encoded size of each element times the
+//number of elements, stays within the limit set through `set_max_response_bytes`
+pub(crate) fn capped_batch_size(max_batch_size: usize, element_bytes: usize) -> usize {
+    batch_size_within(max_batch_size, element_bytes, max_response_bytes())
+}
 
-use self::{erc_4626::ERC4626Vault, uniswap_v2::UniswapV2Pool, uniswap_v3::UniswapV3Pool};
+//Offset and length words of the returned dynamic array
+const RESPONSE_OVERHEAD_BYTES: usize = 64;
+
+fn batch_size_within(
+    max_batch_size: usize,
+    element_bytes: usize,
+    max_response_bytes: usize,
+) -> usize {
+    let elements =
+        max_response_bytes.saturating_sub(RESPONSE_OVERHEAD_BYTES) / element_bytes.max(1);
+    elements.clamp(1, max_batch_size.max(1))
+}
+
+async fn with_timeout<M: Middleware, T>(
+    timeout: Duration,
+    request: impl Future<Output = Result<T, ContractError<M>>>,
+) -> Result<T, AMMError<M>> {
+    match tokio::time::timeout(timeout, request).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(AMMError::RequestTimeout(timeout)),
+    }
+}
 
 #[async_trait]
 pub trait AutomatedMarketMaker {
@@ -37,13 +95,65 @@ pub trait AutomatedMarketMaker {
         amount_in: U256,
     ) -> Result<U256, SwapSimulationError>;
     fn get_token_out(&self, token_in: H160) -> H160;
+    fn max_output(&self, token_out: H160) -> U256;
+
+    //Returns the price of the token denominated in the other token of the amm, regardless of the token ordering
+    fn price_of(&self, token: H160) -> Result<f64, SwapSimulationError> {
+        if !self.tokens().contains(&token) {
+            return Err(SwapSimulationError::TokenNotInPool(token));
+        }
+
+        Ok(self.calculate_price(token)?)
+    }
+
+    //Samples the realized price, ie. amount out per amount in in raw token units, at evenly spaced input sizes up to
+    //max_amount. Samples stop at the first input size the pool cannot simulate, eg. once a v3 pool runs out of liquidity
+    fn price_curve(&self, token_in: H160, max_amount: U256, samples: usize) -> Vec<(U256, f64)> {
+        let mut curve = vec![];
+        if samples == 0 {
+            return curve;
+        }
+
+        for sample in 1..=samples {
+            let amount_in = max_amount * U256::from(sample) / U256::from(samples);
+            if amount_in.is_zero() {
+                continue;
+            }
+
+            match self.simulate_swap(token_in, amount_in) {
+                Ok(amount_out) => {
+                    let realized_price = u256_to_f64(amount_out) / u256_to_f64(amount_in);
+                    curve.push((amount_in, realized_price));
+                }
+                Err(_) => break,
+            }
+        }
+
+        curve
+    }
+}
+
+pub(crate) fn u256_to_f64(value: U256) -> f64 {
+    value.to_string().parse().unwrap_or(f64::INFINITY)
+}
+
+//Collects every distinct token held by the amms, ie. both pool tokens and the vault and asset tokens of vaults
+pub fn unique_tokens(amms: &[AMM]) -> HashSet<H160> {
+    amms.iter().flat_map(|amm| amm.tokens()).collect()
 }
 
+//Variants are serialized by name in checkpoints. The names are pinned so that renaming a variant does not break existing
+//checkpoints, and fields added to the pool structs must be `#[serde(default)]` so that older checkpoints still load
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AMM {
+    #[serde(rename = "UniswapV2Pool")]
     UniswapV2Pool(UniswapV2Pool),
+    #[serde(rename = "UniswapV3Pool")]
     UniswapV3Pool(UniswapV3Pool),
+    #[serde(rename = "ERC4626Vault")]
     ERC4626Vault(ERC4626Vault),
+    #[serde(rename = "AlgebraPool")]
+    AlgebraPool(AlgebraPool),
 }
 
 #[async_trait]
@@ -52,6 +162,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.address,
             AMM::UniswapV3Pool(pool) => pool.address,
+            AMM::AlgebraPool(pool) => pool.pool.address,
             AMM::ERC4626Vault(vault) => vault.vault_token,
         }
     }
@@ -60,6 +171,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.sync(middleware).await,
             AMM::UniswapV3Pool(pool) => pool.sync(middleware).await,
+            AMM::AlgebraPool(pool) => pool.sync(middleware).await,
             AMM::ERC4626Vault(vault) => vault.sync(middleware).await,
         }
     }
@@ -68,6 +180,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.sync_on_event_signatures(),
             AMM::UniswapV3Pool(pool) => pool.sync_on_event_signatures(),
+            AMM::AlgebraPool(pool) => pool.sync_on_event_signatures(),
             AMM::ERC4626Vault(vault) => vault.sync_on_event_signatures(),
         }
     }
@@ -76,6 +189,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.sync_from_log(log),
             AMM::UniswapV3Pool(pool) => pool.sync_from_log(log),
+            AMM::AlgebraPool(pool) => pool.sync_from_log(log),
             AMM::ERC4626Vault(vault) => vault.sync_from_log(log),
         }
     }
@@ -84,6 +198,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.simulate_swap(token_in, amount_in),
             AMM::UniswapV3Pool(pool) => pool.simulate_swap(token_in, amount_in),
+            AMM::AlgebraPool(pool) => pool.simulate_swap(token_in, amount_in),
             AMM::ERC4626Vault(vault) => vault.simulate_swap(token_in, amount_in),
         }
     }
@@ -96,6 +211,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.simulate_swap_mut(token_in, amount_in),
             AMM::UniswapV3Pool(pool) => pool.simulate_swap_mut(token_in, amount_in),
+            AMM::AlgebraPool(pool) => pool.simulate_swap_mut(token_in, amount_in),
             AMM::ERC4626Vault(vault) => vault.simulate_swap_mut(token_in, amount_in),
         }
     }
@@ -104,10 +220,20 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.get_token_out(token_in),
             AMM::UniswapV3Pool(pool) => pool.get_token_out(token_in),
+            AMM::AlgebraPool(pool) => pool.get_token_out(token_in),
             AMM::ERC4626Vault(vault) => vault.get_token_out(token_in),
         }
     }
 
+    fn max_output(&self, token_out: H160) -> U256 {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.max_output(token_out),
+            AMM::UniswapV3Pool(pool) => pool.max_output(token_out),
+            AMM::AlgebraPool(pool) => pool.max_output(token_out),
+            AMM::ERC4626Vault(vault) => vault.max_output(token_out),
+        }
+    }
+
     async fn populate_data<M: Middleware>(
         &mut self,
         block_number: Option<u64>,
@@ -117,6 +243,7 @@ impl AutomatedMarketMaker for AMM {
             AMM::UniswapV2Pool(pool) => pool.populate_data(None, middleware).await,
             AMM::UniswapV3Pool(pool) => pool.populate_data(block_number, middleware).await,
             AMM::ERC4626Vault(vault) => vault.populate_data(None, middleware).await,
+            AMM::AlgebraPool(pool) => pool.populate_data(block_number, middleware).await,
         }
     }
 
@@ -124,6 +251,7 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.tokens(),
             AMM::UniswapV3Pool(pool) => pool.tokens(),
+            AMM::AlgebraPool(pool) => pool.tokens(),
             AMM::ERC4626Vault(vault) => vault.tokens(),
         }
     }
@@ -132,7 +260,512 @@ impl AutomatedMarketMaker for AMM {
         match self {
             AMM::UniswapV2Pool(pool) => pool.calculate_price(base_token),
             AMM::UniswapV3Pool(pool) => pool.calculate_price(base_token),
+            AMM::AlgebraPool(pool) => pool.calculate_price(base_token),
             AMM::ERC4626Vault(vault) => vault.calculate_price(base_token),
         }
     }
 }
+
+//The variant of an amm, used where amms are referenced without their state, eg. in pool list files. Variants are ordered
+//by declaration, which `AMM::canonical_key` relies on for deterministic tie-breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PoolType {
+    #[serde(rename = "UniswapV2Pool")]
+    UniswapV2Pool,
+    #[serde(rename = "UniswapV3Pool")]
+    UniswapV3Pool,
+    #[serde(rename = "ERC4626Vault")]
+    ERC4626Vault,
+    #[serde(rename = "AlgebraPool")]
+    AlgebraPool,
+}
+
+impl PoolType {
+    //Creates an empty amm of this type at the address, to be populated via the batch requests
+    pub fn empty_amm(&self, address: H160) -> AMM {
+        match self {
+            PoolType::UniswapV2Pool => AMM::UniswapV2Pool(UniswapV2Pool {
+                address,
+                ..Default::default()
+            }),
+            PoolType::UniswapV3Pool => AMM::UniswapV3Pool(UniswapV3Pool {
+                address,
+                ..Default::default()
+            }),
+            PoolType::ERC4626Vault => AMM::ERC4626Vault(ERC4626Vault {
+                vault_token: address,
+                ..Default::default()
+            }),
+            PoolType::AlgebraPool => AMM::AlgebraPool(AlgebraPool {
+                pool: UniswapV3Pool {
+                    address,
+                    ..Default::default()
+                },
+            }),
+        }
+    }
+}
+
+impl FromStr for PoolType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UniswapV2Pool" | "uniswap_v2" => Ok(PoolType::UniswapV2Pool),
+            "UniswapV3Pool" | "uniswap_v3" => Ok(PoolType::UniswapV3Pool),
+            "ERC4626Vault" | "erc_4626" => Ok(PoolType::ERC4626Vault),
+            "AlgebraPool" | "algebra" => Ok(PoolType::AlgebraPool),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+//Returns whether the probed function exists on the contract. A revert or return data that does not decode counts as a
+//missing function, while other errors such as timeouts, rate limits or dropped connections are returned
+fn function_exists<M: Middleware, T>(
+    result: Result<T, ContractError<M>>,
+) -> Result<bool, AMMError<M>> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(
+            ContractError::Revert(_)
+            | ContractError::AbiError(_)
+            | ContractError::DecodingError(_)
+            | ContractError::DetokenizationError(_),
+        ) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+//Detects the variant of the pool at the address by probing the view functions that set each variant apart, getReserves
+//for v2 pairs, slot0 for v3 pools, globalState for Algebra pools and asset and totalAssets for ERC4626 vaults. Only a
+//reverting or undecodable call counts as a missing function, so forks exposing the same functions are detected as the
+//variant they mirror. Provider errors are returned instead of moving on to the next variant
+pub async fn detect_amm_type<M: 'static + Middleware>(
+    address: H160,
+    middleware: Arc<M>,
+) -> Result<PoolType, AMMError<M>> {
+    if function_exists(
+        uniswap_v2::IUniswapV2Pair::new(address, middleware.clone())
+            .get_reserves()
+            .call()
+            .await,
+    )? {
+        return Ok(PoolType::UniswapV2Pool);
+    }
+
+    if function_exists(
+        uniswap_v3::IUniswapV3Pool::new(address, middleware.clone())
+            .slot_0()
+            .call()
+            .await,
+    )? {
+        return Ok(PoolType::UniswapV3Pool);
+    }
+
+    if function_exists(
+        algebra::IAlgebraPool::new(address, middleware.clone())
+            .global_state()
+            .call()
+            .await,
+    )? {
+        return Ok(PoolType::AlgebraPool);
+    }
+
+    let vault = erc_4626::IERC4626Vault::new(address, middleware);
+    if function_exists(vault.asset().call().await)?
+        && function_exists(vault.total_assets().call().await)?
+    {
+        return Ok(PoolType::ERC4626Vault);
+    }
+
+    Err(AMMError::UnrecognizedPoolType(address))
+}
+
+impl AMM {
+    //Fetches the pool at the address fully populated at the latest block, ie. its tokens, decimals and reserves or slot0,
+    //without running a sync. The tick maps of v3 and Algebra pools are left empty, see `from_address_with_tick_data`
+    pub async fn from_address<M: 'static + Middleware>(
+        address: H160,
+        pool_type: PoolType,
+        middleware: Arc<M>,
+    ) -> Result<AMM, AMMError<M>> {
+        let mut amm = pool_type.empty_amm(address);
+        amm.populate_data(None, middleware).await?;
+
+        //The batch requests leave the tokens zeroed for addresses that are not a pool of the type
+        if amm.tokens().iter().any(|token| token.is_zero()) {
+            return Err(AMMError::PoolDataError);
+        }
+
+        Ok(amm)
+    }
+
+    //Fetches the pool like `from_address`, also loading the initialized ticks of v3 and Algebra pools within the tick window
+    //around the current tick, see `uniswap_v3::batch_request::populate_tick_data_batch`
+    pub async fn from_address_with_tick_data<M: 'static + Middleware>(
+        address: H160,
+        pool_type: PoolType,
+        tick_window: u16,
+        middleware: Arc<M>,
+    ) -> Result<AMM, AMMError<M>> {
+        let mut amms = [AMM::from_address(address, pool_type, middleware.clone()).await?];
+        uniswap_v3::batch_request::populate_tick_data_batch(
+            &mut amms,
+            tick_window,
+            None,
+            middleware,
+        )
+        .await?;
+
+        let [amm] = amms;
+        Ok(amm)
+    }
+
+    //Fetches the pool like `from_address` for an address of unknown variant, detecting the variant with `detect_amm_type`
+    pub async fn from_address_with_detected_type<M: 'static + Middleware>(
+        address: H160,
+        middleware: Arc<M>,
+    ) -> Result<AMM, AMMError<M>> {
+        let pool_type = detect_amm_type(address, middleware.clone()).await?;
+        AMM::from_address(address, pool_type, middleware).await
+    }
+
+    pub fn pool_type(&self) -> PoolType {
+        match self {
+            AMM::UniswapV2Pool(_) => PoolType::UniswapV2Pool,
+            AMM::UniswapV3Pool(_) => PoolType::UniswapV3Pool,
+            AMM::ERC4626Vault(_) => PoolType::ERC4626Vault,
+            AMM::AlgebraPool(_) => PoolType::AlgebraPool,
+        }
+    }
+
+    //Returns true if both tokens of the amm are the same token, which factories should never create but malformed or
+    //malicious ones can emit. The price math of every variant assumes two distinct tokens
+    pub fn has_identical_tokens(&self) -> bool {
+        let tokens = self.tokens();
+        tokens.len() == 2 && tokens[0] == tokens[1]
+    }
+
+    //Returns a key ordering amms by variant, fee and address, used to break ties deterministically where amms offer the
+    //same output. Fees are in the units of each variant, which are only compared between amms of the same variant
+    pub fn canonical_key(&self) -> (PoolType, u32, H160) {
+        let fee = match self {
+            AMM::UniswapV2Pool(pool) => pool.fee,
+            AMM::UniswapV3Pool(pool) => pool.fee,
+            AMM::AlgebraPool(pool) => pool.fee(),
+            AMM::ERC4626Vault(vault) => vault.deposit_fee,
+        };
+
+        (self.pool_type(), fee, self.address())
+    }
+
+    pub fn tags(&self) -> &[String] {
+        match self {
+            AMM::UniswapV2Pool(pool) => &pool.tags,
+            AMM::UniswapV3Pool(pool) => &pool.tags,
+            AMM::AlgebraPool(pool) => &pool.pool.tags,
+            AMM::ERC4626Vault(vault) => &vault.tags,
+        }
+    }
+
+    pub fn tags_mut(&mut self) -> &mut Vec<String> {
+        match self {
+            AMM::UniswapV2Pool(pool) => &mut pool.tags,
+            AMM::UniswapV3Pool(pool) => &mut pool.tags,
+            AMM::AlgebraPool(pool) => &mut pool.pool.tags,
+            AMM::ERC4626Vault(vault) => &mut vault.tags,
+        }
+    }
+
+    //Returns the factory the amm was discovered from, zero for vaults and amms not discovered through a factory
+    pub fn factory(&self) -> H160 {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.factory,
+            AMM::UniswapV3Pool(pool) => pool.factory,
+            AMM::ERC4626Vault(_) => H160::zero(),
+            AMM::AlgebraPool(pool) => pool.pool.factory,
+        }
+    }
+
+    pub fn set_factory(&mut self, factory: H160) {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.factory = factory,
+            AMM::UniswapV3Pool(pool) => pool.factory = factory,
+            AMM::ERC4626Vault(_) => {}
+            AMM::AlgebraPool(pool) => pool.pool.factory = factory,
+        }
+    }
+
+    //Returns true if the swap keeps the price impact under max_impact, for pruning shallow pools before quoting.
+    //Vaults convert at the share price without price impact, so they can fill any amount they can simulate
+    pub fn can_fill(&self, token_in: H160, amount_in: U256, max_impact: f64) -> bool {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.can_fill(token_in, amount_in, max_impact),
+            AMM::UniswapV3Pool(pool) => pool.can_fill(token_in, amount_in, max_impact),
+            AMM::AlgebraPool(pool) => pool.pool.can_fill(token_in, amount_in, max_impact),
+            AMM::ERC4626Vault(vault) => vault.simulate_swap(token_in, amount_in).is_ok(),
+        }
+    }
+
+    pub fn last_synced_block(&self) -> u64 {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.last_synced_block,
+            AMM::UniswapV3Pool(pool) => pool.last_synced_block,
+            AMM::AlgebraPool(pool) => pool.pool.last_synced_block,
+            AMM::ERC4626Vault(vault) => vault.last_synced_block,
+        }
+    }
+
+    pub fn set_last_synced_block(&mut self, block_number: u64) {
+        match self {
+            AMM::UniswapV2Pool(pool) => pool.last_synced_block = block_number,
+            AMM::UniswapV3Pool(pool) => pool.last_synced_block = block_number,
+            AMM::AlgebraPool(pool) => pool.pool.last_synced_block = block_number,
+            AMM::ERC4626Vault(vault) => vault.last_synced_block = block_number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use ethers::{
+        abi::Token,
+        providers::{
+            Http, JsonRpcError, Middleware, MockProvider, MockResponse, Provider, ProviderError,
+        },
+        types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, H160, U256},
+    };
+
+    use crate::{
+        amm::{
+            erc_4626::ERC4626Vault,
+            uniswap_v2::{batch_request::IGetUniswapV2PoolDataBatchRequest, UniswapV2Pool},
+            uniswap_v3::UniswapV3Pool,
+        },
+        errors::AMMError,
+    };
+
+    use super::{
+        batch_size_within, detect_amm_type, unique_tokens, with_timeout, AutomatedMarketMaker,
+        PoolType, AMM,
+    };
+
+    //Delays every call before forwarding it to the mock provider, like a node that hangs on a heavy batch request
+    #[derive(Debug)]
+    struct SlowMiddleware {
+        inner: Provider<MockProvider>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl Middleware for SlowMiddleware {
+        type Error = ProviderError;
+        type Provider = MockProvider;
+        type Inner = Provider<MockProvider>;
+
+        fn inner(&self) -> &Provider<MockProvider> {
+            &self.inner
+        }
+
+        async fn call(
+            &self,
+            tx: &TypedTransaction,
+            block: Option<BlockId>,
+        ) -> Result<Bytes, Self::Error> {
+            tokio::time::sleep(self.delay).await;
+            self.inner.call(tx, block).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(SlowMiddleware {
+            inner: provider,
+            delay: Duration::from_millis(200),
+        });
+
+        let constructor_args = Token::Tuple(vec![Token::Array(vec![])]);
+        let deployer = IGetUniswapV2PoolDataBatchRequest::deploy(middleware, constructor_args)?;
+
+        let result = with_timeout(Duration::from_millis(20), deployer.call_raw()).await;
+        assert!(matches!(result, Err(AMMError::RequestTimeout(_))));
+
+        mock.push::<Bytes, _>(Bytes::from(vec![1, 2, 3]))?;
+        let return_data = with_timeout(Duration::from_secs(5), deployer.call_raw()).await?;
+        assert_eq!(return_data, Bytes::from(vec![1, 2, 3]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_tokens() {
+        let weth = H160::from_low_u64_be(1);
+        let usdc = H160::from_low_u64_be(2);
+        let dai = H160::from_low_u64_be(3);
+        let vault_token = H160::from_low_u64_be(4);
+
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                token_a: weth,
+                token_b: usdc,
+                ..Default::default()
+            }),
+            AMM::UniswapV3Pool(UniswapV3Pool {
+                token_a: usdc,
+                token_b: weth,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                token_a: dai,
+                token_b: usdc,
+                ..Default::default()
+            }),
+            AMM::ERC4626Vault(ERC4626Vault {
+                vault_token,
+                asset_token: dai,
+                ..Default::default()
+            }),
+        ];
+
+        //Tokens shared by several pools are collected once
+        let tokens = unique_tokens(&amms);
+        assert_eq!(tokens.len(), 4);
+        assert!([weth, usdc, dai, vault_token]
+            .iter()
+            .all(|token| tokens.contains(token)));
+
+        assert!(unique_tokens(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_batch_size_within_response_limit() {
+        let pool_data_bytes = 6 * 32;
+
+        //Without a limit the max batch size is used
+        assert_eq!(batch_size_within(127, pool_data_bytes, usize::MAX), 127);
+
+        //A 4kb limit fits 21 pools after the array overhead, splitting 127 pools into 7 chunks
+        let size = batch_size_within(127, pool_data_bytes, 4096);
+        assert_eq!(size, 21);
+        assert!(size * pool_data_bytes + 64 <= 4096);
+        let pools = vec![0; 127];
+        assert_eq!(pools.chunks(size).count(), 7);
+
+        //A limit below a single element still sends one element per batch
+        assert_eq!(batch_size_within(127, pool_data_bytes, 100), 1);
+    }
+
+    #[tokio::test]
+    async fn test_from_address() -> eyre::Result<()> {
+        let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint)?);
+
+        //The uniswap v2 USDC/WETH pair
+        let amm = AMM::from_address(
+            H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            PoolType::UniswapV2Pool,
+            middleware,
+        )
+        .await?;
+
+        assert_eq!(
+            amm.tokens(),
+            vec![
+                H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?,
+                H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")?,
+            ]
+        );
+        if let AMM::UniswapV2Pool(pool) = amm {
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 18));
+            assert!(pool.reserve_0 > 0 && pool.reserve_1 > 0);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_detect_amm_type() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let address = H160::from_low_u64_be(1);
+        let revert = || {
+            MockResponse::Error(JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            })
+        };
+
+        mock.push::<Bytes, _>(
+            ethers::abi::encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+                Token::Uint(U256::from(1)),
+            ])
+            .into(),
+        )?;
+        assert_eq!(
+            detect_amm_type(address, middleware.clone()).await?,
+            PoolType::UniswapV2Pool
+        );
+
+        //Responses are popped in reverse order, getReserves reverts before slot0 is probed
+        mock.push::<Bytes, _>(
+            ethers::abi::encode(&[
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ])
+            .into(),
+        )?;
+        mock.push_response(revert());
+        assert_eq!(
+            detect_amm_type(address, middleware.clone()).await?,
+            PoolType::UniswapV3Pool
+        );
+
+        mock.push::<Bytes, _>(ethers::abi::encode(&[Token::Uint(U256::from(1000))]).into())?;
+        mock.push::<Bytes, _>(
+            ethers::abi::encode(&[Token::Address(H160::from_low_u64_be(2))]).into(),
+        )?;
+        mock.push_response(revert());
+        mock.push_response(revert());
+        mock.push_response(revert());
+        assert_eq!(
+            detect_amm_type(address, middleware.clone()).await?,
+            PoolType::ERC4626Vault
+        );
+
+        for _ in 0..4 {
+            mock.push_response(revert());
+        }
+        assert!(matches!(
+            detect_amm_type(address, middleware.clone()).await,
+            Err(AMMError::UnrecognizedPoolType(unrecognized)) if unrecognized == address
+        ));
+
+        //A rate limited getReserves is returned instead of probing the other variants
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 429,
+            message: "rate limited".to_string(),
+            data: None,
+        }));
+        assert!(matches!(
+            detect_amm_type(address, middleware).await,
+            Err(AMMError::ContractError(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use ethers::types::H160;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{algebra::AlgebraPool, AMM};
+
+//Flat, serde friendly representation of an AMM that is decoupled from the internal AMM enum layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolInfo {
+    pub address: H160,
+    pub pool_type: String,
+    pub tokens: Vec<H160>,
+    pub decimals: Vec<u8>,
+    pub fee: u32,                 // fee in the native units of the pool type
+    pub extra: serde_json::Value, // type specific fields, big integers are encoded as decimal strings
+}
+
+impl From<&AMM> for PoolInfo {
+    fn from(amm: &AMM) -> Self {
+        match amm {
+            AMM::UniswapV2Pool(pool) => PoolInfo {
+                address: pool.address,
+                pool_type: "UniswapV2Pool".to_string(),
+                tokens: vec![pool.token_a, pool.token_b],
+                decimals: vec![pool.token_a_decimals, pool.token_b_decimals],
+                fee: pool.fee,
+                extra: json!({
+                    "reserve_0": pool.reserve_0.to_string(),
+                    "reserve_1": pool.reserve_1.to_string(),
+                }),
+            },
+            AMM::UniswapV3Pool(pool) => PoolInfo {
+                address: pool.address,
+                pool_type: "UniswapV3Pool".to_string(),
+                tokens: vec![pool.token_a, pool.token_b],
+                decimals: vec![pool.token_a_decimals, pool.token_b_decimals],
+                fee: pool.fee,
+                extra: json!({
+                    "liquidity": pool.liquidity.to_string(),
+                    "sqrt_price": pool.sqrt_price.to_string(),
+                    "tick": pool.tick,
+                    "tick_spacing": pool.tick_spacing,
+                }),
+            },
+            AMM::ERC4626Vault(vault) => PoolInfo {
+                address: vault.vault_token,
+                pool_type: "ERC4626Vault".to_string(),
+                tokens: vec![vault.vault_token, vault.asset_token],
+                decimals: vec![vault.vault_token_decimals, vault.asset_token_decimals],
+                fee: vault.deposit_fee,
+                extra: json!({
+                    "vault_reserve": vault.vault_reserve.to_string(),
+                    "asset_reserve": vault.asset_reserve.to_string(),
+                    "deposit_fee": vault.deposit_fee,
+                    "withdraw_fee": vault.withdraw_fee,
+                }),
+            },
+            AMM::AlgebraPool(AlgebraPool { pool }) => PoolInfo {
+                address: pool.address,
+                pool_type: "AlgebraPool".to_string(),
+                tokens: vec![pool.token_a, pool.token_b],
+                decimals: vec![pool.token_a_decimals, pool.token_b_decimals],
+                fee: pool.fee,
+                extra: json!({
+                    "liquidity": pool.liquidity.to_string(),
+                    "sqrt_price": pool.sqrt_price.to_string(),
+                    "tick": pool.tick,
+                    "tick_spacing": pool.tick_spacing,
+                }),
+            },
+        }
+    }
+}
@@ -7,7 +7,7 @@ use ethers::{
 use std::sync::Arc;
 
 use crate::{
-    amm::{AutomatedMarketMaker, AMM},
+    amm::{log_batch_payload, with_request_timeout, AutomatedMarketMaker, AMM},
     constants::CONSTANT_RETRY,
     errors::AMMError,
 };
@@ -25,6 +25,15 @@ abigen!(
         "src/amm/uniswap_v2/batch_request/GetUniswapV2PoolDataBatchRequestABI.json";
 );
 
+//Max number of pairs returned by a single pairs batch request until the codesize of the request is too large
+pub const MAX_PAIRS_BATCH_SIZE: usize = 766;
+//Each pair is returned as a single address word
+pub const PAIR_RESPONSE_BYTES: usize = 32;
+//Max number of pools populated by a single pool data batch request
+pub const MAX_POOL_DATA_BATCH_SIZE: usize = 127;
+//Tokens, decimals and reserves, six words per pool
+pub const POOL_DATA_RESPONSE_BYTES: usize = 6 * 32;
+
 fn populate_pool_data_from_tokens(
     mut pool: UniswapV2Pool,
     tokens: Vec<Token>,
@@ -35,10 +44,12 @@ fn populate_pool_data_from_tokens(
     pool.token_b_decimals = tokens[3].to_owned().into_uint()?.as_u32() as u8;
     pool.reserve_0 = tokens[4].to_owned().into_uint()?.as_u128();
     pool.reserve_1 = tokens[5].to_owned().into_uint()?.as_u128();
+    pool.check_rebasing_tokens();
 
     Some(pool)
 }
 
+#[tracing::instrument(skip(middleware), err)]
 pub async fn get_pairs_batch_request<M: Middleware>(
     factory: H160,
     from: U256,
@@ -54,9 +65,13 @@ pub async fn get_pairs_batch_request<M: Middleware>(
     ]);
 
     let deployer = IGetUniswapV2PairsBatchRequest::deploy(middleware, constructor_args)?;
-    let call = || async { deployer.call_raw().await };
-    let return_data: Bytes = call.retry(&*CONSTANT_RETRY).await?;
+    let call = || async { with_request_timeout(deployer.call_raw()).await };
+    let return_data: Bytes = call
+        .retry(&*CONSTANT_RETRY)
+        .notify(|err, delay| tracing::warn!(%err, ?delay, "Retrying batch request"))
+        .await?;
 
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Address))],
         &return_data,
@@ -77,8 +92,10 @@ pub async fn get_pairs_batch_request<M: Middleware>(
     Ok(pairs)
 }
 
+#[tracing::instrument(skip(amms, middleware), fields(amms = amms.len()), err)]
 pub async fn get_amm_data_batch_request<M: Middleware>(
     amms: &mut [AMM],
+    block_number: Option<u64>,
     middleware: Arc<M>,
 ) -> Result<(), AMMError<M>> {
     let mut target_addresses = vec![];
@@ -88,10 +105,18 @@ pub async fn get_amm_data_batch_request<M: Middleware>(
 
     let constructor_args = Token::Tuple(vec![Token::Array(target_addresses)]);
 
-    let deployer = IGetUniswapV2PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
+    let mut deployer =
+        IGetUniswapV2PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
+    if let Some(block_number) = block_number {
+        deployer = deployer.block(block_number);
+    }
 
-    let call = || async { deployer.call_raw().await };
-    let return_data: Bytes = call.retry(&*CONSTANT_RETRY).await?;
+    let call = || async { with_request_timeout(deployer.call_raw()).await };
+    let return_data: Bytes = call
+        .retry(&*CONSTANT_RETRY)
+        .notify(|err, delay| tracing::warn!(%err, ?delay, "Retrying batch request"))
+        .await?;
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // token a
@@ -152,8 +177,13 @@ pub async fn get_amm_data_batch_request_optional<M: Middleware>(
     let deployer =
         IGetUniswapV2PoolDataBatchRequest::deploy(middleware.clone(), constructor_args).ok()?;
 
-    let call = || async { deployer.call_raw().await };
-    let return_data: Bytes = call.retry(&*CONSTANT_RETRY).await.ok()?;
+    let call = || async { with_request_timeout(deployer.call_raw()).await };
+    let return_data: Bytes = call
+        .retry(&*CONSTANT_RETRY)
+        .notify(|err, delay| tracing::warn!(%err, ?delay, "Retrying batch request"))
+        .await
+        .ok()?;
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // token a
@@ -208,7 +238,8 @@ pub async fn get_v2_pool_data_batch_request<M: Middleware>(
 
     let deployer = IGetUniswapV2PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = with_request_timeout(deployer.call_raw()).await?;
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // token a
@@ -1,11 +1,12 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
 use ethers::{
     abi::RawLog,
-    prelude::EthEvent,
+    prelude::{ContractError, EthEvent},
     providers::Middleware,
     types::{Log, H160, H256, U256},
+    utils::{get_create2_address_from_hash, keccak256},
 };
 
 use indicatif::ProgressBar;
@@ -14,8 +15,9 @@ use tokio::task::JoinSet;
 
 use crate::{
     amm::{
-        factory::{AutomatedMarketMakerFactory, TASK_LIMIT},
-        AMM,
+        capped_batch_size,
+        factory::{AutomatedMarketMakerFactory, Factory, TASK_LIMIT},
+        AutomatedMarketMaker, AMM,
     },
     constants::{MULTIPROGRESS, SYNC_BAR_STYLE},
     errors::AMMError,
@@ -32,6 +34,7 @@ abigen!(
         function allPairs(uint256 index) external view returns (address)
         event PairCreated(address indexed token0, address indexed token1, address pair, uint256)
         function allPairsLength() external view returns (uint256)
+        function feeTo() external view returns (address)
 
     ]"#;
 );
@@ -46,7 +49,24 @@ pub const PAIR_CREATED_EVENT_SIGNATURE_BYTES: [u8; 32] = [
 pub struct UniswapV2Factory {
     pub address: H160,
     pub creation_block: u64,
+    //Swap fee of the pairs out of 100000, eg. 300 for uniswap and 250 for PancakeSwap V2
     pub fee: u32,
+    //Whether feeTo is set on the factory. The protocol fee mints 1/6 of the growth in sqrt(k) to feeTo as LP tokens
+    //when liquidity is added or removed, reducing LP returns, but it does not change the output of a swap
+    #[serde(default)]
+    pub protocol_fee_on: bool,
+}
+
+//Pairs of a single factory that share the same sorted token pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatePair {
+    pub factory: H160,
+    pub token_a: H160,
+    pub token_b: H160,
+    //The first discovered pair, which is kept as the canonical pair
+    pub canonical: H160,
+    //The later pairs for the same tokens, which are removed
+    pub duplicates: Vec<H160>,
 }
 
 impl UniswapV2Factory {
@@ -55,6 +75,45 @@ impl UniswapV2Factory {
             address,
             creation_block,
             fee,
+            protocol_fee_on: false,
+        }
+    }
+
+    //Computes the CREATE2 address of the pair of the tokens, salted with the sorted token addresses like the factory. The
+    //init code hash differs between forks, so it must be the hash of the pair creation code deployed by this factory
+    pub fn compute_pair_address(&self, token_a: H160, token_b: H160, init_code_hash: H256) -> H160 {
+        let (token_0, token_1) = if token_a < token_b {
+            (token_a, token_b)
+        } else {
+            (token_b, token_a)
+        };
+
+        let salt = keccak256([token_0.as_bytes(), token_1.as_bytes()].concat());
+        get_create2_address_from_hash(self.address, salt, init_code_hash)
+    }
+
+    //Reads feeTo from the factory at the block, the protocol fee is active when feeTo is not the zero address. Factories
+    //without feeTo revert or return undecodable data and have no protocol fee, other errors are returned
+    pub async fn get_protocol_fee_on<M: Middleware>(
+        &self,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<bool, AMMError<M>> {
+        let factory = IUniswapV2Factory::new(self.address, middleware);
+        let mut fee_to = factory.fee_to();
+        if let Some(block_number) = block_number {
+            fee_to = fee_to.block(block_number);
+        }
+
+        match fee_to.call().await {
+            Ok(fee_to) => Ok(!fee_to.is_zero()),
+            Err(
+                ContractError::Revert(_)
+                | ContractError::AbiError(_)
+                | ContractError::DecodingError(_)
+                | ContractError::DetokenizationError(_),
+            ) => Ok(false),
+            Err(err) => Err(err.into()),
         }
     }
 
@@ -65,13 +124,48 @@ impl UniswapV2Factory {
         let factory = IUniswapV2Factory::new(self.address, middleware.clone());
 
         let pairs_length: U256 = factory.all_pairs_length().call().await?;
+
+        self.get_all_pairs_from_length(pairs_length, middleware)
+            .await
+    }
+
+    //Gets all pairs from the factory via PairCreated logs, used when the factory does not implement allPairsLength
+    pub async fn get_all_pairs_via_logs<M: 'static + Middleware>(
+        self,
+        to_block: Option<u64>,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        let to_block = if let Some(block) = to_block {
+            block
+        } else {
+            middleware
+                .get_block_number()
+                .await
+                .map_err(AMMError::MiddlewareError)?
+                .as_u64()
+        };
+
+        Factory::UniswapV2Factory(self)
+            .get_all_pools_from_logs(self.creation_block, to_block, step, None, middleware)
+            .await
+    }
+
+    async fn get_all_pairs_from_length<M: 'static + Middleware>(
+        self,
+        pairs_length: U256,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
         let progress = MULTIPROGRESS.add(
             ProgressBar::new(pairs_length.as_u64())
                 .with_style(SYNC_BAR_STYLE.clone())
                 .with_message(format!("Getting all v2 pools from: {}", self.address)),
         );
 
-        let step = 766; //max batch size for this call until codesize is too large
+        let step = capped_batch_size(
+            batch_request::MAX_PAIRS_BATCH_SIZE,
+            batch_request::PAIR_RESPONSE_BYTES,
+        );
         let mut idx_from = U256::zero();
         let mut idx_to = if step > pairs_length.as_usize() {
             pairs_length
@@ -117,6 +211,47 @@ impl UniswapV2Factory {
         Ok(amms)
     }
 
+    //Removes pairs sharing the same sorted token pair, keeping the first discovered pair and returning the duplicates found
+    pub fn remove_duplicate_pairs(&self, amms: Vec<AMM>) -> (Vec<AMM>, Vec<DuplicatePair>) {
+        let mut canonical_pairs: HashMap<(H160, H160), usize> = HashMap::new();
+        let mut duplicate_pairs: Vec<DuplicatePair> = vec![];
+        let mut unique_amms = vec![];
+
+        for amm in amms {
+            if let AMM::UniswapV2Pool(pool) = &amm {
+                let token_pair = if pool.token_a < pool.token_b {
+                    (pool.token_a, pool.token_b)
+                } else {
+                    (pool.token_b, pool.token_a)
+                };
+
+                if let Some(canonical_idx) = canonical_pairs.get(&token_pair) {
+                    let canonical = unique_amms[*canonical_idx].address();
+                    match duplicate_pairs
+                        .iter_mut()
+                        .find(|duplicate| duplicate.canonical == canonical)
+                    {
+                        Some(duplicate) => duplicate.duplicates.push(pool.address),
+                        None => duplicate_pairs.push(DuplicatePair {
+                            factory: self.address,
+                            token_a: token_pair.0,
+                            token_b: token_pair.1,
+                            canonical,
+                            duplicates: vec![pool.address],
+                        }),
+                    }
+                    continue;
+                }
+
+                canonical_pairs.insert(token_pair, unique_amms.len());
+            }
+
+            unique_amms.push(amm);
+        }
+
+        (unique_amms, duplicate_pairs)
+    }
+
     pub async fn process_amm_from_requests<M: 'static + Middleware>(
         amms: &mut Vec<AMM>,
         mut set: JoinSet<Result<Vec<H160>, AMMError<M>>>,
@@ -158,6 +293,7 @@ impl AutomatedMarketMakerFactory for UniswapV2Factory {
     }
 
     fn new_empty_amm_from_log(log: Log) -> Result<AMM, ethers::abi::Error> {
+        let factory = log.address;
         let pair_created_event = PairCreatedFilter::decode_log(&RawLog::from(log))?;
 
         Ok(AMM::UniswapV2Pool(UniswapV2Pool {
@@ -169,27 +305,61 @@ impl AutomatedMarketMakerFactory for UniswapV2Factory {
             reserve_0: 0,
             reserve_1: 0,
             fee: 0,
+            unreliable_reserves: false,
+            factory,
+            tags: vec![],
+            last_synced_block: 0,
+            total_supply: U256::zero(),
         }))
     }
 
     async fn get_all_amms<M: 'static + Middleware>(
         &self,
-        _to_block: Option<u64>,
+        to_block: Option<u64>,
         middleware: Arc<M>,
-        _step: u64,
+        step: u64,
     ) -> Result<Vec<AMM>, AMMError<M>> {
-        self.get_all_pairs_via_batched_calls(middleware).await
+        let factory = IUniswapV2Factory::new(self.address, middleware.clone());
+
+        match factory.all_pairs_length().call().await {
+            Ok(pairs_length) => {
+                self.get_all_pairs_from_length(pairs_length, middleware)
+                    .await
+            }
+            //Some factory forks do not implement allPairsLength, so fall back to discovering pairs from PairCreated logs.
+            //Only a revert or undecodable return data means the function is missing, other errors such as timeouts or
+            //rate limits are returned instead of silently turning into a full history log scan
+            Err(
+                ContractError::Revert(_)
+                | ContractError::AbiError(_)
+                | ContractError::DecodingError(_)
+                | ContractError::DetokenizationError(_),
+            ) => {
+                tracing::warn!(
+                    factory = ?self.address,
+                    "allPairsLength is not available, falling back to PairCreated logs"
+                );
+
+                self.get_all_pairs_via_logs(to_block, step, middleware)
+                    .await
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 
     async fn populate_amm_data<M: Middleware>(
         &self,
         amms: &mut [AMM],
-        _block_number: Option<u64>,
+        block_number: Option<u64>,
         middleware: Arc<M>,
     ) -> Result<(), AMMError<M>> {
-        let step = 127; //Max batch size for call
+        let step = capped_batch_size(
+            batch_request::MAX_POOL_DATA_BATCH_SIZE,
+            batch_request::POOL_DATA_RESPONSE_BYTES,
+        );
         for amm_chunk in amms.chunks_mut(step) {
-            batch_request::get_amm_data_batch_request(amm_chunk, middleware.clone()).await?;
+            batch_request::get_amm_data_batch_request(amm_chunk, block_number, middleware.clone())
+                .await?;
         }
         Ok(())
     }
@@ -198,3 +368,173 @@ impl AutomatedMarketMakerFactory for UniswapV2Factory {
         self.creation_block
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use ethers::{
+        abi::Token,
+        providers::{JsonRpcError, MockResponse, Provider},
+        types::{Bytes, Log, H160, H256, U256, U64},
+    };
+
+    use crate::amm::{factory::AutomatedMarketMakerFactory, AMM};
+
+    use super::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE};
+
+    #[tokio::test]
+    async fn test_get_protocol_fee_on() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(1), 0, 300);
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::from_low_u64_be(5),
+        )])))?;
+
+        assert!(
+            factory
+                .get_protocol_fee_on(None, middleware.clone())
+                .await?
+        );
+        assert!(
+            !factory
+                .get_protocol_fee_on(None, middleware.clone())
+                .await?
+        );
+
+        //A factory without feeTo reverts and has no protocol fee, while a transport error is returned
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 429,
+            message: "Too many requests".to_string(),
+            data: None,
+        }));
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        assert!(
+            !factory
+                .get_protocol_fee_on(Some(100), middleware.clone())
+                .await?
+        );
+        assert!(factory
+            .get_protocol_fee_on(Some(100), middleware)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_pair_address() -> eyre::Result<()> {
+        let usdc = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48")?;
+        let weth = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")?;
+
+        let uniswap_factory = UniswapV2Factory::new(
+            H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f")?,
+            10000835,
+            300,
+        );
+        let uniswap_init_code_hash =
+            H256::from_str("0x96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f")?;
+        let uniswap_pair = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?;
+
+        let sushi_factory = UniswapV2Factory::new(
+            H160::from_str("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac")?,
+            10794229,
+            300,
+        );
+        let sushi_init_code_hash =
+            H256::from_str("0xe18a34eb0e04b04f7a0ac29a6e80748dca96319b42c54d679cb821dca90c6303")?;
+        let sushi_pair = H160::from_str("0x397FF1542f962076d0BFE58eA045FfA2d347ACa0")?;
+
+        assert_eq!(
+            uniswap_factory.compute_pair_address(usdc, weth, uniswap_init_code_hash),
+            uniswap_pair
+        );
+        assert_eq!(
+            sushi_factory.compute_pair_address(usdc, weth, sushi_init_code_hash),
+            sushi_pair
+        );
+
+        //The tokens are sorted before hashing, so the order they are passed in does not matter
+        assert_eq!(
+            uniswap_factory.compute_pair_address(weth, usdc, uniswap_init_code_hash),
+            uniswap_pair
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_all_amms_falls_back_to_logs() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(1);
+        let token_0 = H160::from_low_u64_be(2);
+        let token_1 = H160::from_low_u64_be(3);
+        let pair = H160::from_low_u64_be(4);
+
+        let pair_created_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(150)),
+            ..Default::default()
+        };
+
+        //Mocked responses are popped in reverse order, the empty return data makes allPairsLength fail to decode
+        mock.push::<Vec<Log>, _>(vec![pair_created_log])?;
+        mock.push::<Bytes, _>(Bytes::default())?;
+
+        let factory = UniswapV2Factory::new(factory_address, 100, 300);
+        let amms = factory
+            .get_all_amms(Some(200), Arc::new(provider), 1000)
+            .await?;
+
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!(pool.address, pair);
+            assert_eq!(pool.token_a, token_0);
+            assert_eq!(pool.token_b, token_1);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_all_amms_returns_transient_errors() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        //A rate limited allPairsLength would succeed through the log scan if it fell back
+        mock.push::<Vec<Log>, _>(vec![])?;
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 429,
+            message: "Too many requests".to_string(),
+            data: None,
+        }));
+
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(1), 100, 300);
+        assert!(factory
+            .get_all_amms(Some(200), Arc::new(provider), 1000)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
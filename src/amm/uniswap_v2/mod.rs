@@ -8,14 +8,17 @@ use ethers::{
     abi::{ethabi::Bytes, RawLog, Token},
     prelude::EthEvent,
     providers::Middleware,
-    types::{Log, H160, H256, U256},
+    types::{Log, H160, H256, U256, U512},
 };
 use num_bigfloat::BigFloat;
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
 
 use crate::{
-    amm::AutomatedMarketMaker,
+    amm::{factory::TASK_LIMIT, u256_to_f64, AutomatedMarketMaker},
+    constants::KNOWN_REBASING_TOKENS,
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+    serde_helpers::{u128_string, u256_string},
 };
 
 use ethers::prelude::abigen;
@@ -28,6 +31,7 @@ abigen!(
         function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
         function token0() external view returns (address)
         function token1() external view returns (address)
+        function totalSupply() external view returns (uint256)
         function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data);
         event Sync(uint112 reserve0, uint112 reserve1)
     ]"#;
@@ -36,10 +40,15 @@ abigen!(
     r#"[
         function balanceOf(address account) external view returns (uint256)
         function decimals() external view returns (uint8)
+        function totalSupply() external view returns (uint256)
     ]"#;
 );
 
 pub const U128_0X10000000000000000: u128 = 18446744073709551616;
+//Denominator of the pool fee, ie. a fee of 300 is 0.3% for uniswap and 250 is 0.25% for PancakeSwap V2
+pub const FEE_DENOMINATOR: u32 = 100000;
+//Liquidity locked forever by the pair on the first mint
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
 pub const SYNC_EVENT_SIGNATURE: H256 = H256([
     28, 65, 30, 154, 150, 224, 113, 36, 28, 47, 33, 247, 114, 107, 23, 174, 137, 227, 202, 180,
     199, 139, 229, 14, 6, 43, 3, 169, 255, 251, 186, 209,
@@ -52,9 +61,27 @@ pub struct UniswapV2Pool {
     pub token_a_decimals: u8,
     pub token_b: H160,
     pub token_b_decimals: u8,
+    #[serde(with = "u128_string")]
     pub reserve_0: u128,
+    #[serde(with = "u128_string")]
     pub reserve_1: u128,
+    //Fee out of FEE_DENOMINATOR, set from the factory fee when syncing
     pub fee: u32,
+    //Set when either token rebases, these pools need frequent reserve refreshes since reserves drift between Sync events
+    #[serde(default)]
+    pub unreliable_reserves: bool,
+    //Factory the pool was discovered from, zero if the pool was not discovered through a factory
+    #[serde(default)]
+    pub factory: H160,
+    //User annotations, preserved across resyncs and untouched by population
+    #[serde(default)]
+    pub tags: Vec<String>,
+    //Block of the last event applied to the amm by the state space, zero if no event has been applied
+    #[serde(default)]
+    pub last_synced_block: u64,
+    //Total supply of the LP token, not returned by the batch requests, see `populate_total_supply`
+    #[serde(default, with = "u256_string")]
+    pub total_supply: U256,
 }
 
 #[async_trait]
@@ -107,6 +134,61 @@ impl AutomatedMarketMaker for UniswapV2Pool {
     }
 
     fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, SwapSimulationError> {
+        if self.unreliable_reserves {
+            return Err(SwapSimulationError::UnreliableReserves(self.address));
+        }
+
+        self.simulate_swap_unchecked(token_in, amount_in)
+    }
+
+    fn simulate_swap_mut(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        if self.unreliable_reserves {
+            return Err(SwapSimulationError::UnreliableReserves(self.address));
+        }
+
+        self.simulate_swap_mut_unchecked(token_in, amount_in)
+    }
+
+    fn get_token_out(&self, token_in: H160) -> H160 {
+        if self.token_a == token_in {
+            self.token_b
+        } else {
+            self.token_a
+        }
+    }
+
+    fn max_output(&self, token_out: H160) -> U256 {
+        if self.token_a == token_out {
+            U256::from(self.reserve_0)
+        } else if self.token_b == token_out {
+            U256::from(self.reserve_1)
+        } else {
+            U256::zero()
+        }
+    }
+}
+
+//Computes a * b / denominator through a 512 bit product so that caller supplied amounts cannot overflow, saturating at
+//U256::MAX when the quotient does not fit
+fn mul_div(a: U256, b: U256, denominator: U256) -> U256 {
+    U256::try_from(a.full_mul(b) / U512::from(denominator)).unwrap_or(U256::MAX)
+}
+
+impl UniswapV2Pool {
+    //Simulates a swap without checking for unreliable reserves, opting in to swaps on pools with rebasing tokens
+    pub fn simulate_swap_unchecked(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if self.token_a == token_in {
             Ok(self.get_amount_out(
                 amount_in,
@@ -122,11 +204,16 @@ impl AutomatedMarketMaker for UniswapV2Pool {
         }
     }
 
-    fn simulate_swap_mut(
+    //Simulates a swap and updates the reserves without checking for unreliable reserves
+    pub fn simulate_swap_mut_unchecked(
         &mut self,
         token_in: H160,
         amount_in: U256,
     ) -> Result<U256, SwapSimulationError> {
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if self.token_a == token_in {
             let amount_out = self.get_amount_out(
                 amount_in,
@@ -152,16 +239,6 @@ impl AutomatedMarketMaker for UniswapV2Pool {
         }
     }
 
-    fn get_token_out(&self, token_in: H160) -> H160 {
-        if self.token_a == token_in {
-            self.token_b
-        } else {
-            self.token_a
-        }
-    }
-}
-
-impl UniswapV2Pool {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         address: H160,
@@ -182,6 +259,11 @@ impl UniswapV2Pool {
             reserve_0,
             reserve_1,
             fee,
+            unreliable_reserves: false,
+            factory: H160::zero(),
+            tags: vec![],
+            last_synced_block: 0,
+            total_supply: U256::zero(),
         }
     }
 
@@ -200,9 +282,15 @@ impl UniswapV2Pool {
             reserve_0: 0,
             reserve_1: 0,
             fee,
+            unreliable_reserves: false,
+            factory: H160::zero(),
+            tags: vec![],
+            last_synced_block: 0,
+            total_supply: U256::zero(),
         };
 
         pool.populate_data(None, middleware.clone()).await?;
+        pool.populate_total_supply(None, middleware.clone()).await?;
 
         if !pool.data_is_populated() {
             return Err(AMMError::PoolDataError);
@@ -240,6 +328,11 @@ impl UniswapV2Pool {
                 reserve_0: 0,
                 reserve_1: 0,
                 fee: 0,
+                unreliable_reserves: false,
+                factory: H160::zero(),
+                tags: vec![],
+                last_synced_block: 0,
+                total_supply: U256::zero(),
             })
         } else {
             Err(EventLogError::InvalidEventSignature)?
@@ -250,13 +343,110 @@ impl UniswapV2Pool {
         self.fee
     }
 
+    //Estimates the yearly fee APR of the liquidity providers as volume_24h * fee / tvl * 365. Swaps are not indexed, so the
+    //24h volume is supplied by the caller, denominated in the same unit as the tvl. Assumes the volume stays constant over
+    //the year and ignores compounding and the protocol fee, returning 0 for a pool without tvl
+    pub fn estimate_fee_apr(&self, volume_24h: U256, tvl: U256) -> f64 {
+        if tvl.is_zero() {
+            return 0.0;
+        }
+
+        //The fee is in units of 1e-5, ie. 300 is 0.3%
+        u256_to_f64(volume_24h) * (self.fee as f64 / 100000.0) / u256_to_f64(tvl) * 365.0
+    }
+
+    //Quotes adding liquidity like the router, returning the LP tokens minted and the amounts of token a and b deposited.
+    //The desired amounts are deposited at the current reserve ratio, using all of one amount and only what the ratio
+    //requires of the other. The first deposit mints sqrt(amount_a * amount_b) less the locked minimum liquidity
+    pub fn quote_add_liquidity(
+        &self,
+        amount_a: U256,
+        amount_b: U256,
+        total_supply: U256,
+    ) -> (U256, U256, U256) {
+        let (reserve_0, reserve_1) = (U256::from(self.reserve_0), U256::from(self.reserve_1));
+
+        if total_supply.is_zero() || reserve_0.is_zero() || reserve_1.is_zero() {
+            let lp_minted = U256::try_from(amount_a.full_mul(amount_b).integer_sqrt())
+                .unwrap_or(U256::MAX)
+                .saturating_sub(U256::from(MINIMUM_LIQUIDITY));
+
+            return (lp_minted, amount_a, amount_b);
+        }
+
+        let amount_b_optimal = mul_div(amount_a, reserve_1, reserve_0);
+        let (amount_a, amount_b) = if amount_b_optimal <= amount_b {
+            (amount_a, amount_b_optimal)
+        } else {
+            (mul_div(amount_b, reserve_0, reserve_1), amount_b)
+        };
+
+        let lp_minted = mul_div(amount_a, total_supply, reserve_0).min(mul_div(
+            amount_b,
+            total_supply,
+            reserve_1,
+        ));
+
+        (lp_minted, amount_a, amount_b)
+    }
+
+    //Quotes burning LP tokens, returning the amounts of token a and b withdrawn, ie. the pro rata share of the reserves
+    pub fn quote_remove_liquidity(&self, lp_amount: U256, total_supply: U256) -> (U256, U256) {
+        if total_supply.is_zero() {
+            return (U256::zero(), U256::zero());
+        }
+
+        (
+            mul_div(lp_amount, U256::from(self.reserve_0), total_supply),
+            mul_div(lp_amount, U256::from(self.reserve_1), total_supply),
+        )
+    }
+
+    //Returns the constant product k = reserve_0 * reserve_1
+    pub fn invariant_k(&self) -> U256 {
+        U256::from(self.reserve_0) * U256::from(self.reserve_1)
+    }
+
+    //Flags the pool as having unreliable reserves if either token is a known rebasing token
+    pub fn check_rebasing_tokens(&mut self) {
+        self.unreliable_reserves = KNOWN_REBASING_TOKENS.contains(&self.token_a)
+            || KNOWN_REBASING_TOKENS.contains(&self.token_b);
+    }
+
     pub fn data_is_populated(&self) -> bool {
         !(self.token_a.is_zero()
             || self.token_b.is_zero()
+            || self.token_a == self.token_b
             || self.reserve_0 == 0
             || self.reserve_1 == 0)
     }
 
+    pub async fn get_total_supply<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<U256, AMMError<M>> {
+        let v2_pair = IUniswapV2Pair::new(self.address, middleware);
+        Ok(v2_pair.total_supply().call().await?)
+    }
+
+    //Reads the total supply of the LP token. The batch requests do not return it, so pools populated through the batch
+    //requests need to call this before quoting liquidity with `total_supply`
+    pub async fn populate_total_supply<M: Middleware>(
+        &mut self,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let v2_pair = IUniswapV2Pair::new(self.address, middleware);
+        let mut total_supply = v2_pair.total_supply();
+        if let Some(block_number) = block_number {
+            total_supply = total_supply.block(block_number);
+        }
+
+        self.total_supply = total_supply.call().await?;
+
+        Ok(())
+    }
+
     pub async fn get_reserves<M: Middleware>(
         &self,
         middleware: Arc<M>,
@@ -272,6 +462,39 @@ impl UniswapV2Pool {
         Ok((reserve_0, reserve_1))
     }
 
+    //Gets the reserves at each of the given blocks as (block, reserve_0, reserve_1) sorted by block.
+    //Reading historical blocks requires an archive node, blocks where the call fails are skipped
+    pub async fn reserves_at_blocks<M: 'static + Middleware>(
+        &self,
+        blocks: &[u64],
+        middleware: Arc<M>,
+    ) -> Vec<(u64, u128, u128)> {
+        let mut reserves = vec![];
+        let mut handles = JoinSet::new();
+
+        for block in blocks.iter().copied() {
+            let v2_pair = IUniswapV2Pair::new(self.address, middleware.clone());
+            handles.spawn(async move {
+                let (reserve_0, reserve_1, _) =
+                    v2_pair.get_reserves().block(block).call().await.ok()?;
+                Some((block, reserve_0, reserve_1))
+            });
+
+            if handles.len() == TASK_LIMIT {
+                while let Some(result) = handles.join_next().await {
+                    reserves.extend(result.ok().flatten());
+                }
+            }
+        }
+
+        while let Some(result) = handles.join_next().await {
+            reserves.extend(result.ok().flatten());
+        }
+
+        reserves.sort_by_key(|(block, _, _)| *block);
+        reserves
+    }
+
     pub async fn get_token_decimals<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
@@ -320,18 +543,21 @@ impl UniswapV2Pool {
     }
 
     pub fn calculate_price_64_x_64(&self, base_token: H160) -> Result<u128, ArithmeticError> {
-        let decimal_shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
+        //Widened so that tokens with more than 127 decimals do not wrap
+        let decimal_shift = self.token_a_decimals as i16 - self.token_b_decimals as i16;
 
         let (r_0, r_1) = if decimal_shift < 0 {
             (
-                U256::from(self.reserve_0)
-                    * U256::from(10u128.pow(decimal_shift.unsigned_abs() as u32)),
+                scale_by_decimal_shift(
+                    U256::from(self.reserve_0),
+                    decimal_shift.unsigned_abs() as u32,
+                )?,
                 U256::from(self.reserve_1),
             )
         } else {
             (
                 U256::from(self.reserve_0),
-                U256::from(self.reserve_1) * U256::from(10u128.pow(decimal_shift as u32)),
+                scale_by_decimal_shift(U256::from(self.reserve_1), decimal_shift as u32)?,
             )
         };
 
@@ -348,16 +574,89 @@ impl UniswapV2Pool {
         }
     }
 
-    pub fn get_amount_out(&self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
-        if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
-            return U256::zero();
+    //Returns the reserves in whole tokens, adjusted by the stored decimals of each token
+    pub fn human_readable_reserves(&self) -> (f64, f64) {
+        (
+            self.reserve_0 as f64 / 10_f64.powi(self.token_a_decimals as i32),
+            self.reserve_1 as f64 / 10_f64.powi(self.token_b_decimals as i32),
+        )
+    }
+
+    //Simulates a swap at the override fee instead of the pool fee, leaving the pool untouched. The fee is in the same
+    //units as the pool fee, where 300 is a 0.3% fee
+    pub fn simulate_swap_with_fee(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        fee_override: u32,
+    ) -> Result<U256, SwapSimulationError> {
+        if self.unreliable_reserves {
+            return Err(SwapSimulationError::UnreliableReserves(self.address));
+        }
+
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
+        let (reserve_in, reserve_out) = if self.token_a == token_in {
+            (U256::from(self.reserve_0), U256::from(self.reserve_1))
+        } else {
+            (U256::from(self.reserve_1), U256::from(self.reserve_0))
+        };
+
+        Ok(get_amount_out_with_fee(
+            amount_in,
+            reserve_in,
+            reserve_out,
+            fee_override,
+        ))
+    }
+
+    //Returns the price impact of the swap, ie. the relative shortfall of the realized price against the spot price of the
+    //reserves, including the pool fee
+    pub fn price_impact(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<f64, SwapSimulationError> {
+        let amount_out = self.simulate_swap(token_in, amount_in)?;
+
+        let (reserve_in, reserve_out) = if self.token_a == token_in {
+            (self.reserve_0, self.reserve_1)
+        } else {
+            (self.reserve_1, self.reserve_0)
+        };
+        if reserve_in == 0 || reserve_out == 0 || amount_in.is_zero() {
+            return Ok(1.0);
+        }
+
+        let realized_price = u256_to_f64(amount_out) / u256_to_f64(amount_in);
+        let spot_price = reserve_out as f64 / reserve_in as f64;
+
+        Ok(1.0 - realized_price / spot_price)
+    }
+
+    //Returns true if the swap keeps the price impact under max_impact. The constant product impact before fees,
+    //amount_in / (reserve_in + amount_in), bounds the impact from below, so shallow pools are rejected without simulating
+    pub fn can_fill(&self, token_in: H160, amount_in: U256, max_impact: f64) -> bool {
+        let reserve_in = if self.token_a == token_in {
+            self.reserve_0
+        } else if self.token_b == token_in {
+            self.reserve_1
+        } else {
+            return false;
+        };
+
+        let amount_in_f64 = u256_to_f64(amount_in);
+        if reserve_in == 0 || amount_in_f64 / (reserve_in as f64 + amount_in_f64) >= max_impact {
+            return false;
         }
-        let fee = (10000 - (self.fee / 10)) / 10; //Fee of 300 => (10,000 - 30) / 10  = 997
-        let amount_in_with_fee = amount_in * U256::from(fee);
-        let numerator = amount_in_with_fee * reserve_out;
-        let denominator = reserve_in * U256::from(1000) + amount_in_with_fee;
 
-        numerator / denominator
+        matches!(self.price_impact(token_in, amount_in), Ok(impact) if impact < max_impact)
+    }
+
+    pub fn get_amount_out(&self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
+        get_amount_out_with_fee(amount_in, reserve_in, reserve_out, self.fee)
     }
 
     pub fn swap_calldata(
@@ -486,6 +785,50 @@ pub fn div_uu(x: U256, y: U256) -> Result<u128, ArithmeticError> {
     }
 }
 
+//Checks that k did not decrease between the two states of the pool, since k should only grow from fees.
+//A decrease points to rebasing drift, a donation attack or incorrectly applied state
+pub fn check_k_monotonic(before: &UniswapV2Pool, after: &UniswapV2Pool) -> bool {
+    after.invariant_k() >= before.invariant_k()
+}
+
+pub fn get_amount_out_with_fee(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> U256 {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return U256::zero();
+    }
+    //The fee is applied at its full resolution, so 300 gives the 997/1000 of uniswap and 250 the 9975/10000 of PancakeSwap
+    let (fee_numerator, fee_denominator) = fee_fraction(fee);
+    let amount_in_with_fee = amount_in * U256::from(fee_numerator);
+    let numerator = amount_in_with_fee * reserve_out;
+    let denominator = reserve_in * U256::from(fee_denominator) + amount_in_with_fee;
+
+    numerator / denominator
+}
+
+//Returns the share of the amount in that is swapped after the fee as a reduced fraction, eg. 997/1000 for a fee of 300
+pub fn fee_fraction(fee: u32) -> (u32, u32) {
+    let numerator = FEE_DENOMINATOR.saturating_sub(fee);
+
+    let (mut a, mut b) = (numerator, FEE_DENOMINATOR);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    (numerator / a, FEE_DENOMINATOR / a)
+}
+
+//Multiplies the value by 10^decimal_shift, returning an error instead of overflowing for large decimal differences
+pub fn scale_by_decimal_shift(value: U256, decimal_shift: u32) -> Result<U256, ArithmeticError> {
+    U256::from(10)
+        .checked_pow(U256::from(decimal_shift))
+        .and_then(|scale| value.checked_mul(scale))
+        .ok_or(ArithmeticError::DecimalScalingOverflow)
+}
+
 //Converts a Q64 fixed point to a Q16 fixed point -> f64
 pub fn q64_to_f64(x: u128) -> f64 {
     BigFloat::from(x)
@@ -499,12 +842,41 @@ mod tests {
 
     use ethers::{
         providers::{Http, Provider},
-        types::{H160, U256},
+        types::{H160, U256, U512},
+    };
+
+    use crate::{
+        amm::AutomatedMarketMaker,
+        errors::{ArithmeticError, SwapSimulationError},
     };
 
-    use crate::amm::AutomatedMarketMaker;
+    use super::{check_k_monotonic, fee_fraction, UniswapV2Pool};
+
+    #[test]
+    fn test_check_k_monotonic() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let before = UniswapV2Pool {
+            token_a,
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000000000000000000,
+            reserve_1: 1000000000000000000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        //The swap fee stays in the pool, so k grows
+        let mut after_swap = before.clone();
+        after_swap.simulate_swap_mut(token_a, U256::from(10000000000000000000_u128))?;
+        assert!(after_swap.invariant_k() > before.invariant_k());
+        assert!(check_k_monotonic(&before, &after_swap));
 
-    use super::UniswapV2Pool;
+        //A negative rebase lowers the reserves without a swap, so k drops
+        let mut after_rebase = before.clone();
+        after_rebase.reserve_0 = 900000000000000000000;
+        assert!(!check_k_monotonic(&before, &after_rebase));
+
+        Ok(())
+    }
 
     #[test]
     fn test_swap_calldata() -> eyre::Result<()> {
@@ -581,6 +953,315 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rebasing_token_flags_unreliable_reserves() -> eyre::Result<()> {
+        let steth = H160::from_str("0xae7ab96520DE3A18E5e111B5EaAb095312D7fE84")?;
+        let weth = H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")?;
+
+        let mut pool = UniswapV2Pool {
+            token_a: steth,
+            token_b: weth,
+            reserve_0: 1000000000000000000000,
+            reserve_1: 1000000000000000000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        pool.check_rebasing_tokens();
+        assert!(pool.unreliable_reserves);
+
+        let amount_in = U256::from(1000000000000000000_u128);
+        assert!(pool.simulate_swap(steth, amount_in).is_err());
+        assert!(!pool.simulate_swap_unchecked(steth, amount_in)?.is_zero());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reserves_at_blocks() -> eyre::Result<()> {
+        let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint)?);
+
+        let pool = UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            ..Default::default()
+        };
+
+        let blocks = [17000002, 17000000, 17000001];
+        let reserves = pool.reserves_at_blocks(&blocks, middleware).await;
+
+        assert_eq!(reserves.len(), 3);
+        assert_eq!(reserves[0].0, 17000000);
+        assert_eq!(reserves[2].0, 17000002);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_of() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let pool = UniswapV2Pool {
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1000000000000000000000,
+            reserve_1: 4000000000000000000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.price_of(token_a)?, 4.0);
+        assert_eq!(pool.price_of(token_b)?, 0.25);
+
+        let foreign_token = H160::from_low_u64_be(3);
+        assert!(matches!(
+            pool.price_of(foreign_token),
+            Err(SwapSimulationError::TokenNotInPool(token)) if token == foreign_token
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_output() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000,
+            reserve_1: 2000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.max_output(pool.token_a), U256::from(1000000));
+        assert_eq!(pool.max_output(pool.token_b), U256::from(2000000));
+        assert_eq!(pool.max_output(H160::from_low_u64_be(3)), U256::zero());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_curve() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000000000000000000,
+            reserve_1: 2000000000000000000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        let max_amount = U256::from(100000000000000000000_u128);
+        let curve = pool.price_curve(pool.token_a, max_amount, 10);
+
+        assert_eq!(curve.len(), 10);
+        assert_eq!(curve[0].0, max_amount / 10);
+        assert_eq!(curve[9].0, max_amount);
+
+        //Larger trades move further along the curve, so every sample realizes a worse price than the previous one
+        for window in curve.windows(2) {
+            assert!(window[1].0 > window[0].0);
+            assert!(window[1].1 < window[0].1);
+        }
+        assert!(curve[0].1 < 2.0);
+
+        assert!(pool.price_curve(pool.token_a, max_amount, 0).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_fee_apr() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            fee: 300,
+            ..Default::default()
+        };
+
+        //1m of daily volume at 0.3% earns 3k a day on 10m of tvl
+        let apr = pool.estimate_fee_apr(U256::from(1000000), U256::from(10000000));
+        assert!((apr - 0.1095).abs() < 1e-12);
+
+        assert_eq!(
+            pool.estimate_fee_apr(U256::from(1000000), U256::zero()),
+            0.0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_liquidity() -> eyre::Result<()> {
+        let e18 = U256::exp10(18);
+
+        //The first deposit mints sqrt(1e18 * 4e18) less the locked minimum liquidity
+        let empty_pool = UniswapV2Pool::default();
+        let (lp_minted, amount_a, amount_b) =
+            empty_pool.quote_add_liquidity(e18, e18 * 4, U256::zero());
+        assert_eq!(lp_minted, U256::from(1999999999999999000_u128));
+        assert_eq!((amount_a, amount_b), (e18, e18 * 4));
+
+        let pool = UniswapV2Pool {
+            reserve_0: 1000000000000000000000,
+            reserve_1: 2000000000000000000000,
+            total_supply: U256::from(1414213562373095048801_u128),
+            ..Default::default()
+        };
+
+        //Like the router only 20 of the 25 token b are deposited at the 1:2 ratio, minting 1% of the supply
+        let (lp_minted, amount_a, amount_b) =
+            pool.quote_add_liquidity(e18 * 10, e18 * 25, pool.total_supply);
+        assert_eq!(lp_minted, U256::from(14142135623730950488_u128));
+        assert_eq!((amount_a, amount_b), (e18 * 10, e18 * 20));
+
+        //And 10 of the 25 token a against 20 token b
+        let (_, amount_a, amount_b) =
+            pool.quote_add_liquidity(e18 * 25, e18 * 20, pool.total_supply);
+        assert_eq!((amount_a, amount_b), (e18 * 10, e18 * 20));
+
+        //Burning the minted LP tokens returns the deposit less rounding
+        let (amount_a, amount_b) = pool.quote_remove_liquidity(lp_minted, pool.total_supply);
+        assert_eq!(amount_a, U256::from(9999999999999999999_u128));
+        assert_eq!(amount_b, U256::from(19999999999999999999_u128));
+
+        assert_eq!(
+            pool.quote_remove_liquidity(lp_minted, U256::zero()),
+            (U256::zero(), U256::zero())
+        );
+
+        //Amounts near U256::MAX are quoted through 512 bit products instead of overflowing, saturating where the
+        //quote itself does not fit
+        let (lp_minted, amount_a, amount_b) =
+            pool.quote_add_liquidity(U256::MAX, U256::MAX, pool.total_supply);
+        assert_eq!((amount_a, amount_b), (U256::MAX, U256::MAX));
+        assert_eq!(
+            U512::from(lp_minted),
+            U256::MAX.full_mul(pool.total_supply) / U512::from(pool.reserve_1)
+        );
+
+        let (amount_a, amount_b) = pool.quote_remove_liquidity(U256::MAX, pool.total_supply);
+        assert_eq!(
+            U512::from(amount_a),
+            U256::MAX.full_mul(U256::from(pool.reserve_0)) / U512::from(pool.total_supply)
+        );
+        assert_eq!(amount_b, U256::MAX);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_can_fill() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000000000,
+            reserve_1: 2000000000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        //1% of the reserves in costs ~0.99% to the curve and 0.3% to the fee
+        let amount_in = U256::from(10000000000_u64);
+        let impact = pool.price_impact(pool.token_a, amount_in)?;
+        assert!(impact > 0.0128 && impact < 0.0129);
+
+        assert!(pool.can_fill(pool.token_a, amount_in, 0.013));
+        assert!(!pool.can_fill(pool.token_a, amount_in, 0.0128));
+        //Rejected by the constant product bound alone
+        assert!(!pool.can_fill(pool.token_a, amount_in, 0.005));
+
+        //The price impact only depends on the share of the input reserve
+        assert!(pool.can_fill(pool.token_b, U256::from(20000000000_u64), 0.013));
+        assert!(!pool.can_fill(pool.token_b, U256::from(20000000000_u64), 0.0128));
+
+        assert!(!pool.can_fill(H160::from_low_u64_be(3), amount_in, 1.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_pancakeswap_swap() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 100000000000000000000,
+            reserve_1: 200000000000000000000,
+            //PancakeSwap V2 charges 0.25%
+            fee: 250,
+            ..Default::default()
+        };
+
+        //The router quotes amount_in * 9975 * reserve_out / (reserve_in * 10000 + amount_in * 9975)
+        let amount_out = pool.simulate_swap(pool.token_a, U256::exp10(18))?;
+        assert_eq!(amount_out, U256::from(1975296418228173964_u128));
+
+        //Uniswap pools keep the 997/1000 quote
+        assert_eq!(fee_fraction(300), (997, 1000));
+        assert_eq!(fee_fraction(250), (399, 400));
+        assert_eq!(
+            pool.simulate_swap_with_fee(pool.token_a, U256::exp10(18), 300)?,
+            U256::from(1974316068794122597_u128)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_with_fee() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000,
+            reserve_1: 1000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        let amount_in = U256::from(1000);
+        assert_eq!(
+            pool.simulate_swap_with_fee(pool.token_a, amount_in, 300)?,
+            pool.simulate_swap(pool.token_a, amount_in)?
+        );
+        assert_eq!(
+            pool.simulate_swap_with_fee(pool.token_a, amount_in, 300)?,
+            U256::from(996)
+        );
+        assert_eq!(
+            pool.simulate_swap_with_fee(pool.token_a, amount_in, 1000)?,
+            U256::from(989)
+        );
+        assert_eq!(pool.fee, 300);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_token_not_in_pool() -> eyre::Result<()> {
+        let mut pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000000,
+            reserve_1: 1000000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        let foreign_token = H160::from_low_u64_be(3);
+        let amount_in = U256::from(1000);
+
+        assert!(matches!(
+            pool.simulate_swap(foreign_token, amount_in),
+            Err(SwapSimulationError::TokenNotInPool(token)) if token == foreign_token
+        ));
+        assert!(matches!(
+            pool.simulate_swap_mut(foreign_token, amount_in),
+            Err(SwapSimulationError::TokenNotInPool(token)) if token == foreign_token
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_calculate_price_edge_case() -> eyre::Result<()> {
         let token_a = H160::from_str("0x0d500b1d8e8ef31e21c99d1db9a6444d3adf1270")?;
@@ -594,6 +1275,11 @@ mod tests {
             reserve_0: 23595096345912178729927,
             reserve_1: 154664232014390554564,
             fee: 300,
+            unreliable_reserves: false,
+            factory: H160::zero(),
+            tags: vec![],
+            last_synced_block: 0,
+            total_supply: U256::zero(),
         };
 
         assert!(x.calculate_price(token_a)? != 0.0);
@@ -601,6 +1287,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_calculate_price_zero_decimals() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let pool = UniswapV2Pool {
+            token_a,
+            token_a_decimals: 0,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1000,
+            reserve_1: 2000 * 10_u128.pow(18),
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.calculate_price(token_a)?, 2.0);
+        assert_eq!(pool.calculate_price(token_b)?, 0.5);
+        assert_eq!(pool.human_readable_reserves(), (1000.0, 2000.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calculate_price_high_decimals() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let mut pool = UniswapV2Pool {
+            token_a,
+            token_a_decimals: 6,
+            token_b,
+            token_b_decimals: 30,
+            reserve_0: 1000 * 10_u128.pow(6),
+            reserve_1: 500 * 10_u128.pow(30),
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.calculate_price(token_a)?, 0.5);
+        assert_eq!(pool.calculate_price(token_b)?, 2.0);
+        assert_eq!(pool.human_readable_reserves(), (1000.0, 500.0));
+
+        //A decimal difference this large cannot be normalized within a U256
+        pool.token_a_decimals = 0;
+        pool.token_b_decimals = 200;
+        assert!(matches!(
+            pool.calculate_price(token_a),
+            Err(ArithmeticError::DecimalScalingOverflow)
+        ));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_calculate_price() -> eyre::Result<()> {
         let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
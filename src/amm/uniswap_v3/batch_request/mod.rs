@@ -7,8 +7,12 @@ use ethers::{
     types::{Bytes, I256, U256, U64},
 };
 
+use tokio::task::JoinSet;
+
 use crate::{
-    amm::{AutomatedMarketMaker, AMM},
+    amm::{
+        factory::TASK_LIMIT, log_batch_payload, with_request_timeout, AutomatedMarketMaker, AMM,
+    },
     constants::CONSTANT_RETRY,
     errors::AMMError,
 };
@@ -27,6 +31,25 @@ abigen!(
 
 );
 
+//Max number of pools populated by a single pool data batch request on mainnet, the default of `set_v3_max_batch_size`
+pub const MAX_POOL_DATA_BATCH_SIZE: usize = 76;
+
+//Pool data batch sizes that fit the gas of a single eth_call on each chain. The batch contract is deployed and run within
+//the call, so the size scales with the block gas limit that most endpoints cap eth_call to:
+//  - Ethereum (1), Optimism (10), Polygon (137) and Base (8453): 76, tuned for the 30M gas limit of mainnet
+//  - BNB Chain (56), Fantom (250) and Arbitrum (42161): 127, their calls are allowed well over 30M gas
+//  - Avalanche C-Chain (43114): 38, half the mainnet size for its 15M gas limit
+//Other chains use the mainnet size. Endpoints with a lower gas cap than their chain should set a smaller size directly
+pub fn max_pool_data_batch_size_for_chain(chain_id: u64) -> usize {
+    match chain_id {
+        56 | 250 | 42161 => 127,
+        43114 => 38,
+        _ => MAX_POOL_DATA_BATCH_SIZE,
+    }
+}
+//Tokens, decimals, liquidity, slot0 values, tick spacing, fee and liquidity net, ten words per pool
+pub const POOL_DATA_RESPONSE_BYTES: usize = 10 * 32;
+
 fn populate_pool_data_from_tokens(
     mut pool: UniswapV3Pool,
     tokens: Vec<Token>,
@@ -54,11 +77,12 @@ pub async fn get_v3_pool_data_batch_request<M: Middleware>(
     let deployer = IGetUniswapV3PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
 
     let return_data: Bytes = if let Some(block_number) = block_number {
-        deployer.block(block_number).call_raw().await?
+        with_request_timeout(deployer.block(block_number).call_raw()).await?
     } else {
-        deployer.call_raw().await?
+        with_request_timeout(deployer.call_raw()).await?
     };
 
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // token a
@@ -116,11 +140,12 @@ pub async fn get_uniswap_v3_tick_data_batch_request<M: Middleware>(
     let deployer = IGetUniswapV3TickDataBatchRequest::deploy(middleware.clone(), constructor_args)?;
 
     let return_data: Bytes = if let Some(block_number) = block_number {
-        deployer.block(block_number).call_raw().await?
+        with_request_timeout(deployer.block(block_number).call_raw()).await?
     } else {
-        deployer.call_raw().await?
+        with_request_timeout(deployer.call_raw()).await?
     };
 
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[
             ParamType::Array(Box::new(ParamType::Tuple(vec![
@@ -178,6 +203,82 @@ pub async fn get_uniswap_v3_tick_data_batch_request<M: Middleware>(
     Ok((tick_data, U64::from(block_number.as_u64())))
 }
 
+//Loads the initialized ticks within `tick_window` steps on each side of the current tick for each v3 pool in the batch,
+//and within `tick_window` words for each Algebra pool, see `AlgebraPool::populate_tick_window`
+pub async fn get_amm_tick_data_batch_request<M: Middleware>(
+    amms: &mut [AMM],
+    tick_window: u16,
+    block_number: Option<u64>,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    for amm in amms.iter_mut() {
+        match amm {
+            AMM::UniswapV3Pool(pool) => {
+                pool.populate_tick_window(tick_window, block_number, middleware.clone())
+                    .await?
+            }
+            AMM::AlgebraPool(pool) => {
+                pool.populate_tick_window(tick_window, block_number, middleware.clone())
+                    .await?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+//Loads the tick windows of the v3 and Algebra pools like `get_amm_tick_data_batch_request`, running up to TASK_LIMIT
+//pools concurrently instead of one pool at a time. Other amms are left untouched
+pub async fn populate_tick_data_batch<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    tick_window: u16,
+    block_number: Option<u64>,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    let mut handles = JoinSet::new();
+    let mut populated_pools = vec![];
+
+    for (idx, amm) in amms.iter().enumerate() {
+        if !matches!(amm, AMM::UniswapV3Pool(_) | AMM::AlgebraPool(_)) {
+            continue;
+        }
+
+        let mut amm = amm.clone();
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            match &mut amm {
+                AMM::UniswapV3Pool(pool) => {
+                    pool.populate_tick_window(tick_window, block_number, middleware)
+                        .await?
+                }
+                AMM::AlgebraPool(pool) => {
+                    pool.populate_tick_window(tick_window, block_number, middleware)
+                        .await?
+                }
+                _ => {}
+            }
+            Ok::<_, AMMError<M>>((idx, amm))
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(result) = handles.join_next().await {
+                populated_pools.push(result??);
+            }
+        }
+    }
+
+    while let Some(result) = handles.join_next().await {
+        populated_pools.push(result??);
+    }
+
+    for (idx, amm) in populated_pools {
+        amms[idx] = amm;
+    }
+
+    Ok(())
+}
+
 pub async fn sync_v3_pool_batch_request<M: Middleware>(
     pool: &mut UniswapV3Pool,
     middleware: Arc<M>,
@@ -186,7 +287,8 @@ pub async fn sync_v3_pool_batch_request<M: Middleware>(
 
     let deployer = ISyncUniswapV3PoolBatchRequest::deploy(middleware.clone(), constructor_args)?;
 
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = with_request_timeout(deployer.call_raw()).await?;
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Tuple(vec![
             ParamType::Uint(128), // liquidity
@@ -231,6 +333,7 @@ pub async fn sync_v3_pool_batch_request<M: Middleware>(
     Ok(())
 }
 
+#[tracing::instrument(skip(amms, middleware), fields(amms = amms.len()), err)]
 pub async fn get_amm_data_batch_request<M: Middleware>(
     amms: &mut [AMM],
     block_number: u64,
@@ -246,9 +349,13 @@ pub async fn get_amm_data_batch_request<M: Middleware>(
     let deployer = IGetUniswapV3PoolDataBatchRequest::deploy(middleware.clone(), constructor_args)?
         .block(block_number);
 
-    let call = || async { deployer.call_raw().await };
-    let return_data: Bytes = call.retry(&*CONSTANT_RETRY).await?;
+    let call = || async { with_request_timeout(deployer.call_raw()).await };
+    let return_data: Bytes = call
+        .retry(&*CONSTANT_RETRY)
+        .notify(|err, delay| tracing::warn!(%err, ?delay, "Retrying batch request"))
+        .await?;
 
+    log_batch_payload(&return_data);
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Tuple(vec![
             ParamType::Address,   // token a
@@ -17,10 +17,11 @@ use tokio::task::JoinSet;
 
 use crate::{
     amm::{
+        capped_batch_size,
         factory::{AutomatedMarketMakerFactory, TASK_LIMIT, TASK_LIMIT_LOGS},
         AutomatedMarketMaker, AMM,
     },
-    constants::{CONSTANT_RETRY, MULTIPROGRESS, SYNC_BAR_STYLE},
+    constants::{v3_max_batch_size, CONSTANT_RETRY, MULTIPROGRESS, SYNC_BAR_STYLE},
     errors::{AMMError, EventLogError},
 };
 
@@ -105,7 +106,8 @@ impl AutomatedMarketMakerFactory for UniswapV3Factory {
         middleware: Arc<M>,
     ) -> Result<(), AMMError<M>> {
         if let Some(block_number) = block_number {
-            let step = 127; //Max batch size for call
+            let step =
+                capped_batch_size(v3_max_batch_size(), batch_request::POOL_DATA_RESPONSE_BYTES);
             for amm_chunk in amms.chunks_mut(step) {
                 batch_request::get_amm_data_batch_request(
                     amm_chunk,
@@ -122,6 +124,7 @@ impl AutomatedMarketMakerFactory for UniswapV3Factory {
     }
 
     fn new_empty_amm_from_log(log: Log) -> Result<AMM, ethers::abi::Error> {
+        let factory = log.address;
         let pool_created_event = PoolCreatedFilter::decode_log(&RawLog::from(log))?;
 
         Ok(AMM::UniswapV3Pool(UniswapV3Pool {
@@ -137,6 +140,11 @@ impl AutomatedMarketMakerFactory for UniswapV3Factory {
             tick: 0,
             tick_bitmap: HashMap::new(),
             ticks: HashMap::new(),
+            observation_cardinality: 0,
+            observation_cardinality_next: 0,
+            factory,
+            tags: vec![],
+            last_synced_block: 0,
         }))
     }
 }
@@ -199,6 +207,9 @@ impl UniswapV3Factory {
                 };
                 let logs = call
                     .retry(&*CONSTANT_RETRY)
+                    .notify(|err, delay| {
+                        tracing::warn!(%err, ?delay, from_block, target_block, "Retrying get logs")
+                    })
                     .await
                     .map_err(AMMError::MiddlewareError)?;
 
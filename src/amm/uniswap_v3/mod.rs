@@ -8,15 +8,16 @@ use std::{
 };
 
 use crate::{
-    amm::AutomatedMarketMaker,
+    amm::{u256_to_f64, AutomatedMarketMaker},
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+    serde_helpers::{u128_string, u256_string},
 };
 use async_trait::async_trait;
 use ethers::{
     abi::{ethabi::Bytes, RawLog, Token},
     prelude::{AbiError, EthEvent},
     providers::Middleware,
-    types::{BlockNumber, Filter, Log, H160, H256, I256, U256, U64},
+    types::{BlockNumber, Filter, Log, H160, H256, I256, U256, U512, U64},
 };
 use num_bigfloat::BigFloat;
 use serde::{Deserialize, Serialize};
@@ -50,6 +51,7 @@ abigen!(
         event Swap(address indexed sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)
         event Burn(address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
         event Mint(address sender, address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
+        event Flash(address indexed sender, address indexed recipient, uint256 amount0, uint256 amount1, uint256 paid0, uint256 paid1)
     ]"#;
 
     IErc20,
@@ -83,6 +85,14 @@ pub const MINT_EVENT_SIGNATURE_BYTES: [u8; 32] = [
     133, 72, 143, 8, 83, 174, 22, 35, 157, 11, 222,
 ];
 
+//Flash event signature
+pub const FLASH_EVENT_SIGNATURE: H256 = H256([
+    189, 189, 183, 29, 120, 96, 55, 107, 165, 43, 37, 165, 2, 139, 238, 162, 53, 129, 54, 74, 64,
+    82, 47, 107, 207, 184, 107, 177, 242, 220, 166, 51,
+]);
+
+//Average mainnet block time used to estimate the time covered by the oracle observations
+pub const AVERAGE_BLOCK_TIME_SECONDS: u64 = 12;
 pub const U256_TWO: U256 = U256([2, 0, 0, 0]);
 pub const Q128: U256 = U256([0, 0, 1, 0]);
 pub const Q224: U256 = U256([0, 0, 0, 4294967296]);
@@ -93,15 +103,54 @@ pub struct UniswapV3Pool {
     pub token_a_decimals: u8,
     pub token_b: H160,
     pub token_b_decimals: u8,
+    #[serde(with = "u128_string")]
     pub liquidity: u128,
+    #[serde(with = "u256_string")]
     pub sqrt_price: U256,
     pub fee: u32,
     pub tick: i32,
     pub tick_spacing: i32,
+    //The tick maps are transient and not serialized with the pool to keep checkpoints compact, while tick maps stored by
+    //older checkpoints are still read. Syncing from a checkpoint re-derives them around the current tick, otherwise they
+    //are loaded with `populate_tick_data` or `populate_tick_window`, or persisted with `checkpoint::construct_checkpoint_with_ticks`
+    #[serde(default, skip_serializing)]
+    pub tick_bitmap: HashMap<i16, U256>,
+    #[serde(default, skip_serializing)]
+    pub ticks: HashMap<i32, Info>,
+    //Number of oracle observations stored by the pool and the number it will grow to on the next write
+    #[serde(default)]
+    pub observation_cardinality: u16,
+    #[serde(default)]
+    pub observation_cardinality_next: u16,
+    //Factory the pool was discovered from, zero if the pool was not discovered through a factory
+    #[serde(default)]
+    pub factory: H160,
+    //User annotations, preserved across resyncs and untouched by population
+    #[serde(default)]
+    pub tags: Vec<String>,
+    //Block of the last event applied to the amm by the state space, zero if no event has been applied
+    #[serde(default)]
+    pub last_synced_block: u64,
+}
+
+//The tick maps of a pool, serialized separately from the pool for users who want to persist tick data
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UniswapV3TickMaps {
+    pub address: H160,
     pub tick_bitmap: HashMap<i16, U256>,
     pub ticks: HashMap<i32, Info>,
 }
 
+impl From<&UniswapV3Pool> for UniswapV3TickMaps {
+    fn from(pool: &UniswapV3Pool) -> Self {
+        UniswapV3TickMaps {
+            address: pool.address,
+            tick_bitmap: pool.tick_bitmap.clone(),
+            ticks: pool.ticks.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Info {
     pub liquidity_gross: u128,
@@ -148,6 +197,8 @@ impl AutomatedMarketMaker for UniswapV3Pool {
             self.sync_from_mint_log(log)?;
         } else if event_signature == SWAP_EVENT_SIGNATURE {
             self.sync_from_swap_log(log)?;
+        } else if event_signature == FLASH_EVENT_SIGNATURE {
+            self.sync_from_flash_log(log)?;
         } else {
             Err(EventLogError::InvalidEventSignature)?
         }
@@ -161,11 +212,12 @@ impl AutomatedMarketMaker for UniswapV3Pool {
 
     fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
         let tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(self.sqrt_price)?;
-        let shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
+        //Widened so that tokens with more than 127 decimals do not wrap
+        let shift = self.token_a_decimals as i32 - self.token_b_decimals as i32;
 
         let price = match shift.cmp(&0) {
-            Ordering::Less => 1.0001_f64.powi(tick) / 10_f64.powi(-shift as i32),
-            Ordering::Greater => 1.0001_f64.powi(tick) * 10_f64.powi(shift as i32),
+            Ordering::Less => 1.0001_f64.powi(tick) / 10_f64.powi(-shift),
+            Ordering::Greater => 1.0001_f64.powi(tick) * 10_f64.powi(shift),
             Ordering::Equal => 1.0001_f64.powi(tick),
         };
 
@@ -183,10 +235,24 @@ impl AutomatedMarketMaker for UniswapV3Pool {
     ) -> Result<(), AMMError<M>> {
         batch_request::get_v3_pool_data_batch_request(self, block_number, middleware.clone())
             .await?;
+        self.populate_observation_cardinality(block_number, middleware)
+            .await?;
         Ok(())
     }
 
     fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, SwapSimulationError> {
+        self.simulate_swap_with_fee(token_in, amount_in, self.fee)
+    }
+
+    fn simulate_swap_mut(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if amount_in.is_zero() {
             return Ok(U256::zero());
         }
@@ -311,27 +377,186 @@ impl AutomatedMarketMaker for UniswapV3Pool {
             }
         }
 
+        //Update the pool state
+        self.liquidity = current_state.liquidity;
+        self.sqrt_price = current_state.sqrt_price_x_96;
+        self.tick = current_state.tick;
+
         Ok((-current_state.amount_calculated).into_raw())
     }
 
-    fn simulate_swap_mut(
-        &mut self,
+    fn get_token_out(&self, token_in: H160) -> H160 {
+        if self.token_a == token_in {
+            self.token_b
+        } else {
+            self.token_a
+        }
+    }
+
+    //Returns the amount of token_out held by the liquidity between the current price and the last loaded initialized tick
+    fn max_output(&self, token_out: H160) -> U256 {
+        let zero_for_one = if self.token_b == token_out {
+            true
+        } else if self.token_a == token_out {
+            false
+        } else {
+            return U256::zero();
+        };
+
+        let mut initialized_ticks = self
+            .ticks
+            .iter()
+            .filter(|(tick, info)| {
+                info.initialized
+                    && if zero_for_one {
+                        **tick <= self.tick
+                    } else {
+                        **tick > self.tick
+                    }
+            })
+            .map(|(tick, info)| (*tick, info.liquidity_net))
+            .collect::<Vec<(i32, i128)>>();
+
+        //Walk the ticks in the order they would be crossed by a swap
+        if zero_for_one {
+            initialized_ticks.sort_by(|a, b| b.0.cmp(&a.0));
+        } else {
+            initialized_ticks.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        let mut amount_out = U256::zero();
+        let mut liquidity = self.liquidity as i128;
+        let mut sqrt_price = self.sqrt_price;
+
+        for (tick, liquidity_net) in initialized_ticks {
+            let Ok(sqrt_price_next) = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick)
+            else {
+                break;
+            };
+
+            if liquidity > 0 {
+                let amount = if zero_for_one {
+                    amount_1_delta(sqrt_price_next, sqrt_price, liquidity as u128)
+                } else {
+                    amount_0_delta(sqrt_price, sqrt_price_next, liquidity as u128)
+                };
+
+                amount_out = amount_out.saturating_add(amount);
+            }
+
+            //Crossing a tick downwards removes liquidity_net, crossing upwards adds it
+            liquidity = if zero_for_one {
+                liquidity - liquidity_net
+            } else {
+                liquidity + liquidity_net
+            };
+
+            if liquidity < 0 {
+                break;
+            }
+
+            sqrt_price = sqrt_price_next;
+        }
+
+        amount_out
+    }
+}
+
+//Amount of token_a between two sqrt prices, where sqrt_price_a <= sqrt_price_b
+fn amount_0_delta(sqrt_price_a: U256, sqrt_price_b: U256, liquidity: u128) -> U256 {
+    if sqrt_price_a.is_zero() || sqrt_price_b <= sqrt_price_a {
+        return U256::zero();
+    }
+
+    let numerator = (U256::from(liquidity) << 96).full_mul(sqrt_price_b - sqrt_price_a);
+    let amount = numerator / U512::from(sqrt_price_b) / U512::from(sqrt_price_a);
+
+    U256::try_from(amount).unwrap_or(U256::MAX)
+}
+
+//Amount of token_b between two sqrt prices, where sqrt_price_a <= sqrt_price_b
+fn amount_1_delta(sqrt_price_a: U256, sqrt_price_b: U256, liquidity: u128) -> U256 {
+    if sqrt_price_b <= sqrt_price_a {
+        return U256::zero();
+    }
+
+    let amount = U256::from(liquidity).full_mul(sqrt_price_b - sqrt_price_a) >> 96;
+
+    U256::try_from(amount).unwrap_or(U256::MAX)
+}
+
+impl UniswapV3Pool {
+    //Simulates a swap at the override fee instead of the pool fee, leaving the pool untouched. The fee is in
+    //hundredths of a bip like the pool fee, so 3000 is a 0.3% fee
+    pub fn simulate_swap_with_fee(
+        &self,
         token_in: H160,
         amount_in: U256,
+        fee_override: u32,
     ) -> Result<U256, SwapSimulationError> {
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
         if amount_in.is_zero() {
             return Ok(U256::zero());
         }
 
-        let zero_for_one = token_in == self.token_a;
-
         //Set sqrt_price_limit_x_96 to the max or min sqrt price in the pool depending on zero_for_one
-        let sqrt_price_limit_x_96 = if zero_for_one {
+        let sqrt_price_limit_x_96 = if token_in == self.token_a {
             MIN_SQRT_RATIO + 1
         } else {
             MAX_SQRT_RATIO - 1
         };
 
+        let (amount_out, _) =
+            self.simulate_swap_to_limit(token_in, amount_in, sqrt_price_limit_x_96, fee_override)?;
+
+        Ok(amount_out)
+    }
+
+    //Simulates a swap that stops once the price reaches the sqrt price limit like the limit of the on chain swap,
+    //returning the output and the input consumed, which is less than the amount in if the limit was reached first.
+    //The limit must be below the current price when selling token a and above it when selling token b
+    pub fn simulate_swap_to_price_limit(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        sqrt_price_limit_x_96: U256,
+    ) -> Result<(U256, U256), SwapSimulationError> {
+        if token_in != self.token_a && token_in != self.token_b {
+            return Err(SwapSimulationError::TokenNotInPool(token_in));
+        }
+
+        let limit_is_valid = if token_in == self.token_a {
+            sqrt_price_limit_x_96 < self.sqrt_price && sqrt_price_limit_x_96 > MIN_SQRT_RATIO
+        } else {
+            sqrt_price_limit_x_96 > self.sqrt_price && sqrt_price_limit_x_96 < MAX_SQRT_RATIO
+        };
+        if !limit_is_valid {
+            return Err(SwapSimulationError::InvalidSqrtPriceLimit(
+                sqrt_price_limit_x_96,
+            ));
+        }
+
+        if amount_in.is_zero() {
+            return Ok((U256::zero(), U256::zero()));
+        }
+
+        self.simulate_swap_to_limit(token_in, amount_in, sqrt_price_limit_x_96, self.fee)
+    }
+
+    //Runs the swap steps until the amount in is consumed or the price reaches the limit, returning the output and the
+    //input consumed including fees
+    fn simulate_swap_to_limit(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        sqrt_price_limit_x_96: U256,
+        fee: u32,
+    ) -> Result<(U256, U256), SwapSimulationError> {
+        let zero_for_one = token_in == self.token_a;
+
         //Initialize a mutable state state struct to hold the dynamic simulated state of the pool
         let mut current_state = CurrentState {
             sqrt_price_x_96: self.sqrt_price, //Active price on the pool
@@ -391,7 +616,7 @@ impl AutomatedMarketMaker for UniswapV3Pool {
                 swap_target_sqrt_ratio,
                 current_state.liquidity,
                 current_state.amount_specified_remaining,
-                self.fee,
+                fee,
             )?;
 
             //Decrement the amount remaining to be swapped and amount received from the step
@@ -443,24 +668,31 @@ impl AutomatedMarketMaker for UniswapV3Pool {
             }
         }
 
-        //Update the pool state
-        self.liquidity = current_state.liquidity;
-        self.sqrt_price = current_state.sqrt_price_x_96;
-        self.tick = current_state.tick;
+        let amount_in_consumed = amount_in
+            - current_state
+                .amount_specified_remaining
+                .max(I256::zero())
+                .into_raw();
 
-        Ok((-current_state.amount_calculated).into_raw())
+        Ok((
+            (-current_state.amount_calculated).into_raw(),
+            amount_in_consumed,
+        ))
     }
 
-    fn get_token_out(&self, token_in: H160) -> H160 {
-        if self.token_a == token_in {
-            self.token_b
-        } else {
-            self.token_a
-        }
+    //Returns a clone of the pool with the swap applied, leaving the pool untouched. Applying a decoded pending swap lets
+    //callers quote against the state the pool will be in once the pending transaction is mined
+    pub fn apply_pending_swap(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<UniswapV3Pool, SwapSimulationError> {
+        let mut pool = self.clone();
+        pool.simulate_swap_mut(token_in, amount_in)?;
+
+        Ok(pool)
     }
-}
 
-impl UniswapV3Pool {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         address: H160,
@@ -489,6 +721,11 @@ impl UniswapV3Pool {
             tick_spacing,
             tick_bitmap,
             ticks,
+            observation_cardinality: 0,
+            observation_cardinality_next: 0,
+            factory: H160::zero(),
+            tags: vec![],
+            last_synced_block: 0,
         }
     }
 
@@ -511,6 +748,11 @@ impl UniswapV3Pool {
             fee: 0,
             tick_bitmap: HashMap::new(),
             ticks: HashMap::new(),
+            observation_cardinality: 0,
+            observation_cardinality_next: 0,
+            factory: H160::zero(),
+            tags: vec![],
+            last_synced_block: 0,
         };
 
         //We need to get tick spacing before populating tick data because tick spacing can not be uninitialized when syncing burn and mint logs
@@ -573,6 +815,11 @@ impl UniswapV3Pool {
                 tick: 0,
                 tick_bitmap: HashMap::new(),
                 ticks: HashMap::new(),
+                observation_cardinality: 0,
+                observation_cardinality_next: 0,
+                factory: H160::zero(),
+                tags: vec![],
+                last_synced_block: 0,
             })
         } else {
             Err(EventLogError::InvalidEventSignature)
@@ -666,12 +913,123 @@ impl UniswapV3Pool {
         Ok(())
     }
 
+    //Loads the initialized ticks within `tick_window` steps on each side of the current tick.
+    //This is much cheaper than populating the full tick data from logs, but swaps that move the price outside of the window will be inaccurate.
+    pub async fn populate_tick_window<M: Middleware>(
+        &mut self,
+        tick_window: u16,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        if self.tick_spacing == 0 {
+            return Err(AMMError::PoolDataError);
+        }
+
+        for zero_for_one in [true, false] {
+            let (tick_data, _) = batch_request::get_uniswap_v3_tick_data_batch_request(
+                self,
+                self.tick,
+                zero_for_one,
+                tick_window,
+                block_number.map(U64::from),
+                middleware.clone(),
+            )
+            .await?;
+
+            for tick in tick_data {
+                if tick.initialized {
+                    self.insert_initialized_tick(tick.tick, tick.liquidity_net);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    //Marks the tick as initialized in the tick bitmap and stores its liquidity net.
+    //The liquidity gross is not known from the tick data batch request, so the absolute liquidity net is used as a lower bound.
+    pub fn insert_initialized_tick(&mut self, tick: i32, liquidity_net: i128) {
+        self.ticks.insert(
+            tick,
+            Info::new(liquidity_net.unsigned_abs(), liquidity_net, true),
+        );
+
+        let (word_pos, bit_pos) = uniswap_v3_math::tick_bitmap::position(tick / self.tick_spacing);
+        *self.tick_bitmap.entry(word_pos).or_insert(U256::zero()) |= U256::one() << bit_pos;
+    }
+
     pub fn fee(&self) -> u32 {
         self.fee
     }
 
+    //Estimates the yearly fee APR of the liquidity providers as volume_24h * fee / tvl * 365. Only liquidity in range of
+    //the current tick earns fees, so the tvl should be the value of the active liquidity rather than of the pool balances,
+    //eg. valued from `calculate_virtual_reserves`. Swaps are not indexed, so the 24h volume is supplied by the caller in
+    //the same unit as the tvl. Assumes the volume and active range stay constant over the year and ignores compounding
+    //and the protocol fee, returning 0 for a pool without tvl
+    pub fn estimate_fee_apr(&self, volume_24h: U256, tvl: U256) -> f64 {
+        if tvl.is_zero() {
+            return 0.0;
+        }
+
+        //The fee is in hundredths of a bip, ie. 3000 is 0.3%
+        u256_to_f64(volume_24h) * (self.fee as f64 / 1000000.0) / u256_to_f64(tvl) * 365.0
+    }
+
+    //Calculates the decimal adjusted price of the base token in terms of the other token from the sqrt price
+    pub fn price(&self, base_token: H160) -> f64 {
+        //Split the sqrt price at 64 bits since sqrtPriceX96 can exceed u128
+        let sqrt_price = (self.sqrt_price >> 64).as_u128() as f64 / 2_f64.powi(32)
+            + self.sqrt_price.low_u64() as f64 / 2_f64.powi(96);
+
+        let price = sqrt_price.powi(2)
+            * 10_f64.powi(self.token_a_decimals as i32 - self.token_b_decimals as i32);
+
+        if base_token == self.token_a {
+            price
+        } else {
+            1.0 / price
+        }
+    }
+
+    //Returns the price impact of the swap, ie. the relative shortfall of the realized price against the spot price of the
+    //current sqrt price, including the pool fee and every crossed tick
+    pub fn price_impact(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<f64, SwapSimulationError> {
+        let amount_out = self.simulate_swap(token_in, amount_in)?;
+        if amount_in.is_zero() || self.sqrt_price.is_zero() {
+            return Ok(1.0);
+        }
+
+        //Raw amount of token_b per token_a, without the decimal adjustment of `price`
+        let sqrt_price = (self.sqrt_price >> 64).as_u128() as f64 / 2_f64.powi(32)
+            + self.sqrt_price.low_u64() as f64 / 2_f64.powi(96);
+        let spot_price = if token_in == self.token_a {
+            sqrt_price.powi(2)
+        } else {
+            1.0 / sqrt_price.powi(2)
+        };
+
+        let realized_price = u256_to_f64(amount_out) / u256_to_f64(amount_in);
+
+        Ok(1.0 - realized_price / spot_price)
+    }
+
+    //Returns true if the swap keeps the price impact under max_impact. Pools without liquidity towards the output token
+    //are rejected without simulating
+    pub fn can_fill(&self, token_in: H160, amount_in: U256, max_impact: f64) -> bool {
+        if self.liquidity == 0 || self.max_output(self.get_token_out(token_in)).is_zero() {
+            return false;
+        }
+
+        matches!(self.price_impact(token_in, amount_in), Ok(impact) if impact < max_impact)
+    }
+
     pub fn data_is_populated(&self) -> bool {
-        !(self.token_a.is_zero() || self.token_b.is_zero())
+        !(self.token_a.is_zero() || self.token_b.is_zero() || self.token_a == self.token_b)
     }
 
     pub async fn get_tick_word<M: Middleware>(
@@ -752,6 +1110,42 @@ impl UniswapV3Pool {
         Ok(v3_pool.slot_0().call().await?)
     }
 
+    //Reads the observation cardinality from slot0. The batch requests do not return it, so pools populated through
+    //the batch requests need to call this to use `twap_available`
+    pub async fn populate_observation_cardinality<M: Middleware>(
+        &mut self,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let v3_pool = IUniswapV3Pool::new(self.address, middleware);
+        let mut slot_0 = v3_pool.slot_0();
+        if let Some(block_number) = block_number {
+            slot_0 = slot_0.block(block_number);
+        }
+
+        let (_, _, _, observation_cardinality, observation_cardinality_next, _, _) =
+            slot_0.call().await?;
+        self.observation_cardinality = observation_cardinality;
+        self.observation_cardinality_next = observation_cardinality_next;
+
+        Ok(())
+    }
+
+    //Estimates whether the oracle holds an observation at least seconds_ago old. Observations are written at most once per
+    //block, so the buffer covers at most observation_cardinality blocks and less if the pool is not swapped every block
+    pub fn twap_available(&self, seconds_ago: u32) -> bool {
+        if seconds_ago == 0 {
+            return true;
+        }
+
+        //A single observation is overwritten on every write, leaving no history to average over
+        if self.observation_cardinality <= 1 {
+            return false;
+        }
+
+        self.observation_cardinality as u64 * AVERAGE_BLOCK_TIME_SECONDS >= seconds_ago as u64
+    }
+
     pub async fn get_liquidity<M: Middleware>(
         &self,
         middleware: Arc<M>,
@@ -892,6 +1286,15 @@ impl UniswapV3Pool {
         Ok(())
     }
 
+    //Flash loans leave the price, liquidity and ticks unchanged, the fees paid only accrue to feeGrowthGlobal, which the
+    //pool does not track. The log is decoded and then intentionally ignored, so flash logs mixed into the event stream of
+    //the pool are accepted. Flash is not in the sync event signatures since it never changes the state of the pool
+    pub fn sync_from_flash_log(&mut self, log: Log) -> Result<(), AbiError> {
+        FlashFilter::decode_log(&RawLog::from(log))?;
+
+        Ok(())
+    }
+
     pub async fn get_token_decimals<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
@@ -981,6 +1384,27 @@ impl UniswapV3Pool {
         ))
     }
 
+    //Returns the tick of the current price
+    pub fn current_tick(&self) -> i32 {
+        self.tick
+    }
+
+    //Rounds the tick to the nearest tick aligned to the tick spacing, rounding halfway ticks up and keeping the tick
+    //within the min and max ticks
+    pub fn nearest_usable_tick(&self, tick: i32) -> i32 {
+        let tick_spacing = self.tick_spacing.max(1) as i64;
+        let rounded =
+            ((2 * tick as i64 + tick_spacing).div_euclid(2 * tick_spacing) * tick_spacing) as i32;
+
+        if rounded < MIN_TICK {
+            rounded + tick_spacing as i32
+        } else if rounded > MAX_TICK {
+            rounded - tick_spacing as i32
+        } else {
+            rounded
+        }
+    }
+
     pub fn calculate_compressed(&self, tick: i32) -> i32 {
         if tick < 0 && tick % self.tick_spacing != 0 {
             (tick / self.tick_spacing) - 1
@@ -1053,7 +1477,7 @@ mod test {
     use super::IUniswapV3Pool;
     #[allow(unused)]
     #[allow(unused)]
-    use super::UniswapV3Pool;
+    use super::{UniswapV3Pool, MAX_TICK, MIN_TICK};
 
     use crate::amm::AutomatedMarketMaker;
 
@@ -1774,6 +2198,30 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_populate_tick_window() -> eyre::Result<()> {
+        let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint)?);
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")?,
+            ..Default::default()
+        };
+
+        pool.populate_data(None, middleware.clone()).await?;
+        pool.populate_tick_window(10, None, middleware.clone())
+            .await?;
+
+        assert!(!pool.ticks.is_empty());
+        assert!(!pool.tick_bitmap.is_empty());
+
+        let amount_in = U256::from_dec_str("10000000000")?; // 10_000 USDC
+        let amount_out = pool.simulate_swap(pool.token_a, amount_in)?;
+        assert!(!amount_out.is_zero());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_new_from_address() -> eyre::Result<()> {
         let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
@@ -1910,4 +2358,471 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_price() -> eyre::Result<()> {
+        let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
+        let middleware = Arc::new(Provider::<Http>::try_from(rpc_endpoint)?);
+
+        //USDC/WETH pool at block 16515398
+        let mut pool = UniswapV3Pool {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640")?,
+            ..Default::default()
+        };
+
+        pool.populate_data(Some(16515398), middleware.clone())
+            .await?;
+
+        let usdc_price = pool.price(pool.token_a);
+        let weth_price = pool.price(pool.token_b);
+
+        assert!((usdc_price - 0.0006081236083117488).abs() / usdc_price < 1e-4);
+        assert!((weth_price - 1644.4025299004006).abs() / weth_price < 1e-4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_observation_cardinality() -> eyre::Result<()> {
+        let (provider, mock) = ethers::providers::Provider::mocked();
+        mock.push::<ethers::types::Bytes, _>(ethers::types::Bytes::from(ethers::abi::encode(&[
+            ethers::abi::Token::Uint(U256::one() << 96),
+            ethers::abi::Token::Int(U256::zero()),
+            ethers::abi::Token::Uint(U256::from(5)),
+            ethers::abi::Token::Uint(U256::from(100)),
+            ethers::abi::Token::Uint(U256::from(200)),
+            ethers::abi::Token::Uint(U256::zero()),
+            ethers::abi::Token::Bool(true),
+        ])))?;
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        };
+        pool.populate_observation_cardinality(Some(100), Arc::new(provider))
+            .await?;
+
+        assert_eq!(pool.observation_cardinality, 100);
+        assert_eq!(pool.observation_cardinality_next, 200);
+
+        //100 observations cover at most 20 minutes of 12 second blocks
+        assert!(pool.twap_available(600));
+        assert!(pool.twap_available(1200));
+        assert!(!pool.twap_available(1800));
+
+        pool.observation_cardinality = 1;
+        assert!(pool.twap_available(0));
+        assert!(!pool.twap_available(12));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_populate_tick_data_batch() -> eyre::Result<()> {
+        use ethers::{
+            abi::{encode, Token},
+            types::{Bytes, I256},
+        };
+
+        use crate::amm::{uniswap_v3::batch_request::populate_tick_data_batch, AMM};
+
+        let (provider, mock) = Provider::mocked();
+
+        //Every tick window request returns the same ticks, so the order the concurrent requests pop them in does not matter
+        let tick_data = encode(&[
+            Token::Array(vec![
+                Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::Int(I256::from(-60).into_raw()),
+                    Token::Int(I256::from(1000).into_raw()),
+                ]),
+                Token::Tuple(vec![
+                    Token::Bool(false),
+                    Token::Int(I256::from(0).into_raw()),
+                    Token::Int(I256::from(0).into_raw()),
+                ]),
+                Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::Int(I256::from(60).into_raw()),
+                    Token::Int(I256::from(-1000).into_raw()),
+                ]),
+            ]),
+            Token::Uint(U256::from(100)),
+        ]);
+
+        let mut amms = (1..=20)
+            .map(|idx| {
+                AMM::UniswapV3Pool(UniswapV3Pool {
+                    address: H160::from_low_u64_be(idx),
+                    tick_spacing: 60,
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<AMM>>();
+        //Two requests per pool, one for each direction from the current tick
+        for _ in 0..amms.len() * 2 {
+            mock.push::<Bytes, _>(tick_data.clone().into())?;
+        }
+
+        populate_tick_data_batch(&mut amms, 10, Some(100), Arc::new(provider)).await?;
+
+        for amm in amms {
+            if let AMM::UniswapV3Pool(pool) = amm {
+                assert_eq!(pool.ticks.len(), 2);
+                assert_eq!(pool.ticks[&-60].liquidity_net, 1000);
+                assert_eq!(pool.ticks[&60].liquidity_net, -1000);
+                assert!(!pool.tick_bitmap.is_empty());
+            } else {
+                panic!("Unexpected AMM variant")
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_of() -> eyre::Result<()> {
+        //A sqrt price of 2 prices token a at 4 token b
+        let pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_a_decimals: 18,
+            token_b: H160::from_low_u64_be(2),
+            token_b_decimals: 18,
+            sqrt_price: U256::from(2) << 96,
+            ..Default::default()
+        };
+
+        assert!((pool.price_of(pool.token_a)? - 4.0).abs() < 1e-3);
+        assert!((pool.price_of(pool.token_b)? - 0.25).abs() < 1e-3);
+        assert!(pool.price_of(H160::from_low_u64_be(3)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_output() -> eyre::Result<()> {
+        let liquidity = 1000000000000000000_u128;
+        let mut pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            liquidity,
+            sqrt_price: U256::one() << 96,
+            tick: 0,
+            tick_spacing: 60,
+            ..Default::default()
+        };
+        pool.ticks
+            .insert(-60, super::Info::new(liquidity, liquidity as i128, true));
+        pool.ticks
+            .insert(60, super::Info::new(liquidity, -(liquidity as i128), true));
+
+        //Liquidity over 30 ticks on either side of price 1 holds ~0.29955% of L of each token
+        let max_a = pool.max_output(pool.token_a);
+        let max_b = pool.max_output(pool.token_b);
+        for max in [max_a, max_b] {
+            assert!(max > U256::from(2995354955900000_u64));
+            assert!(max < U256::from(2995354955920000_u64));
+        }
+
+        assert_eq!(pool.max_output(H160::from_low_u64_be(3)), U256::zero());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_fee_apr() -> eyre::Result<()> {
+        let pool = UniswapV3Pool {
+            fee: 500,
+            ..Default::default()
+        };
+
+        //2m of daily volume at 0.05% earns 1k a day on 1m of active liquidity
+        let apr = pool.estimate_fee_apr(U256::from(2000000), U256::from(1000000));
+        assert!((apr - 0.365).abs() < 1e-12);
+
+        assert_eq!(
+            pool.estimate_fee_apr(U256::from(2000000), U256::zero()),
+            0.0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_with_fee() -> eyre::Result<()> {
+        let liquidity = 1000000000000000000_u128;
+        let mut pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            liquidity,
+            sqrt_price: U256::one() << 96,
+            fee: 3000,
+            tick: 0,
+            tick_spacing: 60,
+            ..Default::default()
+        };
+        pool.insert_initialized_tick(-60, liquidity as i128);
+        pool.insert_initialized_tick(60, -(liquidity as i128));
+
+        let amount_in = U256::from(1000000000000_u64);
+        let amount_out = pool.simulate_swap(pool.token_a, amount_in)?;
+        let amount_out_at_pool_fee = pool.simulate_swap_with_fee(pool.token_a, amount_in, 3000)?;
+        let amount_out_at_low_fee = pool.simulate_swap_with_fee(pool.token_a, amount_in, 500)?;
+
+        assert_eq!(amount_out, amount_out_at_pool_fee);
+        assert!(amount_out_at_low_fee > amount_out);
+        assert_eq!(pool.fee, 3000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_both_directions() -> eyre::Result<()> {
+        let liquidity = 1000000000000000000000_u128;
+        let mut pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            liquidity,
+            //Price of 4 token_b per token_a
+            sqrt_price: U256::from(2) << 96,
+            tick: 13862,
+            tick_spacing: 60,
+            fee: 3000,
+            ..Default::default()
+        };
+        pool.insert_initialized_tick(13200, liquidity as i128);
+        pool.insert_initialized_tick(14400, -(liquidity as i128));
+
+        let within_tolerance =
+            |amount: U256, expected: f64| (amount.as_u128() as f64 / expected - 1.0).abs() < 1e-4;
+
+        //zeroForOne moves the price down towards the min sqrt price and pays out token_b at the price
+        let amount_in = U256::from(1000000000000000_u64);
+        let amount_out = pool.simulate_swap(pool.token_a, amount_in)?;
+        assert!(within_tolerance(amount_out, 4e15 * 0.997));
+
+        //oneForZero moves the price up towards the max sqrt price and pays out token_a at the inverse price
+        let inverse_amount_out =
+            pool.simulate_swap(pool.token_b, U256::from(4000000000000000_u64))?;
+        assert!(within_tolerance(inverse_amount_out, 1e15 * 0.997));
+
+        //Swapping back the output returns the input less the fee of both swaps
+        let sqrt_price = pool.sqrt_price;
+        let amount_out = pool.simulate_swap_mut(pool.token_a, amount_in)?;
+        assert!(pool.sqrt_price < sqrt_price);
+
+        let round_trip_amount = pool.simulate_swap_mut(pool.token_b, amount_out)?;
+        assert!(round_trip_amount < amount_in);
+        assert!(within_tolerance(round_trip_amount, 1e15 * 0.997 * 0.997));
+        assert!(pool.sqrt_price > sqrt_price - (sqrt_price >> 20));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_pending_swap() -> eyre::Result<()> {
+        let liquidity = 1000000000000000000000_u128;
+        let mut pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            liquidity: liquidity * 2,
+            sqrt_price: U256::from(2) << 96,
+            tick: 13862,
+            tick_spacing: 60,
+            fee: 3000,
+            ..Default::default()
+        };
+        //Two positions, one of them ending at the 13200 tick
+        pool.insert_initialized_tick(12000, liquidity as i128);
+        pool.insert_initialized_tick(13200, liquidity as i128);
+        pool.insert_initialized_tick(14400, -(liquidity as i128 * 2));
+
+        //A pending swap large enough to cross the 13200 tick, leaving the position ending there
+        let pending_amount_in = U256::from(50000000000000000000_u128);
+        let projected = pool.apply_pending_swap(pool.token_a, pending_amount_in)?;
+
+        //The original pool is untouched
+        assert_eq!(pool.sqrt_price, U256::from(2) << 96);
+        assert_eq!(pool.tick, 13862);
+        assert_eq!(pool.liquidity, liquidity * 2);
+
+        //The projected state matches applying the swap to the pool
+        let mut applied = pool.clone();
+        applied.simulate_swap_mut(pool.token_a, pending_amount_in)?;
+        assert_eq!(projected.sqrt_price, applied.sqrt_price);
+        assert_eq!(projected.tick, applied.tick);
+        assert_eq!(projected.liquidity, applied.liquidity);
+        assert!(projected.tick < 13200 && projected.tick > 12000);
+        assert_eq!(projected.liquidity, liquidity);
+
+        //Quotes after the pending swap are worse than before it for the same direction
+        let amount_in = U256::from(1000000000000000_u64);
+        assert_eq!(
+            projected.simulate_swap(pool.token_b, amount_in)?,
+            applied.simulate_swap(pool.token_b, amount_in)?
+        );
+        assert!(
+            pool.apply_pending_swap(pool.token_a, amount_in)?
+                .simulate_swap(pool.token_a, amount_in)?
+                < pool.simulate_swap(pool.token_a, amount_in)?
+        );
+
+        assert!(pool
+            .apply_pending_swap(H160::from_low_u64_be(3), amount_in)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_to_price_limit() -> eyre::Result<()> {
+        let liquidity = 1000000000000000000000_u128;
+        let mut pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            liquidity,
+            sqrt_price: U256::from(2) << 96,
+            tick: 13862,
+            tick_spacing: 60,
+            fee: 3000,
+            ..Default::default()
+        };
+        pool.insert_initialized_tick(12000, liquidity as i128);
+        pool.insert_initialized_tick(14400, -(liquidity as i128));
+
+        //The limit stops the swap at the 13200 tick, well before the input is consumed
+        let sqrt_price_limit = uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(13200)?;
+        let amount_in = U256::from(1000000000000000000000_u128);
+        let (amount_out, amount_in_consumed) =
+            pool.simulate_swap_to_price_limit(pool.token_a, amount_in, sqrt_price_limit)?;
+
+        assert!(!amount_out.is_zero());
+        assert!(amount_in_consumed < amount_in);
+        assert!(amount_out < pool.simulate_swap(pool.token_a, amount_in)?);
+
+        //Swapping the consumed input without a limit ends at the limit price, give or take rounding
+        let mut limited = pool.clone();
+        limited.simulate_swap_mut(pool.token_a, amount_in_consumed)?;
+        let price_difference = if limited.sqrt_price > sqrt_price_limit {
+            limited.sqrt_price - sqrt_price_limit
+        } else {
+            sqrt_price_limit - limited.sqrt_price
+        };
+        assert!(price_difference < U256::from(1_u128 << 64));
+
+        //Swaps that do not reach the limit consume the full input
+        let small_amount_in = U256::from(1000000000000000_u64);
+        assert_eq!(
+            pool.simulate_swap_to_price_limit(pool.token_a, small_amount_in, sqrt_price_limit)?,
+            (
+                pool.simulate_swap(pool.token_a, small_amount_in)?,
+                small_amount_in
+            )
+        );
+
+        //The limit has to be on the side of the current price that the swap moves towards
+        assert!(matches!(
+            pool.simulate_swap_to_price_limit(pool.token_b, amount_in, sqrt_price_limit),
+            Err(crate::errors::SwapSimulationError::InvalidSqrtPriceLimit(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nearest_usable_tick() -> eyre::Result<()> {
+        let pool_with_spacing = |tick_spacing: i32| UniswapV3Pool {
+            tick: -1234,
+            tick_spacing,
+            ..Default::default()
+        };
+
+        let pool = pool_with_spacing(1);
+        assert_eq!(pool.current_tick(), -1234);
+        assert_eq!(pool.nearest_usable_tick(5), 5);
+        assert_eq!(pool.nearest_usable_tick(-5), -5);
+        assert_eq!(pool.nearest_usable_tick(MAX_TICK), MAX_TICK);
+
+        let pool = pool_with_spacing(10);
+        assert_eq!(pool.nearest_usable_tick(0), 0);
+        assert_eq!(pool.nearest_usable_tick(14), 10);
+        assert_eq!(pool.nearest_usable_tick(15), 20);
+        assert_eq!(pool.nearest_usable_tick(-15), -10);
+        assert_eq!(pool.nearest_usable_tick(-16), -20);
+        assert_eq!(pool.nearest_usable_tick(pool.current_tick()), -1230);
+
+        let pool = pool_with_spacing(60);
+        assert_eq!(pool.nearest_usable_tick(89), 60);
+        assert_eq!(pool.nearest_usable_tick(90), 120);
+        assert_eq!(pool.nearest_usable_tick(-91), -120);
+        assert_eq!(pool.nearest_usable_tick(MAX_TICK), 887220);
+        assert_eq!(pool.nearest_usable_tick(MIN_TICK), -887220);
+
+        let pool = pool_with_spacing(200);
+        assert_eq!(pool.nearest_usable_tick(299), 200);
+        assert_eq!(pool.nearest_usable_tick(300), 400);
+        assert_eq!(pool.nearest_usable_tick(-301), -400);
+        assert_eq!(pool.nearest_usable_tick(MIN_TICK), -887200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_from_flash_log() -> eyre::Result<()> {
+        use ethers::{
+            abi::{encode, Token},
+            types::{Log, H256},
+        };
+
+        let mut pool = UniswapV3Pool {
+            liquidity: 1_000_000,
+            sqrt_price: U256::one() << 96,
+            tick: 0,
+            ..Default::default()
+        };
+
+        let log = Log {
+            topics: vec![
+                super::FLASH_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(1)),
+                H256::from(H160::from_low_u64_be(2)),
+            ],
+            data: encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+                Token::Uint(U256::from(1)),
+                Token::Uint(U256::from(2)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+        pool.sync_from_log(log)?;
+
+        //Flash loans do not change the state of the pool
+        assert_eq!(pool.liquidity, 1_000_000);
+        assert_eq!(pool.sqrt_price, U256::one() << 96);
+        assert_eq!(pool.tick, 0);
+        assert!(!pool
+            .sync_on_event_signatures()
+            .contains(&super::FLASH_EVENT_SIGNATURE));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_token_not_in_pool() -> eyre::Result<()> {
+        let pool = UniswapV3Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            ..Default::default()
+        };
+
+        let foreign_token = H160::from_low_u64_be(3);
+        assert!(matches!(
+            pool.simulate_swap(foreign_token, U256::from(1000)),
+            Err(crate::errors::SwapSimulationError::TokenNotInPool(token)) if token == foreign_token
+        ));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,69 @@
+//Synchronous wrappers around the sync functions for simple tools and scripts that do not run a tokio runtime.
+//Each call builds a current-thread runtime and blocks on the async function, so async callers and servers
+//should use the async functions directly. Calling these from within a tokio runtime will panic.
+
+use std::sync::Arc;
+
+use ethers::providers::Middleware;
+
+use crate::{
+    amm::{factory::Factory, AMM},
+    errors::AMMError,
+    sync,
+};
+
+fn runtime() -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+//Blocking version of `sync::sync_amms`
+pub fn sync_amms_blocking<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    checkpoint_path: Option<&str>,
+    step: u64,
+) -> Result<(Vec<AMM>, u64), AMMError<M>> {
+    runtime()?.block_on(sync::sync_amms(
+        factories,
+        middleware,
+        checkpoint_path,
+        step,
+    ))
+}
+
+//Blocking version of `sync::checkpoint::sync_amms_from_checkpoint`
+pub fn sync_amms_from_checkpoint_blocking<M: 'static + Middleware>(
+    path_to_checkpoint: &str,
+    step: u64,
+    middleware: Arc<M>,
+) -> Result<(Vec<Factory>, Vec<AMM>), AMMError<M>> {
+    runtime()?.block_on(sync::checkpoint::sync_amms_from_checkpoint(
+        path_to_checkpoint,
+        step,
+        middleware,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers::{providers::Provider, types::U64};
+
+    use super::sync_amms_blocking;
+
+    #[test]
+    fn test_sync_amms_blocking() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let (amms, synced_block) = sync_amms_blocking(vec![], Arc::new(provider), None, 1000)?;
+
+        assert!(amms.is_empty());
+        assert_eq!(synced_block, 100);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,18 @@
-use std::time::Duration;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use backon::ConstantBuilder;
-use indicatif::{MultiProgress, ProgressStyle};
+use ethers::types::H160;
+use indicatif::{MultiProgress, ProgressDrawTarget, ProgressStyle};
 use lazy_static::lazy_static;
 
+use crate::amm::uniswap_v3::batch_request::{
+    max_pool_data_batch_size_for_chain, MAX_POOL_DATA_BATCH_SIZE,
+};
+
 lazy_static! {
     pub static ref MULTIPROGRESS: MultiProgress = MultiProgress::new();
     pub static ref SPINNER_STYLE: ProgressStyle = ProgressStyle::default_spinner()
@@ -15,4 +24,68 @@ lazy_static! {
     pub static ref CONSTANT_RETRY: ConstantBuilder = ConstantBuilder::default()
         .with_max_times(6)
         .with_delay(Duration::from_millis(200));
+    //Tokens that change balances without transfers (stETH, AMPL, sOHM), breaking constant product assumptions on stored reserves
+    pub static ref KNOWN_REBASING_TOKENS: Vec<H160> = vec![
+        H160::from_str("0xae7ab96520DE3A18E5e111B5EaAb095312D7fE84").unwrap(),
+        H160::from_str("0xD46bA6D942050d489DBd938a2C909A5d5039A161").unwrap(),
+        H160::from_str("0x04906695D6D12CF5459975d7C3C03356E4Ccd460").unwrap(),
+    ];
+}
+
+//Timeout of a single batch request attempt in milliseconds. A hung call would otherwise block its sync task indefinitely
+static REQUEST_TIMEOUT_MS: AtomicU64 = AtomicU64::new(30_000);
+
+//Sets the timeout applied to every batch request, a timed out attempt is retried like any other failed request
+pub fn set_request_timeout(timeout: Duration) {
+    REQUEST_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn request_timeout() -> Duration {
+    Duration::from_millis(REQUEST_TIMEOUT_MS.load(Ordering::Relaxed))
+}
+
+//Largest eth_call response in bytes that batch requests are sized for, unlimited by default
+static MAX_RESPONSE_BYTES: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+//Sets the largest response the provider returns for a single eth_call. Batch requests whose estimated response exceeds
+//it are split into smaller chunks than their max batch size, since strict providers truncate or reject larger responses
+pub fn set_max_response_bytes(max_response_bytes: usize) {
+    MAX_RESPONSE_BYTES.store(max_response_bytes, Ordering::Relaxed);
+}
+
+pub fn max_response_bytes() -> usize {
+    MAX_RESPONSE_BYTES.load(Ordering::Relaxed)
+}
+
+//Max pools per uniswap v3 pool data batch request, mainnet tuned by default
+static V3_MAX_BATCH_SIZE: AtomicUsize = AtomicUsize::new(MAX_POOL_DATA_BATCH_SIZE);
+
+//Sets the max pools per uniswap v3 pool data batch request, see `max_pool_data_batch_size_for_chain` for safe sizes
+pub fn set_v3_max_batch_size(max_batch_size: usize) {
+    V3_MAX_BATCH_SIZE.store(max_batch_size.max(1), Ordering::Relaxed);
+}
+
+//Sets the max pools per uniswap v3 pool data batch request to the safe size of the chain
+pub fn set_v3_max_batch_size_for_chain(chain_id: u64) {
+    set_v3_max_batch_size(max_pool_data_batch_size_for_chain(chain_id));
+}
+
+pub fn v3_max_batch_size() -> usize {
+    V3_MAX_BATCH_SIZE.load(Ordering::Relaxed)
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+//Hides every progress bar drawn through MULTIPROGRESS, eg. for services that only report through tracing
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    MULTIPROGRESS.set_draw_target(if quiet {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr()
+    });
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
 }
@@ -14,6 +14,7 @@ use crate::{
 pub enum DiscoverableFactory {
     UniswapV2Factory,
     UniswapV3Factory,
+    AlgebraFactory,
 }
 
 impl DiscoverableFactory {
@@ -26,6 +27,8 @@ impl DiscoverableFactory {
             DiscoverableFactory::UniswapV3Factory => {
                 amm::uniswap_v3::factory::POOL_CREATED_EVENT_SIGNATURE
             }
+
+            DiscoverableFactory::AlgebraFactory => amm::algebra::factory::POOL_EVENT_SIGNATURE,
         }
     }
 }
@@ -95,6 +98,13 @@ pub async fn discover_factories<M: Middleware>(
                             .ok_or(AMMError::BlockNumberNotFound)?
                             .as_u64();
                     }
+                    Factory::AlgebraFactory(algebra_factory) => {
+                        algebra_factory.address = log.address;
+                        algebra_factory.creation_block = log
+                            .block_number
+                            .ok_or(AMMError::BlockNumberNotFound)?
+                            .as_u64();
+                    }
                 }
 
                 identified_factories.insert(log.address, (factory, 0));
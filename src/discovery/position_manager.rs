@@ -0,0 +1,305 @@
+use std::{collections::HashSet, sync::Arc};
+
+use ethers::{
+    prelude::{abigen, ContractError},
+    providers::Middleware,
+    types::{Filter, H160, H256, U256},
+};
+use tokio::task::JoinSet;
+
+use crate::{
+    amm::{
+        factory::TASK_LIMIT,
+        uniswap_v3::{factory::IUniswapV3Factory, UniswapV3Pool},
+        AMM,
+    },
+    errors::AMMError,
+    sync::{populate_amms, remove_empty_amms},
+};
+
+abigen!(
+    INonfungiblePositionManager,
+    r#"[
+        function factory() external view returns (address)
+        function positions(uint256 tokenId) external view returns (uint96 nonce, address operator, address token0, address token1, uint24 fee, int24 tickLower, int24 tickUpper, uint128 liquidity, uint256 feeGrowthInside0LastX128, uint256 feeGrowthInside1LastX128, uint128 tokensOwed0, uint128 tokensOwed1)
+        event IncreaseLiquidity(uint256 indexed tokenId, uint128 liquidity, uint256 amount0, uint256 amount1)
+    ]"#;
+);
+
+pub const INCREASE_LIQUIDITY_EVENT_SIGNATURE: H256 = H256([
+    48, 103, 4, 139, 238, 227, 27, 37, 178, 241, 104, 31, 136, 218, 200, 56, 200, 187, 163, 106,
+    242, 91, 251, 43, 124, 247, 71, 58, 88, 71, 227, 95,
+]);
+
+//Discovers the uniswap v3 pools that liquidity was added to through the position manager within the block range and
+//populates them at the to block. The pool of each position is resolved from its tokens and fee through the factory of
+//the position manager. Only liquidity added within the range is seen, so pools whose positions were all opened before
+//the from block are missed, as are pools only provided liquidity by contracts other than the position manager.
+//Positions burned since the liquidity was added can no longer be resolved and are skipped. The step must be greater
+//than zero
+pub async fn discover_active_v3_pools<M: 'static + Middleware>(
+    position_manager: H160,
+    from_block: u64,
+    to_block: u64,
+    step: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    if step == 0 {
+        return Err(AMMError::InvalidBlockStep);
+    }
+
+    let position_manager = INonfungiblePositionManager::new(position_manager, middleware.clone());
+    let factory =
+        IUniswapV3Factory::new(position_manager.factory().call().await?, middleware.clone());
+
+    //The token id is the only indexed topic of IncreaseLiquidity
+    let filter = Filter::new()
+        .address(position_manager.address())
+        .topic0(INCREASE_LIQUIDITY_EVENT_SIGNATURE);
+    let mut token_ids = HashSet::new();
+    let mut block = from_block;
+    while block <= to_block {
+        let target_block = (block + step - 1).min(to_block);
+        let logs = middleware
+            .get_logs(&filter.clone().from_block(block).to_block(target_block))
+            .await
+            .map_err(AMMError::MiddlewareError)?;
+
+        for log in logs {
+            if let Some(token_id) = log.topics.get(1) {
+                token_ids.insert(U256::from_big_endian(token_id.as_bytes()));
+            }
+        }
+
+        block = target_block + 1;
+    }
+
+    //Resolve the tokens and fee of each position, many positions share the same pool
+    let mut handles = JoinSet::new();
+    let mut pool_keys = HashSet::new();
+    for token_id in token_ids {
+        let position_manager =
+            INonfungiblePositionManager::new(position_manager.address(), middleware.clone());
+        handles.spawn(async move {
+            //Burned positions revert, any other error is propagated so that the pool set is not silently incomplete
+            match position_manager
+                .positions(token_id)
+                .block(to_block)
+                .call()
+                .await
+            {
+                Ok((_, _, token_0, token_1, fee, _, _, _, _, _, _, _)) => {
+                    Ok(Some((token_0, token_1, fee)))
+                }
+                Err(ContractError::Revert(_)) => Ok(None),
+                Err(err) => Err(err),
+            }
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(pool_key) = handles.join_next().await {
+                pool_keys.extend(pool_key??);
+            }
+        }
+    }
+    while let Some(pool_key) = handles.join_next().await {
+        pool_keys.extend(pool_key??);
+    }
+
+    let mut handles = JoinSet::new();
+    let mut pool_addresses = HashSet::new();
+    for (token_0, token_1, fee) in pool_keys {
+        let factory = IUniswapV3Factory::new(factory.address(), middleware.clone());
+        handles.spawn(async move {
+            factory
+                .get_pool(token_0, token_1, fee)
+                .block(to_block)
+                .call()
+                .await
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(pool_address) = handles.join_next().await {
+                let pool_address = pool_address??;
+                if !pool_address.is_zero() {
+                    pool_addresses.insert(pool_address);
+                }
+            }
+        }
+    }
+    while let Some(pool_address) = handles.join_next().await {
+        let pool_address = pool_address??;
+        if !pool_address.is_zero() {
+            pool_addresses.insert(pool_address);
+        }
+    }
+
+    let amms = pool_addresses
+        .into_iter()
+        .map(|address| {
+            AMM::UniswapV3Pool(UniswapV3Pool {
+                address,
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<AMM>>();
+
+    if amms.is_empty() {
+        return Ok(amms);
+    }
+
+    Ok(remove_empty_amms(
+        populate_amms(&amms, to_block, None, middleware).await?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers::{
+        abi::{encode, Token},
+        providers::{JsonRpcError, MockResponse, Provider},
+        types::{Bytes, Log, H160, H256, U256},
+    };
+
+    use crate::{
+        amm::{AutomatedMarketMaker, AMM},
+        errors::AMMError,
+    };
+
+    use super::{discover_active_v3_pools, INCREASE_LIQUIDITY_EVENT_SIGNATURE};
+
+    #[tokio::test]
+    async fn test_discover_active_v3_pools() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let position_manager = H160::from_low_u64_be(10);
+        let factory = H160::from_low_u64_be(11);
+        let pool = H160::from_low_u64_be(12);
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+
+        let increase_liquidity_log = |token_id: u64| Log {
+            address: position_manager,
+            topics: vec![
+                INCREASE_LIQUIDITY_EVENT_SIGNATURE,
+                H256::from_low_u64_be(token_id),
+            ],
+            data: encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(10)),
+                Token::Uint(U256::from(10)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        //Every position is in the same pool, so each positions call has the same response
+        let position = encode(&[
+            Token::Uint(U256::zero()),
+            Token::Address(H160::zero()),
+            Token::Address(token_0),
+            Token::Address(token_1),
+            Token::Uint(U256::from(500)),
+            Token::Int(U256::zero()),
+            Token::Int(U256::from(60)),
+            Token::Uint(U256::from(1000)),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+        ]);
+
+        //Responses are popped in reverse order
+        mock.push::<Bytes, _>(Bytes::from(encode(&[Token::Array(vec![Token::Tuple(
+            vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(6)),
+                Token::Uint(U256::from(1_000_000)),
+                Token::Uint(U256::one() << 96),
+                Token::Int(U256::zero()),
+                Token::Int(U256::from(10)),
+                Token::Uint(U256::from(500)),
+                Token::Int(U256::zero()),
+            ],
+        )])])))?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(pool)]).into())?;
+        mock.push::<Bytes, _>(position.clone().into())?;
+        mock.push::<Bytes, _>(position.into())?;
+        //Liquidity is added to the first position twice
+        mock.push::<Vec<Log>, _>(vec![increase_liquidity_log(2)])?;
+        mock.push::<Vec<Log>, _>(vec![increase_liquidity_log(1), increase_liquidity_log(1)])?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(factory)]).into())?;
+
+        let amms =
+            discover_active_v3_pools(position_manager, 100, 199, 50, Arc::new(provider)).await?;
+
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), pool);
+        assert_eq!(amms[0].tokens(), vec![token_0, token_1]);
+        if let AMM::UniswapV3Pool(pool) = &amms[0] {
+            assert_eq!(pool.fee, 500);
+            assert_eq!(pool.liquidity, 1_000_000);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_discover_active_v3_pools_errors() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let position_manager = H160::from_low_u64_be(10);
+        let factory = H160::from_low_u64_be(11);
+        let increase_liquidity_log = Log {
+            address: position_manager,
+            topics: vec![INCREASE_LIQUIDITY_EVENT_SIGNATURE, H256::from_low_u64_be(1)],
+            data: encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(10)),
+                Token::Uint(U256::from(10)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        //A zero step is rejected before any request is made
+        assert!(matches!(
+            discover_active_v3_pools(position_manager, 100, 199, 0, middleware.clone()).await,
+            Err(AMMError::InvalidBlockStep)
+        ));
+
+        //The position was burned, so it reverts and is skipped
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        mock.push::<Vec<Log>, _>(vec![increase_liquidity_log.clone()])?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(factory)]).into())?;
+        let amms =
+            discover_active_v3_pools(position_manager, 100, 199, 100, middleware.clone()).await?;
+        assert!(amms.is_empty());
+
+        //While a transport error is returned rather than dropping the position
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 429,
+            message: "Too many requests".to_string(),
+            data: None,
+        }));
+        mock.push::<Vec<Log>, _>(vec![increase_liquidity_log])?;
+        mock.push::<Bytes, _>(encode(&[Token::Address(factory)]).into())?;
+        assert!(matches!(
+            discover_active_v3_pools(position_manager, 100, 199, 100, middleware).await,
+            Err(AMMError::ContractError(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use ethers::prelude::{AbiError, ContractError};
 use ethers::providers::{Middleware, ProviderError};
 use ethers::types::{H160, U256};
-use std::time::SystemTimeError;
+use std::time::{Duration, SystemTimeError};
 use thiserror::Error;
 use tokio::task::JoinError;
 use uniswap_v3_math::error::UniswapV3MathError;
@@ -35,6 +35,8 @@ where
     PairDoesNotExistInDexes(H160, H160),
     #[error("Could not initialize new pool from event log")]
     UnrecognizedPoolCreatedEventLog,
+    #[error("Could not detect the pool type of {0}")]
+    UnrecognizedPoolType(H160),
     #[error("Error when syncing pool {0}")]
     SyncError(H160),
     #[error("Error when getting pool data")]
@@ -59,6 +61,16 @@ where
     BatchRequestError(H160),
     #[error("Checkpoint error: {0}")]
     CheckpointError(#[from] CheckpointError),
+    #[error("Log source error: {0}")]
+    LogSourceError(String),
+    #[error("Unsupported pool type: {0}")]
+    UnsupportedPoolType(String),
+    #[error("State at block {0} is unavailable, the node may not be an archive node or the block may predate its fork: {1}")]
+    StateUnavailable(u64, String),
+    #[error("Request timed out after {0:?}")]
+    RequestTimeout(Duration),
+    #[error("Block step must be greater than zero")]
+    InvalidBlockStep,
 }
 
 #[derive(Error, Debug)]
@@ -73,8 +85,14 @@ pub enum ArithmeticError {
     SqrtPriceOverflow,
     #[error("U128 conversion error")]
     U128ConversionError,
+    #[error("Pools {0} and {1} do not trade the same token pair")]
+    TokenPairMismatch(H160, H160),
     #[error("Uniswap v3 math error: {0}")]
     UniswapV3MathError(#[from] UniswapV3MathError),
+    #[error("Decimal scaling overflow")]
+    DecimalScalingOverflow,
+    #[error("Pool {0} is not a concentrated liquidity pool")]
+    NotConcentratedLiquidityPool(H160),
 }
 
 #[derive(Error, Debug)]
@@ -97,6 +115,14 @@ pub enum SwapSimulationError {
     UniswapV3MathError(#[from] UniswapV3MathError),
     #[error("Liquidity underflow")]
     LiquidityUnderflow,
+    #[error("Token {0} is not in the pool")]
+    TokenNotInPool(H160),
+    #[error("Pool {0} has unreliable reserves due to a rebasing token")]
+    UnreliableReserves(H160),
+    #[error("Arithmetic error: {0}")]
+    ArithmeticError(#[from] ArithmeticError),
+    #[error("Sqrt price limit {0} is on the wrong side of the current price")]
+    InvalidSqrtPriceLimit(U256),
 }
 
 #[derive(Error, Debug)]
@@ -107,4 +133,21 @@ pub enum CheckpointError {
     SerdeJsonError(#[from] serde_json::error::Error),
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
+    #[error("Join error: {0}")]
+    JoinError(#[from] JoinError),
+    #[cfg(feature = "encryption")]
+    #[error("Checkpoint encryption failed")]
+    EncryptionFailed,
+    #[cfg(feature = "encryption")]
+    #[error("Checkpoint decryption failed: {0}")]
+    DecryptionFailed(String),
+    #[cfg(feature = "checkpoint-formats")]
+    #[error("Binary checkpoint encode error: {0}")]
+    BinaryEncodeError(#[from] rmp_serde::encode::Error),
+    #[cfg(feature = "checkpoint-formats")]
+    #[error("Binary checkpoint decode error: {0}")]
+    BinaryDecodeError(#[from] rmp_serde::decode::Error),
+    #[cfg(feature = "checkpoint-formats")]
+    #[error("Unknown checkpoint format for path: {0}")]
+    UnknownFormat(String),
 }
@@ -7,7 +7,10 @@ use ethers::{
 use std::sync::Arc;
 
 use crate::{
-    amm::{factory::AutomatedMarketMakerFactory, factory::Factory, AutomatedMarketMaker, AMM},
+    amm::{
+        factory::AutomatedMarketMakerFactory, factory::Factory, with_request_timeout,
+        AutomatedMarketMaker, AMM,
+    },
     errors::AMMError,
 };
 
@@ -160,17 +163,21 @@ async fn get_weth_value_in_amm_batch_request<M: Middleware>(
         .map(|a| Token::Address(a.address()))
         .collect::<Vec<Token>>();
 
+    //Algebra factories have no getPool, which the batch request calls on every v3 factory without a try/catch, so a
+    //single Algebra factory would revert the whole call. They are left out since weth pairs cannot be found through them
+    let factories = factories
+        .iter()
+        .filter(|factory| !matches!(factory, Factory::AlgebraFactory(_)))
+        .collect::<Vec<&Factory>>();
+
     let factory_is_uni_v3 = factories
         .iter()
-        .map(|d| match d {
-            Factory::UniswapV2Factory(_) => Token::Bool(false),
-            Factory::UniswapV3Factory(_) => Token::Bool(true),
-        })
+        .map(|factory| Token::Bool(matches!(factory, Factory::UniswapV3Factory(_))))
         .collect::<Vec<Token>>();
 
     let factories = factories
         .iter()
-        .map(|f| Token::Address(f.address()))
+        .map(|factory| Token::Address(factory.address()))
         .collect::<Vec<Token>>();
 
     let constructor_args = Token::Tuple(vec![
@@ -182,7 +189,7 @@ async fn get_weth_value_in_amm_batch_request<M: Middleware>(
     ]);
 
     let deployer = GetWethValueInAMMBatchRequest::deploy(middleware, constructor_args)?;
-    let return_data: Bytes = deployer.call_raw().await?;
+    let return_data: Bytes = with_request_timeout(deployer.call_raw()).await?;
 
     let return_data_tokens = ethers::abi::decode(
         &[ParamType::Array(Box::new(ParamType::Uint(256)))],
@@ -201,3 +208,114 @@ async fn get_weth_value_in_amm_batch_request<M: Middleware>(
 
     Ok(weth_values_in_pools)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ethers::{
+        abi::Token,
+        providers::{Middleware, MockProvider, Provider, ProviderError},
+        types::{transaction::eip2718::TypedTransaction, BlockId, Bytes, H160, U256},
+    };
+
+    use crate::amm::{
+        algebra::factory::AlgebraFactory,
+        factory::Factory,
+        uniswap_v2::{factory::UniswapV2Factory, UniswapV2Pool},
+        uniswap_v3::factory::UniswapV3Factory,
+        AMM,
+    };
+
+    use super::get_weth_values_in_amms;
+
+    //Middleware recording the data of every call and answering with a weth value of 1 ether per pool
+    #[derive(Debug)]
+    struct RecordingMiddleware {
+        inner: Provider<MockProvider>,
+        pools: usize,
+        calls: Mutex<Vec<Bytes>>,
+    }
+
+    #[async_trait]
+    impl Middleware for RecordingMiddleware {
+        type Error = ProviderError;
+        type Provider = MockProvider;
+        type Inner = Provider<MockProvider>;
+
+        fn inner(&self) -> &Provider<MockProvider> {
+            &self.inner
+        }
+
+        async fn call(
+            &self,
+            tx: &TypedTransaction,
+            _block: Option<BlockId>,
+        ) -> Result<Bytes, Self::Error> {
+            self.calls
+                .lock()
+                .expect("Calls lock should not be poisoned")
+                .push(tx.data().cloned().unwrap_or_default());
+
+            Ok(ethers::abi::encode(&[Token::Array(
+                (0..self.pools)
+                    .map(|_| Token::Uint(U256::exp10(18)))
+                    .collect(),
+            )])
+            .into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_weth_values_skips_algebra_factories() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+        let middleware = Arc::new(RecordingMiddleware {
+            inner: provider,
+            pools: 1,
+            calls: Mutex::new(vec![]),
+        });
+
+        let v2_factory = H160::from_low_u64_be(10);
+        let v3_factory = H160::from_low_u64_be(11);
+        let weth = H160::from_low_u64_be(2);
+        let pool = H160::from_low_u64_be(3);
+        let factories = vec![
+            Factory::UniswapV2Factory(UniswapV2Factory::new(v2_factory, 0, 300)),
+            Factory::AlgebraFactory(AlgebraFactory::new(H160::from_low_u64_be(12), 0)),
+            Factory::UniswapV3Factory(UniswapV3Factory::new(v3_factory, 0)),
+        ];
+        let amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: pool,
+            ..Default::default()
+        })];
+
+        let weth_values = get_weth_values_in_amms(
+            &amms,
+            &factories,
+            weth,
+            U256::zero(),
+            100,
+            middleware.clone(),
+        )
+        .await?;
+        assert_eq!(weth_values, vec![U256::exp10(18)]);
+
+        //The constructor args follow the bytecode and only hold the v2 and v3 factories
+        let constructor_args = ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Array(vec![Token::Address(pool)]),
+            Token::Array(vec![Token::Address(v2_factory), Token::Address(v3_factory)]),
+            Token::Array(vec![Token::Bool(false), Token::Bool(true)]),
+            Token::Address(weth),
+            Token::Uint(U256::zero()),
+        ])]);
+        let calls = middleware
+            .calls
+            .lock()
+            .expect("Calls lock should not be poisoned");
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].ends_with(&constructor_args));
+
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 pub mod amm;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod constants;
 pub mod discovery;
 pub mod errors;
 pub mod filters;
+pub mod routing;
+pub mod serde_helpers;
 pub mod state_space;
 pub mod sync;
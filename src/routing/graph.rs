@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use ethers::types::H160;
+
+use crate::amm::{algebra::AlgebraPool, AutomatedMarketMaker, AMM};
+
+//An undirected graph of tokens, where each edge is a pool trading both tokens
+#[derive(Debug, Clone, Default)]
+pub struct PoolGraph {
+    //Maps each token to its neighbouring tokens and the index of the pool connecting them
+    pub edges: HashMap<H160, Vec<(H160, usize)>>,
+}
+
+impl PoolGraph {
+    pub fn new(pools: &[AMM]) -> Self {
+        let mut edges: HashMap<H160, Vec<(H160, usize)>> = HashMap::new();
+
+        for (pool_idx, pool) in pools.iter().enumerate() {
+            let tokens = pool.tokens();
+            for token_in in tokens.iter() {
+                for token_out in tokens.iter() {
+                    if token_in != token_out {
+                        edges
+                            .entry(*token_in)
+                            .or_default()
+                            .push((*token_out, pool_idx));
+                    }
+                }
+            }
+        }
+
+        PoolGraph { edges }
+    }
+
+    pub fn neighbors(&self, token: H160) -> &[(H160, usize)] {
+        self.edges.get(&token).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    //Renders the graph in GraphViz DOT for debugging connectivity, with a node per token and an undirected edge per pool
+    //labeled with the pool address, its fee in the units of its variant and the balance of each token in whole tokens.
+    //The graph only holds pool indices, so the pools it was built from are needed for the labels
+    pub fn to_dot(&self, pools: &[AMM]) -> String {
+        self.render_dot(pools, None)
+    }
+
+    //Renders the graph like `to_dot`, keeping only the tokens and the pools between them, so that large graphs stay readable
+    pub fn to_dot_for_tokens(&self, pools: &[AMM], tokens: &[H160]) -> String {
+        self.render_dot(pools, Some(&tokens.iter().copied().collect()))
+    }
+
+    fn render_dot(&self, pools: &[AMM], tokens: Option<&HashSet<H160>>) -> String {
+        let included = |token: &H160| match tokens {
+            Some(tokens) => tokens.contains(token),
+            None => true,
+        };
+
+        let mut nodes: Vec<H160> = self.edges.keys().copied().filter(included).collect();
+        nodes.sort();
+
+        //Each pool is stored under both of its tokens, so only the edge from the lower token is kept
+        let mut edges: Vec<(H160, H160, usize)> = self
+            .edges
+            .iter()
+            .filter(|(token_in, _)| included(*token_in))
+            .flat_map(|(token_in, neighbors)| {
+                neighbors
+                    .iter()
+                    .filter(move |(token_out, _)| token_in < token_out && included(token_out))
+                    .map(move |(token_out, pool_idx)| (*token_in, *token_out, *pool_idx))
+            })
+            .collect();
+        edges.sort();
+
+        let mut dot = String::from("graph pools {\n");
+        for token in nodes {
+            let _ = writeln!(dot, "    \"{:?}\";", token);
+        }
+        for (token_a, token_b, pool_idx) in edges {
+            let pool = &pools[pool_idx];
+            let (_, fee, address) = pool.canonical_key();
+            let _ = writeln!(
+                dot,
+                "    \"{:?}\" -- \"{:?}\" [label=\"{:?}\\nfee {}\\ndepth {:.2} / {:.2}\"];",
+                token_a,
+                token_b,
+                address,
+                fee,
+                token_balance(pool, token_a),
+                token_balance(pool, token_b)
+            );
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+//Liquidity of the token held by the pool, used to compare pools sharing the same token
+pub fn liquidity_depth(amm: &AMM, token: H160) -> f64 {
+    match amm {
+        AMM::UniswapV2Pool(pool) => {
+            if pool.token_a == token {
+                pool.reserve_0 as f64
+            } else {
+                pool.reserve_1 as f64
+            }
+        }
+        AMM::UniswapV3Pool(pool) | AMM::AlgebraPool(AlgebraPool { pool }) => {
+            match pool.calculate_virtual_reserves() {
+                Ok((reserve_0, reserve_1)) => {
+                    if pool.token_a == token {
+                        reserve_0 as f64
+                    } else {
+                        reserve_1 as f64
+                    }
+                }
+                Err(_) => 0.0,
+            }
+        }
+        AMM::ERC4626Vault(vault) => {
+            let reserve = if vault.vault_token == token {
+                vault.vault_reserve
+            } else {
+                vault.asset_reserve
+            };
+            reserve.to_string().parse().unwrap_or(0.0)
+        }
+    }
+}
+
+//Balance of the token held by the pool in whole tokens, ie. the liquidity depth scaled down by the token decimals
+pub fn token_balance(amm: &AMM, token: H160) -> f64 {
+    let decimals = match amm {
+        AMM::UniswapV2Pool(pool) => token_decimals(
+            pool.token_a,
+            pool.token_a_decimals,
+            pool.token_b_decimals,
+            token,
+        ),
+        AMM::UniswapV3Pool(pool) | AMM::AlgebraPool(AlgebraPool { pool }) => token_decimals(
+            pool.token_a,
+            pool.token_a_decimals,
+            pool.token_b_decimals,
+            token,
+        ),
+        AMM::ERC4626Vault(vault) => token_decimals(
+            vault.vault_token,
+            vault.vault_token_decimals,
+            vault.asset_token_decimals,
+            token,
+        ),
+    };
+
+    liquidity_depth(amm, token) / 10_f64.powi(decimals as i32)
+}
+
+fn token_decimals(token_a: H160, token_a_decimals: u8, token_b_decimals: u8, token: H160) -> u8 {
+    if token_a == token {
+        token_a_decimals
+    } else {
+        token_b_decimals
+    }
+}
@@ -0,0 +1,964 @@
+pub mod graph;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use ethers::types::{H160, I256, U256, U512};
+
+use crate::{
+    amm::{
+        u256_to_f64,
+        uniswap_v2::{fee_fraction, UniswapV2Pool},
+        AutomatedMarketMaker, AMM,
+    },
+    errors::{ArithmeticError, SwapSimulationError},
+};
+
+pub use graph::PoolGraph;
+
+//Calculates the fractional difference between the spot price of pool b and pool a, ie. (price_b - price_a) / price_a
+pub fn price_spread(pool_a: &AMM, pool_b: &AMM, base_token: H160) -> Result<f64, ArithmeticError> {
+    let mut tokens_a = pool_a.tokens();
+    let mut tokens_b = pool_b.tokens();
+    tokens_a.sort();
+    tokens_b.sort();
+
+    if tokens_a != tokens_b || !tokens_a.contains(&base_token) {
+        return Err(ArithmeticError::TokenPairMismatch(
+            pool_a.address(),
+            pool_b.address(),
+        ));
+    }
+
+    let price_a = pool_a.calculate_price(base_token)?;
+    let price_b = pool_b.calculate_price(base_token)?;
+
+    if price_a == 0.0 {
+        return Err(ArithmeticError::YIsZero);
+    }
+
+    Ok((price_b - price_a) / price_a)
+}
+
+//Combines the spot prices of concentrated liquidity pools trading the same pair, eg. the fee tiers of a v3 pair, into a
+//mid price weighted by the in range liquidity of each pool. Each pool is priced from its own sqrt price, so the tiers
+//are never mixed up, and pools without in range liquidity do not move the mid price
+pub fn weighted_mid_price(pools: &[&AMM], base_token: H160) -> Result<f64, ArithmeticError> {
+    let mut weighted_price = 0.0;
+    let mut total_liquidity = 0.0;
+    for pool in pools {
+        let liquidity = match pool {
+            AMM::UniswapV3Pool(pool) => pool.liquidity,
+            AMM::AlgebraPool(pool) => pool.pool.liquidity,
+            _ => {
+                return Err(ArithmeticError::NotConcentratedLiquidityPool(
+                    pool.address(),
+                ))
+            }
+        };
+
+        let mut tokens = pool.tokens();
+        let mut first_tokens = pools[0].tokens();
+        tokens.sort();
+        first_tokens.sort();
+        if tokens != first_tokens || !tokens.contains(&base_token) {
+            return Err(ArithmeticError::TokenPairMismatch(
+                pools[0].address(),
+                pool.address(),
+            ));
+        }
+
+        if liquidity == 0 {
+            continue;
+        }
+
+        weighted_price += pool.calculate_price(base_token)? * liquidity as f64;
+        total_liquidity += liquidity as f64;
+    }
+
+    if total_liquidity == 0.0 {
+        return Err(ArithmeticError::YIsZero);
+    }
+
+    Ok(weighted_price / total_liquidity)
+}
+
+//Maps the zero address, which stands for the native token, to the wrapped native token that pools actually hold. The
+//pricing and routing functions take the wrapped native token, passing the zero address leaves every token as is
+pub fn resolve_native(token: H160, wrapped_native: H160) -> H160 {
+    if token.is_zero() {
+        wrapped_native
+    } else {
+        token
+    }
+}
+
+//Computes the price of every token reachable from the numeraire, in units of the numeraire.
+//Walks the pool graph breadth first, pricing each newly reached token through the deepest pool connecting it to an already priced token.
+//The native token is priced like the wrapped native token under the zero address, and can be used as the numeraire
+pub fn compute_token_prices(
+    pools: &[AMM],
+    numeraire: H160,
+    wrapped_native: H160,
+) -> HashMap<H160, f64> {
+    compute_token_prices_in_graph(pools, &PoolGraph::new(pools), numeraire, wrapped_native)
+}
+
+//Computes the token prices like `compute_token_prices`, reusing a graph already built from the pools
+pub fn compute_token_prices_in_graph(
+    pools: &[AMM],
+    graph: &PoolGraph,
+    numeraire: H160,
+    wrapped_native: H160,
+) -> HashMap<H160, f64> {
+    let numeraire = resolve_native(numeraire, wrapped_native);
+
+    let mut prices = HashMap::new();
+    prices.insert(numeraire, 1.0);
+
+    let mut queue = VecDeque::from([numeraire]);
+    while let Some(token) = queue.pop_front() {
+        let token_price = prices[&token];
+
+        //Find the deepest pool to each unpriced neighbour
+        let mut deepest: HashMap<H160, (usize, f64)> = HashMap::new();
+        for (neighbor, pool_idx) in graph.neighbors(token) {
+            if prices.contains_key(neighbor) {
+                continue;
+            }
+
+            let depth = graph::liquidity_depth(&pools[*pool_idx], token);
+            let entry = deepest.entry(*neighbor).or_insert((*pool_idx, depth));
+            if depth > entry.1 {
+                *entry = (*pool_idx, depth);
+            }
+        }
+
+        for (neighbor, (pool_idx, _)) in deepest {
+            //Price of the neighbour in units of the priced token
+            if let Ok(price) = pools[pool_idx].calculate_price(neighbor) {
+                if price.is_finite() && price > 0.0 {
+                    prices.insert(neighbor, price * token_price);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+    }
+
+    if !wrapped_native.is_zero() {
+        if let Some(price) = prices.get(&wrapped_native).copied() {
+            prices.insert(H160::zero(), price);
+        }
+    }
+
+    prices
+}
+
+//Sums the value of the amms discovered from the factory in units of the numeraire. The pool graph must be built from the
+//amms, tokens are priced through it and amms holding a token that can not be priced from the numeraire are skipped
+pub fn factory_tvl(
+    amms: &[AMM],
+    factory: H160,
+    numeraire: H160,
+    wrapped_native: H160,
+    pools: &PoolGraph,
+) -> f64 {
+    let prices = compute_token_prices_in_graph(amms, pools, numeraire, wrapped_native);
+
+    amms.iter()
+        .filter(|amm| amm.factory() == factory)
+        .filter_map(|amm| {
+            amm.tokens()
+                .into_iter()
+                .map(|token| Some(graph::token_balance(amm, token) * prices.get(&token)?))
+                .sum::<Option<f64>>()
+        })
+        .sum()
+}
+
+//Max iterations of the ternary search used to find the optimal arbitrage amount for non v2 pool pairs
+const ARB_SEARCH_ITERATIONS: usize = 256;
+
+//Calculates the amount of token_in that maximizes the profit of swapping token_in for the other token in pool a and
+//swapping the output back to token_in in pool b, ie. out_b(out_a(x)) - x. Returns zero if no size is profitable.
+//Two v2 pools are solved in closed form, other pools are searched numerically with the swap simulations
+pub fn optimal_arb_amount(
+    pool_a: &AMM,
+    pool_b: &AMM,
+    token_in: H160,
+) -> Result<U256, ArithmeticError> {
+    let mut tokens_a = pool_a.tokens();
+    let mut tokens_b = pool_b.tokens();
+    tokens_a.sort();
+    tokens_b.sort();
+
+    if tokens_a != tokens_b || !tokens_a.contains(&token_in) {
+        return Err(ArithmeticError::TokenPairMismatch(
+            pool_a.address(),
+            pool_b.address(),
+        ));
+    }
+
+    let amount_in = match (pool_a, pool_b) {
+        (AMM::UniswapV2Pool(pool_a), AMM::UniswapV2Pool(pool_b)) => {
+            optimal_v2_arb_amount(pool_a, pool_b, token_in)
+        }
+        _ => search_arb_amount(pool_a, pool_b, token_in),
+    };
+
+    //Rounding in the swap math can erase the profit of tiny amounts
+    if arb_profit(pool_a, pool_b, token_in, amount_in) > I256::zero() {
+        Ok(amount_in)
+    } else {
+        Ok(U256::zero())
+    }
+}
+
+//Both swaps compose to out(x) = A * x / (B + C * x) with A = fa * fb * ra_out * rb_out, B = da * db * ra_in * rb_in
+//and C = fa * (db * rb_in + fb * ra_out), where fa / da is the share of the amount in left after the fee of pool a.
+//Setting out'(x) = 1 gives x = (sqrt(A * B) - B) / C, which is profitable only when A > B
+fn optimal_v2_arb_amount(pool_a: &UniswapV2Pool, pool_b: &UniswapV2Pool, token_in: H160) -> U256 {
+    let reserves = |pool: &UniswapV2Pool| {
+        if pool.token_a == token_in {
+            (U512::from(pool.reserve_0), U512::from(pool.reserve_1))
+        } else {
+            (U512::from(pool.reserve_1), U512::from(pool.reserve_0))
+        }
+    };
+    let fee_multiplier = |pool: &UniswapV2Pool| {
+        let (numerator, denominator) = fee_fraction(pool.fee);
+        (U512::from(numerator), U512::from(denominator))
+    };
+
+    let (reserve_a_in, reserve_a_out) = reserves(pool_a);
+    //Pool b is swapped in the opposite direction
+    let (reserve_b_out, reserve_b_in) = reserves(pool_b);
+    let ((fee_a, denominator_a), (fee_b, denominator_b)) =
+        (fee_multiplier(pool_a), fee_multiplier(pool_b));
+
+    let a = fee_a * fee_b * reserve_a_out * reserve_b_out;
+    let b = denominator_a * denominator_b * reserve_a_in * reserve_b_in;
+    let c = fee_a * (denominator_b * reserve_b_in + fee_b * reserve_a_out);
+
+    if a <= b || c.is_zero() {
+        return U256::zero();
+    }
+
+    U256::try_from(((a * b).integer_sqrt() - b) / c).unwrap_or(U256::zero())
+}
+
+//The profit is concave in the amount in, so a ternary search over the amounts up to the token_in reserve of pool b finds the maximum
+fn search_arb_amount(pool_a: &AMM, pool_b: &AMM, token_in: H160) -> U256 {
+    //Any amount above the token_in pool b can output is a loss
+    let mut low = U256::zero();
+    let mut high = pool_b.max_output(token_in).min(U256::from(u128::MAX));
+
+    for _ in 0..ARB_SEARCH_ITERATIONS {
+        if high - low < U256::from(3) {
+            break;
+        }
+
+        let third = (high - low) / 3;
+        let mid_low = low + third;
+        let mid_high = high - third;
+
+        if arb_profit(pool_a, pool_b, token_in, mid_low)
+            < arb_profit(pool_a, pool_b, token_in, mid_high)
+        {
+            low = mid_low;
+        } else {
+            high = mid_high;
+        }
+    }
+
+    low
+}
+
+//Profit of the arbitrage for the amount in, failed simulations are treated as the largest loss
+fn arb_profit(pool_a: &AMM, pool_b: &AMM, token_in: H160, amount_in: U256) -> I256 {
+    let token_out = pool_a.get_token_out(token_in);
+
+    match pool_a
+        .simulate_swap(token_in, amount_in)
+        .and_then(|amount_out| pool_b.simulate_swap(token_out, amount_out))
+    {
+        Ok(amount_back) => I256::from_raw(amount_back) - I256::from_raw(amount_in),
+        Err(_) => I256::MIN,
+    }
+}
+
+//Returns the pool trading token_in for token_out with the highest output for the amount in, along with the output. Pools
+//with the same output are picked by the lowest canonical key, so the choice does not depend on the order of the pools.
+//The native token is looked up as the wrapped native token
+pub fn best_pool_for_pair(
+    pools: &[AMM],
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    wrapped_native: H160,
+) -> Option<(&AMM, U256)> {
+    let token_in = resolve_native(token_in, wrapped_native);
+    let token_out = resolve_native(token_out, wrapped_native);
+
+    pools
+        .iter()
+        .filter(|pool| {
+            let tokens = pool.tokens();
+            token_in != token_out && tokens.contains(&token_in) && tokens.contains(&token_out)
+        })
+        .filter_map(|pool| {
+            pool.simulate_swap(token_in, amount_in)
+                .ok()
+                .map(|amount_out| (pool, amount_out))
+        })
+        .max_by(|(pool_a, amount_out_a), (pool_b, amount_out_b)| {
+            amount_out_a
+                .cmp(amount_out_b)
+                .then_with(|| pool_b.canonical_key().cmp(&pool_a.canonical_key()))
+        })
+}
+
+//Largest share of a pool's depth of the token in that a single hop of `find_path_with_min_depth` may swap
+pub const MAX_HOP_DEPTH_SHARE: f64 = 0.1;
+
+//Finds the path from token_in to token_out with the fewest hops, up to max hops, where the amount swapped into every hop
+//is at most `MAX_HOP_DEPTH_SHARE` of the pool's depth of the token in. The amount in of each hop is the simulated output
+//of the previous hop, so shallow pools are pruned as soon as they are reached. The pool graph must be built from the
+//pools, returns the indices of the pools along the path or None if no path keeps every hop deep enough
+pub fn find_path_with_min_depth(
+    pools: &[AMM],
+    graph: &PoolGraph,
+    token_in: H160,
+    token_out: H160,
+    amount: U256,
+    max_hops: usize,
+) -> Option<Vec<usize>> {
+    if token_in == token_out {
+        return None;
+    }
+
+    let mut visited = HashSet::from([token_in]);
+    let mut queue = VecDeque::from([(token_in, amount, vec![])]);
+    while let Some((token, amount_in, path)) = queue.pop_front() {
+        if path.len() == max_hops {
+            continue;
+        }
+
+        for (neighbor, pool_idx) in graph.neighbors(token) {
+            if visited.contains(neighbor) {
+                continue;
+            }
+
+            let pool = &pools[*pool_idx];
+            if u256_to_f64(amount_in) > graph::liquidity_depth(pool, token) * MAX_HOP_DEPTH_SHARE {
+                continue;
+            }
+
+            let Ok(amount_out) = pool.simulate_swap(token, amount_in) else {
+                continue;
+            };
+
+            let mut next_path = path.clone();
+            next_path.push(*pool_idx);
+            if *neighbor == token_out {
+                return Some(next_path);
+            }
+
+            visited.insert(*neighbor);
+            queue.push_back((*neighbor, amount_out, next_path));
+        }
+    }
+
+    None
+}
+
+//Number of equal chunks the amount in is split into when allocating a split swap across pools
+const SPLIT_SWAP_STEPS: u64 = 100;
+
+//Splits the amount in across the pools to maximize the total output, returning the total output and the amount in
+//allocated to each pool. Each chunk of the amount in is water-filled into the pool with the highest output for it,
+//which equalizes the marginal prices of the pools since the output of each pool is concave in its amount in.
+//Pools that cannot simulate the swap are left unallocated, and any amount no pool can take is left unallocated.
+//A native token in is swapped as the wrapped native token
+pub fn simulate_split_swap(
+    pools: &[&AMM],
+    token_in: H160,
+    amount_in: U256,
+    wrapped_native: H160,
+) -> (U256, Vec<U256>) {
+    let token_in = resolve_native(token_in, wrapped_native);
+
+    let mut allocations = vec![U256::zero(); pools.len()];
+    let mut amounts_out = vec![U256::zero(); pools.len()];
+
+    let chunk = (amount_in / SPLIT_SWAP_STEPS).max(U256::one());
+    let mut remaining = amount_in;
+    while !remaining.is_zero() {
+        let step = chunk.min(remaining);
+
+        let best_pool = pools
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, pool)| {
+                pool.simulate_swap(token_in, allocations[idx] + step)
+                    .ok()
+                    .map(|amount_out| (idx, amount_out))
+            })
+            .max_by(|(idx_a, amount_out_a), (idx_b, amount_out_b)| {
+                amount_out_a
+                    .saturating_sub(amounts_out[*idx_a])
+                    .cmp(&amount_out_b.saturating_sub(amounts_out[*idx_b]))
+                    //Chunks with equal output go to the pool with the lowest canonical key
+                    .then_with(|| {
+                        pools[*idx_b]
+                            .canonical_key()
+                            .cmp(&pools[*idx_a].canonical_key())
+                    })
+            });
+
+        match best_pool {
+            Some((idx, amount_out)) => {
+                allocations[idx] += step;
+                amounts_out[idx] = amount_out;
+            }
+            None => break,
+        }
+
+        remaining -= step;
+    }
+
+    (
+        amounts_out
+            .iter()
+            .fold(U256::zero(), |total, amount_out| total + amount_out),
+        allocations,
+    )
+}
+
+//Applies a sequence of swaps to the pools with `simulate_swap_mut`, accumulating the net change of each token balance of
+//the trader across the swaps. Each swap decreases the balance of the token in by the amount in and increases the balance
+//of the token out by the amount out, so tokens passed through every leg of a route net out to zero
+#[derive(Debug, Clone, Default)]
+pub struct SwapBatch {
+    deltas: HashMap<H160, I256>,
+}
+
+impl SwapBatch {
+    pub fn new() -> Self {
+        SwapBatch::default()
+    }
+
+    //Swaps the amount in through the amm, updating its state and the token deltas, and returns the amount out
+    pub fn swap(
+        &mut self,
+        amm: &mut AMM,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        let amount_out = amm.simulate_swap_mut(token_in, amount_in)?;
+        let token_out = amm.get_token_out(token_in);
+
+        let signed_amount_in =
+            I256::try_from(amount_in).map_err(|_| ArithmeticError::ShadowOverflow(amount_in))?;
+        let signed_amount_out =
+            I256::try_from(amount_out).map_err(|_| ArithmeticError::ShadowOverflow(amount_out))?;
+
+        *self.deltas.entry(token_in).or_insert(I256::zero()) -= signed_amount_in;
+        *self.deltas.entry(token_out).or_insert(I256::zero()) += signed_amount_out;
+
+        Ok(amount_out)
+    }
+
+    //Returns the net change of the token balance, zero for tokens not swapped
+    pub fn delta(&self, token: H160) -> I256 {
+        self.deltas.get(&token).copied().unwrap_or(I256::zero())
+    }
+
+    pub fn deltas(&self) -> &HashMap<H160, I256> {
+        &self.deltas
+    }
+
+    pub fn into_deltas(self) -> HashMap<H160, I256> {
+        self.deltas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, I256, U256};
+
+    use crate::amm::{
+        uniswap_v2::UniswapV2Pool, uniswap_v3::UniswapV3Pool, AutomatedMarketMaker, AMM,
+    };
+
+    use super::{
+        best_pool_for_pair, compute_token_prices, factory_tvl, find_path_with_min_depth,
+        optimal_arb_amount, price_spread, search_arb_amount, simulate_split_swap,
+        weighted_mid_price, PoolGraph, SwapBatch,
+    };
+
+    fn v2_pool(
+        address: u64,
+        token_a: H160,
+        token_b: H160,
+        reserve_0: u128,
+        reserve_1: u128,
+    ) -> AMM {
+        AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(address),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_swap_batch_net_deltas() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        //Token b is worth more token a in the second pool, so a round trip from token a through both pools is profitable
+        let mut pool_1 = v2_pool(10, token_a, token_b, 100_000_000, 200_000_000);
+        let mut pool_2 = v2_pool(11, token_a, token_b, 100_000_000, 150_000_000);
+        let expected_b_out = pool_1.simulate_swap(token_a, U256::from(1_000_000))?;
+
+        let mut batch = SwapBatch::new();
+        let b_out = batch.swap(&mut pool_1, token_a, U256::from(1_000_000))?;
+        assert_eq!(b_out, expected_b_out);
+        assert_eq!(batch.delta(token_b), I256::from_raw(b_out));
+
+        let expected_a_out = pool_2.simulate_swap(token_b, b_out)?;
+        let a_out = batch.swap(&mut pool_2, token_b, b_out)?;
+        assert_eq!(a_out, expected_a_out);
+
+        //The intermediate token nets out and the net change of token a is the round trip profit
+        let deltas = batch.into_deltas();
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[&token_b], I256::zero());
+        assert_eq!(
+            deltas[&token_a],
+            I256::from_raw(a_out) - I256::from(1_000_000)
+        );
+        assert!(deltas[&token_a] > I256::zero());
+
+        //The swaps are applied to the pool states
+        if let AMM::UniswapV2Pool(pool) = &pool_1 {
+            assert_eq!(pool.reserve_0, 101_000_000);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_token_prices() -> eyre::Result<()> {
+        let weth = H160::from_low_u64_be(1);
+        let token_a = H160::from_low_u64_be(2);
+        let token_b = H160::from_low_u64_be(3);
+        let token_c = H160::from_low_u64_be(4);
+        let token_d = H160::from_low_u64_be(5);
+
+        let pools = vec![
+            //Shallow pool pricing token a at 3 weth
+            v2_pool(10, token_a, weth, 1000000000000000000, 3000000000000000000),
+            //Deep pool pricing token a at 2 weth
+            v2_pool(
+                11,
+                token_a,
+                weth,
+                500000000000000000000,
+                1000000000000000000000,
+            ),
+            //Token b at 4 token a
+            v2_pool(
+                12,
+                token_b,
+                token_a,
+                100000000000000000000,
+                400000000000000000000,
+            ),
+            //Unreachable from weth
+            v2_pool(
+                13,
+                token_c,
+                token_d,
+                100000000000000000000,
+                100000000000000000000,
+            ),
+        ];
+
+        let prices = compute_token_prices(&pools, weth, H160::zero());
+
+        assert_eq!(prices.len(), 3);
+        assert_eq!(prices[&weth], 1.0);
+        assert!((prices[&token_a] - 2.0).abs() < 1e-9);
+        assert!((prices[&token_b] - 8.0).abs() < 1e-9);
+        assert!(!prices.contains_key(&token_c));
+        assert!(!prices.contains_key(&token_d));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimal_arb_amount() -> eyre::Result<()> {
+        let token_in = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+
+        //Buys token_out at 2 per token_in in pool a and sells it back at 1.1 token_in per 2 token_out in pool b
+        let pool_a = v2_pool(
+            10,
+            token_in,
+            token_out,
+            1000000000000000000000,
+            2000000000000000000000,
+        );
+        let pool_b = v2_pool(
+            11,
+            token_in,
+            token_out,
+            1100000000000000000000,
+            2000000000000000000000,
+        );
+
+        //x = (sqrt(A * B) - B) / C = (sqrt(997^2 * 2000 * 1100 * 10^6 * 1000 * 2000) - 10^6 * 1000 * 2000) / (997 * (1000 * 2000 + 997 * 2000)) * 10^18
+        let optimal_amount = optimal_arb_amount(&pool_a, &pool_b, token_in)?;
+        assert_eq!(optimal_amount, U256::from(22934312012472616824_u128));
+
+        //The numerical search converges on the closed form amount
+        let searched_amount = search_arb_amount(&pool_a, &pool_b, token_in);
+        let difference = if searched_amount > optimal_amount {
+            searched_amount - optimal_amount
+        } else {
+            optimal_amount - searched_amount
+        };
+        assert!(difference < optimal_amount / 10000);
+
+        //No size is profitable across pools with the same price
+        assert_eq!(
+            optimal_arb_amount(&pool_a, &pool_a, token_in)?,
+            U256::zero()
+        );
+
+        //Arbitraging in the other direction loses on both pools
+        assert_eq!(
+            optimal_arb_amount(&pool_b, &pool_a, token_in)?,
+            U256::zero()
+        );
+        assert!(optimal_arb_amount(&pool_a, &pool_b, H160::from_low_u64_be(3)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_native_through_wrapped() -> eyre::Result<()> {
+        let weth = H160::from_low_u64_be(1);
+        let usdc = H160::from_low_u64_be(2);
+        let native = H160::zero();
+
+        //Weth at 2000 usdc
+        let pools = vec![v2_pool(
+            10,
+            weth,
+            usdc,
+            1000000000000000000000,
+            2000000000000000000000000,
+        )];
+
+        //The native token is priced through the weth pool
+        let prices = compute_token_prices(&pools, usdc, weth);
+        assert!((prices[&weth] - 2000.0).abs() < 1e-6);
+        assert_eq!(prices[&native], prices[&weth]);
+
+        //And can be the numeraire
+        let prices = compute_token_prices(&pools, native, weth);
+        assert_eq!(prices[&native], 1.0);
+        assert!((prices[&usdc] - 0.0005).abs() < 1e-12);
+
+        let amount_in = U256::from(1000000000000000000_u128);
+        let (pool, amount_out) = best_pool_for_pair(&pools, native, usdc, amount_in, weth)
+            .expect("The weth pool should trade native for usdc");
+        assert_eq!(pool.address(), pools[0].address());
+        assert_eq!(amount_out, pools[0].simulate_swap(weth, amount_in)?);
+
+        //Without a wrapped native token the zero address is not found in any pool
+        assert!(best_pool_for_pair(&pools, native, usdc, amount_in, H160::zero()).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_split_swap() -> eyre::Result<()> {
+        let token_in = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+
+        let deep_pool = v2_pool(
+            10,
+            token_in,
+            token_out,
+            1000000000000000000000,
+            1000000000000000000000,
+        );
+        let shallow_pool = v2_pool(
+            11,
+            token_in,
+            token_out,
+            500000000000000000000,
+            500000000000000000000,
+        );
+
+        let amount_in = U256::from(100000000000000000000_u128);
+        let (amount_out, allocations) = simulate_split_swap(
+            &[&deep_pool, &shallow_pool],
+            token_in,
+            amount_in,
+            H160::zero(),
+        );
+
+        //Every chunk is allocated, with the deeper pool taking the larger share
+        assert_eq!(allocations[0] + allocations[1], amount_in);
+        assert!(allocations[0] > allocations[1]);
+        assert!(!allocations[1].is_zero());
+
+        //The split beats routing the whole amount through the deepest pool
+        assert!(amount_out > deep_pool.simulate_swap(token_in, amount_in)?);
+        assert_eq!(
+            amount_out,
+            deep_pool.simulate_swap(token_in, allocations[0])?
+                + shallow_pool.simulate_swap(token_in, allocations[1])?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_pool_for_pair_tie_break() -> eyre::Result<()> {
+        let token_in = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+
+        let pool_a = v2_pool(
+            10,
+            token_in,
+            token_out,
+            1000000000000000000000,
+            1000000000000000000000,
+        );
+        let pool_b = v2_pool(
+            11,
+            token_in,
+            token_out,
+            1000000000000000000000,
+            1000000000000000000000,
+        );
+        assert!(pool_a.canonical_key() < pool_b.canonical_key());
+
+        //Pools with equal output resolve to the lowest canonical key whatever their order
+        let amount_in = U256::from(1000000000000000000_u128);
+        for pools in [
+            vec![pool_a.clone(), pool_b.clone()],
+            vec![pool_b.clone(), pool_a.clone()],
+        ] {
+            let (pool, amount_out) =
+                best_pool_for_pair(&pools, token_in, token_out, amount_in, H160::zero())
+                    .expect("A pool should trade the pair");
+            assert_eq!(pool.address(), pool_a.address());
+            assert_eq!(amount_out, pool_a.simulate_swap(token_in, amount_in)?);
+        }
+
+        //Split allocations between equal pools do not depend on their order either
+        let (_, allocations) =
+            simulate_split_swap(&[&pool_a, &pool_b], token_in, amount_in, H160::zero());
+        let (_, reversed_allocations) =
+            simulate_split_swap(&[&pool_b, &pool_a], token_in, amount_in, H160::zero());
+        assert_eq!(allocations[0], reversed_allocations[1]);
+        assert_eq!(allocations[1], reversed_allocations[0]);
+
+        assert!(
+            best_pool_for_pair(&[pool_a], token_in, token_in, amount_in, H160::zero()).is_none()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_spread() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let pool_a = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1000000000000000000000,
+            reserve_1: 2000000000000000000000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let pool_b = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(4),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1000000000000000000000,
+            reserve_1: 2200000000000000000000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let spread = price_spread(&pool_a, &pool_b, token_a)?;
+        assert!((spread - 0.1).abs() < 1e-9);
+
+        let pool_c = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(5),
+            token_a,
+            token_b: H160::from_low_u64_be(6),
+            reserve_0: 1000,
+            reserve_1: 1000,
+            ..Default::default()
+        });
+
+        assert!(price_spread(&pool_a, &pool_c, token_a).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_factory_tvl() {
+        let numeraire = H160::from_low_u64_be(100);
+        let token_a = H160::from_low_u64_be(101);
+        let token_b = H160::from_low_u64_be(102);
+        let factory = H160::from_low_u64_be(200);
+        let other_factory = H160::from_low_u64_be(201);
+
+        let one = 10_u128.pow(18);
+        let mut pool_a = v2_pool(1, numeraire, token_a, 1000 * one, 500 * one);
+        pool_a.set_factory(factory);
+        let mut pool_b = v2_pool(2, token_a, token_b, 100 * one, 400 * one);
+        pool_b.set_factory(factory);
+        let mut pool_c = v2_pool(3, numeraire, token_b, 10 * one, 20 * one);
+        pool_c.set_factory(other_factory);
+
+        let amms = vec![pool_a, pool_b, pool_c];
+        let graph = PoolGraph::new(&amms);
+
+        //token_a is worth 2 numeraire and token_b 0.5 numeraire, each pool holds an equal value of both tokens
+        let tvl = factory_tvl(&amms, factory, numeraire, H160::zero(), &graph);
+        assert!((tvl - (2000.0 + 400.0)).abs() < 1e-6);
+
+        let other_tvl = factory_tvl(&amms, other_factory, numeraire, H160::zero(), &graph);
+        assert!((other_tvl - 20.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_weighted_mid_price() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let v3_pool = |address: u64, fee: u32, tick: i32, liquidity: u128| -> eyre::Result<AMM> {
+            Ok(AMM::UniswapV3Pool(UniswapV3Pool {
+                address: H160::from_low_u64_be(address),
+                token_a,
+                token_a_decimals: 18,
+                token_b,
+                token_b_decimals: 18,
+                fee,
+                liquidity,
+                sqrt_price: uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(tick)?,
+                tick,
+                ..Default::default()
+            }))
+        };
+
+        let pool_500 = v3_pool(10, 500, 0, 1_000_000)?;
+        let pool_3000 = v3_pool(11, 3000, 6932, 3_000_000)?;
+
+        //Each tier is priced from its own sqrt price
+        let price_500 = pool_500.calculate_price(token_a)?;
+        let price_3000 = pool_3000.calculate_price(token_a)?;
+        assert!((price_500 - 1.0).abs() < 1e-9);
+        assert!((price_3000 - 2.0).abs() < 1e-3);
+
+        let mid_price = weighted_mid_price(&[&pool_500, &pool_3000], token_a)?;
+        assert!((mid_price - (price_500 + 3.0 * price_3000) / 4.0).abs() < 1e-9);
+
+        let inverse_mid_price = weighted_mid_price(&[&pool_500, &pool_3000], token_b)?;
+        assert!((inverse_mid_price - (1.0 / price_500 + 3.0 / price_3000) / 4.0).abs() < 1e-9);
+
+        //V2 pools have no in range liquidity to weigh by
+        let v2 = v2_pool(12, token_a, token_b, 1000, 1000);
+        assert!(weighted_mid_price(&[&pool_500, &v2], token_a).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_path_with_min_depth() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let token_c = H160::from_low_u64_be(3);
+
+        let pools = vec![
+            //The direct hop is too shallow for large amounts
+            v2_pool(10, token_a, token_b, 10_u128.pow(18), 10_u128.pow(18)),
+            v2_pool(11, token_a, token_c, 10_u128.pow(24), 10_u128.pow(24)),
+            v2_pool(12, token_c, token_b, 10_u128.pow(24), 10_u128.pow(24)),
+        ];
+        let graph = PoolGraph::new(&pools);
+
+        //The deeper two hop path is taken over the shallow direct pool
+        let amount = U256::from(10_u128.pow(18));
+        assert_eq!(
+            find_path_with_min_depth(&pools, &graph, token_a, token_b, amount, 3),
+            Some(vec![1, 2])
+        );
+        assert_eq!(
+            find_path_with_min_depth(&pools, &graph, token_a, token_b, amount, 1),
+            None
+        );
+
+        //Small amounts fit the direct pool, which is the shortest path
+        let amount = U256::from(10_u128.pow(15));
+        assert_eq!(
+            find_path_with_min_depth(&pools, &graph, token_a, token_b, amount, 3),
+            Some(vec![0])
+        );
+    }
+
+    #[test]
+    fn test_pool_graph_to_dot() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let token_c = H160::from_low_u64_be(3);
+
+        let pools = vec![
+            v2_pool(10, token_a, token_b, 10_u128.pow(18), 2 * 10_u128.pow(18)),
+            v2_pool(11, token_b, token_c, 10_u128.pow(18), 10_u128.pow(18)),
+        ];
+        let graph = PoolGraph::new(&pools);
+
+        let dot = graph.to_dot(&pools);
+        assert!(dot.starts_with("graph pools {\n"));
+        for token in [token_a, token_b, token_c] {
+            assert!(dot.contains(&format!("    \"{:?}\";\n", token)));
+        }
+        //Each pool is a single undirected edge
+        assert_eq!(dot.matches(" -- ").count(), 2);
+        assert!(dot.contains(&format!(
+            "    \"{:?}\" -- \"{:?}\" [label=\"{:?}\\nfee 300\\ndepth 1.00 / 2.00\"];\n",
+            token_a,
+            token_b,
+            H160::from_low_u64_be(10)
+        )));
+
+        //Filtering keeps only the tokens and the pools between them
+        let dot = graph.to_dot_for_tokens(&pools, &[token_a, token_b]);
+        assert_eq!(dot.matches(" -- ").count(), 1);
+        assert!(!dot.contains(&format!("{:?}", token_c)));
+        assert!(!dot.contains(&format!("{:?}", H160::from_low_u64_be(11))));
+    }
+}
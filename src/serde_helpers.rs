@@ -0,0 +1,159 @@
+//Serde helpers for the checkpoint format. Addresses are serialized by ethers as lowercase 0x-hex, while big integers
+//(reserves, liquidity, sqrt price) are serialized as decimal strings so that tools parsing json numbers as f64 do not lose precision
+
+use std::fmt;
+
+use ethers::types::U256;
+use serde::{de, Deserializer, Serializer};
+
+//Serializes a u128 as a decimal string, deserializing from either a decimal string or a json number.
+//Json numbers above u64::MAX in legacy checkpoints are parsed as f64 and may lose precision until the pool is resynced
+pub mod u128_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        deserializer.deserialize_any(U128Visitor)
+    }
+
+    struct U128Visitor;
+
+    impl<'de> de::Visitor<'de> for U128Visitor {
+        type Value = u128;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a u128 as a decimal string or number")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u128, E> {
+            value.parse().map_err(E::custom)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u128, E> {
+            Ok(value as u128)
+        }
+
+        fn visit_u128<E: de::Error>(self, value: u128) -> Result<u128, E> {
+            Ok(value)
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<u128, E> {
+            if value.is_sign_negative() || !value.is_finite() {
+                return Err(E::custom(format!("invalid u128 value: {value}")));
+            }
+            Ok(value as u128)
+        }
+    }
+}
+
+//Serializes a U256 as a decimal string, deserializing from either a decimal string or the legacy 0x-hex string
+pub mod u256_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        deserializer.deserialize_any(U256Visitor)
+    }
+
+    struct U256Visitor;
+
+    impl<'de> de::Visitor<'de> for U256Visitor {
+        type Value = U256;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a U256 as a decimal or 0x-hex string")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<U256, E> {
+            if let Some(hex) = value.strip_prefix("0x") {
+                U256::from_str_radix(hex, 16).map_err(E::custom)
+            } else {
+                U256::from_dec_str(value).map_err(E::custom)
+            }
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<U256, E> {
+            Ok(U256::from(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ethers::types::{H160, U256};
+
+    use crate::amm::{erc_4626::ERC4626Vault, uniswap_v2::UniswapV2Pool, AMM};
+
+    #[test]
+    fn test_checkpoint_format() -> eyre::Result<()> {
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            ..Default::default()
+        });
+
+        let value = serde_json::to_value(&pool)?;
+        let fields = &value["UniswapV2Pool"];
+        assert_eq!(
+            fields["address"],
+            "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc"
+        );
+        assert_eq!(fields["reserve_0"], "47092140895915");
+        assert_eq!(fields["reserve_1"], "28396598565590008529300");
+
+        let round_trip: AMM = serde_json::from_value(value)?;
+        if let AMM::UniswapV2Pool(round_trip) = round_trip {
+            assert_eq!(round_trip.reserve_1, 28396598565590008529300);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_checkpoint_format() -> eyre::Result<()> {
+        let vault: ERC4626Vault = serde_json::from_str(
+            r#"{
+                "vault_token": "0x0000000000000000000000000000000000000001",
+                "vault_token_decimals": 18,
+                "asset_token": "0x0000000000000000000000000000000000000002",
+                "asset_token_decimals": 18,
+                "vault_reserve": "0x3e8",
+                "asset_reserve": "0x7d0",
+                "deposit_fee": 0,
+                "withdraw_fee": 0
+            }"#,
+        )?;
+
+        assert_eq!(vault.vault_reserve, U256::from(1000));
+        assert_eq!(vault.asset_reserve, U256::from(2000));
+
+        let pool: UniswapV2Pool = serde_json::from_str(
+            r#"{
+                "address": "0x0000000000000000000000000000000000000003",
+                "token_a": "0x0000000000000000000000000000000000000001",
+                "token_a_decimals": 18,
+                "token_b": "0x0000000000000000000000000000000000000002",
+                "token_b_decimals": 18,
+                "reserve_0": 1000,
+                "reserve_1": 2000,
+                "fee": 300
+            }"#,
+        )?;
+
+        assert_eq!(pool.reserve_0, 1000);
+        assert_eq!(pool.reserve_1, 2000);
+
+        Ok(())
+    }
+}
@@ -1,16 +1,17 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::Duration,
 };
 
 use crate::{
-    amm::{AutomatedMarketMaker, AMM},
+    amm::{algebra::AlgebraPool, AutomatedMarketMaker, AMM},
     errors::EventLogError,
 };
 use arraydeque::ArrayDeque;
 use ethers::{
     providers::{Middleware, PubsubClient, StreamExt},
-    types::{Block, Filter, Log, H160, H256},
+    types::{Block, BlockNumber, Filter, Log, H160, H256, U256},
 };
 use tokio::{
     sync::{
@@ -22,6 +23,8 @@ use tokio::{
 
 use super::error::{StateChangeError, StateSpaceError};
 
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 pub type StateSpace = HashMap<H160, AMM>;
 pub type StateChangeCache = ArrayDeque<StateChange, 150>;
 
@@ -47,6 +50,8 @@ where
     pub state_change_cache: Arc<RwLock<StateChangeCache>>,
     pub middleware: Arc<M>,
     pub stream_middleware: Arc<P>,
+    //Interval between polls for the latest block when the stream middleware does not support block subscriptions
+    pub poll_interval: Duration,
 }
 
 impl<M, P> StateSpaceManager<M, P>
@@ -65,9 +70,15 @@ where
             state_change_cache: Arc::new(RwLock::new(ArrayDeque::new())),
             middleware,
             stream_middleware,
+            poll_interval: DEFAULT_POLL_INTERVAL,
         }
     }
 
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
     pub async fn get_block_filter(&self) -> Filter {
         let mut event_signatures: Vec<H256> = vec![];
         let mut amm_variants = HashSet::new();
@@ -77,6 +88,7 @@ where
                 AMM::UniswapV2Pool(_) => 0,
                 AMM::UniswapV3Pool(_) => 1,
                 AMM::ERC4626Vault(_) => 2,
+                AMM::AlgebraPool(_) => 3,
             };
 
             if !amm_variants.contains(&variant) {
@@ -112,18 +124,12 @@ where
         let (stream_tx, mut stream_rx): (Sender<Block<H256>>, Receiver<Block<H256>>) =
             tokio::sync::mpsc::channel(channel_buffer);
 
-        let stream_handle = tokio::spawn(async move {
-            let mut block_stream = stream_middleware
-                .subscribe_blocks()
-                .await
-                .map_err(StateSpaceError::PubsubClientError)?;
-
-            while let Some(block) = block_stream.next().await {
-                stream_tx.send(block).await?;
-            }
-
-            Ok::<(), StateSpaceError<M, P>>(())
-        });
+        let stream_handle = spawn_block_stream(
+            stream_middleware,
+            middleware.clone(),
+            self.poll_interval,
+            stream_tx,
+        );
 
         let (new_block_tx, new_block_rx) = tokio::sync::mpsc::channel(channel_buffer);
 
@@ -212,18 +218,12 @@ where
         let (stream_tx, mut stream_rx): (Sender<Block<H256>>, Receiver<Block<H256>>) =
             tokio::sync::mpsc::channel(channel_buffer);
 
-        let stream_handle = tokio::spawn(async move {
-            let mut block_stream = stream_middleware
-                .subscribe_blocks()
-                .await
-                .map_err(StateSpaceError::PubsubClientError)?;
-
-            while let Some(block) = block_stream.next().await {
-                stream_tx.send(block).await?;
-            }
-
-            Ok::<(), StateSpaceError<M, P>>(())
-        });
+        let stream_handle = spawn_block_stream(
+            stream_middleware,
+            middleware.clone(),
+            self.poll_interval,
+            stream_tx,
+        );
 
         let (amms_updated_tx, amms_updated_rx) = tokio::sync::mpsc::channel(channel_buffer);
 
@@ -308,18 +308,12 @@ where
         let (stream_tx, mut stream_rx): (Sender<Block<H256>>, Receiver<Block<H256>>) =
             tokio::sync::mpsc::channel(channel_buffer);
 
-        let stream_handle = tokio::spawn(async move {
-            let mut block_stream = stream_middleware
-                .subscribe_blocks()
-                .await
-                .map_err(StateSpaceError::PubsubClientError)?;
-
-            while let Some(block) = block_stream.next().await {
-                stream_tx.send(block).await?;
-            }
-
-            Ok::<(), StateSpaceError<M, P>>(())
-        });
+        let stream_handle = spawn_block_stream(
+            stream_middleware,
+            middleware.clone(),
+            self.poll_interval,
+            stream_tx,
+        );
 
         let state_change_cache = self.state_change_cache.clone();
         let new_block_handle: JoinHandle<Result<(), StateSpaceError<M, P>>> =
@@ -380,6 +374,132 @@ where
 
         Ok(vec![stream_handle, new_block_handle])
     }
+
+    /// Applies the logs from `block_number` to the state space, stopping before the first log emitted by the transaction at `tx_index`.
+    /// This reconstructs the state as of the start of that transaction and assumes that the state space is synced up to `block_number - 1`.
+    /// Note that the state space is left in a mid block state, so this should not be used while listening for new blocks.
+    pub async fn apply_logs_up_to(
+        &self,
+        block_number: u64,
+        tx_index: u64,
+    ) -> Result<Vec<H160>, StateSpaceError<M, P>> {
+        let filter = self.get_block_filter().await;
+        let logs = self
+            .middleware
+            .get_logs(&filter.from_block(block_number).to_block(block_number))
+            .await
+            .map_err(StateSpaceError::MiddlewareError)?;
+
+        Ok(handle_state_changes_from_logs(
+            self.state.clone(),
+            self.state_change_cache.clone(),
+            filter_logs_before_tx_index(logs, tx_index),
+            self.middleware.clone(),
+        )
+        .await?)
+    }
+
+    /// Applies the logs to the state space, returning the address of each AMM that incurred a state change.
+    /// If `collect_diffs` is set, the old and new values of every reserve, price and liquidity field changed by each log are also returned.
+    pub async fn update_from_logs(
+        &self,
+        logs: Vec<Log>,
+        collect_diffs: bool,
+    ) -> Result<(Vec<H160>, Option<Vec<StateDiff>>), StateSpaceError<M, P>> {
+        let (updated_amms, diffs) = handle_state_changes_from_logs_with_diffs(
+            self.state.clone(),
+            self.state_change_cache.clone(),
+            logs,
+            collect_diffs,
+        )
+        .await?;
+
+        Ok((updated_amms, collect_diffs.then_some(diffs)))
+    }
+
+    //Returns the block of the last event applied to the pool, or None if the pool is not in the state space or has not seen an event
+    pub async fn last_updated_block(&self, address: H160) -> Option<u64> {
+        last_updated_block(&*self.state.read().await, address)
+    }
+
+    //Returns the pools that have not seen an event since `older_than`, which may have drifted and need a refresh
+    pub async fn stale_pools(&self, older_than: u64) -> Vec<H160> {
+        stale_pools(&*self.state.read().await, older_than)
+    }
+}
+
+//Streams new blocks from a block subscription on the stream middleware. Providers without eth_subscribe fail to
+//subscribe, in which case the latest block is polled from the middleware at the poll interval instead, sending each
+//new head. Blocks produced between polls are not sent, but their logs are still fetched with the next head
+fn spawn_block_stream<M, P>(
+    stream_middleware: Arc<P>,
+    middleware: Arc<M>,
+    poll_interval: Duration,
+    stream_tx: Sender<Block<H256>>,
+) -> JoinHandle<Result<(), StateSpaceError<M, P>>>
+where
+    M: 'static + Middleware,
+    P: 'static + MiddlewarePubsub,
+    <P as Middleware>::Provider: PubsubClient,
+{
+    tokio::spawn(async move {
+        match stream_middleware.subscribe_blocks().await {
+            Ok(mut block_stream) => {
+                while let Some(block) = block_stream.next().await {
+                    stream_tx.send(block).await?;
+                }
+            }
+            Err(err) => {
+                tracing::warn!(%err, ?poll_interval, "Block subscriptions are unavailable, polling for new blocks");
+
+                let mut last_block_number = None;
+                let mut interval = tokio::time::interval(poll_interval);
+                loop {
+                    interval.tick().await;
+
+                    let block = middleware
+                        .get_block(BlockNumber::Latest)
+                        .await
+                        .map_err(StateSpaceError::MiddlewareError)?;
+
+                    if let Some(block) = block {
+                        if block.number.is_some() && block.number != last_block_number {
+                            last_block_number = block.number;
+                            stream_tx.send(block).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok::<(), StateSpaceError<M, P>>(())
+    })
+}
+
+pub fn last_updated_block(state: &StateSpace, address: H160) -> Option<u64> {
+    state
+        .get(&address)
+        .map(|amm| amm.last_synced_block())
+        .filter(|block_number| *block_number != 0)
+}
+
+pub fn stale_pools(state: &StateSpace, older_than: u64) -> Vec<H160> {
+    state
+        .values()
+        .filter(|amm| amm.last_synced_block() < older_than)
+        .map(|amm| amm.address())
+        .collect()
+}
+
+//Returns the logs that were emitted by transactions before `tx_index`
+pub fn filter_logs_before_tx_index(logs: Vec<Log>, tx_index: u64) -> Vec<Log> {
+    logs.into_iter()
+        .filter(|log| {
+            log.transaction_index
+                .map(|index| index.as_u64() < tx_index)
+                .unwrap_or(false)
+        })
+        .collect()
 }
 
 pub fn initialize_state_space(amms: Vec<AMM>) -> StateSpace {
@@ -403,6 +523,83 @@ impl StateChange {
     }
 }
 
+//A pool state field tracked by the state diffs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateField {
+    Reserve0,
+    Reserve1,
+    SqrtPrice,
+    Liquidity,
+}
+
+//The old and new value of a pool field changed by a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDiff {
+    pub address: H160,
+    pub field: StateField,
+    pub old: U256,
+    pub new: U256,
+}
+
+//Returns a diff for every tracked field that differs between the old and new state of the amm. Reserves are tracked for
+//v2 pools and vaults, where reserve 0 is the vault reserve, the sqrt price and liquidity for v3 and Algebra pools
+pub fn state_diffs(old_amm: &AMM, new_amm: &AMM) -> Vec<StateDiff> {
+    let fields = match (old_amm, new_amm) {
+        (AMM::UniswapV2Pool(old_pool), AMM::UniswapV2Pool(new_pool)) => vec![
+            (
+                StateField::Reserve0,
+                U256::from(old_pool.reserve_0),
+                U256::from(new_pool.reserve_0),
+            ),
+            (
+                StateField::Reserve1,
+                U256::from(old_pool.reserve_1),
+                U256::from(new_pool.reserve_1),
+            ),
+        ],
+        (AMM::UniswapV3Pool(old_pool), AMM::UniswapV3Pool(new_pool))
+        | (
+            AMM::AlgebraPool(AlgebraPool { pool: old_pool }),
+            AMM::AlgebraPool(AlgebraPool { pool: new_pool }),
+        ) => vec![
+            (
+                StateField::SqrtPrice,
+                old_pool.sqrt_price,
+                new_pool.sqrt_price,
+            ),
+            (
+                StateField::Liquidity,
+                U256::from(old_pool.liquidity),
+                U256::from(new_pool.liquidity),
+            ),
+        ],
+        (AMM::ERC4626Vault(old_vault), AMM::ERC4626Vault(new_vault)) => vec![
+            (
+                StateField::Reserve0,
+                old_vault.vault_reserve,
+                new_vault.vault_reserve,
+            ),
+            (
+                StateField::Reserve1,
+                old_vault.asset_reserve,
+                new_vault.asset_reserve,
+            ),
+        ],
+        _ => vec![],
+    };
+
+    fields
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| StateDiff {
+            address: new_amm.address(),
+            field,
+            old,
+            new,
+        })
+        .collect()
+}
+
 //Unwinds the state changes cache for every block from the most recent state change cache back to the block to unwind -1
 async fn unwind_state_changes(
     state: Arc<RwLock<StateSpace>>,
@@ -462,14 +659,28 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
     logs: Vec<Log>,
     _middleware: Arc<M>,
 ) -> Result<Vec<H160>, StateChangeError> {
+    let (updated_amms, _) =
+        handle_state_changes_from_logs_with_diffs(state, state_change_cache, logs, false).await?;
+
+    Ok(updated_amms)
+}
+
+//Applies the logs like `handle_state_changes_from_logs`, also collecting the state diff of each applied log if `collect_diffs` is set
+pub async fn handle_state_changes_from_logs_with_diffs(
+    state: Arc<RwLock<StateSpace>>,
+    state_change_cache: Arc<RwLock<StateChangeCache>>,
+    logs: Vec<Log>,
+    collect_diffs: bool,
+) -> Result<(Vec<H160>, Vec<StateDiff>), StateChangeError> {
     let mut updated_amms_set = HashSet::new();
     let mut updated_amms = vec![];
     let mut state_changes = vec![];
+    let mut diffs = vec![];
 
     let mut last_log_block_number = if let Some(log) = logs.get(0) {
         get_block_number_from_log(log)?
     } else {
-        return Ok(updated_amms);
+        return Ok((updated_amms, diffs));
     };
 
     for log in logs.into_iter() {
@@ -482,8 +693,14 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
                 updated_amms.push(log.address);
             }
 
-            state_changes.push(amm.clone());
+            let old_amm = amm.clone();
             amm.sync_from_log(log)?;
+            amm.set_last_synced_block(log_block_number);
+
+            if collect_diffs {
+                diffs.extend(state_diffs(&old_amm, amm));
+            }
+            state_changes.push(old_amm);
         }
 
         //Commit state changes if the block has changed since last log
@@ -521,7 +738,7 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
         .await?;
     };
 
-    Ok(updated_amms)
+    Ok((updated_amms, diffs))
 }
 
 pub fn get_block_number_from_log(log: &Log) -> Result<u64, EventLogError> {
@@ -534,20 +751,112 @@ pub fn get_block_number_from_log(log: &Log) -> Result<u64, EventLogError> {
 
 #[cfg(test)]
 mod tests {
-    use std::{default, sync::Arc};
+    use std::{default, fmt::Debug, sync::Arc, time::Duration};
 
-    use crate::amm::{uniswap_v2::UniswapV2Pool, AMM};
+    use crate::amm::{
+        uniswap_v2::{UniswapV2Pool, SYNC_EVENT_SIGNATURE},
+        AMM,
+    };
+    use async_trait::async_trait;
     use ethers::{
-        providers::{Http, Provider, Ws},
-        types::H160,
+        abi::Token,
+        providers::{Http, JsonRpcClient, MockError, MockProvider, Provider, PubsubClient, Ws},
+        types::{Block, Bytes, Log, H160, H256, U256, U64},
     };
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::value::RawValue;
     use tokio::sync::RwLock;
 
     use super::StateSpaceManager;
     use crate::state_space::state::{
-        add_state_change_to_cache, unwind_state_changes, StateChange, StateChangeCache,
+        add_state_change_to_cache, filter_logs_before_tx_index, handle_state_changes_from_logs,
+        handle_state_changes_from_logs_with_diffs, initialize_state_space, last_updated_block,
+        stale_pools, unwind_state_changes, StateChange, StateChangeCache, StateDiff, StateField,
     };
 
+    //Transport that supports requests but rejects eth_subscribe like an http only provider, as its mock has no responses
+    #[derive(Debug)]
+    struct NoSubscriptionProvider(MockProvider);
+
+    #[async_trait]
+    impl JsonRpcClient for NoSubscriptionProvider {
+        type Error = MockError;
+
+        async fn request<T: Debug + Serialize + Send + Sync, R: DeserializeOwned + Send>(
+            &self,
+            method: &str,
+            params: T,
+        ) -> Result<R, MockError> {
+            self.0.request(method, params).await
+        }
+    }
+
+    impl PubsubClient for NoSubscriptionProvider {
+        type NotificationStream = futures::stream::Empty<Box<RawValue>>;
+
+        fn subscribe<T: Into<U256>>(&self, _id: T) -> Result<Self::NotificationStream, MockError> {
+            Ok(futures::stream::empty())
+        }
+
+        fn unsubscribe<T: Into<U256>>(&self, _id: T) -> Result<(), MockError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen_for_state_changes_polling() -> eyre::Result<()> {
+        let address = H160::from_low_u64_be(1);
+        let amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address,
+            ..default::Default::default()
+        })];
+
+        let (provider, mock) = Provider::mocked();
+        //Responses are popped in reverse order, the polled block is followed by the logs of the block
+        mock.push::<Vec<Log>, _>(vec![Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ])),
+            block_number: Some(U64::from(101)),
+            ..default::Default::default()
+        }])?;
+        mock.push::<Block<H256>, _>(Block {
+            number: Some(U64::from(101)),
+            ..default::Default::default()
+        })?;
+
+        let stream_middleware =
+            Arc::new(Provider::new(NoSubscriptionProvider(MockProvider::new())));
+        //The first poll is immediate, the long interval keeps later polls out of the test
+        let state_space_manager =
+            StateSpaceManager::new(amms, Arc::new(provider), stream_middleware)
+                .with_poll_interval(Duration::from_secs(60));
+
+        let (mut state_changes_rx, handles) = state_space_manager
+            .listen_for_state_changes(100, 10)
+            .await?;
+
+        let updated_amms =
+            tokio::time::timeout(Duration::from_secs(5), state_changes_rx.recv()).await?;
+        assert_eq!(updated_amms, Some(vec![address]));
+
+        if let Some(AMM::UniswapV2Pool(pool)) = state_space_manager.state.read().await.get(&address)
+        {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 2000));
+        } else {
+            panic!("Pool not found in state space")
+        }
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_add_state_changes() -> eyre::Result<()> {
         let state_change_cache = Arc::new(RwLock::new(StateChangeCache::new()));
@@ -642,4 +951,150 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_apply_logs_up_to_tx_index() -> eyre::Result<()> {
+        let address = H160::from_low_u64_be(1);
+        let state = Arc::new(RwLock::new(initialize_state_space(vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address,
+                ..default::Default::default()
+            }),
+        ])));
+        let state_change_cache = Arc::new(RwLock::new(StateChangeCache::new()));
+
+        let sync_log = |tx_index: u64, reserve: u128| Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve)),
+                Token::Uint(U256::from(reserve)),
+            ])),
+            block_number: Some(U64::from(100)),
+            transaction_index: Some(U64::from(tx_index)),
+            ..default::Default::default()
+        };
+
+        //Two swaps in the same block, only the first one should be applied
+        let logs = filter_logs_before_tx_index(vec![sync_log(0, 1000), sync_log(1, 2000)], 1);
+        assert_eq!(logs.len(), 1);
+
+        handle_state_changes_from_logs(
+            state.clone(),
+            state_change_cache,
+            logs,
+            Arc::new(Provider::mocked().0),
+        )
+        .await?;
+
+        if let Some(AMM::UniswapV2Pool(pool)) = state.read().await.get(&address) {
+            assert_eq!(pool.reserve_0, 1000);
+            assert_eq!(pool.reserve_1, 1000);
+        } else {
+            panic!("Pool not found in state space")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_diffs_from_sync_log() -> eyre::Result<()> {
+        let address = H160::from_low_u64_be(1);
+        let state = Arc::new(RwLock::new(initialize_state_space(vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address,
+                reserve_0: 1000,
+                reserve_1: 2000,
+                ..default::Default::default()
+            }),
+        ])));
+        let state_change_cache = Arc::new(RwLock::new(StateChangeCache::new()));
+
+        //Only reserve 0 changes, so there is no diff for reserve 1
+        let log = Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(1500)),
+                Token::Uint(U256::from(2000)),
+            ])),
+            block_number: Some(U64::from(100)),
+            ..default::Default::default()
+        };
+
+        let (updated_amms, diffs) = handle_state_changes_from_logs_with_diffs(
+            state.clone(),
+            state_change_cache.clone(),
+            vec![log.clone()],
+            true,
+        )
+        .await?;
+
+        assert_eq!(updated_amms, vec![address]);
+        assert_eq!(
+            diffs,
+            vec![StateDiff {
+                address,
+                field: StateField::Reserve0,
+                old: U256::from(1000),
+                new: U256::from(1500),
+            }]
+        );
+
+        //Diffs are not collected unless requested
+        let (updated_amms, diffs) =
+            handle_state_changes_from_logs_with_diffs(state, state_change_cache, vec![log], false)
+                .await?;
+        assert_eq!(updated_amms, vec![address]);
+        assert!(diffs.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_pools() -> eyre::Result<()> {
+        let active_pool = H160::from_low_u64_be(1);
+        let quiet_pool = H160::from_low_u64_be(2);
+        let state = Arc::new(RwLock::new(initialize_state_space(vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: active_pool,
+                ..default::Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: quiet_pool,
+                ..default::Default::default()
+            }),
+        ])));
+
+        let sync_log = Log {
+            address: active_pool,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(1000)),
+            ])),
+            block_number: Some(U64::from(100)),
+            ..default::Default::default()
+        };
+
+        handle_state_changes_from_logs(
+            state.clone(),
+            Arc::new(RwLock::new(StateChangeCache::new())),
+            vec![sync_log],
+            Arc::new(Provider::mocked().0),
+        )
+        .await?;
+
+        let state = state.read().await;
+        assert_eq!(last_updated_block(&state, active_pool), Some(100));
+        assert_eq!(last_updated_block(&state, quiet_pool), None);
+        assert_eq!(last_updated_block(&state, H160::from_low_u64_be(3)), None);
+
+        assert_eq!(stale_pools(&state, 50), vec![quiet_pool]);
+        let mut stale = stale_pools(&state, 101);
+        stale.sort();
+        assert_eq!(stale, vec![active_pool, quiet_pool]);
+
+        Ok(())
+    }
 }
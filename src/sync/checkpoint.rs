@@ -1,28 +1,53 @@
 use std::{
-    fs::read_to_string,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{read_to_string, File},
+    io::BufReader,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use ethers::{providers::Middleware, types::H160};
 use indicatif::ProgressBar;
-use serde::{Deserialize, Serialize};
+use rand::seq::SliceRandom;
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     amm::{
-        factory::{AutomatedMarketMakerFactory, Factory},
-        uniswap_v2::factory::UniswapV2Factory,
-        uniswap_v3::factory::UniswapV3Factory,
-        AMM,
+        algebra::{factory::AlgebraFactory, AlgebraPool},
+        erc_4626::ERC4626Vault,
+        factory::{
+            AutomatedMarketMakerFactory, Factory, MultiFactoryFilter, RangeExpansion, TASK_LIMIT,
+        },
+        u256_to_f64,
+        uniswap_v2::{factory::UniswapV2Factory, UniswapV2Pool},
+        uniswap_v3::{
+            batch_request::populate_tick_data_batch, factory::UniswapV3Factory, UniswapV3Pool,
+            UniswapV3TickMaps,
+        },
+        AutomatedMarketMaker, AMM,
     },
-    constants::{MULTIPROGRESS, SPINNER_STYLE},
+    constants::{MULTIPROGRESS, SPINNER_STYLE, SYNC_BAR_STYLE},
     errors::{AMMError, CheckpointError},
     sync,
 };
 
-use super::{amms_are_congruent, populate_amms};
+use super::{
+    amms_are_congruent, batch_size, group_congruent, populate_amm_chunk, populate_amms,
+    store::{CheckpointStore, FileStore},
+};
+
+//Tick window loaded around the current tick of the concentrated liquidity pools synced from a checkpoint, whose tick maps
+//are not persisted with the pools
+pub const CHECKPOINT_TICK_WINDOW: u16 = 10;
 
+//The amms of a checkpoint are stored without their transient state, ie. the tick maps of the uniswap v3 and Algebra pools.
+//The state change history of a state space is held by the `StateSpaceManager` and never part of a checkpoint
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Checkpoint {
     pub timestamp: usize,
@@ -45,6 +70,20 @@ impl Checkpoint {
             amms,
         }
     }
+
+    //Creates a checkpoint timestamped with the current system time
+    pub fn now(
+        block_number: u64,
+        factories: Vec<Factory>,
+        amms: Vec<AMM>,
+    ) -> Result<Checkpoint, CheckpointError> {
+        Ok(Checkpoint::new(
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64() as usize,
+            block_number,
+            factories,
+            amms,
+        ))
+    }
 }
 
 //Get all pairs from last synced block and sync reserve values for each Dex in the `dexes` vec.
@@ -52,6 +91,34 @@ pub async fn sync_amms_from_checkpoint<M: 'static + Middleware>(
     path_to_checkpoint: &str,
     step: u64,
     middleware: Arc<M>,
+) -> Result<(Vec<Factory>, Vec<AMM>), AMMError<M>> {
+    sync_amms_from_checkpoint_with_factories(path_to_checkpoint, step, None, middleware).await
+}
+
+//Syncs the amms from the checkpoint, scanning the override factories instead of the checkpoint factories if provided.
+//Factories not in the checkpoint are backfilled from their creation block, and dropped factories are no longer scanned
+//for new pools. The checkpointed pools of dropped factories are removed, while pools without a recorded factory are kept.
+pub async fn sync_amms_from_checkpoint_with_factories<M: 'static + Middleware>(
+    path_to_checkpoint: &str,
+    step: u64,
+    factories: Option<Vec<Factory>>,
+    middleware: Arc<M>,
+) -> Result<(Vec<Factory>, Vec<AMM>), AMMError<M>> {
+    sync_amms_from_store(
+        &FileStore::new(path_to_checkpoint),
+        step,
+        factories,
+        middleware,
+    )
+    .await
+}
+
+//Syncs the amms from the checkpoint in the store like `sync_amms_from_checkpoint_with_factories`, storing the updated checkpoint
+pub async fn sync_amms_from_store<M: 'static + Middleware, S: CheckpointStore>(
+    store: &S,
+    step: u64,
+    factories: Option<Vec<Factory>>,
+    middleware: Arc<M>,
 ) -> Result<(Vec<Factory>, Vec<AMM>), AMMError<M>> {
     let spinner = MULTIPROGRESS.add(
         ProgressBar::new_spinner()
@@ -66,49 +133,55 @@ pub async fn sync_amms_from_checkpoint<M: 'static + Middleware>(
         .map_err(AMMError::MiddlewareError)?
         .as_u64();
 
-    let checkpoint: Checkpoint =
-        serde_json::from_str(read_to_string(path_to_checkpoint)?.as_str())?;
+    let checkpoint = store.load().await?;
 
-    //Sort all of the pools from the checkpoint into uniswap_v2_pools and uniswap_v3_pools pools so we can sync them concurrently
-    let (uniswap_v2_pools, uniswap_v3_pools, erc_4626_pools) = sort_amms(checkpoint.amms);
+    let factories = factories.unwrap_or_else(|| checkpoint.factories.clone());
+    let (existing_factories, new_factories): (Vec<Factory>, Vec<Factory>) =
+        factories.iter().cloned().partition(|factory| {
+            checkpoint
+                .factories
+                .iter()
+                .any(|checkpoint_factory| checkpoint_factory.address() == factory.address())
+        });
+
+    //Drop the pools of the checkpoint factories that are no longer synced
+    let dropped_factories: HashSet<H160> = checkpoint
+        .factories
+        .iter()
+        .map(|factory| factory.address())
+        .filter(|address| {
+            !factories
+                .iter()
+                .any(|factory| factory.address() == *address)
+        })
+        .collect();
+    let checkpoint_amms: Vec<AMM> = checkpoint
+        .amms
+        .into_iter()
+        .filter(|amm| !dropped_factories.contains(&amm.factory()))
+        .collect();
 
     let mut aggregated_amms = vec![];
     let mut handles = JoinSet::new();
 
-    //Sync all uniswap v2 pools from checkpoint
-    if !uniswap_v2_pools.is_empty() {
-        batch_sync_amms_from_checkpoint(
-            &mut handles,
-            uniswap_v2_pools,
-            current_block,
-            middleware.clone(),
-        )
-        .await?;
-    }
-
-    //Sync all uniswap v3 pools from checkpoint
-    if !uniswap_v3_pools.is_empty() {
-        batch_sync_amms_from_checkpoint(
-            &mut handles,
-            uniswap_v3_pools,
-            current_block,
-            middleware.clone(),
-        )
-        .await?;
-    }
-
-    if !erc_4626_pools.is_empty() {
-        // TODO: Batch sync erc4626 pools from checkpoint
-        todo!(
-            r#"""This function will produce an incorrect state if ERC4626 pools are present in the checkpoint. 
+    //Sync each variant of the pools from the checkpoint concurrently
+    for amms in group_congruent(checkpoint_amms) {
+        if let AMM::ERC4626Vault(_) = amms[0] {
+            // TODO: Batch sync erc4626 pools from checkpoint
+            todo!(
+                r#"""This function will produce an incorrect state if ERC4626 pools are present in the checkpoint. 
             This logic needs to be implemented into batch_sync_amms_from_checkpoint"""#
-        );
+            );
+        }
+
+        batch_sync_amms_from_checkpoint(&mut handles, amms, current_block, middleware.clone())
+            .await?;
     }
 
-    //Sync all pools from the since synced block
-    get_new_amms_from_range(
+    //Sync all pools from the since synced block, scanning the logs of all factories together
+    get_new_amms_from_range_combined(
         &mut handles,
-        checkpoint.factories.clone(),
+        existing_factories,
         checkpoint.block_number,
         current_block,
         step,
@@ -116,29 +189,58 @@ pub async fn sync_amms_from_checkpoint<M: 'static + Middleware>(
     )
     .await?;
 
+    //Backfill the factories that were added since the checkpoint from their creation block
+    for factory in new_factories {
+        let creation_block = factory.creation_block();
+        get_new_amms_from_range(
+            &mut handles,
+            vec![factory],
+            creation_block,
+            current_block,
+            step,
+            None,
+            middleware.clone(),
+        )
+        .await?;
+    }
+
     while let Some(amms) = handles.join_next().await {
         aggregated_amms.extend(amms??);
     }
 
+    //The tick maps are not stored in the checkpoint, so they are re-derived before the pools are simulated against
+    populate_tick_data_batch(
+        &mut aggregated_amms,
+        CHECKPOINT_TICK_WINDOW,
+        Some(current_block),
+        middleware.clone(),
+    )
+    .await?;
+
     //update the sync checkpoint
-    construct_checkpoint(
-        checkpoint.factories.clone(),
-        &aggregated_amms,
-        current_block,
-        path_to_checkpoint,
-    )?;
+    let aggregated_amms = store
+        .store(Checkpoint::now(
+            current_block,
+            factories.clone(),
+            aggregated_amms,
+        )?)
+        .await?
+        .amms;
 
     spinner.finish_and_clear();
 
-    Ok((checkpoint.factories, aggregated_amms))
+    Ok((factories, aggregated_amms))
 }
 
+//Spawns a task per factory syncing the amms created within the block range. If max_pools is set, each factory
+//returns only its earliest max_pools amms by block order
 pub async fn get_new_amms_from_range<M: 'static + Middleware>(
     handles: &mut JoinSet<Result<Vec<AMM>, AMMError<M>>>,
     factories: Vec<Factory>,
     from_block: u64,
     to_block: u64,
     step: u64,
+    max_pools: Option<usize>,
     middleware: Arc<M>,
 ) -> Result<(), AMMError<M>> {
     //Create the filter with all the pair created events
@@ -154,16 +256,48 @@ pub async fn get_new_amms_from_range<M: 'static + Middleware>(
 
         //Spawn a new thread to get all pools and sync data for each dex
         handles.spawn(async move {
-            let mut amms = factory
-                .get_all_pools_from_logs(from_block, to_block, step, middleware.clone())
+            let amms = factory
+                .sync_range(from_block, to_block, step, max_pools, middleware)
                 .await?;
 
-            factory
-                .populate_amm_data(&mut amms, Some(to_block), middleware.clone())
-                .await?;
+            spinner.finish_and_clear();
+            Ok::<_, AMMError<M>>(amms)
+        });
+    }
+
+    Ok(())
+}
+
+//Spawns a task per factory syncing the amms created within the block range like `get_new_amms_from_range`, scanning the
+//logs with an expanding range instead of a fixed step to catch up large gaps in fewer get_logs calls
+pub async fn get_new_amms_from_range_expanding<M: 'static + Middleware>(
+    handles: &mut JoinSet<Result<Vec<AMM>, AMMError<M>>>,
+    factories: Vec<Factory>,
+    from_block: u64,
+    to_block: u64,
+    expansion: RangeExpansion,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    for factory in factories.into_iter() {
+        let middleware = middleware.clone();
+        let spinner = MULTIPROGRESS.add(
+            ProgressBar::new_spinner()
+                .with_style(SPINNER_STYLE.clone())
+                .with_message(format!("Fetching new pools from {}...", factory.address())),
+        );
+        spinner.enable_steady_tick(Duration::from_millis(200));
 
-            //Clean empty pools
-            amms = sync::remove_empty_amms(amms);
+        handles.spawn(async move {
+            let (amms, stats) = factory
+                .sync_range_expanding(from_block, to_block, expansion, middleware)
+                .await?;
+            tracing::info!(
+                factory = ?factory.address(),
+                calls = stats.calls(),
+                rejected = stats.rejected,
+                ranges = ?stats.ranges,
+                "Scanned factory logs with expanding ranges"
+            );
 
             spinner.finish_and_clear();
             Ok::<_, AMMError<M>>(amms)
@@ -173,6 +307,43 @@ pub async fn get_new_amms_from_range<M: 'static + Middleware>(
     Ok(())
 }
 
+//Spawns a single task syncing the amms created by all factories within the block range like `get_new_amms_from_range`,
+//scanning the pool created logs of the factories with one get_logs call per block range through a `MultiFactoryFilter`
+pub async fn get_new_amms_from_range_combined<M: 'static + Middleware>(
+    handles: &mut JoinSet<Result<Vec<AMM>, AMMError<M>>>,
+    factories: Vec<Factory>,
+    from_block: u64,
+    to_block: u64,
+    step: u64,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    if factories.is_empty() {
+        return Ok(());
+    }
+
+    let spinner = MULTIPROGRESS.add(
+        ProgressBar::new_spinner()
+            .with_style(SPINNER_STYLE.clone())
+            .with_message(format!(
+                "Fetching new pools from {} factories...",
+                factories.len()
+            )),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(200));
+
+    let multi_factory_filter = MultiFactoryFilter::new(factories);
+    handles.spawn(async move {
+        let amms = multi_factory_filter
+            .sync_range(from_block, to_block, step, middleware)
+            .await?;
+
+        spinner.finish_and_clear();
+        Ok::<_, AMMError<M>>(amms)
+    });
+
+    Ok(())
+}
+
 pub async fn batch_sync_amms_from_checkpoint<M: 'static + Middleware>(
     handles: &mut JoinSet<Result<Vec<AMM>, AMMError<M>>>,
     amms: Vec<AMM>,
@@ -191,6 +362,11 @@ pub async fn batch_sync_amms_from_checkpoint<M: 'static + Middleware>(
             0,
         ))),
 
+        AMM::AlgebraPool(_) => Some(Factory::AlgebraFactory(AlgebraFactory::new(
+            H160::zero(),
+            0,
+        ))),
+
         AMM::ERC4626Vault(_) => None,
     };
 
@@ -220,19 +396,28 @@ pub async fn batch_sync_amms_from_checkpoint<M: 'static + Middleware>(
     }
 }
 
-pub fn sort_amms(amms: Vec<AMM>) -> (Vec<AMM>, Vec<AMM>, Vec<AMM>) {
+//Sorts the amms into uniswap v2 pools, uniswap v3 pools, erc 4626 vaults and Algebra pools.
+//Prefer `group_congruent` which extends to new AMM variants
+pub fn sort_amms(amms: Vec<AMM>) -> (Vec<AMM>, Vec<AMM>, Vec<AMM>, Vec<AMM>) {
     let mut uniswap_v2_pools = vec![];
     let mut uniswap_v3_pools = vec![];
     let mut erc_4626_vaults = vec![];
-    for amm in amms {
-        match amm {
-            AMM::UniswapV2Pool(_) => uniswap_v2_pools.push(amm),
-            AMM::UniswapV3Pool(_) => uniswap_v3_pools.push(amm),
-            AMM::ERC4626Vault(_) => erc_4626_vaults.push(amm),
+    let mut algebra_pools = vec![];
+    for group in group_congruent(amms) {
+        match group[0] {
+            AMM::UniswapV2Pool(_) => uniswap_v2_pools = group,
+            AMM::UniswapV3Pool(_) => uniswap_v3_pools = group,
+            AMM::ERC4626Vault(_) => erc_4626_vaults = group,
+            AMM::AlgebraPool(_) => algebra_pools = group,
         }
     }
 
-    (uniswap_v2_pools, uniswap_v3_pools, erc_4626_vaults)
+    (
+        uniswap_v2_pools,
+        uniswap_v3_pools,
+        erc_4626_vaults,
+        algebra_pools,
+    )
 }
 
 pub async fn get_new_pools_from_range<M: 'static + Middleware>(
@@ -251,18 +436,9 @@ pub async fn get_new_pools_from_range<M: 'static + Middleware>(
 
         //Spawn a new thread to get all pools and sync data for each dex
         handles.push(tokio::spawn(async move {
-            let mut pools = factory
-                .get_all_pools_from_logs(from_block, to_block, step, middleware.clone())
-                .await?;
-
             factory
-                .populate_amm_data(&mut pools, Some(to_block), middleware.clone())
-                .await?;
-
-            //Clean empty pools
-            pools = sync::remove_empty_amms(pools);
-
-            Ok::<_, AMMError<M>>(pools)
+                .sync_range(from_block, to_block, step, None, middleware)
+                .await
         }));
     }
 
@@ -287,8 +463,1327 @@ pub fn construct_checkpoint(
     Ok(())
 }
 
-//Deconstructs the checkpoint into a Vec<AMM>
+//Writes the checkpoint encrypted under the key, see `sync::encryption` for the file layout. Encrypted checkpoints are
+//conventionally named with the `.enc` extension to tell them apart from plain json checkpoints
+#[cfg(feature = "encryption")]
+pub fn construct_encrypted_checkpoint(
+    factories: Vec<Factory>,
+    amms: &[AMM],
+    latest_block: u64,
+    checkpoint_path: &str,
+    key: &[u8; 32],
+) -> Result<(), CheckpointError> {
+    let checkpoint = Checkpoint::new(
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64() as usize,
+        latest_block,
+        factories,
+        amms.to_vec(),
+    );
+
+    std::fs::write(
+        checkpoint_path,
+        sync::encryption::encrypt_checkpoint_bytes(&serde_json::to_vec(&checkpoint)?, key)?,
+    )?;
+
+    Ok(())
+}
+
+//Deconstructs a checkpoint written by `construct_encrypted_checkpoint` into a Vec<AMM>
+#[cfg(feature = "encryption")]
+pub fn deconstruct_encrypted_checkpoint(
+    checkpoint_path: &str,
+    key: &[u8; 32],
+) -> Result<(Vec<AMM>, u64), CheckpointError> {
+    let plaintext =
+        sync::encryption::decrypt_checkpoint_bytes(&std::fs::read(checkpoint_path)?, key)?;
+    let checkpoint: Checkpoint = serde_json::from_slice(&plaintext)?;
+
+    Ok((checkpoint.amms, checkpoint.block_number))
+}
+
+//On disk format of a checkpoint. Binary checkpoints are MessagePack encoded, which is self describing so the flattened
+//pools and string encoded integers read back the same as from json. Gzip checkpoints are gzip compressed json
+#[cfg(feature = "checkpoint-formats")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointFormat {
+    Json,
+    Binary,
+    Gzip,
+}
+
+#[cfg(feature = "checkpoint-formats")]
+impl CheckpointFormat {
+    //Detects the format from the leading bytes of the checkpoint, gzip from its magic number and json from the opening
+    //brace. A MessagePack encoded checkpoint starts with a map marker, which is never a brace
+    pub fn detect(bytes: &[u8]) -> CheckpointFormat {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            CheckpointFormat::Gzip
+        } else if bytes.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{') {
+            CheckpointFormat::Json
+        } else {
+            CheckpointFormat::Binary
+        }
+    }
+
+    //Picks the format from the extension of the path, ie. `.json`, `.bin` or `.gz`
+    pub fn from_path(path: &str) -> Option<CheckpointFormat> {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => Some(CheckpointFormat::Json),
+            Some("bin") => Some(CheckpointFormat::Binary),
+            Some("gz") => Some(CheckpointFormat::Gzip),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, checkpoint: &Checkpoint) -> Result<Vec<u8>, CheckpointError> {
+        match self {
+            CheckpointFormat::Json => Ok(serde_json::to_vec_pretty(checkpoint)?),
+            CheckpointFormat::Binary => Ok(rmp_serde::to_vec_named(checkpoint)?),
+            CheckpointFormat::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                serde_json::to_writer(&mut encoder, checkpoint)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Checkpoint, CheckpointError> {
+        match self {
+            CheckpointFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            CheckpointFormat::Binary => Ok(rmp_serde::from_slice(bytes)?),
+            CheckpointFormat::Gzip => Ok(serde_json::from_reader(flate2::read::GzDecoder::new(
+                bytes,
+            ))?),
+        }
+    }
+}
+
+//Converts the checkpoint at the from path into the format of the to path, detecting the format of the source from its
+//contents, so existing json checkpoints can be migrated to the binary or gzip formats without a resync. Returns the
+//format of the source checkpoint
+#[cfg(feature = "checkpoint-formats")]
+pub fn convert_checkpoint(
+    from_path: &str,
+    to_path: &str,
+) -> Result<CheckpointFormat, CheckpointError> {
+    let target_format = CheckpointFormat::from_path(to_path)
+        .ok_or_else(|| CheckpointError::UnknownFormat(to_path.to_string()))?;
+
+    let bytes = std::fs::read(from_path)?;
+    let source_format = CheckpointFormat::detect(&bytes);
+    let checkpoint = source_format.decode(&bytes)?;
+
+    std::fs::write(to_path, target_format.encode(&checkpoint)?)?;
+
+    Ok(source_format)
+}
+
+//Writes only the factories to a json file, which can seed a sync in another environment without the pool data of a full checkpoint
+pub fn export_factories(factories: &[Factory], path: &str) -> Result<(), CheckpointError> {
+    std::fs::write(path, serde_json::to_string_pretty(factories)?)?;
+
+    Ok(())
+}
+
+//Reads the factories written by `export_factories`
+pub fn import_factories(path: &str) -> Result<Vec<Factory>, CheckpointError> {
+    Ok(serde_json::from_str(read_to_string(path)?.as_str())?)
+}
+
+//A checkpoint that also persists the tick maps of the uniswap v3 and Algebra pools, which are skipped when serializing the pools.
+//The tick maps are stored next to the checkpoint fields so the file can still be read by `deconstruct_checkpoint`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CheckpointWithTicks {
+    #[serde(flatten)]
+    pub checkpoint: Checkpoint,
+    pub tick_maps: Vec<UniswapV3TickMaps>,
+}
+
+pub fn construct_checkpoint_with_ticks(
+    factories: Vec<Factory>,
+    amms: &[AMM],
+    latest_block: u64,
+    checkpoint_path: &str,
+) -> Result<(), CheckpointError> {
+    let tick_maps = amms
+        .iter()
+        .filter_map(|amm| match amm {
+            AMM::UniswapV3Pool(pool) => Some(UniswapV3TickMaps::from(pool)),
+            AMM::AlgebraPool(algebra_pool) => Some(UniswapV3TickMaps::from(&algebra_pool.pool)),
+            _ => None,
+        })
+        .collect();
+
+    let checkpoint = CheckpointWithTicks {
+        checkpoint: Checkpoint::new(
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64() as usize,
+            latest_block,
+            factories,
+            amms.to_vec(),
+        ),
+        tick_maps,
+    };
+
+    std::fs::write(checkpoint_path, serde_json::to_string_pretty(&checkpoint)?)?;
+
+    Ok(())
+}
+
+//Deconstructs a checkpoint written by `construct_checkpoint_with_ticks`, restoring the tick maps of the uniswap v3 and
+//Algebra pools
+pub fn deconstruct_checkpoint_with_ticks(
+    checkpoint_path: &str,
+) -> Result<(Vec<AMM>, u64), CheckpointError> {
+    let CheckpointWithTicks {
+        checkpoint,
+        tick_maps,
+    } = serde_json::from_str(read_to_string(checkpoint_path)?.as_str())?;
+
+    let mut tick_maps: HashMap<H160, UniswapV3TickMaps> = tick_maps
+        .into_iter()
+        .map(|tick_maps| (tick_maps.address, tick_maps))
+        .collect();
+
+    let mut amms = checkpoint.amms;
+    for amm in amms.iter_mut() {
+        let pool = match amm {
+            AMM::UniswapV3Pool(pool) => pool,
+            AMM::AlgebraPool(algebra_pool) => &mut algebra_pool.pool,
+            _ => continue,
+        };
+
+        if let Some(tick_maps) = tick_maps.remove(&pool.address) {
+            pool.tick_bitmap = tick_maps.tick_bitmap;
+            pool.ticks = tick_maps.ticks;
+        }
+    }
+
+    Ok((amms, checkpoint.block_number))
+}
+
+//Relative drift above which a sampled amm is reported as drifted by `verify_checkpoint`
+pub const VERIFY_DRIFT_TOLERANCE: f64 = 0.01;
+
+#[derive(Debug, Clone)]
+pub struct PoolDrift {
+    pub address: H160,
+    //Largest relative difference between the stored and live reserves, liquidity or price
+    pub drift: f64,
+    //False if the live amm could not be read or its tokens differ from the stored amm
+    pub structurally_valid: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub checkpoint_block: u64,
+    pub verified_block: u64,
+    pub sampled: usize,
+    pub drifted: usize,
+    pub invalid: usize,
+    pub pools: Vec<PoolDrift>,
+    //Sampled amms whose batch failed to be read, these are left out of the report instead of failing the verification
+    pub unverified: Vec<H160>,
+    //True if the verification was cancelled, in which case the report only covers the amms verified before
+    pub cancelled: bool,
+}
+
+//Verifies the checkpoint by re-reading a random sample of its amms at the latest block and comparing their state,
+//distinguishing amms that are stale but structurally valid from amms that no longer match the chain
+pub async fn verify_checkpoint<M: 'static + Middleware>(
+    checkpoint_path: &str,
+    middleware: Arc<M>,
+    sample_size: usize,
+) -> Result<VerifyReport, AMMError<M>> {
+    verify_checkpoint_cancellable(
+        checkpoint_path,
+        middleware,
+        sample_size,
+        CancellationToken::new(),
+    )
+    .await
+}
+
+//Verifies the checkpoint like `verify_checkpoint`, showing the checked samples on a progress bar that is hidden in quiet
+//mode. The sample is read in batches with up to TASK_LIMIT batches in flight. Cancelling the token aborts the batches in
+//flight and returns a report of the amms verified so far, with `cancelled` set
+pub async fn verify_checkpoint_cancellable<M: 'static + Middleware>(
+    checkpoint_path: &str,
+    middleware: Arc<M>,
+    sample_size: usize,
+    cancellation_token: CancellationToken,
+) -> Result<VerifyReport, AMMError<M>> {
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let (amms, checkpoint_block) = deconstruct_checkpoint(checkpoint_path)?;
+
+    let sample: Vec<AMM> = amms
+        .choose_multiple(&mut rand::thread_rng(), sample_size)
+        .cloned()
+        .collect();
+
+    //Re-read the sampled amms from empty instances so that amms which can no longer be read are not mistaken for valid ones
+    let empty_sample = sample
+        .iter()
+        .map(|amm| match amm {
+            AMM::UniswapV2Pool(pool) => AMM::UniswapV2Pool(UniswapV2Pool {
+                address: pool.address,
+                ..Default::default()
+            }),
+            AMM::UniswapV3Pool(pool) => AMM::UniswapV3Pool(UniswapV3Pool {
+                address: pool.address,
+                ..Default::default()
+            }),
+            AMM::ERC4626Vault(vault) => AMM::ERC4626Vault(ERC4626Vault {
+                vault_token: vault.vault_token,
+                ..Default::default()
+            }),
+            AMM::AlgebraPool(algebra_pool) => AMM::AlgebraPool(AlgebraPool {
+                pool: UniswapV3Pool {
+                    address: algebra_pool.pool.address,
+                    ..Default::default()
+                },
+            }),
+        })
+        .collect();
+
+    let progress = MULTIPROGRESS.add(
+        ProgressBar::new(sample.len() as u64)
+            .with_style(SYNC_BAR_STYLE.clone())
+            .with_message("Verifying checkpoint"),
+    );
+    progress.tick();
+
+    let mut chunks = group_congruent(empty_sample).into_iter().flat_map(|group| {
+        group
+            .chunks(batch_size(&group[0]))
+            .map(|amm_chunk| amm_chunk.to_vec())
+            .collect::<Vec<_>>()
+    });
+
+    let mut live_amms: HashMap<H160, AMM> = HashMap::new();
+    let mut verified = HashSet::new();
+    let mut unverified = vec![];
+    let mut cancelled = false;
+    let mut handles = JoinSet::new();
+    loop {
+        while handles.len() < TASK_LIMIT {
+            match chunks.next() {
+                Some(amm_chunk) => {
+                    let middleware = middleware.clone();
+                    let addresses: Vec<H160> = amm_chunk.iter().map(|amm| amm.address()).collect();
+                    handles.spawn(async move {
+                        (
+                            addresses,
+                            populate_amm_chunk(amm_chunk, current_block, middleware).await,
+                        )
+                    });
+                }
+                None => break,
+            }
+        }
+
+        if handles.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            biased;
+            _ = cancellation_token.cancelled() => {
+                handles.abort_all();
+                cancelled = true;
+                break;
+            }
+            Some(result) = handles.join_next() => {
+                let (addresses, amm_chunk) = result?;
+                progress.inc(addresses.len() as u64);
+                match amm_chunk {
+                    Ok(amm_chunk) => {
+                        verified.extend(addresses);
+                        for amm in amm_chunk {
+                            live_amms.insert(amm.address(), amm);
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            %err,
+                            pools = addresses.len(),
+                            "Failed to verify batch, skipping its pools"
+                        );
+                        unverified.extend(addresses);
+                    }
+                }
+            }
+        }
+    }
+
+    progress.finish_and_clear();
+
+    let mut pools = vec![];
+    for stored in sample
+        .iter()
+        .filter(|stored| verified.contains(&stored.address()))
+    {
+        let pool_drift = match live_amms.get(&stored.address()) {
+            Some(live) if live.tokens() == stored.tokens() => PoolDrift {
+                address: stored.address(),
+                drift: state_drift(stored, live),
+                structurally_valid: true,
+            },
+            _ => PoolDrift {
+                address: stored.address(),
+                drift: f64::INFINITY,
+                structurally_valid: false,
+            },
+        };
+
+        pools.push(pool_drift);
+    }
+
+    Ok(VerifyReport {
+        checkpoint_block,
+        verified_block: current_block,
+        sampled: pools.len(),
+        drifted: pools
+            .iter()
+            .filter(|pool| pool.structurally_valid && pool.drift > VERIFY_DRIFT_TOLERANCE)
+            .count(),
+        invalid: pools.iter().filter(|pool| !pool.structurally_valid).count(),
+        pools,
+        unverified,
+        cancelled,
+    })
+}
+
+fn state_drift(stored: &AMM, live: &AMM) -> f64 {
+    fn relative_difference(stored: f64, live: f64) -> f64 {
+        if stored == live {
+            0.0
+        } else {
+            (stored - live).abs() / stored.abs().max(live.abs())
+        }
+    }
+
+    match (stored, live) {
+        (AMM::UniswapV2Pool(stored), AMM::UniswapV2Pool(live)) => {
+            relative_difference(stored.reserve_0 as f64, live.reserve_0 as f64).max(
+                relative_difference(stored.reserve_1 as f64, live.reserve_1 as f64),
+            )
+        }
+        (AMM::UniswapV3Pool(stored), AMM::UniswapV3Pool(live))
+        | (
+            AMM::AlgebraPool(AlgebraPool { pool: stored }),
+            AMM::AlgebraPool(AlgebraPool { pool: live }),
+        ) => relative_difference(stored.liquidity as f64, live.liquidity as f64).max(
+            relative_difference(u256_to_f64(stored.sqrt_price), u256_to_f64(live.sqrt_price)),
+        ),
+        (AMM::ERC4626Vault(stored), AMM::ERC4626Vault(live)) => relative_difference(
+            u256_to_f64(stored.vault_reserve),
+            u256_to_f64(live.vault_reserve),
+        )
+        .max(relative_difference(
+            u256_to_f64(stored.asset_reserve),
+            u256_to_f64(live.asset_reserve),
+        )),
+        _ => f64::INFINITY,
+    }
+}
+
+//Merges the tags of the existing amms into the amms with the same address, keeping any tags the amms already have
+pub fn merge_tags(amms: &mut [AMM], existing_amms: &[AMM]) {
+    let existing_tags: HashMap<H160, &[String]> = existing_amms
+        .iter()
+        .filter(|amm| !amm.tags().is_empty())
+        .map(|amm| (amm.address(), amm.tags()))
+        .collect();
+
+    for amm in amms.iter_mut() {
+        if let Some(tags) = existing_tags.get(&amm.address()) {
+            let amm_tags = amm.tags_mut();
+            for tag in tags.iter() {
+                if !amm_tags.contains(tag) {
+                    amm_tags.push(tag.clone());
+                }
+            }
+        }
+    }
+}
+
+//Serializes and writes the checkpoint on the blocking thread pool so that large checkpoints do not stall the async runtime.
+//The amms are moved into the blocking task and returned once the checkpoint is written, avoiding a clone
+pub async fn construct_checkpoint_async(
+    factories: Vec<Factory>,
+    amms: Vec<AMM>,
+    latest_block: u64,
+    checkpoint_path: &str,
+) -> Result<Vec<AMM>, CheckpointError> {
+    let checkpoint_path = checkpoint_path.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        construct_checkpoint(factories, &amms, latest_block, &checkpoint_path)?;
+        Ok(amms)
+    })
+    .await?
+}
+
+//Deconstructs the checkpoint into a Vec<AMM>. The tick maps are not stored in the checkpoint, so the concentrated
+//liquidity pools are returned without them, load them with `populate_tick_data_batch` or use `sync_amms_from_checkpoint`
 pub fn deconstruct_checkpoint(checkpoint_path: &str) -> Result<(Vec<AMM>, u64), CheckpointError> {
     let checkpoint: Checkpoint = serde_json::from_str(read_to_string(checkpoint_path)?.as_str())?;
     Ok((checkpoint.amms, checkpoint.block_number))
 }
+
+//Loads only the amms containing any of the tokens from the checkpoint. The checkpoint is streamed from the file and
+//each amm is dropped as soon as it is deserialized if it does not match, so the full amm list is never held in memory
+pub fn load_checkpoint_filtered(
+    checkpoint_path: &str,
+    tokens: &HashSet<H160>,
+) -> Result<Vec<AMM>, CheckpointError> {
+    let reader = BufReader::new(File::open(checkpoint_path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let amms = FilteredCheckpoint { tokens }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+
+    Ok(amms)
+}
+
+//Deserializes the amms field of a checkpoint through `FilteredAmms`, skipping every other field
+struct FilteredCheckpoint<'a> {
+    tokens: &'a HashSet<H160>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for FilteredCheckpoint<'a> {
+    type Value = Vec<AMM>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FilteredCheckpoint<'a> {
+    type Value = Vec<AMM>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a checkpoint")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut amms = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "amms" {
+                amms = Some(map.next_value_seed(FilteredAmms {
+                    tokens: self.tokens,
+                })?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        amms.ok_or_else(|| de::Error::missing_field("amms"))
+    }
+}
+
+//Deserializes a sequence of amms, keeping only the amms containing any of the tokens
+struct FilteredAmms<'a> {
+    tokens: &'a HashSet<H160>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for FilteredAmms<'a> {
+    type Value = Vec<AMM>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FilteredAmms<'a> {
+    type Value = Vec<AMM>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of amms")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut amms = vec![];
+        while let Some(amm) = seq.next_element::<AMM>()? {
+            if amm.tokens().iter().any(|token| self.tokens.contains(token)) {
+                amms.push(amm);
+            }
+        }
+
+        Ok(amms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use ethers::{
+        abi::Token,
+        providers::Provider,
+        types::{Bytes, Log, H160, H256, I256, U256, U64},
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use crate::amm::{
+        algebra::AlgebraPool,
+        erc_4626::ERC4626Vault,
+        factory::{AutomatedMarketMakerFactory, Factory},
+        uniswap_v2::{
+            factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+            UniswapV2Pool,
+        },
+        uniswap_v3::{factory::UniswapV3Factory, UniswapV3Pool, UniswapV3TickMaps},
+        AutomatedMarketMaker, AMM,
+    };
+
+    use super::{
+        construct_checkpoint, construct_checkpoint_async, construct_checkpoint_with_ticks,
+        deconstruct_checkpoint, deconstruct_checkpoint_with_ticks, export_factories,
+        import_factories, load_checkpoint_filtered, sort_amms, sync_amms_from_checkpoint,
+        sync_amms_from_checkpoint_with_factories, verify_checkpoint, verify_checkpoint_cancellable,
+        Checkpoint,
+    };
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_checkpoint_round_trip() -> eyre::Result<()> {
+        use crate::errors::CheckpointError;
+
+        use super::{construct_encrypted_checkpoint, deconstruct_encrypted_checkpoint};
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_checkpoint.json.enc");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let key = [7_u8; 32];
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            reserve_0: 1000,
+            reserve_1: 2000,
+            ..Default::default()
+        });
+
+        construct_encrypted_checkpoint(vec![], &[pool], 100, checkpoint_path, &key)?;
+
+        //The checkpoint is not readable as plain json
+        assert!(deconstruct_checkpoint(checkpoint_path).is_err());
+
+        let (amms, block_number) = deconstruct_encrypted_checkpoint(checkpoint_path, &key)?;
+        assert_eq!(block_number, 100);
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), H160::from_low_u64_be(1));
+
+        assert!(matches!(
+            deconstruct_encrypted_checkpoint(checkpoint_path, &[8_u8; 32]),
+            Err(CheckpointError::DecryptionFailed(_))
+        ));
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "checkpoint-formats")]
+    #[test]
+    fn test_convert_checkpoint_round_trip() -> eyre::Result<()> {
+        use super::{convert_checkpoint, CheckpointFormat};
+
+        let json_path = std::env::temp_dir().join("amms_test_convert_checkpoint.json");
+        let json_path = json_path.to_str().expect("Temp path should be utf-8");
+        let binary_path = std::env::temp_dir().join("amms_test_convert_checkpoint.bin");
+        let binary_path = binary_path.to_str().expect("Temp path should be utf-8");
+        let converted_path = std::env::temp_dir().join("amms_test_convert_checkpoint_back.json");
+        let converted_path = converted_path.to_str().expect("Temp path should be utf-8");
+
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(1),
+                reserve_0: u128::MAX,
+                reserve_1: 2000,
+                ..Default::default()
+            }),
+            AMM::UniswapV3Pool(UniswapV3Pool {
+                address: H160::from_low_u64_be(2),
+                liquidity: 1_000_000,
+                sqrt_price: U256::one() << 96,
+                tick: -120,
+                ..Default::default()
+            }),
+            AMM::AlgebraPool(AlgebraPool {
+                pool: UniswapV3Pool {
+                    address: H160::from_low_u64_be(3),
+                    fee: 1500,
+                    ..Default::default()
+                },
+            }),
+        ];
+        construct_checkpoint(vec![], &amms, 100, json_path)?;
+
+        assert_eq!(
+            convert_checkpoint(json_path, binary_path)?,
+            CheckpointFormat::Json
+        );
+        //The binary checkpoint is not readable as plain json
+        assert!(deconstruct_checkpoint(binary_path).is_err());
+
+        assert_eq!(
+            convert_checkpoint(binary_path, converted_path)?,
+            CheckpointFormat::Binary
+        );
+        let (converted_amms, block_number) = deconstruct_checkpoint(converted_path)?;
+
+        assert_eq!(block_number, 100);
+        assert_eq!(
+            serde_json::to_value(&converted_amms)?,
+            serde_json::to_value(&amms)?
+        );
+
+        for path in [json_path, binary_path, converted_path] {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import_factories() -> eyre::Result<()> {
+        let factories_path = std::env::temp_dir().join("amms_test_factories.json");
+        let factories_path = factories_path.to_str().expect("Temp path should be utf-8");
+
+        let factories = vec![
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(10), 100, 300)),
+            Factory::UniswapV3Factory(UniswapV3Factory::new(H160::from_low_u64_be(11), 200)),
+        ];
+
+        export_factories(&factories, factories_path)?;
+        let imported = import_factories(factories_path)?;
+
+        assert_eq!(imported.len(), factories.len());
+        for (imported, factory) in imported.iter().zip(factories.iter()) {
+            assert_eq!(imported.address(), factory.address());
+            assert_eq!(imported.creation_block(), factory.creation_block());
+        }
+
+        match &imported[0] {
+            Factory::UniswapV2Factory(factory) => assert_eq!(factory.fee, 300),
+            _ => panic!("Expected a uniswap v2 factory"),
+        }
+        assert!(matches!(imported[1], Factory::UniswapV3Factory(_)));
+
+        std::fs::remove_file(factories_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_checkpoint_with_added_factory() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_added_factory_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let existing_factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(10), 0, 300));
+        let added_factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(11), 10, 300));
+
+        //The checkpoint is at the latest block so that only the added factory is scanned
+        construct_checkpoint(vec![existing_factory.clone()], &[], 200, checkpoint_path)?;
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pair = H160::from_low_u64_be(3);
+
+        //Mocked responses are popped in reverse order: block number, pair created logs, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<Vec<Log>, _>(vec![Log {
+            address: added_factory.address(),
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(50)),
+            ..Default::default()
+        }])?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let (factories, amms) = sync_amms_from_checkpoint_with_factories(
+            checkpoint_path,
+            1000,
+            Some(vec![existing_factory, added_factory.clone()]),
+            Arc::new(provider),
+        )
+        .await?;
+
+        assert_eq!(factories.len(), 2);
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), pair);
+
+        let checkpoint: super::Checkpoint =
+            serde_json::from_str(&std::fs::read_to_string(checkpoint_path)?)?;
+        assert!(checkpoint
+            .factories
+            .iter()
+            .any(|factory| factory.address() == added_factory.address()));
+        assert_eq!(checkpoint.amms.len(), 1);
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_checkpoint_with_dropped_factory() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path =
+            std::env::temp_dir().join("amms_test_dropped_factory_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let kept_factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(10), 0, 300));
+        let dropped_factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(11), 0, 300));
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let kept_pool = H160::from_low_u64_be(3);
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: kept_pool,
+                factory: kept_factory.address(),
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(4),
+                factory: dropped_factory.address(),
+                ..Default::default()
+            }),
+        ];
+
+        //The checkpoint is at the latest block so that only the checkpointed pools are synced
+        construct_checkpoint(
+            vec![kept_factory.clone(), dropped_factory],
+            &amms,
+            200,
+            checkpoint_path,
+        )?;
+
+        //Mocked responses are popped in reverse order: block number, pool data of the kept pool
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let (factories, amms) = sync_amms_from_checkpoint_with_factories(
+            checkpoint_path,
+            1000,
+            Some(vec![kept_factory]),
+            Arc::new(provider),
+        )
+        .await?;
+
+        assert_eq!(factories.len(), 1);
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), kept_pool);
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_skips_tick_maps() -> eyre::Result<()> {
+        let checkpoint_path = std::env::temp_dir().join("amms_test_tick_maps_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(1),
+            sqrt_price: U256::one() << 96,
+            liquidity: 1000,
+            tick_spacing: 60,
+            ..Default::default()
+        };
+        pool.insert_initialized_tick(-60, 1000);
+        pool.insert_initialized_tick(60, -1000);
+        let algebra_pool = AlgebraPool {
+            pool: UniswapV3Pool {
+                address: H160::from_low_u64_be(2),
+                ..pool.clone()
+            },
+        };
+        let amms = vec![AMM::UniswapV3Pool(pool), AMM::AlgebraPool(algebra_pool)];
+
+        //The default checkpoint round trips the pool state without the tick maps
+        construct_checkpoint(vec![], &amms, 100, checkpoint_path)?;
+        assert!(!std::fs::read_to_string(checkpoint_path)?.contains("tick_bitmap"));
+
+        let (checkpoint_amms, _) = deconstruct_checkpoint(checkpoint_path)?;
+        if let AMM::UniswapV3Pool(pool) = &checkpoint_amms[0] {
+            assert_eq!(pool.liquidity, 1000);
+            assert_eq!(pool.sqrt_price, U256::one() << 96);
+            assert!(pool.ticks.is_empty());
+            assert!(pool.tick_bitmap.is_empty());
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        //The tick checkpoint restores the tick maps and stays readable as a default checkpoint
+        construct_checkpoint_with_ticks(vec![], &amms, 100, checkpoint_path)?;
+        let (checkpoint_amms, block_number) = deconstruct_checkpoint_with_ticks(checkpoint_path)?;
+        assert_eq!(block_number, 100);
+        if let (AMM::UniswapV3Pool(pool), AMM::UniswapV3Pool(expected)) =
+            (&checkpoint_amms[0], &amms[0])
+        {
+            assert_eq!(pool.ticks.len(), 2);
+            assert_eq!(pool.ticks[&60].liquidity_net, -1000);
+            assert_eq!(pool.tick_bitmap, expected.tick_bitmap);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        if let AMM::AlgebraPool(algebra_pool) = &checkpoint_amms[1] {
+            assert_eq!(algebra_pool.pool.ticks.len(), 2);
+            assert_eq!(algebra_pool.pool.ticks[&-60].liquidity_net, 1000);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        assert_eq!(deconstruct_checkpoint(checkpoint_path)?.0.len(), 2);
+
+        //Tick maps stored inline by older checkpoints are still read
+        let mut pool_json = serde_json::to_value(&amms[0])?;
+        let tick_maps = match &amms[0] {
+            AMM::UniswapV3Pool(pool) => serde_json::to_value(UniswapV3TickMaps::from(pool))?,
+            _ => panic!("Unexpected AMM variant"),
+        };
+        pool_json["UniswapV3Pool"]["tick_bitmap"] = tick_maps["tick_bitmap"].clone();
+        pool_json["UniswapV3Pool"]["ticks"] = tick_maps["ticks"].clone();
+        match serde_json::from_value::<AMM>(pool_json)? {
+            AMM::UniswapV3Pool(pool) => assert_eq!(pool.ticks.len(), 2),
+            _ => panic!("Unexpected AMM variant"),
+        }
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_from_checkpoint_rederives_tick_maps() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_rederive_ticks_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let mut pool = UniswapV3Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_0,
+            token_b: token_1,
+            tick_spacing: 60,
+            ..Default::default()
+        };
+        pool.insert_initialized_tick(-60, 1000);
+        construct_checkpoint(vec![], &[AMM::UniswapV3Pool(pool)], 200, checkpoint_path)?;
+
+        //Every tick window request returns the same ticks, one request for each direction from the current tick
+        let tick_data = ethers::abi::encode(&[
+            Token::Array(vec![Token::Tuple(vec![
+                Token::Bool(true),
+                Token::Int(I256::from(-60).into_raw()),
+                Token::Int(I256::from(1000).into_raw()),
+            ])]),
+            Token::Uint(U256::from(200)),
+        ]);
+        mock.push::<Bytes, _>(tick_data.clone().into())?;
+        mock.push::<Bytes, _>(tick_data.into())?;
+
+        //Mocked responses are popped in reverse order: block number, pool data, tick windows
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::one() << 96),
+                Token::Int(I256::zero().into_raw()),
+                Token::Int(I256::from(60).into_raw()),
+                Token::Uint(U256::from(3000)),
+                Token::Int(I256::zero().into_raw()),
+            ]),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let (_, amms) =
+            sync_amms_from_checkpoint(checkpoint_path, 1000, Arc::new(provider)).await?;
+
+        match &amms[0] {
+            AMM::UniswapV3Pool(pool) => {
+                assert_eq!(pool.ticks.len(), 1);
+                assert_eq!(pool.ticks[&-60].liquidity_net, 1000);
+                assert!(!pool.tick_bitmap.is_empty());
+            }
+            _ => panic!("Unexpected AMM variant"),
+        }
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_amms_keeps_algebra_pools() {
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool::default()),
+            AMM::AlgebraPool(AlgebraPool::default()),
+            AMM::UniswapV3Pool(UniswapV3Pool::default()),
+        ];
+
+        let (uniswap_v2_pools, uniswap_v3_pools, erc_4626_vaults, algebra_pools) = sort_amms(amms);
+
+        assert_eq!(uniswap_v2_pools.len(), 1);
+        assert_eq!(uniswap_v3_pools.len(), 1);
+        assert!(erc_4626_vaults.is_empty());
+        assert_eq!(algebra_pools.len(), 1);
+        assert!(matches!(algebra_pools[0], AMM::AlgebraPool(_)));
+    }
+
+    #[tokio::test]
+    async fn test_construct_checkpoint_async() -> eyre::Result<()> {
+        let checkpoint_path = std::env::temp_dir().join("amms_test_async_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let amms = (1..=1000)
+            .map(|i| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(i),
+                    reserve_0: i as u128,
+                    reserve_1: i as u128,
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<AMM>>();
+
+        let amms = construct_checkpoint_async(vec![], amms, 100, checkpoint_path).await?;
+
+        let (checkpoint_amms, block_number) = deconstruct_checkpoint(checkpoint_path)?;
+        assert_eq!(block_number, 100);
+        assert_eq!(checkpoint_amms.len(), amms.len());
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_checkpoint_filtered() -> eyre::Result<()> {
+        let checkpoint_path = std::env::temp_dir().join("amms_test_filtered_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let weth = H160::from_low_u64_be(100);
+        let usdc = H160::from_low_u64_be(101);
+        let dai = H160::from_low_u64_be(102);
+        let wbtc = H160::from_low_u64_be(103);
+
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(1),
+                token_a: weth,
+                token_b: usdc,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(2),
+                token_a: dai,
+                token_b: wbtc,
+                ..Default::default()
+            }),
+            AMM::UniswapV3Pool(UniswapV3Pool {
+                address: H160::from_low_u64_be(3),
+                token_a: dai,
+                token_b: usdc,
+                ..Default::default()
+            }),
+            AMM::AlgebraPool(AlgebraPool {
+                pool: UniswapV3Pool {
+                    address: H160::from_low_u64_be(4),
+                    token_a: wbtc,
+                    token_b: dai,
+                    ..Default::default()
+                },
+            }),
+        ];
+        construct_checkpoint(
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                H160::from_low_u64_be(10),
+                100,
+                300,
+            ))],
+            &amms,
+            100,
+            checkpoint_path,
+        )?;
+
+        let filtered = load_checkpoint_filtered(checkpoint_path, &HashSet::from([usdc]))?;
+        assert_eq!(
+            filtered.iter().map(|amm| amm.address()).collect::<Vec<_>>(),
+            vec![H160::from_low_u64_be(1), H160::from_low_u64_be(3)]
+        );
+        assert!(matches!(filtered[1], AMM::UniswapV3Pool(_)));
+
+        let filtered = load_checkpoint_filtered(checkpoint_path, &HashSet::from([wbtc]))?;
+        assert_eq!(
+            filtered.iter().map(|amm| amm.address()).collect::<Vec<_>>(),
+            vec![H160::from_low_u64_be(2), H160::from_low_u64_be(4)]
+        );
+
+        assert!(load_checkpoint_filtered(checkpoint_path, &HashSet::new())?.is_empty());
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_checkpoint() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_verify_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_0,
+            token_b: token_1,
+            reserve_0: 1000,
+            reserve_1: 2000,
+            ..Default::default()
+        })];
+        construct_checkpoint(vec![], &amms, 100, checkpoint_path)?;
+
+        //The live reserves have drifted by 10%
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1100)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let report = verify_checkpoint(checkpoint_path, Arc::new(provider), 10).await?;
+
+        assert_eq!(report.checkpoint_block, 100);
+        assert_eq!(report.verified_block, 200);
+        assert_eq!(report.sampled, 1);
+        assert_eq!(report.drifted, 1);
+        assert_eq!(report.invalid, 0);
+        assert!((report.pools[0].drift - 100.0 / 1100.0).abs() < 1e-9);
+        assert!(!report.cancelled);
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_checkpoint_skips_failed_batches() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path =
+            std::env::temp_dir().join("amms_test_verify_checkpoint_failed_batch.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let vault = H160::from_low_u64_be(3);
+        let amms = vec![AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: vault,
+            asset_token: H160::from_low_u64_be(1),
+            ..Default::default()
+        })];
+        construct_checkpoint(vec![], &amms, 100, checkpoint_path)?;
+
+        //Only the block number is mocked, so reading the vault fails
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let report = verify_checkpoint(checkpoint_path, Arc::new(provider), 10).await?;
+
+        assert_eq!(report.sampled, 0);
+        assert!(report.pools.is_empty());
+        assert_eq!(report.unverified, vec![vault]);
+        assert!(!report.cancelled);
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_checkpoint_cancelled() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path =
+            std::env::temp_dir().join("amms_test_verify_checkpoint_cancelled.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let amms: Vec<AMM> = (0..3)
+            .map(|idx| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(10 + idx),
+                    token_a: H160::from_low_u64_be(1),
+                    token_b: H160::from_low_u64_be(2),
+                    reserve_0: 1000,
+                    reserve_1: 2000,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        construct_checkpoint(vec![], &amms, 100, checkpoint_path)?;
+
+        mock.push::<U64, _>(U64::from(200))?;
+
+        //A verification cancelled before any batch completes returns an empty partial report
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+        let report = verify_checkpoint_cancellable(
+            checkpoint_path,
+            Arc::new(provider),
+            10,
+            cancellation_token,
+        )
+        .await?;
+
+        assert!(report.cancelled);
+        assert_eq!(report.verified_block, 200);
+        assert_eq!(report.sampled, 0);
+        assert!(report.pools.is_empty());
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tags_persist_across_resync() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_tags_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_0,
+            token_b: token_1,
+            reserve_0: 1,
+            reserve_1: 1,
+            tags: vec!["blue-chip".to_string()],
+            ..Default::default()
+        })];
+        construct_checkpoint(vec![], &amms, 100, checkpoint_path)?;
+
+        //Mocked responses are popped in reverse order, so the batch call response is pushed before the block number
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let (_, synced_amms) =
+            sync_amms_from_checkpoint(checkpoint_path, 1000, Arc::new(provider)).await?;
+        assert_eq!(synced_amms[0].tags(), ["blue-chip".to_string()]);
+
+        let (checkpoint_amms, block_number) = deconstruct_checkpoint(checkpoint_path)?;
+        assert_eq!(block_number, 200);
+        assert_eq!(checkpoint_amms[0].tags(), ["blue-chip".to_string()]);
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_legacy_checkpoint() -> eyre::Result<()> {
+        //Checkpoint written before the tags, last synced block, factory, oracle and protocol fee fields were added, with a
+        //field that has since been removed
+        let checkpoint: Checkpoint =
+            serde_json::from_str(include_str!("../../tests/fixtures/legacy_checkpoint.json"))?;
+
+        assert_eq!(checkpoint.block_number, 17000000);
+        assert_eq!(checkpoint.factories.len(), 2);
+        match &checkpoint.factories[0] {
+            Factory::UniswapV2Factory(factory) => {
+                assert_eq!(factory.fee, 300);
+                assert!(!factory.protocol_fee_on);
+            }
+            _ => panic!("Expected a uniswap v2 factory"),
+        }
+
+        assert_eq!(checkpoint.amms.len(), 3);
+        match &checkpoint.amms[0] {
+            AMM::UniswapV2Pool(pool) => {
+                assert_eq!(pool.reserve_0, 47092140895651);
+                assert_eq!(pool.reserve_1, 25968544849682143584522);
+                assert!(!pool.unreliable_reserves);
+                assert!(pool.factory.is_zero());
+            }
+            _ => panic!("Expected a uniswap v2 pool"),
+        }
+        match &checkpoint.amms[1] {
+            AMM::UniswapV3Pool(pool) => {
+                assert_eq!(pool.liquidity, 17126936293933290571);
+                assert_eq!(pool.observation_cardinality, 0);
+                assert_eq!(pool.observation_cardinality_next, 0);
+            }
+            _ => panic!("Expected a uniswap v3 pool"),
+        }
+
+        for amm in checkpoint.amms.iter() {
+            assert!(amm.tags().is_empty());
+            assert_eq!(amm.last_synced_block(), 0);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, H160},
+};
+
+use crate::{
+    amm::{AutomatedMarketMaker, AMM},
+    errors::AMMError,
+};
+
+use super::{stats::ProgressCallback, tuner::BatchTuning};
+
+//The block to sync against, safe and finalized tags avoid syncing state that could be reorged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockTag {
+    #[default]
+    Latest,
+    Safe,
+    Finalized,
+    Number(u64),
+}
+
+impl BlockTag {
+    //Resolves the tag to a block number via the provider
+    pub async fn resolve<M: Middleware>(&self, middleware: Arc<M>) -> Result<u64, AMMError<M>> {
+        let block_number = match self {
+            BlockTag::Latest => {
+                return Ok(middleware
+                    .get_block_number()
+                    .await
+                    .map_err(AMMError::MiddlewareError)?
+                    .as_u64())
+            }
+            BlockTag::Number(block_number) => return Ok(*block_number),
+            BlockTag::Safe => BlockNumber::Safe,
+            BlockTag::Finalized => BlockNumber::Finalized,
+        };
+
+        middleware
+            .get_block(block_number)
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .and_then(|block| block.number)
+            .map(|number| number.as_u64())
+            .ok_or(AMMError::BlockNumberNotFound)
+    }
+}
+
+//How much pool data is read when populating discovered pools
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PopulationMode {
+    //Tokens, decimals and reserves
+    #[default]
+    Full,
+    //Only tokens and decimals, leaving the reserves and slot0 values zeroed
+    MetadataOnly,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncConfig {
+    pub block_tag: BlockTag,
+    pub population_mode: PopulationMode,
+    //Pool addresses that are excluded from the sync output
+    pub denylist: HashSet<H160>,
+    //Hex address prefixes (eg. "0xdead") that exclude every matching pool
+    pub denylist_prefixes: Vec<String>,
+    //Tokens that exclude every pool containing them
+    pub token_denylist: HashSet<H160>,
+    //Abort the sync if any factory fails instead of skipping it
+    pub strict: bool,
+    //Tune the batch sizes of full population from the observed latency instead of using the fixed max batch sizes
+    pub batch_tuning: Option<BatchTuning>,
+    //Only sync pools created at or after the time, converted to the first block at the time. Creation times are only
+    //known from the pool created logs, so every factory is discovered through its logs from that block, including v2
+    //factories that are otherwise discovered through allPairs
+    pub created_after: Option<SystemTime>,
+    //Decimals forced for tokens whose decimals() reverts or returns a wrong value. Overridden tokens are never read on
+    //chain, pools containing them are populated outside the batch requests since the batch contracts skip pools with a
+    //reverting decimals()
+    pub decimal_overrides: HashMap<H160, u8>,
+    //Decimals assumed for tokens whose decimals() reverts, eg. Some(18), which are only applied when explicitly set since a
+    //wrong guess corrupts every price of the pool. Without a fallback, pools with such a token are left out of the synced
+    //pools and reported in `SyncStats::unreadable_decimals` for review. Decimal overrides take precedence
+    pub decimals_fallback: Option<u8>,
+    //Called with the batches completed, total batches and estimated time remaining as the pool population batches complete
+    pub progress_callback: Option<ProgressCallback>,
+    //Drop v2 pools with a reserve exceeding the total supply of its token after population, which indicates a misread
+    pub check_reserve_supply: bool,
+}
+
+impl SyncConfig {
+    //Returns true if the amm is excluded by the address, prefix or token denylists
+    pub fn is_denied(&self, amm: &AMM) -> bool {
+        let address = amm.address();
+        if self.denylist.contains(&address) {
+            return true;
+        }
+
+        if !self.denylist_prefixes.is_empty() {
+            let address = format!("{:x}", address);
+            if self
+                .denylist_prefixes
+                .iter()
+                .any(|prefix| address.starts_with(&prefix.trim_start_matches("0x").to_lowercase()))
+            {
+                return true;
+            }
+        }
+
+        amm.tokens()
+            .iter()
+            .any(|token| self.token_denylist.contains(token))
+    }
+}
@@ -0,0 +1,78 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+
+use crate::errors::CheckpointError;
+
+//Encrypted checkpoints start with a header of the magic bytes, the format version and the nonce, followed by the
+//AES-256-GCM ciphertext of the json checkpoint. The header is authenticated along with the ciphertext, so a tampered
+//version or nonce fails decryption like a tampered checkpoint
+pub const ENCRYPTED_CHECKPOINT_MAGIC: [u8; 4] = *b"AMMC";
+pub const ENCRYPTED_CHECKPOINT_VERSION: u8 = 1;
+pub const ENCRYPTED_CHECKPOINT_EXTENSION: &str = "enc";
+
+const NONCE_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = ENCRYPTED_CHECKPOINT_MAGIC.len() + 1 + NONCE_LENGTH;
+
+//Encrypts the serialized checkpoint under the key with a fresh random nonce, prefixing the header
+pub fn encrypt_checkpoint_bytes(
+    plaintext: &[u8],
+    key: &[u8; 32],
+) -> Result<Vec<u8>, CheckpointError> {
+    let nonce: [u8; NONCE_LENGTH] = rand::random();
+
+    let mut data = Vec::with_capacity(HEADER_LENGTH + plaintext.len());
+    data.extend_from_slice(&ENCRYPTED_CHECKPOINT_MAGIC);
+    data.push(ENCRYPTED_CHECKPOINT_VERSION);
+    data.extend_from_slice(&nonce);
+
+    let ciphertext = cipher(key)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &data,
+            },
+        )
+        .map_err(|_| CheckpointError::EncryptionFailed)?;
+    data.extend(ciphertext);
+
+    Ok(data)
+}
+
+//Decrypts data written by `encrypt_checkpoint_bytes`, failing with `DecryptionFailed` on a missing header, an unsupported
+//format version, a wrong key or a corrupted checkpoint
+pub fn decrypt_checkpoint_bytes(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, CheckpointError> {
+    if data.len() < HEADER_LENGTH
+        || data[..ENCRYPTED_CHECKPOINT_MAGIC.len()] != ENCRYPTED_CHECKPOINT_MAGIC
+    {
+        return Err(CheckpointError::DecryptionFailed(
+            "Missing encrypted checkpoint header".to_string(),
+        ));
+    }
+
+    let version = data[ENCRYPTED_CHECKPOINT_MAGIC.len()];
+    if version != ENCRYPTED_CHECKPOINT_VERSION {
+        return Err(CheckpointError::DecryptionFailed(format!(
+            "Unsupported encrypted checkpoint version {version}"
+        )));
+    }
+
+    let (header, ciphertext) = data.split_at(HEADER_LENGTH);
+    cipher(key)
+        .decrypt(
+            Nonce::from_slice(&header[HEADER_LENGTH - NONCE_LENGTH..]),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| {
+            CheckpointError::DecryptionFailed("Wrong key or corrupted checkpoint".to_string())
+        })
+}
+
+fn cipher(key: &[u8; 32]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
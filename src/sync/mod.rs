@@ -1,17 +1,43 @@
 use crate::{
     amm::{
+        algebra::{self, AlgebraPool},
+        capped_batch_size,
         factory::{AutomatedMarketMakerFactory, Factory, TASK_LIMIT},
-        uniswap_v2, uniswap_v3, AutomatedMarketMaker, AMM,
+        uniswap_v2::{self, factory::DuplicatePair},
+        uniswap_v3, with_request_timeout, AutomatedMarketMaker, PoolType, AMM,
     },
-    constants::{MULTIPROGRESS, SPINNER_STYLE, SYNC_BAR_STYLE},
+    constants::{v3_max_batch_size, MULTIPROGRESS, SPINNER_STYLE, SYNC_BAR_STYLE},
     errors::AMMError,
 };
-use ethers::{providers::Middleware, types::H160};
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, Filter, Log, H160, H256, U256, U64},
+};
 use indicatif::ProgressBar;
-use std::{sync::Arc, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 use tokio::task::JoinSet;
+use tracing::Instrument;
 
 pub mod checkpoint;
+pub mod config;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod stats;
+pub mod store;
+pub mod tuner;
+
+pub use config::{BlockTag, PopulationMode, SyncConfig};
+use stats::ProgressTracker;
+pub use stats::{FailedFactory, ProgressCallback, SyncProgress, SyncStats};
+pub use store::{CheckpointStore, FileStore};
+pub use tuner::{BatchSizeTuner, BatchTuning};
 
 pub async fn sync_amms<M: 'static + Middleware>(
     factories: Vec<Factory>,
@@ -19,6 +45,74 @@ pub async fn sync_amms<M: 'static + Middleware>(
     checkpoint_path: Option<&str>,
     step: u64,
 ) -> Result<(Vec<AMM>, u64), AMMError<M>> {
+    sync_amms_with_config(
+        factories,
+        middleware,
+        checkpoint_path,
+        step,
+        SyncConfig::default(),
+    )
+    .await
+}
+
+//Syncs all amms from the factories, resolving the block to sync against from the sync config
+pub async fn sync_amms_with_config<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    checkpoint_path: Option<&str>,
+    step: u64,
+    config: SyncConfig,
+) -> Result<(Vec<AMM>, u64), AMMError<M>> {
+    let (amms, synced_block, _) =
+        sync_amms_with_stats(factories, middleware, checkpoint_path, step, config).await?;
+
+    Ok((amms, synced_block))
+}
+
+//Syncs all amms from the factories like `sync_amms_with_config`, also returning the stats collected during the sync
+pub async fn sync_amms_with_stats<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    checkpoint_path: Option<&str>,
+    step: u64,
+    config: SyncConfig,
+) -> Result<(Vec<AMM>, u64, SyncStats), AMMError<M>> {
+    let store = checkpoint_path.map(FileStore::new);
+    sync_amms_with_store(factories, middleware, store.as_ref(), step, config).await
+}
+
+//Syncs all amms from the factories like `sync_amms`, returning the amms grouped by the factory they were discovered from
+pub async fn sync_amms_grouped<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    checkpoint_path: Option<&str>,
+    step: u64,
+) -> Result<(HashMap<H160, Vec<AMM>>, u64), AMMError<M>> {
+    let (amms, synced_block) = sync_amms(factories, middleware, checkpoint_path, step).await?;
+
+    Ok((group_by_factory(amms), synced_block))
+}
+
+//Groups the amms by the address of their factory, keeping the order of the amms within each group. Amms that were not
+//discovered from a factory, such as vaults, are grouped under the zero address
+pub fn group_by_factory(amms: Vec<AMM>) -> HashMap<H160, Vec<AMM>> {
+    let mut grouped_amms: HashMap<H160, Vec<AMM>> = HashMap::new();
+    for amm in amms {
+        grouped_amms.entry(amm.factory()).or_default().push(amm);
+    }
+
+    grouped_amms
+}
+
+//Syncs all amms from the factories like `sync_amms_with_stats`, saving the checkpoint to the store if one is provided
+#[tracing::instrument(skip(factories, middleware, store, config), fields(factories = factories.len()), err)]
+pub async fn sync_amms_with_store<M: 'static + Middleware, S: CheckpointStore>(
+    mut factories: Vec<Factory>,
+    middleware: Arc<M>,
+    store: Option<&S>,
+    step: u64,
+    config: SyncConfig,
+) -> Result<(Vec<AMM>, u64, SyncStats), AMMError<M>> {
     let spinner = MULTIPROGRESS.add(
         ProgressBar::new_spinner()
             .with_style(SPINNER_STYLE.clone())
@@ -26,209 +120,3881 @@ pub async fn sync_amms<M: 'static + Middleware>(
     );
     spinner.enable_steady_tick(Duration::from_millis(200));
 
-    let current_block = middleware
-        .get_block_number()
-        .await
-        .map_err(AMMError::MiddlewareError)?
-        .as_u64();
+    let current_block = config.block_tag.resolve(middleware.clone()).await?;
+    tracing::info!(current_block, "Resolved sync block");
+
+    let created_after_block = match config.created_after {
+        Some(created_after) => {
+            let timestamp = created_after
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let block_number =
+                first_block_at_timestamp(timestamp, current_block, middleware.clone()).await?;
+            tracing::info!(block_number, "Resolved created after block");
+            Some(block_number)
+        }
+        None => None,
+    };
 
     //Aggregate the populated pools from each thread
     let mut aggregated_amms: Vec<AMM> = vec![];
     let mut handles = JoinSet::new();
 
+    //A single tuner is shared by the factories so that the converged batch sizes are reused across the sync run
+    let tuner = config
+        .batch_tuning
+        .map(|tuning| Arc::new(BatchSizeTuner::new(tuning)));
+
+    //Batches are counted across all factories so that the estimated time remaining covers the whole sync run
+    let progress_tracker = Arc::new(ProgressTracker::new(config.progress_callback.clone()));
+
     //For each dex supplied, get all pair created events and get reserve values
     for factory in factories.clone() {
         let middleware = middleware.clone();
+        let config = config.clone();
+        let tuner = tuner.clone();
+        let progress_tracker = progress_tracker.clone();
 
         //Spawn a new thread to get all pools and sync data for each dex
-        handles.spawn(async move {
-            //Get all of the amms from the factory
-            let mut amms: Vec<AMM> = factory
-                .get_all_amms(Some(current_block), middleware.clone(), step)
-                .await?;
-            //Populate the amms with data
-            amms = populate_amms(
-                &amms,
-                current_block,
-                Some(factory.address()),
-                middleware.clone(),
-            )
-            .await?;
-
-            //Clean empty pools
-            amms = remove_empty_amms(amms);
-
-            // If the factory is UniswapV2, set the fee for each pool according to the factory fee
-            if let Factory::UniswapV2Factory(factory) = factory {
-                for amm in amms.iter_mut() {
-                    if let AMM::UniswapV2Pool(ref mut pool) = amm {
-                        pool.fee = factory.fee;
-                    }
-                }
+        let factory_span = tracing::info_span!("sync_factory", factory = ?factory.address());
+        handles.spawn(
+            async move {
+                let factory_address = factory.address();
+                let result = sync_factory(
+                    factory,
+                    current_block,
+                    created_after_block,
+                    step,
+                    &config,
+                    tuner.as_deref(),
+                    progress_tracker,
+                    middleware,
+                )
+                .await;
+                (factory_address, result)
             }
-
-            Ok::<_, AMMError<M>>(amms)
-        });
+            .instrument(factory_span),
+        );
     }
 
-    while let Some(amm) = handles.join_next().await {
-        aggregated_amms.extend(amm??);
+    //Failed factories are skipped and reported in the stats unless the sync is strict
+    let mut stats = SyncStats::default();
+    while let Some(result) = handles.join_next().await {
+        match result? {
+            (_, Ok((synced_factory, amms, duplicate_pairs, unreadable_decimals))) => {
+                //Keep the protocol fee state read by the factory sync
+                if let Some(factory) = factories
+                    .iter_mut()
+                    .find(|factory| factory.address() == synced_factory.address())
+                {
+                    *factory = synced_factory;
+                }
+                aggregated_amms.extend(amms);
+                stats.duplicate_pairs.extend(duplicate_pairs);
+                stats.unreadable_decimals.extend(unreadable_decimals);
+            }
+            (factory_address, Err(err)) if !config.strict => {
+                tracing::error!(factory = ?factory_address, %err, "Skipping factory that failed to sync");
+                stats.failed_factories.push(FailedFactory {
+                    address: factory_address,
+                    error: err.to_string(),
+                });
+            }
+            (_, Err(err)) => return Err(err),
+        }
     }
 
-    //Save a checkpoint if a path is provided
+    //Failed factories are left out of the checkpoint so that they are backfilled once they are added back
+    factories.retain(|factory| {
+        !stats
+            .failed_factories
+            .iter()
+            .any(|failed_factory| failed_factory.address == factory.address())
+    });
 
-    if let Some(checkpoint_path) = checkpoint_path {
+    //Save a checkpoint if a store is provided
+    if let Some(store) = store {
         spinner.set_message("Saving checkpoint...");
-        checkpoint::construct_checkpoint(
-            factories,
-            &aggregated_amms,
-            current_block,
-            checkpoint_path,
-        )?;
+
+        //Preserve the tags of amms already present in an existing checkpoint
+        if let Ok(existing_checkpoint) = store.load().await {
+            checkpoint::merge_tags(&mut aggregated_amms, &existing_checkpoint.amms);
+        }
+        aggregated_amms = store
+            .store(checkpoint::Checkpoint::now(
+                current_block,
+                factories,
+                aggregated_amms,
+            )?)
+            .await?
+            .amms;
     }
 
     spinner.finish_and_clear();
 
     //Return the populated aggregated amms vec
-    Ok((aggregated_amms, current_block))
+    Ok((aggregated_amms, current_block, stats))
 }
 
-pub fn amms_are_congruent(amms: &[AMM]) -> bool {
-    let expected_amm = &amms[0];
+//Gets all amms from the factory and populates them according to the sync config, returning the factory with the
+//protocol fee state of v2 factories read at the current block
+async fn sync_factory<M: 'static + Middleware>(
+    mut factory: Factory,
+    current_block: u64,
+    created_after_block: Option<u64>,
+    step: u64,
+    config: &SyncConfig,
+    tuner: Option<&BatchSizeTuner>,
+    progress_tracker: Arc<ProgressTracker>,
+    middleware: Arc<M>,
+) -> Result<(Factory, Vec<AMM>, Vec<DuplicatePair>, Vec<H160>), AMMError<M>> {
+    if let Factory::UniswapV2Factory(ref mut factory) = factory {
+        factory.protocol_fee_on = factory
+            .get_protocol_fee_on(Some(current_block), middleware.clone())
+            .await?;
+    }
 
-    for amm in amms {
-        if std::mem::discriminant(expected_amm) != std::mem::discriminant(amm) {
-            return false;
+    //Get all of the amms from the factory, or only the amms created from the created after block through the logs
+    let mut amms: Vec<AMM> = match created_after_block {
+        Some(from_block) => {
+            factory
+                .get_all_pools_from_logs(
+                    from_block.max(factory.creation_block()),
+                    current_block,
+                    step,
+                    None,
+                    middleware.clone(),
+                )
+                .await?
         }
-    }
-    true
-}
+        None => {
+            factory
+                .get_all_amms(Some(current_block), middleware.clone(), step)
+                .await?
+        }
+    };
 
-//Gets all pool data and sync reserves
-pub async fn populate_amms<M: 'static + Middleware>(
-    amms: &[AMM],
-    block_number: u64,
-    address: Option<H160>,
-    middleware: Arc<M>,
-) -> Result<Vec<AMM>, AMMError<M>> {
-    let progress = MULTIPROGRESS.add(
-        ProgressBar::new(amms.len() as u64)
-            .with_style(SYNC_BAR_STYLE.clone())
-            .with_message(match address {
-                Some(address) => format!("Populating pools data from: {}", address),
-                None => "Populating pools data".to_string(),
-            }),
-    );
-    progress.tick();
+    //Drop denylisted pools before they are populated
+    amms.retain(|amm| !config.is_denied(amm));
 
-    let mut handles = JoinSet::new();
-    let mut updated_amms = vec![];
+    let batches = population_batches(&amms);
+    progress_tracker.add_batches(batches);
 
-    if amms_are_congruent(amms) {
-        match amms[0] {
-            AMM::UniswapV2Pool(_) => {
-                let step = 127; //Max batch size for call
-                for amm_chunk in amms.chunks(step) {
-                    let middleware = middleware.clone();
-                    let progress = progress.clone();
-                    let mut amm_chunk = amm_chunk.to_vec();
-                    handles.spawn(async move {
-                        uniswap_v2::batch_request::get_amm_data_batch_request(
-                            &mut amm_chunk,
-                            middleware.clone(),
-                        )
-                        .await?;
-                        progress.inc(amm_chunk.len() as u64);
-                        Ok::<_, AMMError<M>>(amm_chunk)
-                    });
+    //Populate the amms with data, only the fixed size batches of full population are reported as they complete. Pools with
+    //a token whose decimals() reverts are flagged by full population
+    let mut unreadable_amms = vec![];
+    amms = match config.population_mode {
+        PopulationMode::Full if tuner.is_some() => {
+            let tuner = tuner.expect("Tuner is checked to be set");
 
-                    if handles.len() == TASK_LIMIT {
-                        process_updated_amm(&mut updated_amms, handles).await?;
-                        handles = JoinSet::new();
-                    }
-                }
+            //The batch contracts skip pools with a token whose decimals() reverts, so the overridden amms are kept out
+            //of the tuned batches like in `populate_amms_with_decimal_overrides`
+            let (overridden_amms, amms): (Vec<AMM>, Vec<AMM>) = amms
+                .into_iter()
+                .partition(|amm| has_decimal_override(amm, &config.decimal_overrides));
+
+            let mut populated_amms = vec![];
+            for group in group_congruent(amms) {
+                populated_amms.extend(
+                    populate_amms_adaptive(&group, current_block, tuner, middleware.clone())
+                        .await?,
+                );
             }
 
-            AMM::UniswapV3Pool(_) => {
-                let step = 76; //Max batch size for call
-                for amm_chunk in amms.chunks(step) {
-                    let middleware = middleware.clone();
-                    let progress = progress.clone();
-                    let mut amm_chunk = amm_chunk.to_vec();
-                    handles.spawn(async move {
-                        uniswap_v3::batch_request::get_amm_data_batch_request(
-                            &mut amm_chunk,
-                            block_number,
-                            middleware.clone(),
-                        )
-                        .await?;
-                        progress.inc(amm_chunk.len() as u64);
-                        Ok::<_, AMMError<M>>(amm_chunk)
-                    });
+            let (populated_amms, unreadable) = populate_overridden_amms(
+                populated_amms,
+                overridden_amms,
+                current_block,
+                &config.decimal_overrides,
+                config.decimals_fallback,
+                middleware.clone(),
+            )
+            .await?;
+            unreadable_amms = unreadable;
+            progress_tracker.complete_batches(batches);
+            populated_amms
+        }
+        PopulationMode::Full
+            if !config.decimal_overrides.is_empty() || config.decimals_fallback.is_some() =>
+        {
+            let (populated_amms, unreadable) = populate_amms_with_decimal_overrides(
+                amms,
+                current_block,
+                Some(factory.address()),
+                &config.decimal_overrides,
+                config.decimals_fallback,
+                middleware.clone(),
+            )
+            .await?;
+            unreadable_amms = unreadable;
+            progress_tracker.complete_batches(batches);
+            populated_amms
+        }
+        PopulationMode::Full => {
+            let (skipped_amms, populated_amms): (Vec<AMM>, Vec<AMM>) = populate_amms_with_progress(
+                &amms,
+                current_block,
+                Some(factory.address()),
+                Some(progress_tracker),
+                middleware.clone(),
+            )
+            .await?
+            .into_iter()
+            .partition(|amm| amm.tokens().iter().any(|token| token.is_zero()));
 
-                    if handles.len() == TASK_LIMIT {
-                        process_updated_amm(&mut updated_amms, handles).await?;
-                        handles = JoinSet::new();
-                    }
-                }
+            //Pools skipped by the batch requests are only checked for a reverting decimals() to flag them
+            if !skipped_amms.is_empty() {
+                let (skipped_amms, unreadable_tokens) = unreadable_decimals(
+                    skipped_amms,
+                    current_block,
+                    &config.decimal_overrides,
+                    middleware.clone(),
+                )
+                .await?;
+                unreadable_amms = unreadable_decimals_amms(&skipped_amms, &unreadable_tokens);
             }
 
-            // TODO: Implement batch request
-            AMM::ERC4626Vault(_) => {
-                for amm in amms {
-                    let mut amm = amm.clone();
-                    let progress = progress.clone();
-                    let middleware = middleware.clone();
-                    handles.spawn(async move {
-                        amm.populate_data(None, middleware.clone()).await?;
-                        progress.inc(1);
-                        Ok::<_, AMMError<M>>(vec![amm])
-                    });
-                }
-            }
-        };
+            populated_amms
+        }
+        PopulationMode::MetadataOnly => {
+            let populated_amms = populate_amms_metadata_with_overrides(
+                &amms,
+                current_block,
+                &config.decimal_overrides,
+                middleware.clone(),
+            )
+            .await?;
+            progress_tracker.complete_batches(batches);
+            populated_amms
+        }
+    };
+    apply_decimal_overrides(&mut amms, &config.decimal_overrides);
 
-        process_updated_amm(&mut updated_amms, handles).await?;
-        progress.finish_and_clear();
+    //Clean empty pools
+    amms = remove_empty_amms(amms);
 
-        Ok(updated_amms)
-    } else {
-        Err(AMMError::IncongruentAMMs)
+    if config.check_reserve_supply && config.population_mode == PopulationMode::Full {
+        amms = remove_reserves_exceeding_supply(amms, current_block, middleware.clone()).await?;
+    }
+
+    //Tokens of pools discovered without logs are only known after population
+    amms.retain(|amm| !config.is_denied(amm));
+
+    // If the factory is UniswapV2, set the fee for each pool according to the factory fee
+    // and keep only the first pair registered for each token pair
+    let mut duplicate_pairs = vec![];
+    if let Factory::UniswapV2Factory(factory) = factory {
+        for amm in amms.iter_mut() {
+            if let AMM::UniswapV2Pool(ref mut pool) = amm {
+                pool.fee = factory.fee;
+            }
+        }
+
+        (amms, duplicate_pairs) = factory.remove_duplicate_pairs(amms);
+        for duplicate in duplicate_pairs.iter() {
+            tracing::warn!(
+                canonical = ?duplicate.canonical,
+                duplicates = ?duplicate.duplicates,
+                "Factory registered multiple pairs for the same tokens"
+            );
+        }
     }
+
+    tracing::info!(amms = amms.len(), "Synced factory");
+    Ok((factory, amms, duplicate_pairs, unreadable_amms))
 }
 
-pub async fn process_updated_amm<M: 'static + Middleware>(
-    amms: &mut Vec<AMM>,
-    mut set: JoinSet<Result<Vec<AMM>, AMMError<M>>>,
-) -> Result<(), AMMError<M>> {
-    while let Some(amm_chunk) = set.join_next().await {
-        amms.extend(amm_chunk??);
+//An entry of a pool list file, eg. `{"address": "0x...", "type": "UniswapV2Pool"}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolListEntry {
+    pub address: H160,
+    #[serde(rename = "type")]
+    pub pool_type: String,
+    //Fee of a uniswap v2 pool in the same units as the factory fee, defaulting to 300
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u32>,
+}
+
+//Loads the pools listed in the json pool list file and populates them via the batch requests, bypassing factory discovery
+pub async fn load_pools_from_list<M: 'static + Middleware>(
+    path: &str,
+    middleware: Arc<M>,
+) -> Result<(Vec<AMM>, u64), AMMError<M>> {
+    let entries: Vec<PoolListEntry> = serde_json::from_str(read_to_string(path)?.as_str())?;
+
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    //Validate every listed type before making any requests
+    let mut empty_amms = vec![];
+    let mut fees = HashMap::new();
+    for entry in entries {
+        let pool_type =
+            PoolType::from_str(&entry.pool_type).map_err(AMMError::UnsupportedPoolType)?;
+        fees.insert(entry.address, entry.fee.unwrap_or(300));
+        empty_amms.push(pool_type.empty_amm(entry.address));
     }
-    Ok(())
+
+    let mut amms = vec![];
+    for group in group_congruent(empty_amms) {
+        amms.extend(populate_amms(&group, current_block, None, middleware.clone()).await?);
+    }
+
+    //Clean empty pools
+    let mut amms = remove_empty_amms(amms);
+
+    for amm in amms.iter_mut() {
+        if let AMM::UniswapV2Pool(pool) = amm {
+            pool.fee = fees[&pool.address];
+        }
+    }
+
+    Ok((amms, current_block))
 }
 
-pub fn remove_empty_amms(amms: Vec<AMM>) -> Vec<AMM> {
-    let mut cleaned_amms = vec![];
+//Re-populates the pools that failed to populate during a sync from their addresses and types, returning the populated
+//pools and the pools that still fail. A failed batch is retried pool by pool so that one failing pool does not fail the
+//rest of its batch
+pub async fn retry_failed<M: 'static + Middleware>(
+    failures: Vec<(H160, PoolType)>,
+    middleware: Arc<M>,
+) -> Result<(Vec<AMM>, Vec<(H160, AMMError<M>)>), AMMError<M>> {
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
 
-    for amm in amms.into_iter() {
-        match amm {
-            AMM::UniswapV2Pool(ref uniswap_v2_pool) => {
-                if !uniswap_v2_pool.token_a.is_zero() && !uniswap_v2_pool.token_b.is_zero() {
-                    cleaned_amms.push(amm)
+    let empty_amms = failures
+        .into_iter()
+        .map(|(address, pool_type)| pool_type.empty_amm(address))
+        .collect::<Vec<AMM>>();
+
+    let mut populated_amms = vec![];
+    let mut failed_amms = vec![];
+    for group in group_congruent(empty_amms) {
+        let mut handles = JoinSet::new();
+        for amm_chunk in group.chunks(batch_size(&group[0])) {
+            let middleware = middleware.clone();
+            let amm_chunk = amm_chunk.to_vec();
+            handles.spawn(retry_amm_chunk(amm_chunk, current_block, middleware));
+
+            if handles.len() == TASK_LIMIT {
+                if let Some(result) = handles.join_next().await {
+                    let (amms, failed) = result?;
+                    populated_amms.extend(amms);
+                    failed_amms.extend(failed);
                 }
             }
-            AMM::UniswapV3Pool(ref uniswap_v3_pool) => {
-                if !uniswap_v3_pool.token_a.is_zero() && !uniswap_v3_pool.token_b.is_zero() {
-                    cleaned_amms.push(amm)
-                }
+        }
+
+        while let Some(result) = handles.join_next().await {
+            let (amms, failed) = result?;
+            populated_amms.extend(amms);
+            failed_amms.extend(failed);
+        }
+    }
+
+    Ok((populated_amms, failed_amms))
+}
+
+//Populates the chunk, falling back to populating each amm on its own if the batch fails. Amms skipped by the batch
+//contract are reported as failed with `AMMError::PoolDataError`
+async fn retry_amm_chunk<M: 'static + Middleware>(
+    amm_chunk: Vec<AMM>,
+    block_number: u64,
+    middleware: Arc<M>,
+) -> (Vec<AMM>, Vec<(H160, AMMError<M>)>) {
+    let mut populated_amms = vec![];
+    let mut failed_amms = vec![];
+
+    let results = match populate_amm_chunk(amm_chunk.clone(), block_number, middleware.clone())
+        .await
+    {
+        Ok(amms) => vec![(amm_chunk, Ok(amms))],
+        Err(err) if amm_chunk.len() == 1 => vec![(amm_chunk, Err(err))],
+        Err(_) => {
+            let mut results = vec![];
+            for amm in amm_chunk {
+                let result =
+                    populate_amm_chunk(vec![amm.clone()], block_number, middleware.clone()).await;
+                results.push((vec![amm], result));
             }
-            AMM::ERC4626Vault(ref erc4626_vault) => {
-                if !erc4626_vault.vault_token.is_zero() && !erc4626_vault.asset_token.is_zero() {
-                    cleaned_amms.push(amm)
+            results
+        }
+    };
+
+    for (amm_chunk, result) in results {
+        match result {
+            Ok(amms) => {
+                let amms = remove_empty_amms(amms);
+                for amm in amm_chunk {
+                    if !amms
+                        .iter()
+                        .any(|populated| populated.address() == amm.address())
+                    {
+                        failed_amms.push((amm.address(), AMMError::PoolDataError));
+                    }
                 }
+                populated_amms.extend(amms);
             }
+            //Failed batches are only kept for single amm chunks
+            Err(err) => failed_amms.push((amm_chunk[0].address(), err)),
         }
     }
 
-    cleaned_amms
+    (populated_amms, failed_amms)
+}
+
+//Syncs all amms from the factories, handing each populated amm to the sink as its batch completes instead of aggregating them
+pub async fn sync_amms_streaming<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    step: u64,
+    mut sink: impl FnMut(AMM),
+) -> Result<u64, AMMError<M>> {
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    for factory in factories {
+        let amms: Vec<AMM> = factory
+            .get_all_amms(Some(current_block), middleware.clone(), step)
+            .await?;
+
+        let chunk_size = amms.first().map(batch_size).unwrap_or(1);
+
+        let mut handles = JoinSet::new();
+        for amm_chunk in amms.chunks(chunk_size) {
+            let middleware = middleware.clone();
+            let amm_chunk = amm_chunk.to_vec();
+            handles.spawn(
+                async move { populate_amm_chunk(amm_chunk, current_block, middleware).await },
+            );
+
+            if handles.len() == TASK_LIMIT {
+                if let Some(amm_chunk) = handles.join_next().await {
+                    stream_populated_amms(amm_chunk??, &factory, &mut sink);
+                }
+            }
+        }
+
+        while let Some(amm_chunk) = handles.join_next().await {
+            stream_populated_amms(amm_chunk??, &factory, &mut sink);
+        }
+    }
+
+    Ok(current_block)
+}
+
+//Syncs all amms from the factories like `sync_amms` without a checkpoint, overlapping discovery with population. The pool
+//created logs of each factory are scanned in a background task, and the discovered pools are split into batches that
+//start populating as soon as a full batch has been discovered instead of once every factory has been scanned. Pools are
+//always discovered from the logs, so the result matches `Factory::sync_range` from the creation block of each factory,
+//with the amms ordered by batch completion. The sequential `sync_amms` remains the simpler mode
+pub async fn sync_amms_pipelined<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    step: u64,
+) -> Result<(Vec<AMM>, u64), AMMError<M>> {
+    let started = Instant::now();
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(TASK_LIMIT);
+    let mut discovery = JoinSet::new();
+    for factory in factories.iter().cloned() {
+        let middleware = middleware.clone();
+        let sender = sender.clone();
+        discovery.spawn(async move {
+            factory
+                .send_pools_from_logs(
+                    factory.creation_block(),
+                    current_block,
+                    step,
+                    middleware,
+                    sender,
+                )
+                .await
+        });
+    }
+    //The receiver closes once every discovery task has dropped its sender
+    drop(sender);
+
+    let factories: HashMap<H160, Factory> = factories
+        .into_iter()
+        .map(|factory| (factory.address(), factory))
+        .collect();
+
+    let mut populated_amms = vec![];
+    let mut sink = |amm| populated_amms.push(amm);
+    let mut pending: HashMap<H160, Vec<AMM>> = HashMap::new();
+    let mut handles = JoinSet::new();
+
+    let spawn_chunk = |handles: &mut JoinSet<(H160, Result<Vec<AMM>, AMMError<M>>)>,
+                       factory_address: H160,
+                       amm_chunk: Vec<AMM>| {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            (
+                factory_address,
+                populate_amm_chunk(amm_chunk, current_block, middleware).await,
+            )
+        });
+    };
+
+    while let Some((factory_address, amms)) = receiver.recv().await {
+        let pending_amms = pending.entry(factory_address).or_default();
+        pending_amms.extend(amms);
+
+        //Only full batches are populated while discovery is running, the remainder waits for more pools
+        let chunk_size = pending_amms.first().map(batch_size).unwrap_or(1);
+        while pending_amms.len() >= chunk_size {
+            let amm_chunk = pending_amms.drain(..chunk_size).collect();
+            spawn_chunk(&mut handles, factory_address, amm_chunk);
+
+            if handles.len() == TASK_LIMIT {
+                if let Some(amm_chunk) = handles.join_next().await {
+                    let (factory_address, amm_chunk) = amm_chunk?;
+                    stream_populated_amms(amm_chunk?, &factories[&factory_address], &mut sink);
+                }
+            }
+        }
+    }
+
+    while let Some(result) = discovery.join_next().await {
+        result??;
+    }
+
+    for (factory_address, amms) in pending {
+        if !amms.is_empty() {
+            spawn_chunk(&mut handles, factory_address, amms);
+        }
+    }
+
+    while let Some(amm_chunk) = handles.join_next().await {
+        let (factory_address, amm_chunk) = amm_chunk?;
+        stream_populated_amms(amm_chunk?, &factories[&factory_address], &mut sink);
+    }
+
+    tracing::info!(
+        amms = populated_amms.len(),
+        elapsed = ?started.elapsed(),
+        "Pipelined sync complete"
+    );
+
+    Ok((populated_amms, current_block))
+}
+
+//Discovers the amms of the factories from their pool created logs without populating any pool data. The amms only hold
+//what the logs hold, ie. the address, tokens and factory, leaving decimals and reserves zeroed for a custom populator
+pub async fn discover_amms<M: 'static + Middleware>(
+    factories: Vec<Factory>,
+    middleware: Arc<M>,
+    to_block: u64,
+    step: u64,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    let mut amms = vec![];
+
+    for factory in factories {
+        let mut factory_amms = factory
+            .get_all_pools_from_logs(
+                factory.creation_block(),
+                to_block,
+                step,
+                None,
+                middleware.clone(),
+            )
+            .await?;
+
+        //The fee of a v2 pool is set by its factory instead of being read from the pool
+        if let Factory::UniswapV2Factory(factory) = factory {
+            for amm in factory_amms.iter_mut() {
+                if let AMM::UniswapV2Pool(pool) = amm {
+                    pool.fee = factory.fee;
+                }
+            }
+        }
+
+        tracing::info!(factory = ?factory.address(), amms = factory_amms.len(), "Discovered factory pools");
+        amms.extend(factory_amms);
+    }
+
+    Ok(amms)
+}
+
+//Repopulates the given amms in place, grouping them by variant and refreshing each group via its batch request
+pub async fn refresh_amms<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    //Group the indices of the amms by variant so that the refreshed amms can be written back in place
+    let mut groups: HashMap<PoolType, Vec<usize>> = HashMap::new();
+    for (idx, amm) in amms.iter().enumerate() {
+        groups.entry(amm.pool_type()).or_default().push(idx);
+    }
+
+    let mut handles = JoinSet::new();
+    for group in groups.into_values() {
+        for idx_chunk in group.chunks(batch_size(&amms[group[0]])) {
+            let middleware = middleware.clone();
+            let idx_chunk = idx_chunk.to_vec();
+            let amm_chunk: Vec<AMM> = idx_chunk.iter().map(|idx| amms[*idx].clone()).collect();
+            handles.spawn(async move {
+                let amm_chunk = populate_amm_chunk(amm_chunk, block_number, middleware).await?;
+                Ok::<_, AMMError<M>>((idx_chunk, amm_chunk))
+            });
+
+            if handles.len() == TASK_LIMIT {
+                if let Some(result) = handles.join_next().await {
+                    let (idx_chunk, amm_chunk) = result??;
+                    write_back_amms(amms, idx_chunk, amm_chunk);
+                }
+            }
+        }
+    }
+
+    while let Some(result) = handles.join_next().await {
+        let (idx_chunk, amm_chunk) = result??;
+        write_back_amms(amms, idx_chunk, amm_chunk);
+    }
+
+    Ok(())
+}
+
+//Writes the refreshed amms of a chunk back to their indices
+fn write_back_amms(amms: &mut [AMM], idx_chunk: Vec<usize>, amm_chunk: Vec<AMM>) {
+    for (idx, amm) in idx_chunk.into_iter().zip(amm_chunk) {
+        amms[idx] = amm;
+    }
+}
+
+//Refreshes the prices of the uniswap v3 pools in place by reading only slot0 at the latest block, updating the sqrt price
+//and tick while leaving the liquidity and tick maps untouched. Algebra pools are refreshed from globalState, which also
+//updates their dynamic fee. This is much cheaper than `refresh_amms` for loops that only need fresh prices, but the
+//liquidity goes stale once positions change. Other amm variants are left untouched
+pub async fn refresh_v3_prices<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    let pool_indices: Vec<usize> = amms
+        .iter()
+        .enumerate()
+        .filter(|(_, amm)| matches!(amm, AMM::UniswapV3Pool(_) | AMM::AlgebraPool(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+
+    for idx_chunk in pool_indices.chunks(TASK_LIMIT) {
+        let mut handles = JoinSet::new();
+        for idx in idx_chunk {
+            let idx = *idx;
+            let address = amms[idx].address();
+            let middleware = middleware.clone();
+            let is_algebra = matches!(amms[idx], AMM::AlgebraPool(_));
+            handles.spawn(async move {
+                if is_algebra {
+                    let algebra_pool = algebra::IAlgebraPool::new(address, middleware);
+                    let global_state = algebra_pool.global_state();
+                    let (sqrt_price, tick, fee, _, _, _, _) =
+                        with_request_timeout(global_state.call()).await?;
+                    Ok::<_, AMMError<M>>((idx, sqrt_price, tick, Some(fee as u32)))
+                } else {
+                    let v3_pool = uniswap_v3::IUniswapV3Pool::new(address, middleware);
+                    let slot_0 = v3_pool.slot_0();
+                    let (sqrt_price, tick, _, _, _, _, _) =
+                        with_request_timeout(slot_0.call()).await?;
+                    Ok::<_, AMMError<M>>((idx, sqrt_price, tick, None))
+                }
+            });
+        }
+
+        while let Some(result) = handles.join_next().await {
+            let (idx, sqrt_price, tick, fee) = result??;
+            match &mut amms[idx] {
+                AMM::UniswapV3Pool(pool) => {
+                    pool.sqrt_price = sqrt_price;
+                    pool.tick = tick;
+                }
+                AMM::AlgebraPool(algebra_pool) => {
+                    algebra_pool.pool.sqrt_price = sqrt_price;
+                    algebra_pool.pool.tick = tick;
+                    if let Some(fee) = fee {
+                        algebra_pool.pool.fee = fee;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//Re-reads the state of the amms at the latest block and corrects the amms whose stored state drifted from the chain,
+//returning the addresses of the corrected amms. Long running state managers can call this periodically to recover
+//from state accumulated through missed logs or reorgs
+pub async fn reconcile<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    middleware: Arc<M>,
+) -> Result<Vec<H160>, AMMError<M>> {
+    let current_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let stored_amms = amms.to_vec();
+    refresh_amms(amms, current_block, middleware).await?;
+
+    Ok(stored_amms
+        .iter()
+        .zip(amms.iter())
+        .filter(|(stored, live)| state_differs(stored, live))
+        .map(|(_, live)| live.address())
+        .collect())
+}
+
+fn state_differs(stored: &AMM, live: &AMM) -> bool {
+    match (stored, live) {
+        (AMM::UniswapV2Pool(stored), AMM::UniswapV2Pool(live)) => {
+            (stored.reserve_0, stored.reserve_1) != (live.reserve_0, live.reserve_1)
+        }
+        (AMM::UniswapV3Pool(stored), AMM::UniswapV3Pool(live)) => {
+            (stored.liquidity, stored.sqrt_price, stored.tick)
+                != (live.liquidity, live.sqrt_price, live.tick)
+        }
+        (AMM::ERC4626Vault(stored), AMM::ERC4626Vault(live)) => {
+            (stored.vault_reserve, stored.asset_reserve) != (live.vault_reserve, live.asset_reserve)
+        }
+        (AMM::AlgebraPool(stored), AMM::AlgebraPool(live)) => {
+            (
+                stored.pool.liquidity,
+                stored.pool.sqrt_price,
+                stored.pool.tick,
+                stored.pool.fee,
+            ) != (
+                live.pool.liquidity,
+                live.pool.sqrt_price,
+                live.pool.tick,
+                live.pool.fee,
+            )
+        }
+        _ => true,
+    }
+}
+
+//Syncs the reserves of the uniswap v2 pools by applying the last Sync event emitted by each pool within the block range.
+//Pools without a Sync event in the range fall back to a batched getReserves at to_block, other amm variants are left untouched
+pub async fn sync_reserves_from_logs<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    from_block: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    let pool_indices: HashMap<H160, usize> = amms
+        .iter()
+        .enumerate()
+        .filter(|(_, amm)| matches!(amm, AMM::UniswapV2Pool(_)))
+        .map(|(idx, amm)| (amm.address(), idx))
+        .collect();
+
+    let addresses: Vec<H160> = pool_indices.keys().copied().collect();
+
+    //Keep only the last Sync log for each pool
+    let mut last_sync_logs: HashMap<H160, Log> = HashMap::new();
+    let logs = get_logs_paged(
+        &addresses,
+        vec![uniswap_v2::SYNC_EVENT_SIGNATURE],
+        from_block,
+        to_block,
+        middleware.clone(),
+    )
+    .await?;
+
+    for log in logs {
+        match last_sync_logs.get(&log.address) {
+            Some(last_log)
+                if (last_log.block_number, last_log.log_index)
+                    > (log.block_number, log.log_index) => {}
+            _ => {
+                last_sync_logs.insert(log.address, log);
+            }
+        }
+    }
+
+    let mut stale_indices = vec![];
+    for (address, idx) in pool_indices {
+        if let Some(log) = last_sync_logs.remove(&address) {
+            amms[idx].sync_from_log(log)?;
+        } else {
+            stale_indices.push(idx);
+        }
+    }
+
+    //Fall back to getReserves for the pools without a Sync event in the range
+    if !stale_indices.is_empty() {
+        let mut stale_amms: Vec<AMM> = stale_indices.iter().map(|idx| amms[*idx].clone()).collect();
+        refresh_amms(&mut stale_amms, to_block, middleware).await?;
+
+        for (idx, amm) in stale_indices.into_iter().zip(stale_amms) {
+            amms[idx] = amm;
+        }
+    }
+
+    Ok(())
+}
+
+//Returns the addresses of the amms that emitted a state changing event between from_block and to_block, in the order of the amms
+pub async fn changed_pools_in_range<M: 'static + Middleware>(
+    amms: &[AMM],
+    from_block: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<H160>, AMMError<M>> {
+    let mut event_signatures: Vec<H256> = amms
+        .iter()
+        .flat_map(|amm| amm.sync_on_event_signatures())
+        .collect();
+    event_signatures.sort();
+    event_signatures.dedup();
+
+    let addresses: Vec<H160> = amms.iter().map(|amm| amm.address()).collect();
+
+    let mut changed_addresses: HashSet<H160> = get_logs_paged(
+        &addresses,
+        event_signatures,
+        from_block,
+        to_block,
+        middleware,
+    )
+    .await?
+    .into_iter()
+    .map(|log| log.address)
+    .collect();
+
+    let mut changed_pools = vec![];
+    for address in addresses {
+        if changed_addresses.remove(&address) {
+            changed_pools.push(address);
+        }
+    }
+
+    Ok(changed_pools)
+}
+
+//Estimates how fast the factory adds pools as the pools created per block over the last window blocks, ending at the latest
+//block, eg. to schedule more frequent resyncs of fast growing factories. The window is clamped to the creation block of
+//the factory and its pool created events are read with a single log request
+pub async fn pool_creation_rate<M: 'static + Middleware>(
+    factory: &Factory,
+    window_blocks: u64,
+    middleware: Arc<M>,
+) -> Result<f64, AMMError<M>> {
+    let latest_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let from_block = (latest_block + 1)
+        .saturating_sub(window_blocks)
+        .max(factory.creation_block());
+    if window_blocks == 0 || from_block > latest_block {
+        return Ok(0.0);
+    }
+
+    let pools_created = middleware
+        .get_logs(&factory.amm_created_filter(from_block, latest_block))
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .len();
+
+    Ok(pools_created as f64 / (latest_block - from_block + 1) as f64)
+}
+
+//Brings amms restored from a snapshot, eg. with `checkpoint::deconstruct_checkpoint`, up to the latest block by applying
+//every state changing event emitted after the snapshot block in chain order. Returns the latest block, from which a
+//state space manager can continue listening
+pub async fn apply_catchup_logs<M: 'static + Middleware>(
+    amms: &mut [AMM],
+    snapshot_block: u64,
+    middleware: Arc<M>,
+) -> Result<u64, AMMError<M>> {
+    let latest_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    if latest_block <= snapshot_block {
+        return Ok(latest_block);
+    }
+
+    let mut event_signatures: Vec<H256> = amms
+        .iter()
+        .flat_map(|amm| amm.sync_on_event_signatures())
+        .collect();
+    event_signatures.sort();
+    event_signatures.dedup();
+
+    let amm_indices: HashMap<H160, usize> = amms
+        .iter()
+        .enumerate()
+        .map(|(idx, amm)| (amm.address(), idx))
+        .collect();
+    let addresses: Vec<H160> = amm_indices.keys().copied().collect();
+
+    let mut logs = get_logs_paged(
+        &addresses,
+        event_signatures,
+        snapshot_block + 1,
+        latest_block,
+        middleware,
+    )
+    .await?;
+
+    //Logs of different address chunks and block ranges are interleaved, so they are ordered before being applied
+    logs.sort_by_key(|log| (log.block_number, log.log_index));
+
+    for log in logs {
+        if let Some(idx) = amm_indices.get(&log.address) {
+            let amm = &mut amms[*idx];
+
+            //The filter covers the event signatures of every variant, only the events of the amm itself are applied
+            //Anonymous logs have no signature topic and are never emitted by the supported amms
+            let event_signature = match log.topics.first() {
+                Some(event_signature) => event_signature,
+                None => continue,
+            };
+            if !amm.sync_on_event_signatures().contains(event_signature) {
+                continue;
+            }
+
+            let block_number = log.block_number.map(|block_number| block_number.as_u64());
+            amm.sync_from_log(log)?;
+            if let Some(block_number) = block_number {
+                amm.set_last_synced_block(block_number);
+            }
+        }
+    }
+
+    Ok(latest_block)
+}
+
+//Block range of each get_logs call of `get_logs_paged`, within the range limit of public and hosted providers
+const LOG_RANGE_STEP: u64 = 2_000;
+
+//Gets the logs with any of the event signatures emitted by the addresses within the block range, splitting the addresses
+//into chunks of 1000 and the range into steps of `LOG_RANGE_STEP` blocks so that no request exceeds the provider limits.
+//Logs are returned grouped by address chunk and range, callers needing chain order have to sort them
+async fn get_logs_paged<M: 'static + Middleware>(
+    addresses: &[H160],
+    event_signatures: Vec<H256>,
+    from_block: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<Log>, AMMError<M>> {
+    let mut logs = vec![];
+    for address_chunk in addresses.chunks(1000) {
+        let mut block = from_block;
+        while block <= to_block {
+            let target_block = (block + LOG_RANGE_STEP - 1).min(to_block);
+            logs.extend(
+                middleware
+                    .get_logs(
+                        &Filter::new()
+                            .topic0(event_signatures.clone())
+                            .address(address_chunk.to_vec())
+                            .from_block(BlockNumber::Number(U64([block])))
+                            .to_block(BlockNumber::Number(U64([target_block]))),
+                    )
+                    .await
+                    .map_err(AMMError::MiddlewareError)?,
+            );
+
+            block = target_block + 1;
+        }
+    }
+
+    Ok(logs)
+}
+
+//Number of blocks sampled to compute the average block time in `blocks_for_duration`
+const BLOCK_TIME_SAMPLE_SIZE: u64 = 1000;
+
+//Estimates the block range covering the last duration, ending at the latest block, eg. to pass to `checkpoint::get_new_amms_from_range`.
+//The average block time is taken over the last 1000 blocks, which needs only three requests but may be off by a number of
+//blocks if the block time varied within the duration. Use `blocks_for_duration_exact` when the range must be accurate.
+pub async fn blocks_for_duration<M: 'static + Middleware>(
+    duration: Duration,
+    middleware: Arc<M>,
+) -> Result<(u64, u64), AMMError<M>> {
+    let latest_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let sample_block = latest_block.saturating_sub(BLOCK_TIME_SAMPLE_SIZE);
+    if sample_block == latest_block {
+        return Ok((latest_block, latest_block));
+    }
+
+    let latest_timestamp = block_timestamp(latest_block, middleware.clone()).await?;
+    let sample_timestamp = block_timestamp(sample_block, middleware).await?;
+
+    let average_block_time = latest_timestamp.saturating_sub(sample_timestamp) as f64
+        / (latest_block - sample_block) as f64;
+    if average_block_time == 0.0 {
+        return Ok((latest_block, latest_block));
+    }
+
+    let blocks = (duration.as_secs_f64() / average_block_time).ceil() as u64;
+
+    Ok((latest_block.saturating_sub(blocks), latest_block))
+}
+
+//Finds the block range covering the last duration, ending at the latest block, by binary searching the block timestamps for
+//the first block within the duration. This is exact but takes a request per step, around 25 requests on mainnet.
+pub async fn blocks_for_duration_exact<M: 'static + Middleware>(
+    duration: Duration,
+    middleware: Arc<M>,
+) -> Result<(u64, u64), AMMError<M>> {
+    let latest_block = middleware
+        .get_block_number()
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .as_u64();
+
+    let target_timestamp = block_timestamp(latest_block, middleware.clone())
+        .await?
+        .saturating_sub(duration.as_secs());
+
+    Ok((
+        first_block_at_timestamp(target_timestamp, latest_block, middleware).await?,
+        latest_block,
+    ))
+}
+
+//Binary searches the block timestamps up to to_block for the first block at or after the unix timestamp, returning to_block
+//if every block is older
+pub async fn first_block_at_timestamp<M: 'static + Middleware>(
+    timestamp: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+) -> Result<u64, AMMError<M>> {
+    let (mut low, mut high) = (0, to_block);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if block_timestamp(mid, middleware.clone()).await? >= timestamp {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    Ok(low)
+}
+
+async fn block_timestamp<M: 'static + Middleware>(
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<u64, AMMError<M>> {
+    middleware
+        .get_block(block_number)
+        .await
+        .map_err(AMMError::MiddlewareError)?
+        .map(|block| block.timestamp.as_u64())
+        .ok_or(AMMError::BlockNumberNotFound)
+}
+
+//Max batch size for the batch request call of the amm variant, capped by the max response size
+fn batch_size(amm: &AMM) -> usize {
+    match amm {
+        AMM::UniswapV2Pool(_) => capped_batch_size(
+            uniswap_v2::batch_request::MAX_POOL_DATA_BATCH_SIZE,
+            uniswap_v2::batch_request::POOL_DATA_RESPONSE_BYTES,
+        ),
+        AMM::UniswapV3Pool(_) => v3_batch_size(v3_max_batch_size()),
+        AMM::ERC4626Vault(_) | AMM::AlgebraPool(_) => 1,
+    }
+}
+
+//Batch size of the v3 pool data batch request for the max batch size, capped by the max response size
+fn v3_batch_size(max_batch_size: usize) -> usize {
+    capped_batch_size(
+        max_batch_size,
+        uniswap_v3::batch_request::POOL_DATA_RESPONSE_BYTES,
+    )
+}
+
+//Number of batch requests needed to populate the amms, counting the amms of each variant in chunks of its batch size
+fn population_batches(amms: &[AMM]) -> usize {
+    let mut amm_counts: HashMap<PoolType, (usize, usize)> = HashMap::new();
+    for amm in amms {
+        amm_counts
+            .entry(amm.pool_type())
+            .or_insert((0, batch_size(amm)))
+            .0 += 1;
+    }
+
+    amm_counts
+        .values()
+        .map(|(count, size)| (count + size - 1) / size)
+        .sum()
+}
+
+//Messages returned by nodes when the state at a block is pruned, such as by a non archive node or a fork queried before its fork block
+const STATE_UNAVAILABLE_MESSAGES: [&str; 3] =
+    ["missing trie node", "header not found", "is not available"];
+
+//Maps errors caused by missing historical state to `AMMError::StateUnavailable`, so that syncing at a block the node
+//cannot serve fails with a clear error instead of a contract or provider error
+fn map_state_unavailable<M: Middleware>(err: AMMError<M>, block_number: u64) -> AMMError<M> {
+    let message = err.to_string();
+    let lowercase_message = message.to_lowercase();
+
+    if STATE_UNAVAILABLE_MESSAGES
+        .iter()
+        .any(|state_message| lowercase_message.contains(state_message))
+    {
+        AMMError::StateUnavailable(block_number, message)
+    } else {
+        err
+    }
+}
+
+//Populates congruent amms one batch at a time with the batch size chosen by the tuner, timing each batch so that the
+//tuner can grow or shrink the size. Failed batches are retried at the reduced size until the tuner gives up
+pub async fn populate_amms_adaptive<M: 'static + Middleware>(
+    amms: &[AMM],
+    block_number: u64,
+    tuner: &BatchSizeTuner,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    if !amms_are_congruent(amms) {
+        return Err(AMMError::IncongruentAMMs);
+    }
+
+    let mut populated_amms = vec![];
+    let mut offset = 0;
+    while offset < amms.len() {
+        let pool_type = amms[offset].pool_type();
+        let size = tuner.size(pool_type, batch_size(&amms[offset]));
+        let amm_chunk = amms[offset..(offset + size).min(amms.len())].to_vec();
+        let chunk_len = amm_chunk.len();
+
+        let started = Instant::now();
+        match populate_amm_chunk(amm_chunk, block_number, middleware.clone()).await {
+            Ok(amm_chunk) => {
+                tuner.record_success(pool_type, started.elapsed());
+                populated_amms.extend(amm_chunk);
+                offset += chunk_len;
+            }
+            Err(err) => {
+                tracing::warn!(%err, size, "Batch failed, reducing the batch size");
+                if !tuner.record_failure(pool_type) {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    Ok(populated_amms)
+}
+
+//Populates a single chunk of congruent amms with the batch request for its variant
+async fn populate_amm_chunk<M: 'static + Middleware>(
+    mut amm_chunk: Vec<AMM>,
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    match amm_chunk.first() {
+        Some(AMM::UniswapV2Pool(_)) => {
+            uniswap_v2::batch_request::get_amm_data_batch_request(
+                &mut amm_chunk,
+                Some(block_number),
+                middleware,
+            )
+            .await
+            .map_err(|err| map_state_unavailable(err, block_number))?;
+        }
+        Some(AMM::UniswapV3Pool(_)) => {
+            uniswap_v3::batch_request::get_amm_data_batch_request(
+                &mut amm_chunk,
+                block_number,
+                middleware,
+            )
+            .await
+            .map_err(|err| map_state_unavailable(err, block_number))?;
+        }
+        Some(AMM::ERC4626Vault(_)) => {
+            for amm in amm_chunk.iter_mut() {
+                amm.populate_data(None, middleware.clone()).await?;
+            }
+        }
+        Some(AMM::AlgebraPool(_)) => {
+            for amm in amm_chunk.iter_mut() {
+                amm.populate_data(Some(block_number), middleware.clone())
+                    .await
+                    .map_err(|err| map_state_unavailable(err, block_number))?;
+            }
+        }
+        None => {}
+    }
+
+    Ok(amm_chunk)
+}
+
+fn stream_populated_amms(amms: Vec<AMM>, factory: &Factory, sink: &mut impl FnMut(AMM)) {
+    for mut amm in remove_empty_amms(amms) {
+        // If the factory is UniswapV2, set the fee for each pool according to the factory fee
+        if let (Factory::UniswapV2Factory(factory), AMM::UniswapV2Pool(pool)) = (factory, &mut amm)
+        {
+            pool.fee = factory.fee;
+        }
+
+        sink(amm);
+    }
+}
+
+pub fn amms_are_congruent(amms: &[AMM]) -> bool {
+    let expected_amm = &amms[0];
+
+    for amm in amms {
+        if std::mem::discriminant(expected_amm) != std::mem::discriminant(amm) {
+            return false;
+        }
+    }
+    true
+}
+
+//Partitions the amms into congruent groups, returning one group per AMM variant present in the order that each variant first appears
+pub fn group_congruent(amms: Vec<AMM>) -> Vec<Vec<AMM>> {
+    #[cfg(feature = "rayon")]
+    {
+        group_congruent_parallel(amms)
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    {
+        group_congruent_sequential(amms)
+    }
+}
+
+fn group_congruent_sequential(amms: Vec<AMM>) -> Vec<Vec<AMM>> {
+    let mut groups: Vec<Vec<AMM>> = vec![];
+
+    for amm in amms {
+        push_congruent(&mut groups, amm);
+    }
+
+    groups
+}
+
+//Groups chunks of the amms across the rayon thread pool and merges the groups of adjacent chunks in order,
+//so the groups and the order within them match the sequential path
+#[cfg(feature = "rayon")]
+fn group_congruent_parallel(amms: Vec<AMM>) -> Vec<Vec<AMM>> {
+    use rayon::prelude::*;
+
+    amms.into_par_iter()
+        .fold(Vec::new, |mut groups, amm| {
+            push_congruent(&mut groups, amm);
+            groups
+        })
+        .reduce(Vec::new, |mut groups, other_groups| {
+            for other_group in other_groups {
+                match groups.iter_mut().find(|group| {
+                    std::mem::discriminant(&group[0]) == std::mem::discriminant(&other_group[0])
+                }) {
+                    Some(group) => group.extend(other_group),
+                    None => groups.push(other_group),
+                }
+            }
+            groups
+        })
+}
+
+fn push_congruent(groups: &mut Vec<Vec<AMM>>, amm: AMM) {
+    if let Some(group) = groups
+        .iter_mut()
+        .find(|group| std::mem::discriminant(&group[0]) == std::mem::discriminant(&amm))
+    {
+        group.push(amm);
+    } else {
+        groups.push(vec![amm]);
+    }
+}
+
+//Gets all pool data and sync reserves
+#[tracing::instrument(skip(amms, middleware), fields(amms = amms.len()), err)]
+pub async fn populate_amms<M: 'static + Middleware>(
+    amms: &[AMM],
+    block_number: u64,
+    address: Option<H160>,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    populate_amms_with_progress(amms, block_number, address, None, middleware).await
+}
+
+//Populates the amms like `populate_amms`, reporting each completed batch to the progress tracker
+async fn populate_amms_with_progress<M: 'static + Middleware>(
+    amms: &[AMM],
+    block_number: u64,
+    address: Option<H160>,
+    progress_tracker: Option<Arc<ProgressTracker>>,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    //Checked before the progress bar is added, a bar that is never finished would stay drawn
+    if amms.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let progress = MULTIPROGRESS.add(
+        ProgressBar::new(amms.len() as u64)
+            .with_style(SYNC_BAR_STYLE.clone())
+            .with_message(match address {
+                Some(address) => format!("Populating pools data from: {}", address),
+                None => "Populating pools data".to_string(),
+            }),
+    );
+    progress.tick();
+
+    let mut handles = JoinSet::new();
+    let mut updated_amms = vec![];
+
+    if amms_are_congruent(amms) {
+        match amms[0] {
+            AMM::UniswapV2Pool(_) => {
+                let step = batch_size(&amms[0]);
+                for amm_chunk in amms.chunks(step) {
+                    let middleware = middleware.clone();
+                    let progress = progress.clone();
+                    let progress_tracker = progress_tracker.clone();
+                    let mut amm_chunk = amm_chunk.to_vec();
+                    handles.spawn(async move {
+                        uniswap_v2::batch_request::get_amm_data_batch_request(
+                            &mut amm_chunk,
+                            Some(block_number),
+                            middleware.clone(),
+                        )
+                        .await
+                        .map_err(|err| map_state_unavailable(err, block_number))?;
+                        progress.inc(amm_chunk.len() as u64);
+                        if let Some(progress_tracker) = progress_tracker {
+                            progress_tracker.complete_batches(1);
+                        }
+                        Ok::<_, AMMError<M>>(amm_chunk)
+                    });
+
+                    if handles.len() == TASK_LIMIT {
+                        process_updated_amm(&mut updated_amms, handles).await?;
+                        handles = JoinSet::new();
+                    }
+                }
+            }
+
+            AMM::UniswapV3Pool(_) => {
+                let step = batch_size(&amms[0]);
+                for amm_chunk in amms.chunks(step) {
+                    let middleware = middleware.clone();
+                    let progress = progress.clone();
+                    let progress_tracker = progress_tracker.clone();
+                    let mut amm_chunk = amm_chunk.to_vec();
+                    handles.spawn(async move {
+                        uniswap_v3::batch_request::get_amm_data_batch_request(
+                            &mut amm_chunk,
+                            block_number,
+                            middleware.clone(),
+                        )
+                        .await
+                        .map_err(|err| map_state_unavailable(err, block_number))?;
+                        progress.inc(amm_chunk.len() as u64);
+                        if let Some(progress_tracker) = progress_tracker {
+                            progress_tracker.complete_batches(1);
+                        }
+                        Ok::<_, AMMError<M>>(amm_chunk)
+                    });
+
+                    if handles.len() == TASK_LIMIT {
+                        process_updated_amm(&mut updated_amms, handles).await?;
+                        handles = JoinSet::new();
+                    }
+                }
+            }
+
+            // TODO: Implement batch request
+            AMM::ERC4626Vault(_) => {
+                for amm in amms {
+                    let mut amm = amm.clone();
+                    let progress = progress.clone();
+                    let progress_tracker = progress_tracker.clone();
+                    let middleware = middleware.clone();
+                    handles.spawn(async move {
+                        amm.populate_data(None, middleware.clone()).await?;
+                        progress.inc(1);
+                        if let Some(progress_tracker) = progress_tracker {
+                            progress_tracker.complete_batches(1);
+                        }
+                        Ok::<_, AMMError<M>>(vec![amm])
+                    });
+                }
+            }
+
+            //Algebra pools have no batch request for the globalState layout, so up to TASK_LIMIT pools are populated
+            //concurrently instead
+            AMM::AlgebraPool(_) => {
+                for amm in amms {
+                    let mut amm = amm.clone();
+                    let progress = progress.clone();
+                    let progress_tracker = progress_tracker.clone();
+                    let middleware = middleware.clone();
+                    handles.spawn(async move {
+                        amm.populate_data(Some(block_number), middleware.clone())
+                            .await
+                            .map_err(|err| map_state_unavailable(err, block_number))?;
+                        progress.inc(1);
+                        if let Some(progress_tracker) = progress_tracker {
+                            progress_tracker.complete_batches(1);
+                        }
+                        Ok::<_, AMMError<M>>(vec![amm])
+                    });
+
+                    if handles.len() == TASK_LIMIT {
+                        process_updated_amm(&mut updated_amms, handles).await?;
+                        handles = JoinSet::new();
+                    }
+                }
+            }
+        };
+
+        process_updated_amm(&mut updated_amms, handles).await?;
+        progress.finish_and_clear();
+
+        Ok(updated_amms)
+    } else {
+        Err(AMMError::IncongruentAMMs)
+    }
+}
+
+//Populates only the tokens and token decimals of the amms, skipping the reserve and slot0 reads.
+//Decimals are read once per unique token and amms with unreadable tokens or decimals are dropped.
+//ERC4626 vaults are fully populated since the asset token is read alongside the reserves, as are Algebra pools.
+pub async fn populate_amms_metadata<M: 'static + Middleware>(
+    amms: &[AMM],
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    populate_amms_metadata_with_overrides(amms, block_number, &HashMap::new(), middleware).await
+}
+
+//Populates the metadata of the amms like `populate_amms_metadata`, taking the decimals of the overridden tokens from the
+//overrides instead of reading them
+pub async fn populate_amms_metadata_with_overrides<M: 'static + Middleware>(
+    amms: &[AMM],
+    block_number: u64,
+    decimal_overrides: &HashMap<H160, u8>,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    //Fill in the tokens of the amms that were not discovered from a log
+    let mut handles = JoinSet::new();
+    let mut amms_with_tokens = vec![];
+    for amm in amms.iter().cloned() {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            let mut amm = amm;
+            populate_amm_tokens(&mut amm, block_number, middleware)
+                .await
+                .ok()
+                .map(|_| amm)
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(amm) = handles.join_next().await {
+                amms_with_tokens.extend(amm?);
+            }
+        }
+    }
+    while let Some(amm) = handles.join_next().await {
+        amms_with_tokens.extend(amm?);
+    }
+
+    //Read the decimals of each unique token once, skipping the overridden tokens
+    let mut tokens: Vec<H160> = amms_with_tokens
+        .iter()
+        .filter(|amm| !matches!(amm, AMM::ERC4626Vault(_) | AMM::AlgebraPool(_)))
+        .flat_map(|amm| amm.tokens())
+        .filter(|token| !decimal_overrides.contains_key(token))
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+
+    let mut token_decimals: HashMap<H160, u8> = decimal_overrides.clone();
+    let mut handles = JoinSet::new();
+    for token in tokens {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            let decimals = uniswap_v2::IErc20::new(token, middleware)
+                .decimals()
+                .block(block_number)
+                .call()
+                .await
+                .ok();
+            (token, decimals)
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(result) = handles.join_next().await {
+                if let (token, Some(decimals)) = result? {
+                    token_decimals.insert(token, decimals);
+                }
+            }
+        }
+    }
+    while let Some(result) = handles.join_next().await {
+        if let (token, Some(decimals)) = result? {
+            token_decimals.insert(token, decimals);
+        }
+    }
+
+    let mut populated_amms = vec![];
+    for mut amm in amms_with_tokens {
+        let populated = match &mut amm {
+            AMM::UniswapV2Pool(pool) => {
+                match (
+                    token_decimals.get(&pool.token_a),
+                    token_decimals.get(&pool.token_b),
+                ) {
+                    (Some(token_a_decimals), Some(token_b_decimals)) => {
+                        pool.token_a_decimals = *token_a_decimals;
+                        pool.token_b_decimals = *token_b_decimals;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            AMM::UniswapV3Pool(pool) => {
+                match (
+                    token_decimals.get(&pool.token_a),
+                    token_decimals.get(&pool.token_b),
+                ) {
+                    (Some(token_a_decimals), Some(token_b_decimals)) => {
+                        pool.token_a_decimals = *token_a_decimals;
+                        pool.token_b_decimals = *token_b_decimals;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            AMM::ERC4626Vault(_) | AMM::AlgebraPool(_) => true,
+        };
+
+        if populated {
+            populated_amms.push(amm);
+        }
+    }
+
+    Ok(populated_amms)
+}
+
+//Reads the tokens of an amm that does not have them yet
+async fn populate_amm_tokens<M: 'static + Middleware>(
+    amm: &mut AMM,
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<(), AMMError<M>> {
+    match amm {
+        AMM::UniswapV2Pool(pool) => {
+            if pool.token_a.is_zero() || pool.token_b.is_zero() {
+                pool.token_a = pool.get_token_0(pool.address, middleware.clone()).await?;
+                pool.token_b = pool.get_token_1(pool.address, middleware).await?;
+            }
+        }
+        AMM::UniswapV3Pool(pool) => {
+            if pool.token_a.is_zero() || pool.token_b.is_zero() {
+                pool.token_a = pool.get_token_0(middleware.clone()).await?;
+                pool.token_b = pool.get_token_1(middleware).await?;
+            }
+        }
+        AMM::ERC4626Vault(vault) => vault.populate_data(Some(block_number), middleware).await?,
+        AMM::AlgebraPool(pool) => pool.populate_data(Some(block_number), middleware).await?,
+    }
+
+    Ok(())
+}
+
+//Populates the amms through the batch requests, except for the amms containing an overridden token. The batch contracts
+//skip pools with a token whose decimals() reverts, so these amms are populated through their metadata with the
+//overrides and then synced at the latest block. V2 pools discovered through allPairs only learn their tokens from the
+//batch request, so the pools the batch request skipped have their tokens read to find the ones with an overridden token.
+//Tokens of skipped pools whose decimals() reverts are given the fallback decimals if one is set, like an override, and
+//the pools holding them are otherwise returned separately to be flagged for review
+async fn populate_amms_with_decimal_overrides<M: 'static + Middleware>(
+    amms: Vec<AMM>,
+    block_number: u64,
+    factory_address: Option<H160>,
+    decimal_overrides: &HashMap<H160, u8>,
+    decimals_fallback: Option<u8>,
+    middleware: Arc<M>,
+) -> Result<(Vec<AMM>, Vec<H160>), AMMError<M>> {
+    let (overridden_amms, amms): (Vec<AMM>, Vec<AMM>) = amms
+        .into_iter()
+        .partition(|amm| has_decimal_override(amm, decimal_overrides));
+
+    let populated_amms =
+        populate_amms(&amms, block_number, factory_address, middleware.clone()).await?;
+
+    populate_overridden_amms(
+        populated_amms,
+        overridden_amms,
+        block_number,
+        decimal_overrides,
+        decimals_fallback,
+        middleware,
+    )
+    .await
+}
+
+//Completes the population of `populate_amms_with_decimal_overrides` from the amms populated by the batch requests,
+//populating the overridden amms along with the pools the batch requests skipped that hold an overridden token
+async fn populate_overridden_amms<M: 'static + Middleware>(
+    populated_amms: Vec<AMM>,
+    mut overridden_amms: Vec<AMM>,
+    block_number: u64,
+    decimal_overrides: &HashMap<H160, u8>,
+    decimals_fallback: Option<u8>,
+    middleware: Arc<M>,
+) -> Result<(Vec<AMM>, Vec<H160>), AMMError<M>> {
+    let (skipped_amms, mut populated_amms): (Vec<AMM>, Vec<AMM>) = populated_amms
+        .into_iter()
+        .partition(|amm| amm.tokens().iter().any(|token| token.is_zero()));
+
+    let (skipped_amms, unreadable_tokens) = unreadable_decimals(
+        skipped_amms,
+        block_number,
+        decimal_overrides,
+        middleware.clone(),
+    )
+    .await?;
+
+    let mut decimal_overrides = decimal_overrides.clone();
+    let unreadable_amms = match decimals_fallback {
+        Some(decimals) => {
+            for token in unreadable_tokens {
+                decimal_overrides.insert(token, decimals);
+            }
+            vec![]
+        }
+        None => unreadable_decimals_amms(&skipped_amms, &unreadable_tokens),
+    };
+    overridden_amms.extend(skipped_amms);
+
+    for mut amm in populate_amms_metadata_with_overrides(
+        &overridden_amms,
+        block_number,
+        &decimal_overrides,
+        middleware.clone(),
+    )
+    .await?
+    {
+        if has_decimal_override(&amm, &decimal_overrides) {
+            match amm.sync(middleware.clone()).await {
+                Ok(()) => populated_amms.push(amm),
+                Err(err) => {
+                    tracing::warn!(pool = ?amm.address(), %err, "Dropping pool that failed to sync")
+                }
+            }
+        }
+    }
+
+    Ok((populated_amms, unreadable_amms))
+}
+
+fn has_decimal_override(amm: &AMM, decimal_overrides: &HashMap<H160, u8>) -> bool {
+    amm.tokens()
+        .iter()
+        .any(|token| decimal_overrides.contains_key(token))
+}
+
+//Reads the tokens of the amms skipped by the batch requests and the decimals of their tokens that are not overridden,
+//returning the amms whose tokens could be read along with the tokens whose decimals() reverts
+async fn unreadable_decimals<M: 'static + Middleware>(
+    amms: Vec<AMM>,
+    block_number: u64,
+    decimal_overrides: &HashMap<H160, u8>,
+    middleware: Arc<M>,
+) -> Result<(Vec<AMM>, HashSet<H160>), AMMError<M>> {
+    let mut handles = JoinSet::new();
+    let mut amms_with_tokens = vec![];
+    for amm in amms {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            let mut amm = amm;
+            populate_amm_tokens(&mut amm, block_number, middleware)
+                .await
+                .ok()
+                .map(|_| amm)
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(amm) = handles.join_next().await {
+                amms_with_tokens.extend(amm?);
+            }
+        }
+    }
+    while let Some(amm) = handles.join_next().await {
+        amms_with_tokens.extend(amm?);
+    }
+
+    let mut tokens: Vec<H160> = amms_with_tokens
+        .iter()
+        .filter(|amm| matches!(amm, AMM::UniswapV2Pool(_) | AMM::UniswapV3Pool(_)))
+        .flat_map(|amm| amm.tokens())
+        .filter(|token| !decimal_overrides.contains_key(token))
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+
+    let mut unreadable_tokens = HashSet::new();
+    let mut handles = JoinSet::new();
+    for token in tokens {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            let decimals = uniswap_v2::IErc20::new(token, middleware)
+                .decimals()
+                .block(block_number)
+                .call()
+                .await;
+            (token, decimals.is_err())
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(result) = handles.join_next().await {
+                if let (token, true) = result? {
+                    unreadable_tokens.insert(token);
+                }
+            }
+        }
+    }
+    while let Some(result) = handles.join_next().await {
+        if let (token, true) = result? {
+            unreadable_tokens.insert(token);
+        }
+    }
+
+    Ok((amms_with_tokens, unreadable_tokens))
+}
+
+//Returns the addresses of the amms holding a token whose decimals() reverts, which are left out of the populated amms
+fn unreadable_decimals_amms(amms: &[AMM], unreadable_tokens: &HashSet<H160>) -> Vec<H160> {
+    amms.iter()
+        .filter(|amm| {
+            amm.tokens()
+                .iter()
+                .any(|token| unreadable_tokens.contains(token))
+        })
+        .map(|amm| {
+            tracing::warn!(pool = ?amm.address(), "Dropping pool with a token whose decimals() reverts");
+            amm.address()
+        })
+        .collect()
+}
+
+//Sets the decimals of the overridden tokens on the amms, replacing the decimals read from the chain
+pub fn apply_decimal_overrides(amms: &mut [AMM], decimal_overrides: &HashMap<H160, u8>) {
+    let override_decimals = |token: &H160, decimals: &mut u8| {
+        if let Some(overridden) = decimal_overrides.get(token) {
+            *decimals = *overridden;
+        }
+    };
+
+    for amm in amms.iter_mut() {
+        match amm {
+            AMM::UniswapV2Pool(pool) => {
+                override_decimals(&pool.token_a, &mut pool.token_a_decimals);
+                override_decimals(&pool.token_b, &mut pool.token_b_decimals);
+            }
+            AMM::UniswapV3Pool(pool) | AMM::AlgebraPool(AlgebraPool { pool }) => {
+                override_decimals(&pool.token_a, &mut pool.token_a_decimals);
+                override_decimals(&pool.token_b, &mut pool.token_b_decimals);
+            }
+            AMM::ERC4626Vault(vault) => {
+                override_decimals(&vault.vault_token, &mut vault.vault_token_decimals);
+                override_decimals(&vault.asset_token, &mut vault.asset_token_decimals);
+            }
+        }
+    }
+}
+
+//Drops the v2 pools with a reserve exceeding the total supply of its token. A pool can never hold more than the supply,
+//so this can only be a misread, eg. a pair whose getReserves does not follow the v2 layout. The total supply of each
+//unique token is read once at the block, the reserves of tokens whose totalSupply reverts are not checked
+pub async fn remove_reserves_exceeding_supply<M: 'static + Middleware>(
+    amms: Vec<AMM>,
+    block_number: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<AMM>, AMMError<M>> {
+    let mut tokens: Vec<H160> = amms
+        .iter()
+        .filter(|amm| matches!(amm, AMM::UniswapV2Pool(_)))
+        .flat_map(|amm| amm.tokens())
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+
+    let mut total_supplies: HashMap<H160, U256> = HashMap::new();
+    let mut handles = JoinSet::new();
+    for token in tokens {
+        let middleware = middleware.clone();
+        handles.spawn(async move {
+            let total_supply = uniswap_v2::IErc20::new(token, middleware)
+                .total_supply()
+                .block(block_number)
+                .call()
+                .await
+                .ok();
+            (token, total_supply)
+        });
+
+        if handles.len() == TASK_LIMIT {
+            if let Some(result) = handles.join_next().await {
+                if let (token, Some(total_supply)) = result? {
+                    total_supplies.insert(token, total_supply);
+                }
+            }
+        }
+    }
+    while let Some(result) = handles.join_next().await {
+        if let (token, Some(total_supply)) = result? {
+            total_supplies.insert(token, total_supply);
+        }
+    }
+
+    let exceeds_supply = |token: &H160, reserve: u128| matches!(total_supplies.get(token), Some(total_supply) if U256::from(reserve) > *total_supply);
+
+    Ok(amms
+        .into_iter()
+        .filter(|amm| match amm {
+            AMM::UniswapV2Pool(pool) => {
+                let exceeds = exceeds_supply(&pool.token_a, pool.reserve_0)
+                    || exceeds_supply(&pool.token_b, pool.reserve_1);
+                if exceeds {
+                    tracing::warn!(pool = ?pool.address, reserve_0 = pool.reserve_0, reserve_1 = pool.reserve_1, "Dropping pool with a reserve exceeding the token supply");
+                }
+                !exceeds
+            }
+            _ => true,
+        })
+        .collect())
+}
+
+pub async fn process_updated_amm<M: 'static + Middleware>(
+    amms: &mut Vec<AMM>,
+    mut set: JoinSet<Result<Vec<AMM>, AMMError<M>>>,
+) -> Result<(), AMMError<M>> {
+    while let Some(amm_chunk) = set.join_next().await {
+        amms.extend(amm_chunk??);
+    }
+    Ok(())
+}
+
+pub fn remove_empty_amms(amms: Vec<AMM>) -> Vec<AMM> {
+    let mut cleaned_amms = vec![];
+
+    for amm in amms.into_iter() {
+        if amm.has_identical_tokens() {
+            tracing::warn!(pool = ?amm.address(), "Dropping pool with identical tokens");
+            continue;
+        }
+
+        match amm {
+            AMM::UniswapV2Pool(ref uniswap_v2_pool) => {
+                if !uniswap_v2_pool.token_a.is_zero() && !uniswap_v2_pool.token_b.is_zero() {
+                    cleaned_amms.push(amm)
+                }
+            }
+            AMM::UniswapV3Pool(ref uniswap_v3_pool) => {
+                if !uniswap_v3_pool.token_a.is_zero() && !uniswap_v3_pool.token_b.is_zero() {
+                    cleaned_amms.push(amm)
+                }
+            }
+            AMM::ERC4626Vault(ref erc4626_vault) => {
+                if !erc4626_vault.vault_token.is_zero() && !erc4626_vault.asset_token.is_zero() {
+                    cleaned_amms.push(amm)
+                }
+            }
+            AMM::AlgebraPool(ref algebra_pool) => {
+                if algebra_pool.data_is_populated() {
+                    cleaned_amms.push(amm)
+                }
+            }
+        }
+    }
+
+    cleaned_amms
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr, sync::Arc, time::UNIX_EPOCH};
+
+    use async_trait::async_trait;
+    use ethers::{
+        abi::Token,
+        providers::{
+            JsonRpcError, Middleware, MiddlewareError, MockProvider, MockResponse, Provider,
+            ProviderError,
+        },
+        types::{
+            transaction::eip2718::TypedTransaction, Block, BlockId, BlockNumber, Bytes, Filter,
+            Log, NameOrAddress, ValueOrArray, H160, H256, U256, U64,
+        },
+    };
+    use thiserror::Error;
+
+    use crate::{
+        amm::{
+            algebra::AlgebraPool,
+            factory::Factory,
+            uniswap_v2::{
+                factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+                UniswapV2Pool, SYNC_EVENT_SIGNATURE,
+            },
+            uniswap_v3::{
+                batch_request::{max_pool_data_batch_size_for_chain, MAX_POOL_DATA_BATCH_SIZE},
+                UniswapV3Pool,
+            },
+            AutomatedMarketMaker, PoolType, AMM,
+        },
+        errors::{AMMError, CheckpointError},
+    };
+
+    use super::{
+        apply_catchup_logs, apply_decimal_overrides, batch_size, blocks_for_duration,
+        blocks_for_duration_exact, changed_pools_in_range, checkpoint, discover_amms,
+        group_by_factory, load_pools_from_list, map_state_unavailable, pool_creation_rate,
+        populate_amm_chunk, populate_amms_adaptive, population_batches, reconcile, refresh_amms,
+        refresh_v3_prices, remove_reserves_exceeding_supply, retry_failed, sync_amms_grouped,
+        sync_amms_pipelined, sync_amms_with_config, sync_amms_with_stats, sync_amms_with_store,
+        sync_reserves_from_logs, v3_batch_size, BatchSizeTuner, BatchTuning, BlockTag,
+        CheckpointStore, PopulationMode, SyncConfig, LOG_RANGE_STEP,
+    };
+
+    #[tokio::test]
+    async fn test_load_pools_from_list() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+
+        let list_path = std::env::temp_dir().join("amms_test_pool_list.json");
+        let list_path = list_path.to_str().expect("Temp path should be utf-8");
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        std::fs::write(
+            list_path,
+            r#"[{"address": "0x0000000000000000000000000000000000000003", "type": "UniswapV2Pool", "fee": 250}]"#,
+        )?;
+
+        //Mocked responses are popped in reverse order, so the batch call response is pushed before the block number
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(200))?;
+
+        let (amms, block_number) = load_pools_from_list(list_path, middleware.clone()).await?;
+
+        assert_eq!(block_number, 200);
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!(pool.address, H160::from_low_u64_be(3));
+            assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 2000));
+            assert_eq!(pool.fee, 250);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        std::fs::write(
+            list_path,
+            r#"[{"address": "0x0000000000000000000000000000000000000003", "type": "CurvePool"}]"#,
+        )?;
+        mock.push::<U64, _>(U64::from(200))?;
+        assert!(load_pools_from_list(list_path, middleware).await.is_err());
+
+        std::fs::remove_file(list_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_amms() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+
+        let mut amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(3),
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 1,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(4),
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 1,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+        ];
+
+        let pool_data = |reserve_0: u64, reserve_1: u64| {
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])
+        };
+
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            pool_data(1000, 2000),
+            pool_data(3000, 4000),
+        ])])))?;
+
+        refresh_amms(&mut amms, 100, Arc::new(provider)).await?;
+
+        if let (AMM::UniswapV2Pool(pool_a), AMM::UniswapV2Pool(pool_b)) = (&amms[0], &amms[1]) {
+            assert_eq!(pool_a.address, H160::from_low_u64_be(3));
+            assert_eq!((pool_a.reserve_0, pool_a.reserve_1), (1000, 2000));
+            assert_eq!(pool_b.address, H160::from_low_u64_be(4));
+            assert_eq!((pool_b.reserve_0, pool_b.reserve_1), (3000, 4000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_state_unavailable() {
+        let err = AMMError::<Provider<MockProvider>>::ProviderError(ProviderError::CustomError(
+            "missing trie node 0x1234 (path )".to_string(),
+        ));
+        assert!(matches!(
+            map_state_unavailable(err, 100),
+            AMMError::StateUnavailable(100, _)
+        ));
+
+        let err = AMMError::<Provider<MockProvider>>::ProviderError(ProviderError::CustomError(
+            "execution reverted".to_string(),
+        ));
+        assert!(matches!(
+            map_state_unavailable(err, 100),
+            AMMError::ProviderError(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+
+        //The second pool holds a deliberately wrong reserve
+        let mut amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(3),
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 1000,
+                reserve_1: 2000,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(4),
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 3000,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+        ];
+
+        let pool_data = |reserve_0: u64, reserve_1: u64| {
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])
+        };
+
+        //Mocked responses are popped in reverse order: block number, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            pool_data(1000, 2000),
+            pool_data(3000, 4000),
+        ])])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let drifted = reconcile(&mut amms, Arc::new(provider)).await?;
+
+        assert_eq!(drifted, vec![H160::from_low_u64_be(4)]);
+        if let AMM::UniswapV2Pool(pool) = &amms[1] {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (3000, 4000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_v3_prices() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let sqrt_price = U256::from_dec_str("79228162514264337593543950336")?;
+        let mut amms = vec![
+            AMM::UniswapV3Pool(UniswapV3Pool {
+                address: H160::from_low_u64_be(3),
+                liquidity: 1000,
+                sqrt_price: U256::one(),
+                tick: -100,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(4),
+                reserve_0: 1,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+            AMM::AlgebraPool(AlgebraPool {
+                pool: UniswapV3Pool {
+                    address: H160::from_low_u64_be(5),
+                    liquidity: 1000,
+                    fee: 100,
+                    ..Default::default()
+                },
+            }),
+        ];
+
+        //Only slot0 of the v3 pool and globalState of the Algebra pool are read. The payload decodes as both, with the
+        //dynamic fee of globalState in place of the observation index of slot0, so the order of the reads does not matter
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(sqrt_price),
+                Token::Int(U256::from(200)),
+                Token::Uint(U256::from(500)),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ])))?;
+        }
+
+        refresh_v3_prices(&mut amms, Arc::new(provider)).await?;
+
+        if let AMM::UniswapV3Pool(pool) = &amms[0] {
+            assert_eq!(pool.sqrt_price, sqrt_price);
+            assert_eq!(pool.tick, 200);
+            assert_eq!(pool.liquidity, 1000);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        if let AMM::UniswapV2Pool(pool) = &amms[1] {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (1, 1));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        if let AMM::AlgebraPool(algebra_pool) = &amms[2] {
+            assert_eq!(algebra_pool.pool.sqrt_price, sqrt_price);
+            assert_eq!(algebra_pool.pool.tick, 200);
+            assert_eq!(algebra_pool.fee(), 500);
+            assert_eq!(algebra_pool.pool.liquidity, 1000);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_with_finalized_block_tag() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        mock.push::<Block<H256>, _>(Block {
+            number: Some(U64::from(100)),
+            ..Default::default()
+        })?;
+
+        let config = SyncConfig {
+            block_tag: BlockTag::Finalized,
+            ..Default::default()
+        };
+        let (amms, synced_block) =
+            sync_amms_with_config(vec![], Arc::new(provider), None, 1000, config).await?;
+
+        assert!(amms.is_empty());
+        assert_eq!(synced_block, 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_with_denylist() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let checkpoint_path = std::env::temp_dir().join("amms_test_denylist_checkpoint.json");
+        let checkpoint_path = checkpoint_path.to_str().expect("Temp path should be utf-8");
+        let _ = std::fs::remove_file(checkpoint_path);
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let honeypot_token = H160::from_low_u64_be(9);
+        let denied_pool = H160::from_low_u64_be(3);
+        let kept_pool = H160::from_low_u64_be(4);
+        let honeypot_pool = H160::from_low_u64_be(5);
+
+        let pool_data = |token_b: H160| {
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_b),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ])
+        };
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            pool_data(token_1),
+            pool_data(honeypot_token),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(denied_pool),
+            Token::Address(kept_pool),
+            Token::Address(honeypot_pool),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(3),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let config = SyncConfig {
+            denylist: [denied_pool].into_iter().collect(),
+            token_denylist: [honeypot_token].into_iter().collect(),
+            ..Default::default()
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(10),
+            0,
+            300,
+        ))];
+
+        let (amms, _) = sync_amms_with_config(
+            factories,
+            Arc::new(provider),
+            Some(checkpoint_path),
+            1000,
+            config,
+        )
+        .await?;
+
+        assert_eq!(
+            amms.iter().map(|amm| amm.address()).collect::<Vec<H160>>(),
+            vec![kept_pool]
+        );
+
+        let (checkpoint_amms, _) = checkpoint::deconstruct_checkpoint(checkpoint_path)?;
+        assert_eq!(
+            checkpoint_amms
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![kept_pool]
+        );
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_metadata_only() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pool_address = H160::from_low_u64_be(3);
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, tokens, decimals
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(6),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(6),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_1)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_0)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(pool_address),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(1),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let config = SyncConfig {
+            population_mode: PopulationMode::MetadataOnly,
+            ..Default::default()
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(10),
+            0,
+            300,
+        ))];
+
+        let (amms, _) =
+            sync_amms_with_config(factories, Arc::new(provider), None, 1000, config).await?;
+
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.token_a, pool.token_b), (token_0, token_1));
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 6));
+            assert_eq!((pool.reserve_0, pool.reserve_1), (0, 0));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_decimal_overrides() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pool_address = H160::from_low_u64_be(3);
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, tokens and only the
+        //decimals of token 1, reading the decimals of token 0 would fail on the empty mock and drop the pool
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(18),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_1)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_0)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(pool_address),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(1),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let config = SyncConfig {
+            population_mode: PopulationMode::MetadataOnly,
+            decimal_overrides: HashMap::from([(token_0, 6)]),
+            ..Default::default()
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(10),
+            0,
+            300,
+        ))];
+
+        let (amms, _) =
+            sync_amms_with_config(factories, Arc::new(provider), None, 1000, config).await?;
+
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.token_a, pool.token_b), (token_0, token_1));
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 18));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        let mut amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            token_a: token_0,
+            token_a_decimals: 18,
+            token_b: token_1,
+            token_b_decimals: 18,
+            ..Default::default()
+        })];
+        apply_decimal_overrides(&mut amms, &HashMap::from([(token_0, 6)]));
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 18));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_reverting_decimals() -> eyre::Result<()> {
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pool_address = H160::from_low_u64_be(3);
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(10),
+            0,
+            300,
+        ))];
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, pool data skipping the
+        //pool, tokens and both decimals reverting
+        let mocked_provider = || -> eyre::Result<(Provider<MockProvider>, MockProvider)> {
+            let (provider, mock) = Provider::mocked();
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+                Token::Uint(U256::zero()),
+            ])))?;
+            for _ in 0..2 {
+                mock.push_response(MockResponse::Error(JsonRpcError {
+                    code: 3,
+                    message: "execution reverted".to_string(),
+                    data: None,
+                }));
+            }
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_1)])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_0)])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+                Token::Tuple(vec![
+                    Token::Address(H160::zero()),
+                    Token::Uint(U256::zero()),
+                    Token::Address(H160::zero()),
+                    Token::Uint(U256::zero()),
+                    Token::Uint(U256::zero()),
+                    Token::Uint(U256::zero()),
+                ]),
+            ])])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+                Token::Address(pool_address),
+            ])])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::one()),
+            ])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+                H160::zero(),
+            )])))?;
+            mock.push::<U64, _>(U64::from(100))?;
+            Ok((provider, mock))
+        };
+
+        //Without a fallback the pool is left out and flagged for review
+        let (provider, _mock) = mocked_provider()?;
+        let (amms, _, stats) = sync_amms_with_stats(
+            factories.clone(),
+            Arc::new(provider),
+            None,
+            1000,
+            SyncConfig::default(),
+        )
+        .await?;
+
+        assert!(amms.is_empty());
+        assert_eq!(stats.unreadable_decimals, vec![pool_address]);
+
+        //With a fallback the decimals are assumed and the pool is synced
+        let (provider, _mock) = mocked_provider()?;
+        let config = SyncConfig {
+            decimals_fallback: Some(18),
+            ..Default::default()
+        };
+        let (amms, _, stats) =
+            sync_amms_with_stats(factories, Arc::new(provider), None, 1000, config).await?;
+
+        assert!(stats.unreadable_decimals.is_empty());
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.token_a, pool.token_b), (token_0, token_1));
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (18, 18));
+            assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 2000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_tuned_decimal_overrides() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pool_address = H160::from_low_u64_be(3);
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, a tuned batch skipping
+        //the pool, tokens, the decimals of token 1 for the revert check and the metadata, then the reserves. Reading the
+        //decimals of the overridden token 0 would fail on the empty mock
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::from(1000)),
+            Token::Uint(U256::from(2000)),
+            Token::Uint(U256::zero()),
+        ])))?;
+        for _ in 0..2 {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+                U256::from(18),
+            )])))?;
+        }
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_1)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(token_0)])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(H160::zero()),
+                Token::Uint(U256::zero()),
+                Token::Address(H160::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+            ]),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(pool_address),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::one()),
+        ])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let config = SyncConfig {
+            batch_tuning: Some(BatchTuning::default()),
+            decimal_overrides: HashMap::from([(token_0, 6)]),
+            ..Default::default()
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(10),
+            0,
+            300,
+        ))];
+
+        let (amms, _, stats) =
+            sync_amms_with_stats(factories, Arc::new(provider), None, 1000, config).await?;
+
+        assert!(stats.unreadable_decimals.is_empty());
+        assert_eq!(amms.len(), 1);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.token_a, pool.token_b), (token_0, token_1));
+            assert_eq!((pool.token_a_decimals, pool.token_b_decimals), (6, 18));
+            assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 2000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_grouped() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(10);
+        let pair = H160::from_low_u64_be(3);
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(H160::from_low_u64_be(1)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(2)),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(pair),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::one()),
+        ])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            factory_address,
+            0,
+            300,
+        ))];
+        let (grouped_amms, synced_block) =
+            sync_amms_grouped(factories, Arc::new(provider), None, 1000).await?;
+
+        assert_eq!(synced_block, 100);
+        assert_eq!(grouped_amms.len(), 1);
+        assert_eq!(
+            grouped_amms[&factory_address]
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![pair]
+        );
+
+        //Pools of several factories are kept apart
+        let other_factory = H160::from_low_u64_be(11);
+        let pool = |address: u64, factory: H160| {
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(address),
+                factory,
+                ..Default::default()
+            })
+        };
+        let grouped_amms = group_by_factory(vec![
+            pool(3, factory_address),
+            pool(4, other_factory),
+            pool(5, factory_address),
+        ]);
+
+        assert_eq!(grouped_amms.len(), 2);
+        assert_eq!(
+            grouped_amms[&factory_address]
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![H160::from_low_u64_be(3), H160::from_low_u64_be(5)]
+        );
+        assert_eq!(grouped_amms[&other_factory].len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_discover_amms() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(10);
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let pair = H160::from_low_u64_be(3);
+
+        let pair_created_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(50)),
+            ..Default::default()
+        };
+
+        //Only the pair created logs are fetched, any population call would fail on the empty mock
+        mock.push::<Vec<Log>, _>(vec![pair_created_log])?;
+
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            factory_address,
+            0,
+            300,
+        ))];
+        let amms = discover_amms(factories, Arc::new(provider), 100, 1000).await?;
+
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].factory(), factory_address);
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!(pool.address, pair);
+            assert_eq!((pool.token_a, pool.token_b), (token_0, token_1));
+            assert_eq!((pool.reserve_0, pool.reserve_1), (0, 0));
+            assert_eq!(pool.fee, 300);
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_duplicate_pairs() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let canonical_pair = H160::from_low_u64_be(3);
+        let duplicate_pair = H160::from_low_u64_be(4);
+
+        let pool_data = Token::Tuple(vec![
+            Token::Address(token_0),
+            Token::Uint(U256::from(18)),
+            Token::Address(token_1),
+            Token::Uint(U256::from(18)),
+            Token::Uint(U256::from(1000)),
+            Token::Uint(U256::from(2000)),
+        ]);
+
+        //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            pool_data.clone(),
+            pool_data,
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(canonical_pair),
+            Token::Address(duplicate_pair),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+            U256::from(2),
+        )])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let factory_address = H160::from_low_u64_be(10);
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            factory_address,
+            0,
+            300,
+        ))];
+
+        let (amms, _, stats) = sync_amms_with_stats(
+            factories,
+            Arc::new(provider),
+            None,
+            1000,
+            SyncConfig::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            amms.iter().map(|amm| amm.address()).collect::<Vec<H160>>(),
+            vec![canonical_pair]
+        );
+        assert_eq!(stats.duplicate_pairs.len(), 1);
+        assert_eq!(stats.duplicate_pairs[0].factory, factory_address);
+        assert_eq!(stats.duplicate_pairs[0].canonical, canonical_pair);
+        assert_eq!(stats.duplicate_pairs[0].duplicates, vec![duplicate_pair]);
+
+        Ok(())
+    }
+
+    //Middleware serving blocks 12 seconds apart from timestamp 1000 and the logs within the filter range, forwarding calls
+    //to the mock provider
+    #[derive(Debug)]
+    struct ChainMiddleware {
+        inner: Provider<MockProvider>,
+        latest_block: u64,
+        logs: Vec<Log>,
+    }
+
+    #[async_trait]
+    impl Middleware for ChainMiddleware {
+        type Error = ProviderError;
+        type Provider = MockProvider;
+        type Inner = Provider<MockProvider>;
+
+        fn inner(&self) -> &Provider<MockProvider> {
+            &self.inner
+        }
+
+        async fn get_block_number(&self) -> Result<U64, Self::Error> {
+            Ok(U64::from(self.latest_block))
+        }
+
+        async fn get_block<T: Into<BlockId> + Send + Sync>(
+            &self,
+            block_hash_or_number: T,
+        ) -> Result<Option<Block<H256>>, Self::Error> {
+            let block_number = match block_hash_or_number.into() {
+                BlockId::Number(BlockNumber::Number(number)) => number.as_u64(),
+                _ => self.latest_block,
+            };
+
+            Ok(Some(Block {
+                number: Some(U64::from(block_number)),
+                timestamp: U256::from(1000 + 12 * block_number),
+                ..Default::default()
+            }))
+        }
+
+        async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+            let from_block = filter.get_from_block().unwrap_or_default();
+            let to_block = filter.get_to_block().unwrap_or_default();
+
+            Ok(self
+                .logs
+                .iter()
+                .filter(|log| {
+                    let block_number = log.block_number.unwrap_or_default();
+                    from_block <= block_number && block_number <= to_block
+                })
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_created_after() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(10);
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let old_pair = H160::from_low_u64_be(3);
+        let new_pair = H160::from_low_u64_be(4);
+
+        let pair_created_log = |pair: H160, block_number: u64| Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_0),
+                H256::from(token_1),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(pair),
+                Token::Uint(U256::one()),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            ..Default::default()
+        };
+
+        //Mocked calls are popped in reverse order: feeTo, pool data
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(2000)),
+            ]),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+            H160::zero(),
+        )])))?;
+
+        let middleware = Arc::new(ChainMiddleware {
+            inner: provider,
+            latest_block: 100,
+            logs: vec![
+                pair_created_log(old_pair, 20),
+                pair_created_log(new_pair, 80),
+            ],
+        });
+
+        //Block 50 is the first block at timestamp 1600
+        let config = SyncConfig {
+            created_after: Some(UNIX_EPOCH + std::time::Duration::from_secs(1600)),
+            ..Default::default()
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            factory_address,
+            0,
+            300,
+        ))];
+
+        let (amms, synced_block) =
+            sync_amms_with_config(factories, middleware, None, 1000, config).await?;
+
+        assert_eq!(synced_block, 100);
+        assert_eq!(
+            amms.iter().map(|amm| amm.address()).collect::<Vec<H160>>(),
+            vec![new_pair]
+        );
+
+        Ok(())
+    }
+
+    //Middleware failing every call and log request to the unreachable factory
+    #[derive(Debug)]
+    struct UnreachableFactoryMiddleware<M> {
+        inner: M,
+        factory: H160,
+    }
+
+    #[derive(Error, Debug)]
+    enum UnreachableFactoryError<M: Middleware> {
+        #[error("{0}")]
+        MiddlewareError(M::Error),
+        #[error("Factory is unreachable")]
+        Unreachable,
+    }
+
+    impl<M: Middleware> MiddlewareError for UnreachableFactoryError<M> {
+        type Inner = M::Error;
+
+        fn from_err(src: M::Error) -> Self {
+            UnreachableFactoryError::MiddlewareError(src)
+        }
+
+        fn as_inner(&self) -> Option<&Self::Inner> {
+            match self {
+                UnreachableFactoryError::MiddlewareError(err) => Some(err),
+                UnreachableFactoryError::Unreachable => None,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<M: Middleware> Middleware for UnreachableFactoryMiddleware<M> {
+        type Error = UnreachableFactoryError<M>;
+        type Provider = M::Provider;
+        type Inner = M;
+
+        fn inner(&self) -> &M {
+            &self.inner
+        }
+
+        async fn call(
+            &self,
+            tx: &TypedTransaction,
+            block: Option<BlockId>,
+        ) -> Result<Bytes, Self::Error> {
+            if tx.to() == Some(&NameOrAddress::Address(self.factory)) {
+                return Err(UnreachableFactoryError::Unreachable);
+            }
+
+            self.inner
+                .call(tx, block)
+                .await
+                .map_err(UnreachableFactoryError::MiddlewareError)
+        }
+
+        async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+            if filter.address == Some(ValueOrArray::Value(self.factory)) {
+                return Err(UnreachableFactoryError::Unreachable);
+            }
+
+            self.inner
+                .get_logs(filter)
+                .await
+                .map_err(UnreachableFactoryError::MiddlewareError)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_skips_failed_factory() -> eyre::Result<()> {
+        let good_factory = H160::from_low_u64_be(10);
+        let bad_factory = H160::from_low_u64_be(11);
+        let pool_address = H160::from_low_u64_be(3);
+
+        let factories = vec![
+            Factory::UniswapV2Factory(UniswapV2Factory::new(good_factory, 0, 300)),
+            Factory::UniswapV2Factory(UniswapV2Factory::new(bad_factory, 0, 300)),
+        ];
+
+        let push_good_factory_responses = |mock: &ethers::providers::MockProvider| {
+            //Mocked responses are popped in reverse order: block number, feeTo, allPairsLength, pairs, pool data
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+                Token::Tuple(vec![
+                    Token::Address(H160::from_low_u64_be(1)),
+                    Token::Uint(U256::from(18)),
+                    Token::Address(H160::from_low_u64_be(2)),
+                    Token::Uint(U256::from(18)),
+                    Token::Uint(U256::from(1000)),
+                    Token::Uint(U256::from(2000)),
+                ]),
+            ])])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+                Token::Address(pool_address),
+            ])])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::one()),
+            ])))?;
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Address(
+                H160::zero(),
+            )])))?;
+            mock.push::<U64, _>(U64::from(100))
+        };
+
+        let (provider, mock) = Provider::mocked();
+        push_good_factory_responses(&mock)?;
+        let middleware = Arc::new(UnreachableFactoryMiddleware {
+            inner: provider,
+            factory: bad_factory,
+        });
+
+        let (amms, _, stats) = sync_amms_with_stats(
+            factories.clone(),
+            middleware,
+            None,
+            1000,
+            SyncConfig::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            amms.iter().map(|amm| amm.address()).collect::<Vec<H160>>(),
+            vec![pool_address]
+        );
+        assert_eq!(stats.failed_factories.len(), 1);
+        assert_eq!(stats.failed_factories[0].address, bad_factory);
+
+        //Strict syncs abort on the failed factory
+        let (provider, mock) = Provider::mocked();
+        push_good_factory_responses(&mock)?;
+        let middleware = Arc::new(UnreachableFactoryMiddleware {
+            inner: provider,
+            factory: bad_factory,
+        });
+        let config = SyncConfig {
+            strict: true,
+            ..Default::default()
+        };
+
+        assert!(
+            sync_amms_with_stats(factories, middleware, None, 1000, config)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    //Compares the parallel and sequential grouping of 1M pools, reporting the timings through tracing. Run with
+    //`cargo test --release --features rayon -- --ignored`
+    #[cfg(feature = "rayon")]
+    #[test]
+    #[ignore]
+    fn bench_group_congruent_parallel() {
+        use crate::amm::erc_4626::ERC4626Vault;
+
+        let amms: Vec<AMM> = (0..1_000_000_u64)
+            .map(|idx| match idx % 3 {
+                0 => AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(idx),
+                    ..Default::default()
+                }),
+                1 => AMM::UniswapV3Pool(UniswapV3Pool {
+                    address: H160::from_low_u64_be(idx),
+                    ..Default::default()
+                }),
+                _ => AMM::ERC4626Vault(ERC4626Vault {
+                    vault_token: H160::from_low_u64_be(idx),
+                    ..Default::default()
+                }),
+            })
+            .collect();
+
+        let start = std::time::Instant::now();
+        let sequential_groups = super::group_congruent_sequential(amms.clone());
+        tracing::info!(elapsed = ?start.elapsed(), "Sequential grouping of 1M pools");
+
+        let start = std::time::Instant::now();
+        let parallel_groups = super::group_congruent_parallel(amms);
+        tracing::info!(elapsed = ?start.elapsed(), "Parallel grouping of 1M pools");
+
+        assert_eq!(sequential_groups.len(), parallel_groups.len());
+        for (sequential_group, parallel_group) in
+            sequential_groups.iter().zip(parallel_groups.iter())
+        {
+            assert_eq!(
+                sequential_group
+                    .iter()
+                    .map(|amm| amm.address())
+                    .collect::<Vec<H160>>(),
+                parallel_group
+                    .iter()
+                    .map(|amm| amm.address())
+                    .collect::<Vec<H160>>()
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryStore {
+        checkpoint: std::sync::Mutex<Option<checkpoint::Checkpoint>>,
+    }
+
+    #[async_trait]
+    impl CheckpointStore for MemoryStore {
+        async fn load(&self) -> Result<checkpoint::Checkpoint, CheckpointError> {
+            self.checkpoint
+                .lock()
+                .expect("Lock should not be poisoned")
+                .clone()
+                .ok_or_else(|| {
+                    CheckpointError::IOError(std::io::Error::from(std::io::ErrorKind::NotFound))
+                })
+        }
+
+        async fn store(
+            &self,
+            checkpoint: checkpoint::Checkpoint,
+        ) -> Result<checkpoint::Checkpoint, CheckpointError> {
+            *self.checkpoint.lock().expect("Lock should not be poisoned") =
+                Some(checkpoint.clone());
+            Ok(checkpoint)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_with_store() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<U64, _>(U64::from(100))?;
+
+        let store = MemoryStore::default();
+        let (amms, synced_block, _) = sync_amms_with_store(
+            vec![],
+            Arc::new(provider),
+            Some(&store),
+            1000,
+            SyncConfig::default(),
+        )
+        .await?;
+
+        assert!(amms.is_empty());
+        assert_eq!(synced_block, 100);
+
+        let checkpoint = store.load().await?;
+        assert_eq!(checkpoint.block_number, 100);
+        assert!(checkpoint.amms.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_denylist_prefixes() {
+        let config = SyncConfig {
+            denylist_prefixes: vec!["0xDEAD".to_string()],
+            ..Default::default()
+        };
+
+        let pool = |address: &str| {
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: address.parse().expect("Valid address"),
+                ..Default::default()
+            })
+        };
+
+        assert!(config.is_denied(&pool("0xdead000000000000000000000000000000000001")));
+        assert!(!config.is_denied(&pool("0x00000000000000000000000000000000000dead1")));
+    }
+
+    #[tokio::test]
+    async fn test_changed_pools_in_range() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let amms: Vec<AMM> = (3..6)
+            .map(|idx| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(idx),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let sync_log = |address: H160| Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            ..Default::default()
+        };
+        mock.push::<Vec<Log>, _>(vec![
+            sync_log(H160::from_low_u64_be(5)),
+            sync_log(H160::from_low_u64_be(3)),
+            sync_log(H160::from_low_u64_be(5)),
+        ])?;
+
+        let changed_pools = changed_pools_in_range(&amms, 100, 200, Arc::new(provider)).await?;
+
+        assert_eq!(
+            changed_pools,
+            vec![H160::from_low_u64_be(3), H160::from_low_u64_be(5)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changed_pools_in_wide_range() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+
+        let amms: Vec<AMM> = (3..6)
+            .map(|idx| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(idx),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let sync_log = |address: u64, block_number: u64| Log {
+            address: H160::from_low_u64_be(address),
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            block_number: Some(U64::from(block_number)),
+            ..Default::default()
+        };
+        let middleware = Arc::new(RangeLimitMiddleware {
+            inner: ChainMiddleware {
+                inner: provider,
+                latest_block: 10_000,
+                logs: vec![sync_log(5, 200), sync_log(3, 9500)],
+            },
+            max_range: LOG_RANGE_STEP,
+        });
+
+        let changed_pools = changed_pools_in_range(&amms, 100, 10_000, middleware).await?;
+
+        assert_eq!(
+            changed_pools,
+            vec![H160::from_low_u64_be(3), H160::from_low_u64_be(5)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocks_for_duration() -> eyre::Result<()> {
+        let block = |number: u64, timestamp: u64| Block::<H256> {
+            number: Some(U64::from(number)),
+            timestamp: U256::from(timestamp),
+            ..Default::default()
+        };
+
+        //Mocked responses are popped in reverse order: block number, latest block, sample block
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Block<H256>, _>(block(9000, 108000))?;
+        mock.push::<Block<H256>, _>(block(10000, 120000))?;
+        mock.push::<U64, _>(U64::from(10000))?;
+
+        //12 second blocks over the last hour
+        let range =
+            blocks_for_duration(std::time::Duration::from_secs(3600), Arc::new(provider)).await?;
+        assert_eq!(range, (9700, 10000));
+
+        //Binary search over blocks with timestamp 1000 + 12 * block for the first block within the last 30 seconds,
+        //visiting blocks 4, 6 and 5 after the latest block
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Block<H256>, _>(block(5, 1060))?;
+        mock.push::<Block<H256>, _>(block(6, 1072))?;
+        mock.push::<Block<H256>, _>(block(4, 1048))?;
+        mock.push::<Block<H256>, _>(block(8, 1096))?;
+        mock.push::<U64, _>(U64::from(8))?;
+
+        let range =
+            blocks_for_duration_exact(std::time::Duration::from_secs(30), Arc::new(provider))
+                .await?;
+        assert_eq!(range, (6, 8));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_reserves_from_logs() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let token_0 = H160::from_low_u64_be(1);
+        let token_1 = H160::from_low_u64_be(2);
+        let synced_pool = H160::from_low_u64_be(3);
+        let stale_pool = H160::from_low_u64_be(4);
+
+        let mut amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: synced_pool,
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 1,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: stale_pool,
+                token_a: token_0,
+                token_b: token_1,
+                reserve_0: 1,
+                reserve_1: 1,
+                ..Default::default()
+            }),
+        ];
+
+        let sync_log = |block_number: u64, reserve_0: u64, reserve_1: u64| Log {
+            address: synced_pool,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        };
+
+        //Mocked responses are popped in reverse order, so the getReserves fallback is pushed before the logs
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(5000)),
+                Token::Uint(U256::from(6000)),
+            ]),
+        ])])))?;
+        mock.push::<Vec<Log>, _>(vec![sync_log(110, 1000, 2000), sync_log(120, 3000, 4000)])?;
+
+        sync_reserves_from_logs(&mut amms, 100, 200, Arc::new(provider)).await?;
+
+        if let (AMM::UniswapV2Pool(synced), AMM::UniswapV2Pool(stale)) = (&amms[0], &amms[1]) {
+            assert_eq!((synced.reserve_0, synced.reserve_1), (3000, 4000));
+            assert_eq!((stale.reserve_0, stale.reserve_1), (5000, 6000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_reserves_from_logs_wide_range() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+
+        let pool = H160::from_low_u64_be(3);
+        let mut amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: pool,
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1,
+            reserve_1: 1,
+            ..Default::default()
+        })];
+
+        let sync_log = |block_number: u64, reserve_0: u64, reserve_1: u64| Log {
+            address: pool,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        };
+        let middleware = Arc::new(RangeLimitMiddleware {
+            inner: ChainMiddleware {
+                inner: provider,
+                latest_block: 10_000,
+                logs: vec![sync_log(9500, 3000, 4000), sync_log(200, 1000, 2000)],
+            },
+            max_range: LOG_RANGE_STEP,
+        });
+
+        sync_reserves_from_logs(&mut amms, 100, 10_000, middleware).await?;
+
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (3000, 4000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+
+        Ok(())
+    }
+
+    //Middleware answering v2 pool data batch requests itself, rejecting batches with more pools than the limit
+    #[derive(Debug)]
+    struct BatchLimitMiddleware<M> {
+        inner: M,
+        max_batch_size: usize,
+    }
+
+    #[derive(Error, Debug)]
+    enum BatchLimitError<M: Middleware> {
+        #[error("{0}")]
+        MiddlewareError(M::Error),
+        #[error("Batch of {0} pools exceeds the limit")]
+        BatchTooLarge(usize),
+    }
+
+    impl<M: Middleware> MiddlewareError for BatchLimitError<M> {
+        type Inner = M::Error;
+
+        fn from_err(src: M::Error) -> Self {
+            BatchLimitError::MiddlewareError(src)
+        }
+
+        fn as_inner(&self) -> Option<&Self::Inner> {
+            match self {
+                BatchLimitError::MiddlewareError(err) => Some(err),
+                BatchLimitError::BatchTooLarge(_) => None,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl<M: Middleware> Middleware for BatchLimitMiddleware<M> {
+        type Error = BatchLimitError<M>;
+        type Provider = M::Provider;
+        type Inner = M;
+
+        fn inner(&self) -> &M {
+            &self.inner
+        }
+
+        async fn call(
+            &self,
+            tx: &TypedTransaction,
+            _block: Option<BlockId>,
+        ) -> Result<Bytes, Self::Error> {
+            //The constructor args end with the abi encoded pool array, ie. its offset, length and the pool addresses
+            let data = tx.data().cloned().unwrap_or_default();
+            let word = |idx: usize| U256::from_big_endian(&data[data.len() - 32 * idx..][..32]);
+            let pools = (1..data.len() / 32 - 1)
+                .find(|n| word(n + 1) == U256::from(*n) && word(n + 2) == U256::from(32))
+                .unwrap_or(0);
+
+            if pools > self.max_batch_size {
+                return Err(BatchLimitError::BatchTooLarge(pools));
+            }
+
+            let pool_data = (0..pools)
+                .map(|_| {
+                    Token::Tuple(vec![
+                        Token::Address(H160::from_low_u64_be(1)),
+                        Token::Uint(U256::from(18)),
+                        Token::Address(H160::from_low_u64_be(2)),
+                        Token::Uint(U256::from(18)),
+                        Token::Uint(U256::from(1000)),
+                        Token::Uint(U256::from(2000)),
+                    ])
+                })
+                .collect();
+
+            Ok(ethers::abi::encode(&[Token::Array(pool_data)]).into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(BatchLimitMiddleware {
+            inner: provider,
+            max_batch_size: 1,
+        });
+
+        let failures = (0..3)
+            .map(|idx| (H160::from_low_u64_be(0x1000 + idx), PoolType::UniswapV2Pool))
+            .collect::<Vec<_>>();
+
+        //The batch of all three pools fails as it did during the sync
+        let amm_chunk = failures
+            .iter()
+            .map(|(address, pool_type)| pool_type.empty_amm(*address))
+            .collect::<Vec<AMM>>();
+        assert!(populate_amm_chunk(amm_chunk, 100, middleware.clone())
+            .await
+            .is_err());
+
+        //Retrying falls back to one pool per batch, which the middleware accepts
+        mock.push::<U64, _>(U64::from(100))?;
+        let (populated, failed) = retry_failed(failures.clone(), middleware).await?;
+
+        assert!(failed.is_empty());
+        let mut addresses = populated
+            .iter()
+            .map(|amm| amm.address())
+            .collect::<Vec<_>>();
+        addresses.sort();
+        assert_eq!(
+            addresses,
+            failures
+                .iter()
+                .map(|(address, _)| *address)
+                .collect::<Vec<_>>()
+        );
+        assert!(populated.iter().all(|amm| match amm {
+            AMM::UniswapV2Pool(pool) => pool.reserve_0 == 1000 && pool.reserve_1 == 2000,
+            _ => false,
+        }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_amms_pipelined_matches_sequential() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+
+        let factory_address = H160::from_low_u64_be(10);
+        let pair_created_log = |idx: u64| Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(1)),
+                H256::from(H160::from_low_u64_be(2)),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(0x1000 + idx)),
+                Token::Uint(U256::from(idx)),
+            ])),
+            block_number: Some(U64::from(100 + idx)),
+            ..Default::default()
+        };
+
+        //One pair per block, scanned in ranges of 10 blocks so that several log groups are sent while the earlier ones
+        //are being populated
+        let middleware = Arc::new(BatchLimitMiddleware {
+            inner: ChainMiddleware {
+                inner: provider,
+                latest_block: 400,
+                logs: (0..300).map(pair_created_log).collect(),
+            },
+            max_batch_size: usize::MAX,
+        });
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 100, 300));
+
+        let sequential = factory
+            .sync_range(100, 400, 10, None, middleware.clone())
+            .await?;
+        let (pipelined, synced_block) = sync_amms_pipelined(vec![factory], middleware, 10).await?;
+
+        assert_eq!(synced_block, 400);
+        assert_eq!(sequential.len(), 300);
+
+        let pool_state = |amms: Vec<AMM>| {
+            let mut pools = amms
+                .into_iter()
+                .map(|amm| match amm {
+                    AMM::UniswapV2Pool(pool) => {
+                        (pool.address, pool.reserve_0, pool.reserve_1, pool.fee)
+                    }
+                    _ => panic!("Unexpected AMM variant"),
+                })
+                .collect::<Vec<_>>();
+            pools.sort();
+            pools
+        };
+        assert_eq!(pool_state(pipelined), pool_state(sequential));
+
+        Ok(())
+    }
+
+    //Compares the sequential and pipelined sync of the sushiswap factory, reporting the timings through tracing. Run with
+    //`cargo test --release bench_sync_amms_pipelined -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_sync_amms_pipelined() -> eyre::Result<()> {
+        let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
+        let middleware = Arc::new(Provider::<ethers::providers::Http>::try_from(rpc_endpoint)?);
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_str("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac")?,
+            10794229,
+            300,
+        ));
+
+        let start = std::time::Instant::now();
+        let (pipelined, synced_block) =
+            sync_amms_pipelined(vec![factory.clone()], middleware.clone(), 10000).await?;
+        tracing::info!(
+            pools = pipelined.len(),
+            elapsed = ?start.elapsed(),
+            "Pipelined sync"
+        );
+
+        let start = std::time::Instant::now();
+        let sequential = factory
+            .sync_range(10794229, synced_block, 10000, None, middleware)
+            .await?;
+        tracing::info!(
+            pools = sequential.len(),
+            elapsed = ?start.elapsed(),
+            "Sequential sync"
+        );
+
+        assert_eq!(pipelined.len(), sequential.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_populate_amms_adaptive_backs_off() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+        let middleware = Arc::new(BatchLimitMiddleware {
+            inner: provider,
+            max_batch_size: 20,
+        });
+
+        let amms: Vec<AMM> = (0..100)
+            .map(|idx| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(0x1000 + idx),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        //The first batch exceeds the limit, so the tuner has to back off before any pool is populated
+        let tuner = BatchSizeTuner::new(BatchTuning {
+            target_latency: std::time::Duration::from_secs(60),
+            initial_size: 32,
+            max_failures: 3,
+        });
+
+        let populated = populate_amms_adaptive(&amms, 100, &tuner, middleware).await?;
+
+        assert_eq!(populated.len(), 100);
+        assert!(populated.iter().all(|amm| match amm {
+            AMM::UniswapV2Pool(pool) => pool.reserve_0 == 1000 && pool.reserve_1 == 2000,
+            _ => false,
+        }));
+        assert!(tuner.size(PoolType::UniswapV2Pool, 127) <= 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_v3_batch_size_is_configurable() {
+        let amms: Vec<AMM> = (0..250)
+            .map(|idx| {
+                AMM::UniswapV3Pool(UniswapV3Pool {
+                    address: H160::from_low_u64_be(0x1000 + idx),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        //The configured size is passed in directly, the global setting is left alone since other tests populate v3
+        //pools in parallel
+        assert_eq!(v3_batch_size(100), 100);
+        assert_eq!(amms.chunks(v3_batch_size(100)).count(), 3);
+        assert_eq!(v3_batch_size(MAX_POOL_DATA_BATCH_SIZE), 76);
+        assert_eq!(
+            amms.chunks(v3_batch_size(MAX_POOL_DATA_BATCH_SIZE)).count(),
+            4
+        );
+        assert_eq!(v3_batch_size(0), 1);
+
+        assert_eq!(
+            max_pool_data_batch_size_for_chain(1),
+            MAX_POOL_DATA_BATCH_SIZE
+        );
+        assert!(max_pool_data_batch_size_for_chain(43114) < MAX_POOL_DATA_BATCH_SIZE);
+        assert!(max_pool_data_batch_size_for_chain(42161) > MAX_POOL_DATA_BATCH_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_pool_creation_rate() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(10), 0, 300));
+
+        let pair_created_log = || Log {
+            address: H160::from_low_u64_be(10),
+            topics: vec![PAIR_CREATED_EVENT_SIGNATURE],
+            ..Default::default()
+        };
+
+        //Responses are popped in reverse order, five pools are created in the last 1000 blocks
+        mock.push::<Vec<Log>, _>((0..5).map(|_| pair_created_log()).collect::<Vec<_>>())?;
+        mock.push::<U64, _>(U64::from(10_000))?;
+
+        let rate = pool_creation_rate(&factory, 1000, middleware.clone()).await?;
+        assert!((rate - 0.005).abs() < f64::EPSILON);
+
+        //A window reaching past the creation block only counts the blocks since the creation
+        let factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(10), 9_951, 300));
+        mock.push::<Vec<Log>, _>((0..5).map(|_| pair_created_log()).collect::<Vec<_>>())?;
+        mock.push::<U64, _>(U64::from(10_000))?;
+
+        let rate = pool_creation_rate(&factory, 1000, middleware).await?;
+        assert!((rate - 0.1).abs() < f64::EPSILON);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_catchup_logs() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let pool = H160::from_low_u64_be(3);
+        let mut amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: pool,
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1,
+            reserve_1: 1,
+            ..Default::default()
+        })];
+
+        let sync_log = |block_number: u64, log_index: u64, reserve_0: u64, reserve_1: u64| Log {
+            address: pool,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            log_index: Some(U256::from(log_index)),
+            ..Default::default()
+        };
+
+        //Mocked responses are popped in reverse order, so the logs are pushed before the latest block
+        mock.push::<Vec<Log>, _>(vec![
+            sync_log(120, 1, 5000, 6000),
+            sync_log(110, 0, 1000, 2000),
+            sync_log(120, 0, 3000, 4000),
+        ])?;
+        mock.push::<U64, _>(U64::from(150))?;
+
+        let latest_block = apply_catchup_logs(&mut amms, 100, Arc::new(provider)).await?;
+        assert_eq!(latest_block, 150);
+
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (5000, 6000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        assert_eq!(amms[0].last_synced_block(), 120);
+
+        Ok(())
+    }
+
+    //Middleware rejecting log requests over a wider block range than the limit, like hosted providers do
+    #[derive(Debug)]
+    struct RangeLimitMiddleware {
+        inner: ChainMiddleware,
+        max_range: u64,
+    }
+
+    #[async_trait]
+    impl Middleware for RangeLimitMiddleware {
+        type Error = ProviderError;
+        type Provider = MockProvider;
+        type Inner = ChainMiddleware;
+
+        fn inner(&self) -> &ChainMiddleware {
+            &self.inner
+        }
+
+        async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+            let from_block = filter.get_from_block().unwrap_or_default();
+            let to_block = filter.get_to_block().unwrap_or_default();
+            if (to_block - from_block).as_u64() >= self.max_range {
+                return Err(ProviderError::CustomError(
+                    "Block range exceeds the limit".to_string(),
+                ));
+            }
+
+            self.inner.get_logs(filter).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_catchup_logs_pages_range() -> eyre::Result<()> {
+        let (provider, _) = Provider::mocked();
+
+        let pool = H160::from_low_u64_be(3);
+        let mut amms = vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: pool,
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1,
+            reserve_1: 1,
+            ..Default::default()
+        })];
+
+        let sync_log = |block_number: u64, reserve_0: u64, reserve_1: u64| Log {
+            address: pool,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])),
+            block_number: Some(U64::from(block_number)),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        };
+        //An anonymous log has no signature topic and is skipped
+        let anonymous_log = Log {
+            address: pool,
+            block_number: Some(U64::from(5000)),
+            ..Default::default()
+        };
+
+        let middleware = Arc::new(RangeLimitMiddleware {
+            inner: ChainMiddleware {
+                inner: provider,
+                latest_block: 10_000,
+                logs: vec![
+                    sync_log(150, 1000, 2000),
+                    anonymous_log,
+                    sync_log(9000, 5000, 6000),
+                ],
+            },
+            max_range: LOG_RANGE_STEP,
+        });
+
+        let latest_block = apply_catchup_logs(&mut amms, 100, middleware).await?;
+        assert_eq!(latest_block, 10_000);
+
+        if let AMM::UniswapV2Pool(pool) = &amms[0] {
+            assert_eq!((pool.reserve_0, pool.reserve_1), (5000, 6000));
+        } else {
+            panic!("Unexpected AMM variant")
+        }
+        assert_eq!(amms[0].last_synced_block(), 9000);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_reserves_exceeding_supply() -> eyre::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let pool = |address: u64, reserve_0: u128| {
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(address),
+                token_a: H160::from_low_u64_be(1),
+                token_b: H160::from_low_u64_be(2),
+                reserve_0,
+                reserve_1: 2000,
+                ..Default::default()
+            })
+        };
+        //The second pool reports more of the first token than will ever exist
+        let amms = vec![pool(0x1000, 1000), pool(0x1001, 10_u128.pow(30))];
+
+        //Both tokens have the same supply, so the order the supplies are read in does not matter
+        let total_supply: Bytes = ethers::abi::encode(&[Token::Uint(U256::from(1_000_000))]).into();
+        mock.push::<Bytes, _>(total_supply.clone())?;
+        mock.push::<Bytes, _>(total_supply)?;
+
+        let amms = remove_reserves_exceeding_supply(amms, 100, Arc::new(provider)).await?;
+
+        assert_eq!(amms.len(), 1);
+        assert_eq!(amms[0].address(), H160::from_low_u64_be(0x1000));
+
+        Ok(())
+    }
 }
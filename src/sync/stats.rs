@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ethers::types::H160;
+
+use crate::amm::uniswap_v2::factory::DuplicatePair;
+
+//A factory that failed to sync and was skipped
+#[derive(Debug, Clone)]
+pub struct FailedFactory {
+    pub address: H160,
+    pub error: String,
+}
+
+//Statistics collected while syncing amms from the factories
+#[derive(Debug, Clone, Default)]
+pub struct SyncStats {
+    //Uniswap v2 pairs registered more than once for the same tokens within a factory
+    pub duplicate_pairs: Vec<DuplicatePair>,
+    //Factories that failed to sync, which are only collected when the sync is not strict
+    pub failed_factories: Vec<FailedFactory>,
+    //Pools left out of the sync because a token's decimals() reverts, see `SyncConfig::decimals_fallback`
+    pub unreadable_decimals: Vec<H160>,
+}
+
+//Progress of the pool population batches of a sync run, passed to the progress callback of the sync config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncProgress {
+    pub batches_completed: usize,
+    //Batches of the factories discovered so far, which grows while factories are still being discovered
+    pub total_batches: usize,
+    //Time since the sync started
+    pub elapsed: Duration,
+}
+
+impl SyncProgress {
+    //Estimated time remaining from the average time per completed batch, None until the first batch completes
+    pub fn eta(&self) -> Option<Duration> {
+        if self.batches_completed == 0 {
+            return None;
+        }
+
+        let remaining_batches = self.total_batches.saturating_sub(self.batches_completed);
+        Some(
+            self.elapsed
+                .mul_f64(remaining_batches as f64 / self.batches_completed as f64),
+        )
+    }
+}
+
+//Called with the current progress every time a population batch completes
+#[derive(Clone)]
+pub struct ProgressCallback(pub Arc<dyn Fn(SyncProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(SyncProgress) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+//Counts the population batches of all factories of a sync run, reporting to the callback as batches complete
+#[derive(Debug)]
+pub(crate) struct ProgressTracker {
+    started: Instant,
+    total_batches: AtomicUsize,
+    batches_completed: AtomicUsize,
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(callback: Option<ProgressCallback>) -> Self {
+        ProgressTracker {
+            started: Instant::now(),
+            total_batches: AtomicUsize::new(0),
+            batches_completed: AtomicUsize::new(0),
+            callback,
+        }
+    }
+
+    pub(crate) fn add_batches(&self, batches: usize) {
+        self.total_batches.fetch_add(batches, Ordering::Relaxed);
+    }
+
+    pub(crate) fn complete_batches(&self, batches: usize) {
+        if batches == 0 {
+            return;
+        }
+
+        let batches_completed =
+            self.batches_completed.fetch_add(batches, Ordering::Relaxed) + batches;
+        if let Some(callback) = &self.callback {
+            (callback.0)(SyncProgress {
+                batches_completed,
+                total_batches: self.total_batches.load(Ordering::Relaxed),
+                elapsed: self.started.elapsed(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{ProgressCallback, ProgressTracker, SyncProgress};
+
+    #[test]
+    fn test_eta_decreases_with_progress() {
+        let progress = |batches_completed: usize| SyncProgress {
+            batches_completed,
+            total_batches: 10,
+            elapsed: Duration::from_secs(batches_completed as u64),
+        };
+
+        assert_eq!(progress(0).eta(), None);
+        assert_eq!(progress(1).eta(), Some(Duration::from_secs(9)));
+        assert_eq!(progress(5).eta(), Some(Duration::from_secs(5)));
+        assert_eq!(progress(10).eta(), Some(Duration::ZERO));
+
+        //The tracker reports every completed batch to the callback
+        let reported = Arc::new(Mutex::new(vec![]));
+        let callback_reported = reported.clone();
+        let tracker = ProgressTracker::new(Some(ProgressCallback::new(move |progress| {
+            callback_reported.lock().unwrap().push(progress)
+        })));
+        tracker.add_batches(4);
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(5));
+            tracker.complete_batches(1);
+        }
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 4);
+        assert_eq!(reported[3].batches_completed, 4);
+        assert_eq!(reported[3].total_batches, 4);
+        assert!(reported[0].eta().unwrap() > reported[2].eta().unwrap());
+        assert_eq!(reported[3].eta(), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs::read_to_string;
+
+use async_trait::async_trait;
+
+use crate::errors::CheckpointError;
+
+use super::checkpoint::Checkpoint;
+
+//Backend that checkpoints are loaded from and stored to. `FileStore` keeps the checkpoint in a json file, other
+//backends such as object storage or a key value store can be supported by implementing this trait
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    async fn load(&self) -> Result<Checkpoint, CheckpointError>;
+
+    //Stores the checkpoint, returning it once stored so that the caller can keep its amms without a clone
+    async fn store(&self, checkpoint: Checkpoint) -> Result<Checkpoint, CheckpointError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    pub path: String,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<String>) -> FileStore {
+        FileStore { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileStore {
+    async fn load(&self) -> Result<Checkpoint, CheckpointError> {
+        Ok(serde_json::from_str(read_to_string(&self.path)?.as_str())?)
+    }
+
+    //Serializes and writes the checkpoint on the blocking thread pool so that large checkpoints do not stall the async runtime
+    async fn store(&self, checkpoint: Checkpoint) -> Result<Checkpoint, CheckpointError> {
+        let path = self.path.clone();
+
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(path, serde_json::to_string_pretty(&checkpoint)?)?;
+            Ok(checkpoint)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::H160;
+
+    use crate::amm::{uniswap_v2::UniswapV2Pool, AutomatedMarketMaker, AMM};
+
+    use super::{Checkpoint, CheckpointStore, FileStore};
+
+    #[tokio::test]
+    async fn test_file_store_round_trip() -> eyre::Result<()> {
+        let checkpoint_path = std::env::temp_dir().join("amms_test_file_store_checkpoint.json");
+        let store = FileStore::new(checkpoint_path.to_str().expect("Temp path should be utf-8"));
+
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            reserve_0: 1000,
+            reserve_1: 2000,
+            ..Default::default()
+        });
+
+        let stored = store
+            .store(Checkpoint::new(1, 100, vec![], vec![pool]))
+            .await?;
+        assert_eq!(stored.amms.len(), 1);
+
+        let loaded = store.load().await?;
+        assert_eq!(loaded.block_number, 100);
+        assert_eq!(loaded.amms.len(), 1);
+        assert_eq!(loaded.amms[0].address(), H160::from_low_u64_be(1));
+
+        std::fs::remove_file(checkpoint_path)?;
+
+        Ok(())
+    }
+}
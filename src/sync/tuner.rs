@@ -0,0 +1,142 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use crate::amm::PoolType;
+
+//Parameters of the adaptive batch size tuning, enabled through `SyncConfig::batch_tuning`
+#[derive(Debug, Clone, Copy)]
+pub struct BatchTuning {
+    //Latency of a single batch request that the tuner converges towards
+    pub target_latency: Duration,
+    //Conservative batch size that every pool variant starts from, capped at the max batch size of the variant
+    pub initial_size: usize,
+    //Consecutive failed batches tolerated before the error is returned
+    pub max_failures: usize,
+}
+
+impl Default for BatchTuning {
+    fn default() -> Self {
+        BatchTuning {
+            target_latency: Duration::from_secs(2),
+            initial_size: 16,
+            max_failures: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TunedSize {
+    size: usize,
+    //Upper bound of the size, lowered below every size that failed or exceeded the target latency
+    max_size: usize,
+    failures: usize,
+}
+
+//Tunes the batch size of each pool variant from the observed batch latency and failures. Sizes grow halfway to the upper
+//bound while batches finish well under the target latency, and shrink on slow or failed batches, lowering the upper bound
+//so the tuner converges below the size the provider rejects. Sizes are kept for the lifetime of the tuner, so a single
+//tuner is shared by all factories of a sync run
+#[derive(Debug)]
+pub struct BatchSizeTuner {
+    tuning: BatchTuning,
+    sizes: Mutex<HashMap<PoolType, TunedSize>>,
+}
+
+impl BatchSizeTuner {
+    pub fn new(tuning: BatchTuning) -> Self {
+        BatchSizeTuner {
+            tuning,
+            sizes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //Returns the current batch size of the pool variant, never exceeding the max batch size of its batch request
+    pub fn size(&self, pool_type: PoolType, max_batch_size: usize) -> usize {
+        let mut sizes = self
+            .sizes
+            .lock()
+            .expect("Tuner lock should not be poisoned");
+        let tuned = sizes.entry(pool_type).or_insert(TunedSize {
+            size: self.tuning.initial_size.clamp(1, max_batch_size.max(1)),
+            max_size: max_batch_size.max(1),
+            failures: 0,
+        });
+
+        tuned.size
+    }
+
+    pub fn record_success(&self, pool_type: PoolType, latency: Duration) {
+        let mut sizes = self
+            .sizes
+            .lock()
+            .expect("Tuner lock should not be poisoned");
+        if let Some(tuned) = sizes.get_mut(&pool_type) {
+            tuned.failures = 0;
+
+            if latency > self.tuning.target_latency {
+                tuned.max_size = (tuned.size - 1).max(1);
+                tuned.size = (tuned.size * 3 / 4).max(1);
+            } else if latency < self.tuning.target_latency / 2 {
+                tuned.size = (tuned.size + tuned.max_size + 1) / 2;
+            }
+        }
+    }
+
+    //Shrinks the batch size after a failed batch, returning false once the consecutive failures exceed the threshold
+    pub fn record_failure(&self, pool_type: PoolType) -> bool {
+        let mut sizes = self
+            .sizes
+            .lock()
+            .expect("Tuner lock should not be poisoned");
+        match sizes.get_mut(&pool_type) {
+            Some(tuned) => {
+                tuned.failures += 1;
+                tuned.max_size = (tuned.size - 1).max(1);
+                tuned.size = (tuned.size / 2).max(1);
+
+                tuned.failures <= self.tuning.max_failures
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::amm::PoolType;
+
+    use super::{BatchSizeTuner, BatchTuning};
+
+    #[test]
+    fn test_tuner_grows_and_backs_off() {
+        let tuner = BatchSizeTuner::new(BatchTuning {
+            target_latency: Duration::from_secs(1),
+            initial_size: 16,
+            max_failures: 2,
+        });
+
+        assert_eq!(tuner.size(PoolType::UniswapV2Pool, 127), 16);
+
+        //Fast batches grow the size halfway towards the max batch size
+        tuner.record_success(PoolType::UniswapV2Pool, Duration::from_millis(10));
+        assert_eq!(tuner.size(PoolType::UniswapV2Pool, 127), 72);
+
+        //A failure halves the size and keeps it below the failed size from then on
+        assert!(tuner.record_failure(PoolType::UniswapV2Pool));
+        assert_eq!(tuner.size(PoolType::UniswapV2Pool, 127), 36);
+        tuner.record_success(PoolType::UniswapV2Pool, Duration::from_millis(10));
+        assert_eq!(tuner.size(PoolType::UniswapV2Pool, 127), 54);
+
+        //Slow batches shrink the size
+        tuner.record_success(PoolType::UniswapV2Pool, Duration::from_secs(2));
+        assert_eq!(tuner.size(PoolType::UniswapV2Pool, 127), 40);
+
+        //Other variants are tuned independently
+        assert_eq!(tuner.size(PoolType::UniswapV3Pool, 76), 16);
+
+        assert!(tuner.record_failure(PoolType::UniswapV2Pool));
+        assert!(tuner.record_failure(PoolType::UniswapV2Pool));
+        assert!(!tuner.record_failure(PoolType::UniswapV2Pool));
+    }
+}
@@ -0,0 +1,112 @@
+//Integration tests syncing against an Anvil fork of mainnet, where the factory state mirrors mainnet at the fork block
+//but reserves can be mutated locally. Requires `anvil` on the PATH and an archive node at ETHEREUM_RPC_ENDPOINT, run with
+//`cargo test --features anvil-tests --test anvil_fork`. The tests are skipped when ETHEREUM_RPC_ENDPOINT is not set
+#![cfg(feature = "anvil-tests")]
+
+use std::{str::FromStr, sync::Arc};
+
+use amms::{
+    amm::{factory::Factory, uniswap_v2::factory::UniswapV2Factory, AMM},
+    sync,
+};
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::{H160, H256, U256},
+    utils::{Anvil, AnvilInstance},
+};
+
+//Shortly after the uniswap v2 factory was deployed, so that the factory only holds a small number of pairs
+const FORK_BLOCK: u64 = 10010000;
+const UNISWAP_V2_FACTORY: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
+const UNISWAP_V2_FACTORY_CREATION_BLOCK: u64 = 10000835;
+//Storage slot packing reserve0, reserve1 and blockTimestampLast in a uniswap v2 pair
+const RESERVES_SLOT: u64 = 8;
+
+fn spawn_fork() -> eyre::Result<Option<(AnvilInstance, Arc<Provider<Http>>)>> {
+    let Ok(rpc_endpoint) = std::env::var("ETHEREUM_RPC_ENDPOINT") else {
+        eprintln!("ETHEREUM_RPC_ENDPOINT is not set, skipping the anvil fork test");
+        return Ok(None);
+    };
+
+    let anvil = Anvil::new()
+        .fork(rpc_endpoint)
+        .fork_block_number(FORK_BLOCK)
+        .spawn();
+    let provider = Arc::new(Provider::<Http>::try_from(anvil.endpoint())?);
+
+    Ok(Some((anvil, provider)))
+}
+
+fn uniswap_v2_factory() -> eyre::Result<Factory> {
+    Ok(Factory::UniswapV2Factory(UniswapV2Factory::new(
+        H160::from_str(UNISWAP_V2_FACTORY)?,
+        UNISWAP_V2_FACTORY_CREATION_BLOCK,
+        300,
+    )))
+}
+
+#[tokio::test]
+async fn test_sync_amms_on_fork() -> eyre::Result<()> {
+    let Some((_anvil, provider)) = spawn_fork()? else {
+        return Ok(());
+    };
+
+    let (amms, synced_block) =
+        sync::sync_amms(vec![uniswap_v2_factory()?], provider.clone(), None, 1000).await?;
+
+    assert_eq!(synced_block, FORK_BLOCK);
+    assert!(!amms.is_empty());
+
+    //The synced reserves match the reserves read directly from each pair on the fork
+    for amm in amms.iter().take(10) {
+        if let AMM::UniswapV2Pool(pool) = amm {
+            assert_eq!(
+                pool.get_reserves(provider.clone()).await?,
+                (pool.reserve_0, pool.reserve_1)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_amms_reads_local_fork_state() -> eyre::Result<()> {
+    let Some((_anvil, provider)) = spawn_fork()? else {
+        return Ok(());
+    };
+
+    let (mut amms, _) =
+        sync::sync_amms(vec![uniswap_v2_factory()?], provider.clone(), None, 1000).await?;
+    amms.truncate(1);
+    let pair = match &amms[0] {
+        AMM::UniswapV2Pool(pool) => pool.address,
+        _ => panic!("Unexpected AMM variant"),
+    };
+
+    //Overwrite the reserves of the pair locally and mine a block so the batch request reads the mutated state
+    let reserves = (U256::from(2000) << 112) | U256::from(1000);
+    let mut value = [0_u8; 32];
+    reserves.to_big_endian(&mut value);
+    provider
+        .request::<_, bool>(
+            "anvil_setStorageAt",
+            (
+                pair,
+                H256::from_low_u64_be(RESERVES_SLOT),
+                H256::from(value),
+            ),
+        )
+        .await?;
+    provider.request::<_, U256>("evm_mine", ()).await?;
+
+    let block_number = provider.get_block_number().await?.as_u64();
+    sync::refresh_amms(&mut amms, block_number, provider.clone()).await?;
+
+    match &amms[0] {
+        AMM::UniswapV2Pool(pool) => assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 2000)),
+        _ => panic!("Unexpected AMM variant"),
+    }
+
+    Ok(())
+}